@@ -1,20 +1,104 @@
-use std::path::PathBuf;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-use clap::{Args, Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+
+use crate::capabilities::Capability;
+use crate::color::ColorSpace;
+use crate::colormap::Colormap;
+use crate::demo;
+use crate::import::{Units, UpAxis};
 
 #[derive(Debug, Clone, Subcommand)]
 pub enum Source {
-    /// Publish a single file or directory
-    File { name: PathBuf },
+    /// Publish files or directories, each file as its own scene
+    File {
+        #[arg(required = true)]
+        names: Vec<PathBuf>,
+
+        #[command(flatten)]
+        transform: SourceTransform,
+
+        /// Remove scenes from this source once they are older than this
+        #[arg(long)]
+        ttl: Option<HumanDuration>,
+
+        #[command(flatten)]
+        policy: ImportPolicy,
+    },
 
     /// Watch a directory; new files will be loaded as soon as they appear.
     Watch(Directory),
 
     /// Listen on a websocket for geometry (NYI)
     Websocket { port: String },
+
+    /// Publish generated scenes, for benchmarking clients and networks
+    /// without shipping large files
+    GenTest {
+        #[command(subcommand)]
+        kind: TestScene,
+    },
+
+    /// Publish a few bundled sample models in a grid, spinning on a
+    /// turntable. A quick target for trying out clients.
+    Demo {
+        /// Distance between the centers of neighboring models
+        #[arg(long, default_value_t = 1.5)]
+        spacing: f32,
+    },
+}
+
+/// Kinds of generated test scenes
+#[derive(Debug, Clone, Copy, PartialEq, Subcommand)]
+pub enum TestScene {
+    /// A flat checkerboard
+    Grid {
+        /// Cells along each side
+        #[arg(long, default_value_t = 16)]
+        size: u32,
+    },
+
+    /// A row of smooth, colored spheres
+    Spheres {
+        /// Number of spheres
+        #[arg(long, default_value_t = 8)]
+        count: u32,
+
+        /// Segments around each sphere
+        #[arg(long, default_value_t = 32)]
+        segments: u32,
+    },
+
+    /// Many entities, each with its own mesh
+    Stress {
+        /// Number of entities
+        #[arg(long, default_value_t = 100)]
+        entities: u32,
+
+        /// Triangles in each entity
+        #[arg(long, default_value_t = 1000)]
+        tris: u32,
+    },
 }
 
-#[derive(Debug, Clone, Args)]
+impl TestScene {
+    /// Name of this kind of scene
+    pub fn name(&self) -> &'static str {
+        match self {
+            TestScene::Grid { .. } => "grid",
+            TestScene::Spheres { .. } => "spheres",
+            TestScene::Stress { .. } => "stress",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Args)]
 pub struct Directory {
     /// Directory to watch for changes
     pub dir: PathBuf,
@@ -27,9 +111,280 @@ pub struct Directory {
     #[arg(short, long)]
     pub latest_only: bool,
 
+    /// Play new files as a sequence: each is shown in place of the one
+    /// before, and this many are kept for clients to step back through
+    #[arg(long, conflicts_with = "latest_only")]
+    pub sequence: Option<NonZeroUsize>,
+
     /// New files may show up in subdirectories. Combine with `latest_only`.
     #[arg(short, long)]
     pub organize_by_dir: bool,
+
+    #[command(flatten)]
+    pub transform: SourceTransform,
+
+    /// Remove scenes from this directory once they are older than this
+    #[arg(long)]
+    pub ttl: Option<HumanDuration>,
+
+    #[command(flatten)]
+    pub policy: ImportPolicy,
+
+    /// Only load files matching one of these patterns, such as "*.glb". May
+    /// be given more than once.
+    #[arg(long)]
+    pub include: Vec<FilePattern>,
+
+    /// Never load files matching one of these patterns, such as "*.tmp".
+    /// May be given more than once.
+    #[arg(long)]
+    pub exclude: Vec<FilePattern>,
+
+    /// Scan the directory for new and changed files this often, such as
+    /// "5", instead of waiting for change events. For network shares, which
+    /// often do not deliver events.
+    #[arg(long)]
+    pub poll: Option<HumanDuration>,
+
+    /// Watch subdirectories at most this deep; 1 is the directory's own
+    /// subdirectories, but not theirs
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+
+    /// Only watch files directly in the directory. Same as `--max-depth 0`.
+    #[arg(long, conflicts_with = "max_depth")]
+    pub no_recursive: bool,
+}
+
+impl Directory {
+    /// How many levels of subdirectories to watch, if limited
+    pub fn depth_limit(&self) -> Option<usize> {
+        if self.no_recursive {
+            Some(0)
+        } else {
+            self.max_depth
+        }
+    }
+
+    /// How many subdirectories down a path is. Entries of the directory
+    /// itself are at depth 0.
+    pub fn depth(&self, path: &Path) -> usize {
+        let relative = path.strip_prefix(&self.dir).unwrap_or(path);
+        relative.components().count().saturating_sub(1)
+    }
+
+    /// Whether the contents of a subdirectory are within the depth limit
+    pub fn watches_dir(&self, path: &Path) -> bool {
+        self.depth_limit().is_none_or(|f| self.depth(path) < f)
+    }
+
+    /// Whether a file in this directory is within the depth limit and passes
+    /// the include and exclude patterns. Excludes win.
+    pub fn accepts(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.dir).unwrap_or(path);
+
+        self.depth_limit().is_none_or(|f| self.depth(path) <= f)
+            && (self.include.is_empty() || self.include.iter().any(|f| f.matches(relative)))
+            && !self.exclude.iter().any(|f| f.matches(relative))
+    }
+}
+
+/// A glob for files in a watched directory. Patterns with a '/' match the
+/// path within the directory, such as "scans/*.ply"; others match the file
+/// name alone.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct FilePattern(glob::Pattern);
+
+impl FilePattern {
+    pub fn matches(&self, relative: &Path) -> bool {
+        if self.0.as_str().contains('/') {
+            let options = glob::MatchOptions {
+                require_literal_separator: true,
+                ..Default::default()
+            };
+            return self.0.matches_path_with(relative, options);
+        }
+
+        relative
+            .file_name()
+            .is_some_and(|f| self.0.matches(&f.to_string_lossy()))
+    }
+}
+
+impl FromStr for FilePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        glob::Pattern::new(s)
+            .map(FilePattern)
+            .map_err(|e| format!("Bad pattern {s:?}: {e}"))
+    }
+}
+
+impl TryFrom<String> for FilePattern {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Source {
+    /// Transform overrides for everything from this source
+    pub fn transform(&self) -> SourceTransform {
+        match self {
+            Source::File { transform, .. } => *transform,
+            Source::Watch(dir) => dir.transform,
+            Source::Websocket { .. } | Source::GenTest { .. } | Source::Demo { .. } => {
+                SourceTransform::default()
+            }
+        }
+    }
+
+    /// How long scenes from this source live, if set for the source
+    pub fn ttl(&self) -> Option<Duration> {
+        match self {
+            Source::File { ttl, .. } => ttl.map(|f| f.0),
+            Source::Watch(dir) => dir.ttl.map(|f| f.0),
+            Source::Websocket { .. } | Source::GenTest { .. } | Source::Demo { .. } => None,
+        }
+    }
+
+    /// How imports from this source are scheduled
+    pub fn policy(&self) -> ImportPolicy {
+        match self {
+            Source::File { policy, .. } => *policy,
+            Source::Watch(dir) => dir.policy,
+            Source::Websocket { .. } | Source::GenTest { .. } | Source::Demo { .. } => {
+                ImportPolicy::default()
+            }
+        }
+    }
+
+    /// Files and directories content is loaded from
+    pub fn paths(&self) -> Vec<PathBuf> {
+        match self {
+            Source::File { names, .. } => names.clone(),
+            Source::Watch(dir) => vec![dir.dir.clone()],
+            Source::Websocket { .. } | Source::GenTest { .. } | Source::Demo { .. } => vec![],
+        }
+    }
+}
+
+/// Transform overrides for a single source. Anything left unset falls back
+/// to the global option.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Args)]
+pub struct SourceTransform {
+    /// Offset this source by a vector, such as "1,0,-2"
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub offset: Option<[f32; 3]>,
+
+    /// Rescale this source by this factor
+    #[arg(long)]
+    pub rescale: Option<f32>,
+
+    /// Rotate this source by angles in degrees about the x, y, and z axes
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub rotate: Option<[f32; 3]>,
+}
+
+impl SourceTransform {
+    pub fn is_empty(&self) -> bool {
+        self.offset.is_none() && self.rescale.is_none() && self.rotate.is_none()
+    }
+
+    /// Fill in anything unset from `other`
+    pub fn or(self, other: SourceTransform) -> SourceTransform {
+        SourceTransform {
+            offset: self.offset.or(other.offset),
+            rescale: self.rescale.or(other.rescale),
+            rotate: self.rotate.or(other.rotate),
+        }
+    }
+}
+
+/// How imports from a source are scheduled against those from others
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Args)]
+pub struct ImportPolicy {
+    /// Imports from sources with a higher priority go first
+    #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+    pub priority: i32,
+
+    /// Most imports from this source to run at once. The global import
+    /// concurrency still applies.
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+}
+
+/// A length of time such as "90s", "15m", "2h", or "1d". Bare numbers are
+/// seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HumanDuration(pub Duration);
+
+impl FromStr for HumanDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit() && c != '.');
+        let (number, unit) = s.split_at(split.unwrap_or(s.len()));
+
+        let scale = match unit.trim() {
+            "" | "s" => 1.0,
+            "m" => 60.0,
+            "h" => 60.0 * 60.0,
+            "d" => 24.0 * 60.0 * 60.0,
+            unit => return Err(format!("Unknown unit {unit:?}; use s, m, h, or d")),
+        };
+
+        let number: f64 = number
+            .parse()
+            .map_err(|e| format!("Bad duration {s:?}: {e}"))?;
+
+        Duration::try_from_secs_f64(number * scale)
+            .map(HumanDuration)
+            .map_err(|e| format!("Bad duration {s:?}: {e}"))
+    }
+}
+
+impl TryFrom<String> for HumanDuration {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Which entities of a scene are given methods
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MethodAttachment {
+    /// Only the root of each scene
+    #[default]
+    Root,
+    /// The root, and every named entity below it
+    Named,
+    /// Nothing. Methods that change scenes are not offered at all.
+    None,
+}
+
+/// What to do when an internal queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    /// Wait for room. Nothing is lost, but the producer stalls
+    #[default]
+    Block,
+    /// Discard the new item and log a warning
+    Drop,
+    /// Discard the oldest waiting item to make room, and log a warning
+    DropOldest,
+    /// Discard items identical to one already waiting, and wait for room
+    /// otherwise. A file written in many pieces fills the queue with the
+    /// same event.
+    Coalesce,
 }
 
 #[derive(Parser)]
@@ -38,7 +393,17 @@ pub struct Directory {
 #[command(about = "Publish meshes to the NOODLES protocol", long_about = None)]
 pub struct Arguments {
     #[command(subcommand)]
-    pub source: Source,
+    pub source: Option<Source>,
+
+    /// Read settings and sources from this TOML file. Flags given on the
+    /// command line take precedence, and a source given on the command line
+    /// replaces those in the file.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Everything to publish, from the command line or the config file
+    #[arg(skip)]
+    pub sources: Vec<Source>,
 
     /// Host address to bind to
     #[arg(short, long)]
@@ -56,11 +421,942 @@ pub struct Arguments {
     #[arg(short, long)]
     pub rescale: Option<f32>,
 
-    ///Offset content by a vector as provided by a string
-    #[arg(short, long)]
-    pub offset: Option<String>,
+    ///Offset content by a vector, such as "1,0,-2"
+    #[arg(short, long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub offset: Option<[f32; 3]>,
+
+    /// Rotate content by angles in degrees about the x, y, and z axes,
+    /// applied in that order. Use "-90,0,0" to stand Z-up data upright.
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub rotate: Option<[f32; 3]>,
+
+    /// Up axis of the source content. Z-up content is turned Y-up.
+    #[arg(long, value_enum, default_value_t = UpAxis::Y)]
+    pub up_axis: UpAxis,
+
+    /// Units of the source content, which is then scaled to meters
+    #[arg(long, value_enum)]
+    pub units: Option<Units>,
+
+    /// Color space of vertex and point colors in source files. Most formats
+    /// store sRGB; NOODLES clients expect linear, so sRGB colors are
+    /// converted.
+    #[arg(long, value_enum, default_value_t = ColorSpace::Srgb)]
+    pub vertex_color_space: ColorSpace,
+
+    /// Move each scene so the center of its bounds is at the origin
+    #[arg(long)]
+    pub center: bool,
+
+    /// Scale each scene so its largest side is this long
+    #[arg(long)]
+    pub fit: Option<f32>,
+
+    /// Remove scenes once they are older than this, such as "30m". Sources
+    /// may set their own.
+    #[arg(long)]
+    pub ttl: Option<HumanDuration>,
+
+    /// Show this mesh file until the first real scene is loaded
+    #[arg(long)]
+    pub placeholder: Option<PathBuf>,
+
+    /// Show this text until the first real scene is loaded
+    #[arg(long, conflicts_with = "placeholder")]
+    pub placeholder_text: Option<String>,
+
+    /// Download resources referenced by remote URIs and serve them ourselves
+    #[arg(long)]
+    pub fetch_remote: bool,
+
+    /// Let clients load models by URL, with the same limits as remote
+    /// resources
+    #[arg(long)]
+    pub load_url: bool,
+
+    /// Let clients export scenes as GLB files into this directory
+    #[arg(long)]
+    pub export_dir: Option<PathBuf>,
+
+    /// Write every scene, as it is then, to this GLB file when platter is
+    /// shut down with platter.shutdown
+    #[arg(long)]
+    pub export_on_exit: Option<PathBuf>,
+
+    /// Let clients watch directories below this one, and stop watching any
+    /// directory
+    #[arg(long)]
+    pub watch_root: Option<PathBuf>,
+
+    /// Priority of models clients load by URL. Sources default to 0, so
+    /// these go ahead of directory backfills.
+    #[arg(long, default_value_t = 10, allow_hyphen_values = true)]
+    pub url_priority: i32,
+
+    /// Most imports to run at once
+    #[arg(long, default_value_t = 1)]
+    pub import_concurrency: usize,
+
+    /// Timeout in seconds for remote downloads
+    #[arg(long, default_value_t = 30)]
+    pub fetch_timeout: u64,
+
+    /// Size in bytes of the largest allowed remote download
+    #[arg(long, default_value_t = 256 * 1024 * 1024)]
+    pub fetch_max_size: u64,
+
+    /// Produce the same ordering and asset ids each run for the same input
+    #[arg(long)]
+    pub deterministic: bool,
+
+    /// Show each scene's name as a label above it
+    #[arg(long)]
+    pub label_scenes: bool,
+
+    /// Put a soft shadow on the ground under each scene, for clients
+    /// without shadows of their own
+    #[arg(long)]
+    pub ground_shadows: bool,
+
+    /// Spin every scene about its vertical axis at this many degrees per
+    /// second
+    #[arg(long, allow_hyphen_values = true)]
+    pub turntable: Option<f32>,
+
+    /// Tint everything from a watched directory with a color of its own
+    #[arg(long)]
+    pub tint_sources: bool,
+
+    /// Wake less often when idle: stretch timers and group their wakeups.
+    /// Useful for battery powered demos.
+    #[arg(long)]
+    pub low_power: bool,
+
+    /// Log memory use, asset bytes, scene and task counts, and queue depths
+    /// this often, such as "1m"
+    #[arg(long)]
+    pub self_report: Option<HumanDuration>,
+
+    /// Sweep for published assets that no scene uses this often, and remove
+    /// them. Zero turns the sweep off.
+    #[arg(long, default_value = "10m")]
+    pub gc_interval: HumanDuration,
+
+    /// Compute smooth normals for meshes that do not provide them
+    #[arg(long)]
+    pub generate_normals: bool,
+
+    /// Publish zero-area and duplicate triangles instead of removing them
+    #[arg(long)]
+    pub keep_degenerate: bool,
+
+    /// Merge vertices that are nearly the same, for exports that give each
+    /// face its own corners
+    #[arg(long)]
+    pub weld: bool,
+
+    /// Distance within which welded positions are merged
+    #[arg(long, default_value_t = 1e-5)]
+    pub weld_position: f32,
+
+    /// Distance within which welded normals are merged
+    #[arg(long, default_value_t = 1e-3)]
+    pub weld_normal: f32,
+
+    /// Distance within which welded texture coordinates are merged
+    #[arg(long, default_value_t = 1e-4)]
+    pub weld_texture: f32,
+
+    /// Draw a mesh placed by at least this many nodes of a glTF file with
+    /// instances, rather than an entity per node. Zero turns this off.
+    #[arg(long, default_value_t = 4)]
+    pub instance_threshold: usize,
+
+    /// Scale textures down so neither side is longer than this many pixels,
+    /// for clients that can't hold huge images
+    #[arg(long)]
+    pub max_texture_size: Option<u32>,
+
+    /// Most bytes of assets to publish over all scenes. Files that would
+    /// go over are refused before anything is published.
+    #[arg(long)]
+    pub asset_limit: Option<u64>,
+
+    /// Most transform updates per second sent for each scene. Faster
+    /// changes are merged, keeping the latest. Use 0 for no limit.
+    #[arg(long, default_value_t = 30.0, value_parser = parse_rate)]
+    pub transform_rate: f32,
+
+    /// Capacity of the command queue. Watchers wait when it is full, so no
+    /// loads are lost.
+    #[arg(long, default_value_t = 16)]
+    pub command_queue: usize,
+
+    /// Capacity of the watch request queue. Requests beyond this are rejected.
+    #[arg(long, default_value_t = 16)]
+    pub watch_queue: usize,
+
+    /// Capacity of each directory watcher's filesystem event queue
+    #[arg(long, default_value_t = 16)]
+    pub fs_event_queue: usize,
+
+    /// What to do with filesystem events when their queue is full. Blocking
+    /// stalls the OS notification thread; dropping may miss new files;
+    /// coalescing only sheds repeats, and blocks when events differ.
+    #[arg(long, value_enum, default_value_t = Overflow::Block)]
+    pub fs_event_overflow: Overflow,
+
+    /// Milliseconds between checks that a new file in a watched directory
+    /// has stopped changing. Files are loaded once their size and
+    /// modification time hold still, so partly copied files are not. Zero
+    /// loads files as soon as they are closed after writing.
+    #[arg(long, default_value_t = 250)]
+    pub settle_interval: u64,
+
+    /// Checks in a row that a new file must pass unchanged to be loaded
+    #[arg(long, default_value_t = 2)]
+    pub settle_checks: u32,
+
+    /// Directory for temporary files. Defaults to the system temp directory.
+    #[arg(long)]
+    pub scratch_dir: Option<PathBuf>,
+
+    /// Maximum size in bytes of all temporary files
+    #[arg(long, default_value_t = 4 * 1024 * 1024 * 1024)]
+    pub scratch_quota: u64,
+
+    /// Column layout for xyz/pts/pcd point clouds, such as "x y z r g b".
+    /// Use "_" to skip a column.
+    #[arg(long)]
+    pub point_columns: Option<String>,
+
+    /// Names of the x, y, and z columns of CSV/TSV tables
+    #[arg(long, default_value = "x,y,z")]
+    pub table_position: String,
+
+    /// Color table rows by this column
+    #[arg(long)]
+    pub table_color: Option<String>,
+
+    /// Colormap for scalar data, such as --table-color and VTK scalars
+    #[arg(long, value_enum, default_value_t = Colormap::Viridis)]
+    pub colormap: Colormap,
+
+    /// Show table rows as instanced cubes of this size, instead of points
+    #[arg(long)]
+    pub table_glyph_size: Option<f32>,
+
+    /// Import PNG and TIFF images as heightmap terrain. Off by default, as
+    /// images in a watched directory are more often textures of the models
+    /// next to them.
+    #[arg(long)]
+    pub heightmaps: bool,
+
+    /// Horizontal distance between pixels of grayscale PNG/TIFF heightmaps
+    #[arg(long, default_value_t = 1.0)]
+    pub heightmap_spacing: f32,
+
+    /// Height of a white heightmap pixel. Floating point TIFFs are
+    /// multiplied by this.
+    #[arg(long, default_value_t = 1.0)]
+    pub heightmap_scale: f32,
+
+    /// Do not publish this kind of component, for clients that cannot
+    /// handle it. May be repeated.
+    #[arg(long, value_enum)]
+    pub disable_capability: Vec<Capability>,
+
+    /// Publish this kind of component, which is off by default, for clients
+    /// that handle it. Only ktx2 is off by default. May be repeated.
+    #[arg(long, value_enum)]
+    pub enable_capability: Vec<Capability>,
+
+    /// Which entities of each scene get methods to move, reload, or delete
+    /// the scene. Use none for content clients may only look at.
+    #[arg(long, value_enum, default_value_t = MethodAttachment::Root)]
+    pub method_attachment: MethodAttachment,
+
+    /// Secret that clients must give to control the server remotely, such
+    /// as with platter.shutdown. Control methods are not offered without it.
+    #[arg(long)]
+    pub control_token: Option<String>,
+}
+
+/// Parse a rate, which may be zero but not negative
+fn parse_rate(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(f) if f.is_finite() && f >= 0.0 => Ok(f),
+        Ok(f) => Err(format!("Rate must be zero or more, got {f}")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parse a comma separated vector. Missing components are zero.
+fn parse_vec3(s: &str) -> Result<[f32; 3], String> {
+    let mut ret = [0.0; 3];
+    let parts: Vec<_> = s.split(',').map(str::trim).collect();
+
+    if parts.len() > 3 {
+        return Err(format!(
+            "Expected at most three components, got {}",
+            parts.len()
+        ));
+    }
+
+    for (slot, part) in ret.iter_mut().zip(parts) {
+        *slot = part
+            .parse()
+            .map_err(|e| format!("Bad component {part:?}: {e}"))?;
+    }
+
+    Ok(ret)
+}
+
+/// A `[[source]]` table in a config file
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct SourceConfig {
+    file: Option<PathBuf>,
+    watch: Option<PathBuf>,
+    #[serde(default)]
+    load_existing: bool,
+    #[serde(default)]
+    latest_only: bool,
+    sequence: Option<NonZeroUsize>,
+    #[serde(default)]
+    organize_by_dir: bool,
+    offset: Option<[f32; 3]>,
+    rescale: Option<f32>,
+    rotate: Option<[f32; 3]>,
+    ttl: Option<HumanDuration>,
+    #[serde(default)]
+    priority: i32,
+    concurrency: Option<usize>,
+    #[serde(default)]
+    include: Vec<FilePattern>,
+    #[serde(default)]
+    exclude: Vec<FilePattern>,
+    poll: Option<HumanDuration>,
+    max_depth: Option<usize>,
+    #[serde(default)]
+    no_recursive: bool,
+}
+
+impl SourceConfig {
+    /// Resolve paths relative to the config file they came from
+    fn into_source(self, base: &Path) -> Result<Source, String> {
+        let transform = SourceTransform {
+            offset: self.offset,
+            rescale: self.rescale,
+            rotate: self.rotate,
+        };
+
+        let policy = ImportPolicy {
+            priority: self.priority,
+            concurrency: self.concurrency,
+        };
+
+        match (self.file, self.watch) {
+            (Some(file), None) => {
+                if self.load_existing
+                    || self.latest_only
+                    || self.sequence.is_some()
+                    || self.organize_by_dir
+                    || !self.include.is_empty()
+                    || !self.exclude.is_empty()
+                    || self.poll.is_some()
+                    || self.max_depth.is_some()
+                    || self.no_recursive
+                {
+                    return Err(format!(
+                        "Source {} is a file; watch options do not apply",
+                        file.display()
+                    ));
+                }
+                Ok(Source::File {
+                    names: vec![base.join(file)],
+                    transform,
+                    ttl: self.ttl,
+                    policy,
+                })
+            }
+            (None, Some(dir)) if self.latest_only && self.sequence.is_some() => Err(format!(
+                "Source {} may be latest-only or a sequence, not both",
+                dir.display()
+            )),
+            (None, Some(dir)) => Ok(Source::Watch(Directory {
+                dir: base.join(dir),
+                load_existing: self.load_existing,
+                latest_only: self.latest_only,
+                sequence: self.sequence,
+                organize_by_dir: self.organize_by_dir,
+                transform,
+                ttl: self.ttl,
+                policy,
+                include: self.include,
+                exclude: self.exclude,
+                poll: self.poll,
+                max_depth: self.max_depth,
+                no_recursive: self.no_recursive,
+            })),
+            _ => Err("Each source needs exactly one of file or watch".into()),
+        }
+    }
+}
+
+/// Settings from a config file. Keys are named as their command line flags.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    address: Option<url::Url>,
+    port: Option<u16>,
+    size_large_limit: Option<u64>,
+    rescale: Option<f32>,
+    offset: Option<[f32; 3]>,
+    rotate: Option<[f32; 3]>,
+    up_axis: Option<UpAxis>,
+    units: Option<Units>,
+    vertex_color_space: Option<ColorSpace>,
+    center: Option<bool>,
+    fit: Option<f32>,
+    ttl: Option<HumanDuration>,
+    placeholder: Option<PathBuf>,
+    placeholder_text: Option<String>,
+    fetch_remote: Option<bool>,
+    load_url: Option<bool>,
+    export_dir: Option<PathBuf>,
+    export_on_exit: Option<PathBuf>,
+    watch_root: Option<PathBuf>,
+    url_priority: Option<i32>,
+    import_concurrency: Option<usize>,
+    fetch_timeout: Option<u64>,
+    fetch_max_size: Option<u64>,
+    deterministic: Option<bool>,
+    label_scenes: Option<bool>,
+    ground_shadows: Option<bool>,
+    turntable: Option<f32>,
+    tint_sources: Option<bool>,
+    low_power: Option<bool>,
+    self_report: Option<HumanDuration>,
+    gc_interval: Option<HumanDuration>,
+    generate_normals: Option<bool>,
+    keep_degenerate: Option<bool>,
+    weld: Option<bool>,
+    weld_position: Option<f32>,
+    weld_normal: Option<f32>,
+    weld_texture: Option<f32>,
+    instance_threshold: Option<usize>,
+    max_texture_size: Option<u32>,
+    asset_limit: Option<u64>,
+    transform_rate: Option<f32>,
+    command_queue: Option<usize>,
+    watch_queue: Option<usize>,
+    fs_event_queue: Option<usize>,
+    fs_event_overflow: Option<Overflow>,
+    settle_interval: Option<u64>,
+    settle_checks: Option<u32>,
+    scratch_dir: Option<PathBuf>,
+    scratch_quota: Option<u64>,
+    point_columns: Option<String>,
+    table_position: Option<String>,
+    table_color: Option<String>,
+    colormap: Option<Colormap>,
+    table_glyph_size: Option<f32>,
+    heightmaps: Option<bool>,
+    heightmap_spacing: Option<f32>,
+    heightmap_scale: Option<f32>,
+    disable_capability: Option<Vec<Capability>>,
+    enable_capability: Option<Vec<Capability>>,
+    method_attachment: Option<MethodAttachment>,
+    control_token: Option<String>,
+    #[serde(default, rename = "source")]
+    sources: Vec<SourceConfig>,
+}
+
+/// Fill in each listed field from the config, unless it was given on the
+/// command line
+macro_rules! merge {
+    ($args:ident, $config:ident, $matches:ident; $($field:ident),* $(,)?) => {
+        $(
+            if let Some(v) = $config.$field {
+                if $matches.value_source(stringify!($field)) != Some(ValueSource::CommandLine) {
+                    $args.$field = v.into();
+                }
+            }
+        )*
+    };
+}
+
+impl Arguments {
+    /// Apply settings from a config file, found relative to `base`
+    fn apply_config(
+        &mut self,
+        text: &str,
+        base: &Path,
+        matches: &ArgMatches,
+    ) -> Result<(), String> {
+        let mut config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+
+        // Only one kind of placeholder may be in use, so the command line
+        // wins over both
+        if matches.value_source("placeholder") == Some(ValueSource::CommandLine)
+            || matches.value_source("placeholder_text") == Some(ValueSource::CommandLine)
+        {
+            config.placeholder = None;
+            config.placeholder_text = None;
+        }
+
+        config.placeholder = config.placeholder.map(|f| base.join(f));
+        config.scratch_dir = config.scratch_dir.map(|f| base.join(f));
+        config.export_dir = config.export_dir.map(|f| base.join(f));
+        config.export_on_exit = config.export_on_exit.map(|f| base.join(f));
+        config.watch_root = config.watch_root.map(|f| base.join(f));
+
+        merge!(self, config, matches;
+            address,
+            port,
+            size_large_limit,
+            rescale,
+            offset,
+            rotate,
+            up_axis,
+            units,
+            vertex_color_space,
+            center,
+            fit,
+            ttl,
+            placeholder,
+            placeholder_text,
+            fetch_remote,
+            load_url,
+            export_dir,
+            export_on_exit,
+            watch_root,
+            url_priority,
+            import_concurrency,
+            fetch_timeout,
+            fetch_max_size,
+            deterministic,
+            label_scenes,
+            ground_shadows,
+            turntable,
+            tint_sources,
+            low_power,
+            self_report,
+            gc_interval,
+            generate_normals,
+            keep_degenerate,
+            weld,
+            weld_position,
+            weld_normal,
+            weld_texture,
+            instance_threshold,
+            max_texture_size,
+            asset_limit,
+            transform_rate,
+            command_queue,
+            watch_queue,
+            fs_event_queue,
+            fs_event_overflow,
+            settle_interval,
+            settle_checks,
+            scratch_dir,
+            scratch_quota,
+            point_columns,
+            table_position,
+            table_color,
+            colormap,
+            table_glyph_size,
+            heightmaps,
+            heightmap_spacing,
+            heightmap_scale,
+            disable_capability,
+            enable_capability,
+            method_attachment,
+            control_token,
+        );
+
+        if !(self.transform_rate.is_finite() && self.transform_rate >= 0.0) {
+            return Err(format!(
+                "transform-rate must be zero or more, got {}",
+                self.transform_rate
+            ));
+        }
+
+        self.sources = config
+            .sources
+            .into_iter()
+            .map(|f| f.into_source(base))
+            .collect::<Result<_, _>>()?;
+
+        Ok(())
+    }
+
+    /// Build arguments from parsed matches, reading the config file if one
+    /// was given
+    fn from_matches(matches: &ArgMatches) -> Result<Arguments, clap::Error> {
+        let mut args = Arguments::from_arg_matches(matches)?;
+
+        if let Some(path) = args.config.clone() {
+            let text = std::fs::read_to_string(&path).map_err(|e| {
+                clap::Error::raw(
+                    ErrorKind::Io,
+                    format!("Unable to read config {}: {e}", path.display()),
+                )
+            })?;
+
+            let base = path.parent().unwrap_or(Path::new(""));
+
+            args.apply_config(&text, base, matches).map_err(|e| {
+                clap::Error::raw(
+                    ErrorKind::InvalidValue,
+                    format!("Bad config {}: {e}", path.display()),
+                )
+            })?;
+        }
+
+        if let Some(source) = args.source.take() {
+            args.sources = vec![source];
+        }
+
+        // The demo frames and spins its samples, unless told otherwise
+        if args
+            .sources
+            .iter()
+            .any(|f| matches!(f, Source::Demo { .. }))
+        {
+            args.center = true;
+            args.fit = args.fit.or(Some(demo::FIT));
+            args.turntable = args.turntable.or(Some(demo::TURNTABLE));
+        }
+
+        if args.sources.is_empty() {
+            return Err(clap::Error::raw(
+                ErrorKind::MissingSubcommand,
+                "Nothing to publish. Give a source, or a config file with sources.",
+            ));
+        }
+
+        Ok(args)
+    }
 }
 
 pub fn get_arguments() -> Arguments {
-    Arguments::parse()
+    let mut command = Arguments::command();
+    let matches = command.get_matches_mut();
+
+    Arguments::from_matches(&matches).unwrap_or_else(|e| e.format(&mut command).exit())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Arguments, clap::Error> {
+        let matches = Arguments::command().try_get_matches_from(args)?;
+        Arguments::from_matches(&matches)
+    }
+
+    #[test]
+    fn test_file_list() {
+        let args = parse(&["platter", "file", "a.glb", "b.obj", "scans/"]).unwrap();
+
+        let [Source::File {
+            names, transform, ..
+        }] = args.sources.as_slice()
+        else {
+            panic!("Expected file source");
+        };
+        assert_eq!(names, &["a.glb", "b.obj", "scans/"].map(PathBuf::from));
+        assert!(transform.is_empty());
+
+        assert!(parse(&["platter", "file"]).is_err());
+        assert!(parse(&["platter"]).is_err());
+    }
+
+    #[test]
+    fn test_gen_test() {
+        let args = parse(&[
+            "platter",
+            "gen-test",
+            "stress",
+            "--entities",
+            "5",
+            "--tris",
+            "7",
+        ])
+        .unwrap();
+
+        let [Source::GenTest { kind }] = args.sources.as_slice() else {
+            panic!("Expected generated source");
+        };
+        assert_eq!(
+            *kind,
+            TestScene::Stress {
+                entities: 5,
+                tris: 7
+            }
+        );
+
+        let args = parse(&["platter", "gen-test", "grid"]).unwrap();
+        assert!(args.sources[0].paths().is_empty());
+
+        assert!(parse(&["platter", "gen-test"]).is_err());
+    }
+
+    #[test]
+    fn test_demo() {
+        let args = parse(&["platter", "demo"]).unwrap();
+
+        assert!(matches!(args.sources[0], Source::Demo { .. }));
+        assert!(args.center);
+        assert_eq!(args.fit, Some(demo::FIT));
+        assert_eq!(args.turntable, Some(demo::TURNTABLE));
+
+        let args = parse(&["platter", "--turntable", "0", "--fit", "3", "demo"]).unwrap();
+        assert_eq!(args.fit, Some(3.0));
+        assert_eq!(args.turntable, Some(0.0));
+    }
+
+    #[test]
+    fn test_file_patterns() {
+        let args = parse(&[
+            "platter",
+            "watch",
+            "scans",
+            "--include",
+            "*.glb",
+            "--include",
+            "raw/*.ply",
+            "--exclude",
+            "draft*",
+        ])
+        .unwrap();
+
+        let Source::Watch(dir) = &args.sources[0] else {
+            panic!("Expected watch source");
+        };
+
+        let accepts = |f: &str| dir.accepts(&Path::new("scans").join(f));
+
+        assert!(accepts("a.glb"));
+        assert!(accepts("deep/down/a.glb"));
+        assert!(accepts("raw/b.ply"));
+        assert!(!accepts("raw/deep/b.ply"));
+        assert!(!accepts("b.ply"));
+        assert!(!accepts("a.glb.tmp"));
+        assert!(!accepts("draft.glb"));
+
+        assert!(parse(&["platter", "watch", "scans", "--include", "[a"]).is_err());
+
+        let args = parse(&["platter", "watch", "scans", "--max-depth", "1"]).unwrap();
+        let Source::Watch(dir) = &args.sources[0] else {
+            panic!("Expected watch source");
+        };
+
+        let path = |f: &str| Path::new("scans").join(f);
+
+        assert!(dir.accepts(&path("a/b.glb")));
+        assert!(!dir.accepts(&path("a/b/c.glb")));
+        assert!(dir.watches_dir(&path("a")));
+        assert!(!dir.watches_dir(&path("a/b")));
+
+        let args = parse(&["platter", "watch", "scans", "--sequence", "5"]).unwrap();
+        let Source::Watch(dir) = &args.sources[0] else {
+            panic!("Expected watch source");
+        };
+        assert_eq!(dir.sequence, NonZeroUsize::new(5));
+        assert!(parse(&["platter", "watch", "scans", "-l", "--sequence", "5"]).is_err());
+        assert!(parse(&["platter", "watch", "scans", "--sequence", "0"]).is_err());
+
+        let args = parse(&["platter", "watch", "scans", "--no-recursive"]).unwrap();
+        let Source::Watch(dir) = &args.sources[0] else {
+            panic!("Expected watch source");
+        };
+        assert_eq!(dir.depth_limit(), Some(0));
+        assert!(dir.accepts(&path("b.glb")));
+        assert!(!dir.accepts(&path("a/b.glb")));
+
+        assert!(parse(&[
+            "platter",
+            "watch",
+            "scans",
+            "--no-recursive",
+            "--max-depth",
+            "2"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_vec3() {
+        assert_eq!(parse_vec3("-1, 2"), Ok([-1.0, 2.0, 0.0]));
+        assert!(parse_vec3("1,2,3,4").is_err());
+        assert!(parse_vec3("1,x").is_err());
+
+        let args = parse(&["platter", "-o", "-1,0,3", "file", "a.glb"]).unwrap();
+        assert_eq!(args.offset, Some([-1.0, 0.0, 3.0]));
+    }
+
+    #[test]
+    fn test_source_transform() {
+        let args = parse(&[
+            "platter",
+            "--rescale",
+            "2",
+            "watch",
+            "scans",
+            "-l",
+            "--rescale",
+            "0.5",
+            "--rotate",
+            "-90,0,0",
+        ])
+        .unwrap();
+
+        assert_eq!(args.rescale, Some(2.0));
+
+        let Source::Watch(dir) = &args.sources[0] else {
+            panic!("Expected watch source");
+        };
+        assert!(dir.latest_only);
+        assert_eq!(
+            dir.transform,
+            SourceTransform {
+                offset: None,
+                rescale: Some(0.5),
+                rotate: Some([-90.0, 0.0, 0.0]),
+            }
+        );
+
+        let args = parse(&["platter", "file", "a.glb", "--offset", "1,2,3"]).unwrap();
+        let global = SourceTransform {
+            offset: Some([0.0; 3]),
+            rescale: Some(3.0),
+            rotate: None,
+        };
+
+        assert_eq!(
+            args.sources[0].transform().or(global),
+            SourceTransform {
+                offset: Some([1.0, 2.0, 3.0]),
+                rescale: Some(3.0),
+                rotate: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_ttl() {
+        let secs = |s: &str| s.parse::<HumanDuration>().map(|f| f.0.as_secs_f64());
+
+        assert_eq!(secs("90"), Ok(90.0));
+        assert_eq!(secs("1.5m"), Ok(90.0));
+        assert_eq!(secs("2h"), Ok(7200.0));
+        assert_eq!(secs("1d"), Ok(86400.0));
+        assert!(secs("").is_err());
+        assert!(secs("5w").is_err());
+        assert!(secs("-5s").is_err());
+
+        let args = parse(&["platter", "--ttl", "1h", "watch", "drop", "--ttl", "5m"]).unwrap();
+        assert_eq!(args.ttl, Some(HumanDuration(Duration::from_secs(3600))));
+        assert_eq!(args.sources[0].ttl(), Some(Duration::from_secs(300)));
+
+        let args = parse(&["platter", "file", "a.glb"]).unwrap();
+        assert_eq!(args.sources[0].ttl(), None);
+    }
+
+    #[test]
+    fn test_config() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = dir.path().join("platter.toml");
+
+        std::fs::write(
+            &config,
+            r#"
+port = 50000
+size-large-limit = 100
+rescale = 2.0
+colormap = "coolwarm"
+method-attachment = "none"
+disable-capability = ["text"]
+enable-capability = ["ktx2"]
+
+[[source]]
+file = "a.glb"
+offset = [1, 0, 0]
+
+[[source]]
+watch = "incoming"
+latest-only = true
+exclude = ["*.tmp"]
+poll = "30"
+ttl = "10m"
+priority = -5
+concurrency = 2
+"#,
+        )
+        .unwrap();
+
+        let config = config.to_str().unwrap();
+
+        let args = parse(&["platter", "--config", config, "--port", "1234"]).unwrap();
+
+        // The command line wins
+        assert_eq!(args.port, Some(1234));
+        assert_eq!(args.size_large_limit, 100);
+        assert_eq!(args.rescale, Some(2.0));
+        assert_eq!(args.colormap, Colormap::Coolwarm);
+        assert_eq!(args.method_attachment, MethodAttachment::None);
+        assert_eq!(args.disable_capability, vec![Capability::Text]);
+        assert_eq!(args.enable_capability, vec![Capability::Ktx2]);
+
+        assert_eq!(args.sources.len(), 2);
+        assert_eq!(args.sources[0].paths(), [dir.path().join("a.glb")]);
+        assert_eq!(args.sources[0].transform().offset, Some([1.0, 0.0, 0.0]));
+
+        let Source::Watch(watch) = &args.sources[1] else {
+            panic!("Expected watch source");
+        };
+        assert_eq!(watch.dir, dir.path().join("incoming"));
+        assert!(watch.latest_only);
+        assert!(!watch.accepts(&watch.dir.join("part.tmp")));
+        assert_eq!(watch.poll, Some(HumanDuration(Duration::from_secs(30))));
+        assert_eq!(args.sources[1].ttl(), Some(Duration::from_secs(600)));
+        assert_eq!(
+            args.sources[1].policy(),
+            ImportPolicy {
+                priority: -5,
+                concurrency: Some(2),
+            }
+        );
+        assert_eq!(args.sources[0].policy(), ImportPolicy::default());
+
+        // A source on the command line replaces those in the file
+        let args = parse(&["platter", "--config", config, "file", "b.obj"]).unwrap();
+        assert_eq!(args.sources.len(), 1);
+        assert_eq!(args.size_large_limit, 100);
+    }
+
+    #[test]
+    fn test_bad_config() {
+        let base = Path::new("");
+        let matches = Arguments::command()
+            .try_get_matches_from(["platter"])
+            .unwrap();
+        let mut args = Arguments::from_arg_matches(&matches).unwrap();
+
+        let bad = [
+            "[[source]]\nfile = \"a.obj\"\nwatch = \"dir\"",
+            "[[source]]\nfile = \"a.obj\"\nlatest-only = true",
+            "prot = 1234",
+            "colormap = \"rainbow\"",
+            "ttl = \"soon\"",
+            "transform-rate = -1.0",
+        ];
+
+        for text in bad {
+            assert!(args.apply_config(text, base, &matches).is_err(), "{text}");
+        }
+    }
 }
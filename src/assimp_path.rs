@@ -1,3 +0,0 @@
-pub mod assimp_import;
-pub mod intermediate;
-pub mod intermediate_to_noodles;
@@ -0,0 +1,122 @@
+//! Optional component types, which older clients may not handle
+
+use std::collections::HashSet;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::import::ImportError;
+
+/// Something platter may publish that not every client understands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    /// Light components, from formats that carry them
+    Lights,
+    /// Text entities, such as scene labels and placeholder text
+    Text,
+    /// Point geometry, from point cloud and splat formats
+    Points,
+    /// Instanced rendering, such as glyphs for table rows
+    Instances,
+    /// KTX2 textures, as published by glTF files using `KHR_texture_basisu`.
+    /// Off unless enabled; without it, the file's fallback image is used
+    /// where there is one, and other KTX2 images are transcoded to PNG where
+    /// they can be.
+    Ktx2,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [
+        Capability::Lights,
+        Capability::Text,
+        Capability::Points,
+        Capability::Instances,
+        Capability::Ktx2,
+    ];
+
+    /// Name used on the command line and when advertising to clients
+    pub fn name(&self) -> String {
+        self.to_possible_value().unwrap().get_name().to_string()
+    }
+
+    /// Whether this is used without being enabled. Few clients decode KTX2,
+    /// so that has to be asked for.
+    pub fn is_default(&self) -> bool {
+        !matches!(self, Capability::Ktx2)
+    }
+}
+
+/// The set of capabilities in use. Everything but KTX2 is enabled by
+/// default.
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    enabled: HashSet<Capability>,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::new(&[], &[])
+    }
+}
+
+impl Capabilities {
+    /// The default capabilities, plus those enabled, less those disabled.
+    /// Disabling wins.
+    pub fn new(enabled: &[Capability], disabled: &[Capability]) -> Self {
+        Self {
+            enabled: Capability::ALL
+                .into_iter()
+                .filter(|f| f.is_default() || enabled.contains(f))
+                .filter(|f| !disabled.contains(f))
+                .collect(),
+        }
+    }
+
+    pub fn has(&self, c: Capability) -> bool {
+        self.enabled.contains(&c)
+    }
+
+    /// All enabled capabilities, in a stable order
+    pub fn enabled(&self) -> impl Iterator<Item = Capability> + '_ {
+        Capability::ALL.into_iter().filter(|f| self.has(*f))
+    }
+
+    /// Fail an import that cannot work without a capability
+    pub fn require(&self, c: Capability) -> Result<(), ImportError> {
+        match self.has(c) {
+            true => Ok(()),
+            false => Err(ImportError::UnableToImport(format!(
+                "Capability {} is disabled",
+                c.name()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let caps = Capabilities::new(&[], &[Capability::Lights]);
+
+        assert!(!caps.has(Capability::Lights));
+        assert!(caps.require(Capability::Lights).is_err());
+        assert!(caps.require(Capability::Points).is_ok());
+
+        let names: Vec<_> = caps.enabled().map(|f| f.name()).collect();
+        assert_eq!(names, vec!["text", "points", "instances"]);
+
+        // KTX2 has to be asked for
+        assert!(!Capabilities::default().has(Capability::Ktx2));
+
+        let caps = Capabilities::new(&[Capability::Ktx2], &[]);
+        assert!(caps.has(Capability::Ktx2));
+        assert!(caps.has(Capability::Lights));
+
+        let caps = Capabilities::new(&[Capability::Ktx2], &[Capability::Ktx2]);
+        assert!(!caps.has(Capability::Ktx2));
+    }
+}
@@ -0,0 +1,159 @@
+//! Rate limiting for transform updates.
+//!
+//! Every transform change is broadcast to every client. A client dragging a
+//! scene can produce far more changes than anyone needs to see, so updates to
+//! an entity are held back to at most a set rate. The first change in a quiet
+//! period goes out immediately; changes after that are merged, and only the
+//! latest is sent once the interval is up, so the final position is never
+//! lost.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use colabrodo_common::common::ComponentID;
+use colabrodo_server::server::tokio;
+use colabrodo_server::server_messages::*;
+use tokio::time::Instant;
+
+use crate::scheduler::Scheduler;
+
+/// What to do with a new transform
+#[derive(Debug, PartialEq)]
+enum Action {
+    /// Send it now
+    Send,
+    /// Hold it, and send it at this time
+    Defer(Instant),
+    /// A send is already scheduled; it will pick this one up
+    Merge,
+}
+
+/// Rate state for a single entity
+struct Slot {
+    last_sent: Instant,
+    pending: Option<(EntityReference, [f32; 16])>,
+}
+
+impl Slot {
+    /// Decide what to do with a transform offered at `now`
+    fn offer(slot: Option<&Slot>, now: Instant, interval: Duration) -> Action {
+        match slot {
+            Some(s) if s.pending.is_some() => Action::Merge,
+            Some(s) if now < s.last_sent + interval => Action::Defer(s.last_sent + interval),
+            _ => Action::Send,
+        }
+    }
+}
+
+/// Shared handle to the transform rate limiter
+#[derive(Clone)]
+pub struct Coalescer {
+    interval: Duration,
+    scheduler: Scheduler,
+    slots: Arc<Mutex<HashMap<ComponentID, Slot>>>,
+}
+
+impl std::fmt::Debug for Coalescer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Coalescer")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl Coalescer {
+    /// Limit each entity to `rate` transform updates per second
+    pub fn new(rate: f32, scheduler: Scheduler) -> Self {
+        Self {
+            interval: Duration::from_secs_f32(1.0 / rate),
+            scheduler,
+            slots: Default::default(),
+        }
+    }
+
+    /// Send a transform for an entity, now or once its interval is up
+    pub fn submit(&self, ent: &EntityReference, transform: [f32; 16]) {
+        let now = Instant::now();
+        let mut slots = self.slots.lock().unwrap();
+
+        // Entities that have been quiet for a full interval need no state
+        let interval = self.interval;
+        slots.retain(|_, f| f.pending.is_some() || now < f.last_sent + interval);
+
+        match Slot::offer(slots.get(&ent.id()), now, interval) {
+            Action::Send => {
+                send(ent, transform);
+                slots.insert(
+                    ent.id(),
+                    Slot {
+                        last_sent: now,
+                        pending: None,
+                    },
+                );
+            }
+            Action::Defer(due) => {
+                let slot = slots.get_mut(&ent.id()).unwrap();
+                slot.pending = Some((ent.clone(), transform));
+
+                let flush_slots = self.slots.clone();
+                let id = ent.id();
+
+                self.scheduler.at(due, move || flush(&flush_slots, id));
+            }
+            Action::Merge => {
+                let slot = slots.get_mut(&ent.id()).unwrap();
+                slot.pending = Some((ent.clone(), transform));
+            }
+        }
+    }
+}
+
+/// Send the held transform for an entity
+fn flush(slots: &Mutex<HashMap<ComponentID, Slot>>, id: ComponentID) {
+    let mut slots = slots.lock().unwrap();
+
+    let Some(slot) = slots.get_mut(&id) else {
+        return;
+    };
+
+    if let Some((ent, transform)) = slot.pending.take() {
+        send(&ent, transform);
+        slot.last_sent = Instant::now();
+    }
+}
+
+fn send(ent: &EntityReference, transform: [f32; 16]) {
+    ServerEntityStateUpdatable {
+        transform: Some(transform),
+        ..Default::default()
+    }
+    .patch(ent);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_offer() {
+        let now = Instant::now();
+        let interval = Duration::from_millis(50);
+
+        let quiet = Slot {
+            last_sent: now - Duration::from_secs(1),
+            pending: None,
+        };
+        let busy = Slot {
+            last_sent: now - Duration::from_millis(10),
+            pending: None,
+        };
+
+        assert_eq!(Slot::offer(None, now, interval), Action::Send);
+        assert_eq!(Slot::offer(Some(&quiet), now, interval), Action::Send);
+        assert_eq!(
+            Slot::offer(Some(&busy), now, interval),
+            Action::Defer(now + Duration::from_millis(40))
+        );
+    }
+}
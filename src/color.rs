@@ -0,0 +1,202 @@
+//! Color spaces.
+//!
+//! NOODLES follows glTF: vertex colors and material factors are linear, and
+//! color textures (base color and emissive) are sRGB. Source formats are not
+//! so consistent, so importers convert into these spaces here.
+
+use std::io::Cursor;
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// How color values are encoded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorSpace {
+    /// Gamma encoded, as most 8-bit colors and images are
+    #[default]
+    Srgb,
+    /// Proportional to light intensity
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorSpace::Srgb => "srgb",
+            ColorSpace::Linear => "linear",
+        }
+    }
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Convert an 8-bit color to the linear space NOODLES expects for vertex and
+/// instance colors. Alpha is always linear, and is kept.
+pub fn vertex_color(c: [u8; 4], space: ColorSpace) -> [u8; 4] {
+    if space == ColorSpace::Linear {
+        return c;
+    }
+
+    static TABLE: OnceLock<[u8; 256]> = OnceLock::new();
+
+    let table = TABLE.get_or_init(|| {
+        std::array::from_fn(|i| (srgb_to_linear(i as f32 / 255.0) * 255.0).round() as u8)
+    });
+
+    [
+        table[c[0] as usize],
+        table[c[1] as usize],
+        table[c[2] as usize],
+        c[3],
+    ]
+}
+
+/// Convert the colors of packed vertices in place. Each vertex is `stride`
+/// bytes, with an RGBA color at `offset`.
+pub fn convert_packed(bytes: &mut [u8], stride: usize, offset: usize, space: ColorSpace) {
+    if space == ColorSpace::Linear {
+        return;
+    }
+
+    for vertex in bytes.chunks_exact_mut(stride) {
+        let c = &mut vertex[offset..offset + 4];
+        let converted = vertex_color(c.try_into().unwrap(), space);
+        c.copy_from_slice(&converted);
+    }
+}
+
+/// The color space an encoded image declares, if any. Only PNG can say; its
+/// `sRGB` chunk, or a `gAMA` chunk near 1.0 or 1/2.2, decides.
+pub fn image_color_space(bytes: &[u8]) -> Option<ColorSpace> {
+    let mut rest = bytes.strip_prefix(b"\x89PNG\r\n\x1a\n")?;
+
+    while rest.len() >= 8 {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let kind = &rest[4..8];
+        let data = rest.get(8..8 + len)?;
+
+        match kind {
+            b"sRGB" => return Some(ColorSpace::Srgb),
+            b"gAMA" if len == 4 => {
+                // Stored as 100000 times the gamma the image was encoded with
+                let gamma = u32::from_be_bytes(data.try_into().unwrap()) as f32 / 100000.0;
+
+                if (gamma - 1.0).abs() < 0.05 {
+                    return Some(ColorSpace::Linear);
+                }
+                if (gamma - 1.0 / 2.2).abs() < 0.05 {
+                    return Some(ColorSpace::Srgb);
+                }
+                return None;
+            }
+            // Color information has to come before the image data
+            b"IDAT" | b"IEND" => return None,
+            _ => (),
+        }
+
+        // Skip the data and the CRC
+        rest = rest.get(12 + len..)?;
+    }
+
+    None
+}
+
+/// Prepare an image for use as a color texture, which clients decode as
+/// sRGB. Images that are linear, by their own say or `declared` by the
+/// source, are converted and re-encoded as PNG; others are kept as they are.
+pub fn color_texture(bytes: Vec<u8>, declared: Option<ColorSpace>) -> Result<Vec<u8>> {
+    let space = declared.or_else(|| image_color_space(&bytes));
+
+    if space != Some(ColorSpace::Linear) {
+        return Ok(bytes);
+    }
+
+    log::debug!("Converting linear color texture to sRGB");
+
+    let mut image = image::load_from_memory(&bytes)?.to_rgba16();
+
+    for px in image.pixels_mut() {
+        for c in &mut px.0[..3] {
+            *c = (linear_to_srgb(*c as f32 / 65535.0) * 65535.0).round() as u16;
+        }
+    }
+
+    let mut ret = Vec::new();
+    image::DynamicImage::ImageRgba16(image)
+        .into_rgba8()
+        .write_to(&mut Cursor::new(&mut ret), image::ImageFormat::Png)?;
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A PNG with a single chunk ahead of the image data
+    fn png_with(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut ret = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, data) in [(kind, data), (b"IDAT", &[][..])] {
+            ret.extend((data.len() as u32).to_be_bytes());
+            ret.extend(kind);
+            ret.extend(data);
+            ret.extend([0; 4]);
+        }
+        ret
+    }
+
+    #[test]
+    fn test_vertex_color() {
+        assert_eq!(
+            vertex_color([0, 255, 128, 128], ColorSpace::Srgb),
+            [0, 255, 55, 128]
+        );
+        assert_eq!(
+            vertex_color([0, 255, 128, 128], ColorSpace::Linear),
+            [0, 255, 128, 128]
+        );
+
+        for c in [0.0, 0.002, 0.2, 0.5, 1.0] {
+            assert!((srgb_to_linear(linear_to_srgb(c)) - c).abs() < 1e-5);
+        }
+
+        let mut packed = vec![9, 128, 128, 128, 255, 9, 0, 0, 0, 0];
+        convert_packed(&mut packed, 5, 1, ColorSpace::Srgb);
+        assert_eq!(packed, [9, 55, 55, 55, 255, 9, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_image_color_space() {
+        assert_eq!(
+            image_color_space(&png_with(b"sRGB", &[0])),
+            Some(ColorSpace::Srgb)
+        );
+        assert_eq!(
+            image_color_space(&png_with(b"gAMA", &100000u32.to_be_bytes())),
+            Some(ColorSpace::Linear)
+        );
+        assert_eq!(
+            image_color_space(&png_with(b"gAMA", &45455u32.to_be_bytes())),
+            Some(ColorSpace::Srgb)
+        );
+        assert_eq!(image_color_space(&png_with(b"tEXt", b"a")), None);
+        assert_eq!(image_color_space(b"GIF89a"), None);
+    }
+}
@@ -0,0 +1,294 @@
+//! Meshes with vertex or face colors, packed and published without
+//! textures. Shared by formats that carry colors rather than materials.
+
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Result;
+use nalgebra::Vector3;
+
+use crate::color;
+use crate::geometry::{self, Cleanup};
+use crate::import::{self, ImportOptions};
+use crate::scene::{Bounds, Scene, SceneObject};
+use colabrodo_common::{components::*, types::Format};
+use colabrodo_server::{server_http::*, server_messages::*, server_state::*};
+
+/// Size of a packed vertex: position, normal, then an RGBA color
+const VERTEX_STRIDE: usize = 28;
+
+/// A polygon mesh with optional per-vertex and per-face colors
+#[derive(Debug, Default)]
+pub struct ColoredMesh {
+    pub vertices: Vec<[f32; 3]>,
+    pub vertex_colors: Vec<Option<[u8; 4]>>,
+    /// Polygons, with an optional color
+    pub faces: Vec<(Vec<u32>, Option<[u8; 4]>)>,
+}
+
+impl ColoredMesh {
+    pub fn has_alpha(&self) -> bool {
+        let faces = self.faces.iter().filter_map(|f| f.1);
+        self.vertex_colors
+            .iter()
+            .flatten()
+            .copied()
+            .chain(faces)
+            .any(|c| c[3] < 255)
+    }
+}
+
+/// Triangulate and pack a mesh into interleaved vertex bytes and triangle
+/// indices, optionally dropping degenerate and duplicate triangles. Polygons
+/// touching a NaN or infinite position are always dropped.
+///
+/// Face colors need their own vertices, as do flat normals; smooth shading
+/// shares vertices where it can.
+fn pack_mesh(
+    mesh: &ColoredMesh,
+    smooth: bool,
+    clean: bool,
+) -> (Vec<u8>, Vec<[u32; 3]>, Option<Bounds>, Cleanup) {
+    let mut corners = Vec::<([f32; 3], Vector3<f32>, [u8; 4])>::new();
+    let mut faces = Vec::<[u32; 3]>::new();
+
+    // Shared vertices for smooth shading, by source vertex and face color
+    let mut remap = HashMap::<(u32, Option<[u8; 4]>), u32>::new();
+
+    let finite: Vec<bool> = mesh
+        .vertices
+        .iter()
+        .map(|v| v.iter().all(|f| f.is_finite()))
+        .collect();
+
+    let nonfinite = finite.iter().filter(|f| !**f).count() as u64;
+
+    for (polygon, face_color) in &mesh.faces {
+        if polygon.len() < 3 || polygon.iter().any(|i| !finite[*i as usize]) {
+            continue;
+        }
+
+        let [a, b, c] = [0, 1, 2].map(|f| Vector3::from(mesh.vertices[polygon[f] as usize]));
+        let normal = (b - a).cross(&(c - a));
+
+        let mut corner = |i: u32| {
+            let color = mesh.vertex_colors[i as usize]
+                .or(*face_color)
+                .unwrap_or([255; 4]);
+            let position = mesh.vertices[i as usize];
+
+            if !smooth {
+                corners.push((position, normal, color));
+                return corners.len() as u32 - 1;
+            }
+
+            let id = *remap.entry((i, *face_color)).or_insert_with(|| {
+                corners.push((position, Vector3::zeros(), color));
+                corners.len() as u32 - 1
+            });
+            corners[id as usize].1 += normal;
+            id
+        };
+
+        let first = corner(polygon[0]);
+        let mut prev = corner(polygon[1]);
+
+        // Fan triangulation, fine for convex polygons
+        for i in &polygon[2..] {
+            let next = corner(*i);
+            faces.push([first, prev, next]);
+            prev = next;
+        }
+    }
+
+    let mut cleanup = Cleanup {
+        nonfinite,
+        ..Default::default()
+    };
+
+    if clean {
+        cleanup += geometry::clean_faces(&mut faces, |i| corners[i as usize].0);
+    }
+
+    let bounds = Bounds::from_points(corners.iter().map(|f| &f.0));
+
+    let mut bytes = Vec::with_capacity(corners.len() * VERTEX_STRIDE);
+
+    for (position, normal, color) in corners {
+        let normal: [f32; 3] = normal
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_default()
+            .into();
+
+        for v in position.iter().chain(normal.iter()) {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes.extend_from_slice(&color);
+    }
+
+    (bytes, faces, bounds, cleanup)
+}
+
+/// Publish a mesh as a single entity named after `path`
+pub fn publish_mesh(
+    mesh: &ColoredMesh,
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let (mut bytes, faces, bounds, cleanup) =
+        pack_mesh(mesh, options.generate_normals, !options.keep_degenerate);
+
+    color::convert_packed(&mut bytes, VERTEX_STRIDE, 24, options.vertex_colors);
+
+    let vertex_count = bytes.len() / VERTEX_STRIDE;
+    let vertex_size = bytes.len() as u64;
+
+    let index_format = geometry::index_format(vertex_count);
+    geometry::pack_indices(faces.iter().flatten().copied(), index_format, &mut bytes);
+
+    let size = bytes.len() as u64;
+
+    let mut published = Vec::new();
+
+    let data = import::publish_buffer(&asset_store, path, &bytes, options, &mut published);
+
+    let mut lock = state.lock().unwrap();
+
+    // Colors live on the vertices; keep the material plain
+    let material = lock.materials.new_component(ServerMaterialState {
+        name: None,
+        mutable: ServerMaterialStateUpdatable {
+            pbr_info: Some(PBRInfo {
+                base_color: options.tinted([1.0; 4]),
+                metallic: Some(0.0),
+                roughness: Some(1.0),
+                ..Default::default()
+            }),
+            use_alpha: mesh.has_alpha().then_some(true),
+            ..Default::default()
+        },
+    });
+
+    let buffer = data.create(&mut lock);
+
+    let view = lock.buffer_views.new_component(ServerBufferViewState {
+        name: None,
+        source_buffer: buffer,
+        view_type: BufferViewType::Geometry,
+        offset: 0,
+        length: size,
+    });
+
+    let attribute = |semantic, offset: u32, format, normalized| ServerGeometryAttribute {
+        view: view.clone(),
+        semantic,
+        channel: None,
+        offset: Some(offset),
+        stride: Some(VERTEX_STRIDE as u32),
+        format,
+        normalized: Some(normalized),
+        minimum_value: None,
+        maximum_value: None,
+    };
+
+    let geom = lock.geometries.new_component(ServerGeometryState {
+        name: None,
+        patches: vec![ServerGeometryPatch {
+            attributes: vec![
+                attribute(AttributeSemantic::Position, 0, Format::VEC3, false),
+                attribute(AttributeSemantic::Normal, 12, Format::VEC3, false),
+                attribute(AttributeSemantic::Color, 24, Format::U8VEC4, true),
+            ],
+            vertex_count: vertex_count as u64,
+            indices: Some(ServerGeometryIndex {
+                view: view.clone(),
+                count: (faces.len() * 3) as u32,
+                offset: Some(vertex_size as u32),
+                stride: None,
+                format: index_format,
+            }),
+            patch_type: PrimitiveType::Triangles,
+            material: material.clone(),
+        }],
+    });
+
+    let entity = lock.entities.new_component(ServerEntityState {
+        name: import::display_name(path),
+        mutable: ServerEntityStateUpdatable {
+            representation: Some(ServerEntityRepresentation::new_render(
+                RenderRepresentation {
+                    mesh: geom,
+                    instances: None,
+                },
+            )),
+            ..Default::default()
+        },
+    });
+
+    let mut scene = Scene::new(
+        SceneObject {
+            parts: vec![entity],
+            children: vec![],
+        },
+        published,
+        Some(asset_store),
+    );
+
+    scene.materials.push(material);
+
+    scene.info.bounds = bounds;
+    scene.info.triangles = faces.len() as u64;
+    scene.info.cleanup = cleanup;
+
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pack_mesh() {
+        // A colored square and a triangle, as in the OFF tests
+        let mesh = ColoredMesh {
+            vertices: vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0],
+            ],
+            vertex_colors: vec![Some([255, 0, 0, 255]), None, None, None, None],
+            faces: vec![
+                (vec![0, 1, 2, 3], None),
+                (vec![0, 1, 4], Some([0, 0, 255, 255])),
+            ],
+        };
+
+        assert!(!mesh.has_alpha());
+
+        let (bytes, faces, bounds, _) = pack_mesh(&mesh, false, true);
+
+        // The square becomes two triangles, sharing its corners
+        assert_eq!(faces.len(), 3);
+        assert_eq!(bytes.len(), 7 * VERTEX_STRIDE);
+        assert_eq!(bounds.unwrap().max, Vector3::new(1.0, 1.0, 1.0));
+
+        // First corner: red, facing +z
+        let nz = f32::from_le_bytes(bytes[20..24].try_into().unwrap());
+        assert_eq!(nz, 1.0);
+        assert_eq!(&bytes[24..28], &[255, 0, 0, 255]);
+
+        // Face colors fill in for vertices without one
+        assert_eq!(
+            &bytes[6 * VERTEX_STRIDE + 24..7 * VERTEX_STRIDE],
+            &[0, 0, 255, 255]
+        );
+
+        // Smooth shading shares vertices, except across face colors
+        let (bytes, faces, _, _) = pack_mesh(&mesh, true, true);
+        assert_eq!(faces.len(), 3);
+        assert_eq!(bytes.len(), 7 * VERTEX_STRIDE);
+    }
+}
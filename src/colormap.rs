@@ -0,0 +1,107 @@
+//! Colormaps for scalar data
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Maps a normalized value to a color
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Colormap {
+    /// Perceptually uniform, dark blue to yellow
+    #[default]
+    Viridis,
+    /// Perceptually uniform, dark purple to yellow
+    Plasma,
+    /// Diverging, blue to white to red
+    Coolwarm,
+    Gray,
+}
+
+impl Colormap {
+    fn stops(&self) -> &'static [[f32; 3]] {
+        match self {
+            Colormap::Viridis => &[
+                [0.267, 0.005, 0.329],
+                [0.283, 0.141, 0.458],
+                [0.254, 0.265, 0.530],
+                [0.207, 0.372, 0.553],
+                [0.164, 0.471, 0.558],
+                [0.128, 0.567, 0.551],
+                [0.135, 0.659, 0.518],
+                [0.267, 0.749, 0.441],
+                [0.478, 0.821, 0.317],
+                [0.741, 0.873, 0.150],
+                [0.993, 0.906, 0.144],
+            ],
+            Colormap::Plasma => &[
+                [0.050, 0.030, 0.528],
+                [0.254, 0.014, 0.615],
+                [0.417, 0.001, 0.658],
+                [0.563, 0.052, 0.642],
+                [0.692, 0.165, 0.565],
+                [0.798, 0.280, 0.470],
+                [0.881, 0.393, 0.383],
+                [0.949, 0.518, 0.296],
+                [0.988, 0.652, 0.211],
+                [0.988, 0.810, 0.145],
+                [0.940, 0.975, 0.131],
+            ],
+            Colormap::Coolwarm => &[
+                [0.230, 0.299, 0.754],
+                [0.552, 0.690, 0.996],
+                [0.865, 0.865, 0.865],
+                [0.958, 0.604, 0.482],
+                [0.706, 0.016, 0.150],
+            ],
+            Colormap::Gray => &[[0.0; 3], [1.0; 3]],
+        }
+    }
+
+    /// Look up a value in 0-1, clamping anything outside
+    pub fn sample(&self, t: f32) -> [u8; 3] {
+        let stops = self.stops();
+
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        let at = t * (stops.len() - 1) as f32;
+        let i = (at as usize).min(stops.len() - 2);
+        let f = at - i as f32;
+
+        [0, 1, 2].map(|c| {
+            let v = stops[i][c] + (stops[i + 1][c] - stops[i][c]) * f;
+            (v * 255.0).round() as u8
+        })
+    }
+
+    /// Color values stretched over their range. Values that are not finite
+    /// are shown as gray.
+    pub fn colorize(&self, values: &[f32]) -> Vec<[u8; 4]> {
+        let (lo, hi) = values
+            .iter()
+            .filter(|f| f.is_finite())
+            .fold((f32::MAX, f32::MIN), |(lo, hi), v| (lo.min(*v), hi.max(*v)));
+
+        values
+            .iter()
+            .map(|v| {
+                if !v.is_finite() {
+                    return [128, 128, 128, 255];
+                }
+                let t = if hi > lo { (v - lo) / (hi - lo) } else { 0.5 };
+                let [r, g, b] = self.sample(t);
+                [r, g, b, 255]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_colormap() {
+        assert_eq!(Colormap::Gray.sample(0.5), [128; 3]);
+        assert_eq!(Colormap::Coolwarm.sample(0.5), [221, 221, 221]);
+        assert_eq!(Colormap::Viridis.sample(2.0), Colormap::Viridis.sample(1.0));
+    }
+}
@@ -0,0 +1,102 @@
+//! Demo mode.
+//!
+//! A few sample models are built into the binary, so `platter demo` gives a
+//! working server with nothing to download or point at. The samples are
+//! written to scratch space and loaded as ordinary file sources, each offset
+//! to its own cell of a grid.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+
+use crate::arguments::{Source, SourceTransform};
+use crate::scratch::{ScratchDir, ScratchSpace};
+
+/// Size of the largest side of each sample, unless `--fit` is given
+pub const FIT: f32 = 1.0;
+
+/// Turntable speed in degrees per second, unless `--turntable` is given
+pub const TURNTABLE: f32 = 20.0;
+
+/// Bundled samples, by file name
+const SAMPLES: &[(&str, &[u8])] = &[
+    ("cube.glb", include_bytes!("../assets/cube.glb")),
+    ("monkey.obj", include_bytes!("../assets/monkey.obj")),
+    ("cube.obj", include_bytes!("../assets/cube.obj")),
+];
+
+/// Offset of the `index`th of `count` cells of a square grid on the ground,
+/// centered on the origin
+fn grid_offset(index: usize, count: usize, spacing: f32) -> [f32; 3] {
+    let cols = (count as f32).sqrt().ceil().max(1.0) as usize;
+    let rows = count.div_ceil(cols);
+
+    let (row, col) = (index / cols, index % cols);
+
+    [
+        (col as f32 - (cols - 1) as f32 / 2.0) * spacing,
+        0.0,
+        (row as f32 - (rows - 1) as f32 / 2.0) * spacing,
+    ]
+}
+
+/// Write the samples to scratch space, and give a file source for each. The
+/// files last as long as the returned directory.
+pub fn sources(scratch: &ScratchSpace, spacing: f32) -> Result<(ScratchDir, Vec<Source>)> {
+    let mut dir = scratch.allocate("demo")?;
+
+    let sources = SAMPLES
+        .iter()
+        .enumerate()
+        .map(|(i, (name, bytes))| {
+            let path: PathBuf = dir.write(name, bytes)?;
+
+            Ok(Source::File {
+                names: vec![path],
+                transform: SourceTransform {
+                    offset: Some(grid_offset(i, SAMPLES.len(), spacing)),
+                    ..Default::default()
+                },
+                ttl: None,
+                policy: Default::default(),
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok((dir, sources))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_grid_offset() {
+        // Three samples take two rows of two
+        let offsets: Vec<_> = (0..3).map(|i| grid_offset(i, 3, 2.0)).collect();
+        assert_eq!(
+            offsets,
+            [[-1.0, 0.0, -1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, 1.0]]
+        );
+
+        assert_eq!(grid_offset(0, 1, 2.0), [0.0, 0.0, 0.0]);
+        assert_eq!(grid_offset(4, 9, 1.5), [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_sources() {
+        let root = tempfile::TempDir::new().unwrap();
+        let scratch = ScratchSpace::new(Some(root.path()), u64::MAX).unwrap();
+
+        let (_dir, sources) = sources(&scratch, 1.0).unwrap();
+
+        assert_eq!(sources.len(), SAMPLES.len());
+
+        for (source, (name, bytes)) in sources.iter().zip(SAMPLES) {
+            let [path] = source.paths().try_into().unwrap();
+            assert!(path.ends_with(name));
+            assert_eq!(std::fs::read(path).unwrap(), *bytes);
+            assert!(source.transform().offset.is_some());
+        }
+    }
+}
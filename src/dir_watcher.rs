@@ -1,40 +1,389 @@
 //! Module to implement file and directory watching
 
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime};
 
+use crate::arguments::Overflow;
+use crate::import::CancelToken;
+use crate::manifest::{self, Manifest};
 use crate::platter_state::Tag;
+use crate::scheduler::Scheduler;
 use crate::{arguments::Directory, platter_state::PlatterCommand};
 use colabrodo_server::server::tokio;
-use notify::event::AccessKind;
+use notify::event::{AccessKind, AccessMode, CreateKind};
 use notify::EventKind;
 use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
 
 use tokio::sync::mpsc;
 
+/// Delays between attempts to watch a directory that can't be watched yet
+const RETRY_MIN: Duration = Duration::from_secs(1);
+const RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// Queue settings for filesystem notifications
+#[derive(Debug, Clone)]
+pub struct WatcherOptions {
+    /// Number of filesystem events that can be waiting
+    pub event_queue: usize,
+
+    /// What to do when that queue is full
+    pub overflow: Overflow,
+
+    /// Time between checks that a new file has stopped changing. Zero loads
+    /// new files once they are closed after writing.
+    pub settle_interval: Duration,
+
+    /// Checks in a row that a new file must pass unchanged
+    pub settle_checks: u32,
+
+    /// Timer task to wait on, so waits are stretched in low power mode.
+    /// Without one, each wait has its own timer.
+    pub scheduler: Option<Scheduler>,
+}
+
+impl Default for WatcherOptions {
+    fn default() -> Self {
+        Self {
+            event_queue: 16,
+            overflow: Overflow::Block,
+            settle_interval: Duration::from_millis(250),
+            settle_checks: 2,
+            scheduler: None,
+        }
+    }
+}
+
+/// Wait for `delay`, through the scheduler if there is one
+async fn sleep(scheduler: Option<&Scheduler>, delay: Duration) {
+    match scheduler {
+        Some(s) => s.sleep(delay).await,
+        None => tokio::time::sleep(delay).await,
+    }
+}
+
+/// Size and modification time of a file, to tell when it has changed
+pub type Stamp = (u64, Option<SystemTime>);
+
+/// The stamp of a file, if it is there
+pub fn stamp(path: &Path) -> Option<Stamp> {
+    let meta = fs::metadata(path).ok().filter(|f| f.is_file())?;
+    Some((meta.len(), meta.modified().ok()))
+}
+
+/// Whether a filesystem event may mean a file needs loading. Platforms
+/// report a new file differently: a create and then a close on Linux, a
+/// create and some modifies on macOS, a run of modifies on Windows. Each
+/// comes down to "this path may have changed"; [`FileStates`] decides
+/// whether it has. Reads are not changes, or imports would set off more
+/// imports.
+pub fn touches_files(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Create(CreateKind::Folder) => false,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => true,
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        _ => false,
+    }
+}
+
+/// Whether an event may mean a file is ready to load right away, when new
+/// files are not settled. Only a close after writing says a copy is done;
+/// macOS reports no closes, so there a create has to do. Removals still
+/// count, so the file is forgotten.
+fn finishes_files(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) | EventKind::Remove(_) => true,
+        EventKind::Create(CreateKind::File | CreateKind::Any) => cfg!(target_os = "macos"),
+        _ => false,
+    }
+}
+
+/// Where a file is in being picked up. A new file settles until it stops
+/// changing, and is then loaded. Events for a file that is settling, or that
+/// is loaded and unchanged since, are duplicates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileState {
+    /// Waiting for the file to stop changing
+    Settling,
+
+    /// Sent to be loaded, as it was then
+    Loaded(Stamp),
+}
+
+#[derive(Debug, Default)]
+struct FileStates(HashMap<PathBuf, FileState>);
+
+impl FileStates {
+    /// Whether a change to a file, as it is `now`, calls for a load
+    fn wants(&self, path: &Path, now: Option<Stamp>) -> bool {
+        match (self.0.get(path), now) {
+            (_, None) | (Some(FileState::Settling), _) => false,
+            (None, Some(_)) => true,
+            (Some(FileState::Loaded(then)), Some(now)) => *then != now,
+        }
+    }
+
+    /// Note a change to a file. Returns true if it should now be settled and
+    /// loaded.
+    fn changed(&mut self, path: &Path, now: Option<Stamp>) -> bool {
+        // Gone; if it comes back, it is new. One still settling is dropped
+        // when the wait notices.
+        if now.is_none() && self.0.get(path) != Some(&FileState::Settling) {
+            self.0.remove(path);
+        }
+
+        if !self.wants(path, now) {
+            return false;
+        }
+
+        self.0.insert(path.into(), FileState::Settling);
+        true
+    }
+
+    /// A file was sent to be loaded as it is `now`
+    fn loaded(&mut self, path: &Path, now: Stamp) {
+        self.0.insert(path.into(), FileState::Loaded(now));
+    }
+
+    /// A file went away, or its load was cancelled, before it settled
+    fn dropped(&mut self, path: &Path) {
+        self.0.remove(path);
+    }
+}
+
+/// Holds back new files until they stop changing, so files still being
+/// copied in are not imported half written. Each version of a file is only
+/// loaded once, however many events announce it.
+#[derive(Clone)]
+pub(crate) struct Settle {
+    interval: Duration,
+    checks: u32,
+    scheduler: Option<Scheduler>,
+    files: Arc<Mutex<FileStates>>,
+}
+
+impl Settle {
+    pub(crate) fn new(options: &WatcherOptions) -> Self {
+        Self {
+            interval: options.settle_interval,
+            checks: options.settle_checks,
+            scheduler: options.scheduler.clone(),
+            files: Default::default(),
+        }
+    }
+
+    /// Whether an event of this kind is worth looking into
+    pub(crate) fn heeds(&self, kind: &EventKind) -> bool {
+        match self.interval.is_zero() {
+            true => finishes_files(kind),
+            false => touches_files(kind),
+        }
+    }
+
+    /// Whether an event for a file is news, rather than a duplicate
+    fn wants(&self, path: &Path) -> bool {
+        self.files.lock().unwrap().wants(path, stamp(path))
+    }
+
+    /// Forget a file that is gone, so it is new if it comes back
+    fn gone(&self, path: &Path) {
+        self.files.lock().unwrap().changed(path, None);
+    }
+
+    /// Note that a file was loaded some other way, as it is now
+    fn loaded(&self, path: &Path) {
+        if let Some(now) = stamp(path) {
+            self.files.lock().unwrap().loaded(path, now);
+        }
+    }
+
+    /// Load a file once it has settled. Waiting happens on its own task, so
+    /// other events are not held up. The load is dropped if the file goes
+    /// away or its import is cancelled in the meantime.
+    async fn load(
+        &self,
+        tx: &mpsc::Sender<PlatterCommand>,
+        p: PathBuf,
+        source_id: Tag,
+        cancel: &CancelToken,
+    ) {
+        let command = PlatterCommand::LoadFile(p.clone(), Some(source_id), Some(cancel.clone()));
+        self.send(tx, p, command, Some(cancel)).await;
+    }
+
+    /// Send a command about a file once the file has settled, as for
+    /// [`Self::load`]. Events for a version of the file already sent are
+    /// duplicates, and send nothing.
+    pub(crate) async fn send(
+        &self,
+        tx: &mpsc::Sender<PlatterCommand>,
+        p: PathBuf,
+        command: PlatterCommand,
+        cancel: Option<&CancelToken>,
+    ) {
+        if !self.files.lock().unwrap().changed(&p, stamp(&p)) {
+            log::debug!("{} is already loaded or settling", p.display());
+            return;
+        }
+
+        if self.interval.is_zero() {
+            self.loaded(&p);
+            tx.send(command).await.unwrap();
+            return;
+        }
+
+        let this = self.clone();
+        let tx = tx.clone();
+        let cancel = cancel.cloned();
+
+        tokio::spawn(async move {
+            let settled =
+                wait_until_settled(&p, this.interval, this.checks, this.scheduler.as_ref()).await;
+
+            {
+                let mut files = this.files.lock().unwrap();
+
+                let cancelled = cancel.as_ref().is_some_and(|f| f.is_cancelled());
+
+                let Some(now) = settled.filter(|_| !cancelled) else {
+                    if settled.is_none() {
+                        log::info!("{} went away before it settled", p.display());
+                    }
+                    files.dropped(&p);
+                    return;
+                };
+
+                files.loaded(&p, now);
+            }
+
+            let _ = tx.send(command).await;
+        });
+    }
+}
+
+/// Wait until a file's size and modification time are the same for `checks`
+/// checks in a row, `interval` apart. Returns the settled stamp, or nothing
+/// if the file goes away.
+async fn wait_until_settled(
+    path: &Path,
+    interval: Duration,
+    checks: u32,
+    scheduler: Option<&Scheduler>,
+) -> Option<Stamp> {
+    let mut last = stamp(path)?;
+    let mut same = 0;
+
+    while same < checks {
+        sleep(scheduler, interval).await;
+
+        let now = stamp(path)?;
+
+        if now == last {
+            same += 1;
+        } else {
+            log::debug!("{} is still changing", path.display());
+            same = 0;
+            last = now;
+        }
+    }
+
+    Some(last)
+}
+
 /// Create the file watcher loop
 ///
-/// Takes a channel to send commands back to the platter system, an ID to mark
-/// resources loaded from this watcher, and a directory to watch.
+/// Takes a channel to send commands back to the platter system, and a
+/// directory to watch. Resources loaded from the watcher are marked with a
+/// tag of their own, and what platter keeps for that tag is dropped once the
+/// watcher stops.
 pub async fn launch_file_watcher(
+    tx: mpsc::Sender<PlatterCommand>,
+    dir: Directory,
+    stopper: tokio::sync::broadcast::Receiver<bool>,
+    options: WatcherOptions,
+) {
+    let tag = Tag::new();
+
+    watch_directory(tx.clone(), dir, stopper, options, tag).await;
+
+    let _ = tx.send(PlatterCommand::Unwatched(tag)).await;
+}
+
+async fn watch_directory(
     tx: mpsc::Sender<PlatterCommand>,
     dir: Directory,
     mut stopper: tokio::sync::broadcast::Receiver<bool>,
+    options: WatcherOptions,
+    latest_tag: Tag,
 ) {
     log::info!("Watching directory {}", dir.dir.display());
 
-    let (mut watcher, mut rx) = setup_watcher().unwrap();
-
     let mut latest_dir = Option::<PathBuf>::default();
-    let latest_tag = Tag::new();
+    let settle = Settle::new(&options);
+
+    // Imports queued since the last clear; cancelled at the next one
+    let mut cancel = CancelToken::default();
+
+    if let Some(length) = dir.sequence {
+        tx.send(PlatterCommand::Sequence(latest_tag, length.get()))
+            .await
+            .unwrap();
+    }
+
+    // A manifest already in place is followed from the start
+    let mut manifest = None;
+    let manifest_path = dir.dir.join(manifest::FILE_NAME);
+
+    if manifest_path.is_file() {
+        take_manifest(&tx, &manifest_path, latest_tag, &mut manifest, &mut cancel).await;
+    }
 
     if dir.load_existing {
-        load_existing(&dir, &tx, latest_tag).await;
+        load_existing(&dir, &tx, latest_tag, &cancel, &settle, manifest.as_ref()).await;
     }
 
-    watcher
-        .watch(dir.dir.as_path(), RecursiveMode::Recursive)
-        .unwrap();
+    if let Some(period) = dir.poll {
+        poll_directory(
+            &tx,
+            &dir,
+            period.0,
+            stopper,
+            latest_tag,
+            &mut cancel,
+            &settle,
+            &mut manifest,
+        )
+        .await;
+        return;
+    }
+
+    let (mut watcher, rx) = match setup_watcher(&options) {
+        Ok(f) => f,
+        Err(e) => {
+            log::error!("Unable to watch {}: {e}", dir.dir.display());
+            return;
+        }
+    };
+
+    // The directory may be gone for a while, such as a share that has not
+    // mounted yet; try again, less often the longer it stays gone
+    let mut delay = RETRY_MIN;
+
+    while let Err(e) = watch_tree(&mut watcher, &dir, &dir.dir) {
+        log::warn!(
+            "Unable to watch {}: {e}; trying again in {delay:?}",
+            dir.dir.display()
+        );
+
+        tokio::select! {
+            _ = stopper.recv() => return,
+            _ = sleep(options.scheduler.as_ref(), delay) => {}
+        }
+
+        delay = (delay * 2).min(RETRY_MAX);
+    }
 
     loop {
         tokio::select! {
@@ -42,75 +391,266 @@ pub async fn launch_file_watcher(
                     let _ = watcher.unwatch(dir.dir.as_path());
                     return;
                 }
-                Some(msg) = rx.recv() => {
-                    if let Ok(event) = msg {
-                        log::debug!("Filesystem change: {event:?}");
-
-                        match event.kind {
-                            EventKind::Access(e) => match e {
-                                AccessKind::Close(_) => {
-                                    for p in event.paths {
-                                        handle_file_closed(&tx, p, latest_tag, &dir, &latest_dir).await;
-                                    }
-                                }
-                                _ => {}
-                            },
-                            EventKind::Create(e) => match e {
-                                notify::event::CreateKind::File => {
-                                    for p in event.paths {
-                                        handle_file_created(&tx, p, latest_tag, &dir, &latest_dir).await;
-                                    }
-                                }
-                                notify::event::CreateKind::Folder => {
-                                    if dir.organize_by_dir && dir.latest_only {
-                                        // clear all the old dirs
-                                        tx.send(PlatterCommand::ClearTag(latest_tag)).await.unwrap();
-
-                                        // use this new dir
-                                        latest_dir = event.paths.into_iter().take(1).next();
-                                    }
+                event = rx.recv() => {
+                    log::debug!("Filesystem change: {event:?}");
+
+                    if event.kind == EventKind::Create(CreateKind::Folder) {
+                        let paths: Vec<_> = event.paths.into_iter().filter(|f| dir.watches_dir(f)).collect();
+
+                        // Limited watches are made directory by directory
+                        if dir.depth_limit().is_some() {
+                            for p in &paths {
+                                if let Err(e) = watch_tree(&mut watcher, &dir, p) {
+                                    log::warn!("Unable to watch {}: {e}", p.display());
                                 }
-                                _ => {}
-                            },
-                            _ => {}
+                            }
+                        }
+
+                        if dir.organize_by_dir && dir.latest_only && !paths.is_empty() {
+                            // clear all the old dirs
+                            clear_tag(&tx, latest_tag, &mut cancel).await;
+
+                            // use this new dir
+                            latest_dir = paths.into_iter().take(1).next();
+                        }
+                    } else if settle.heeds(&event.kind) {
+                        for p in event.paths {
+                            handle_new_file(&tx, p, latest_tag, &dir, &latest_dir, &mut cancel, &settle, &mut manifest).await;
                         }
                     }
-            }
+                }
         }
     }
 }
 
-async fn handle_file_closed(
-    tx: &mpsc::Sender<PlatterCommand>,
-    p: std::path::PathBuf,
-    source_id: Tag,
+/// Watch a directory and everything below it, within the depth limit. With no
+/// limit, one recursive watch covers it all; otherwise each directory is
+/// watched on its own, so events from deeper down never arrive.
+fn watch_tree(
+    watcher: &mut RecommendedWatcher,
     dir: &Directory,
-    latest: &Option<PathBuf>,
-) {
-    handle_new_file(&tx, p, source_id, &dir, &latest).await;
+    root: &Path,
+) -> notify::Result<()> {
+    let Some(limit) = dir.depth_limit() else {
+        return watcher.watch(root, RecursiveMode::Recursive);
+    };
+
+    watcher.watch(root, RecursiveMode::NonRecursive)?;
+
+    // Levels left below `root`
+    let below = limit.saturating_sub(dir.depth(root) + usize::from(root != dir.dir));
+
+    for sub in Scan::of(root, Some(below)).unwrap_or_default().dirs {
+        if let Err(e) = watcher.watch(&sub, RecursiveMode::NonRecursive) {
+            log::warn!("Unable to watch {}: {e}", sub.display());
+        }
+    }
+
+    Ok(())
 }
 
-async fn handle_file_created(
+/// Size and modification time of each file below a directory, and the
+/// directories themselves
+#[derive(Debug, Default)]
+struct Scan {
+    files: HashMap<PathBuf, Stamp>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl Scan {
+    /// Scan a directory, going at most `limit` levels of subdirectories down.
+    /// Returns None if the directory itself can't be read; subdirectories
+    /// that can't be read are left out.
+    fn of(root: &Path, limit: Option<usize>) -> Option<Self> {
+        let mut ret = Self::default();
+        let mut stack = vec![(root.to_path_buf(), 0)];
+
+        while let Some((dir, depth)) = stack.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(f) => f,
+                Err(_) if dir == root => return None,
+                Err(_) => continue,
+            };
+
+            for entry in entries.flatten() {
+                let Ok(meta) = entry.metadata() else {
+                    continue;
+                };
+
+                if meta.is_dir() {
+                    if limit.is_none_or(|f| depth < f) {
+                        ret.dirs.insert(entry.path());
+                        stack.push((entry.path(), depth + 1));
+                    }
+                } else {
+                    ret.files
+                        .insert(entry.path(), (meta.len(), meta.modified().ok()));
+                }
+            }
+        }
+
+        Some(ret)
+    }
+
+    /// Scan off the async threads; network shares can be slow to list
+    async fn of_blocking(root: &Path, limit: Option<usize>) -> Option<Self> {
+        let root = root.to_path_buf();
+        tokio::task::spawn_blocking(move || Self::of(&root, limit))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Directories in this scan but not in `before`, in name order
+    fn new_dirs(&self, before: &Scan) -> Vec<PathBuf> {
+        let mut ret: Vec<_> = self.dirs.difference(&before.dirs).cloned().collect();
+        ret.sort();
+        ret
+    }
+
+    /// Files that are new or have changed size or time since `before`, in
+    /// name order
+    fn changed_files(&self, before: &Scan) -> Vec<PathBuf> {
+        let mut ret: Vec<_> = self
+            .files
+            .iter()
+            .filter(|(path, stamp)| before.files.get(*path) != Some(stamp))
+            .map(|(path, _)| path.clone())
+            .collect();
+        ret.sort();
+        ret
+    }
+}
+
+/// Watch a directory by scanning it every `period`, for network shares and
+/// other filesystems that do not deliver change events. Files already there
+/// when polling starts, or when the directory can first be read, are left
+/// alone.
+#[allow(clippy::too_many_arguments)]
+async fn poll_directory(
     tx: &mpsc::Sender<PlatterCommand>,
-    p: std::path::PathBuf,
-    source_id: Tag,
     dir: &Directory,
-    latest: &Option<PathBuf>,
+    period: Duration,
+    mut stopper: tokio::sync::broadcast::Receiver<bool>,
+    source_id: Tag,
+    cancel: &mut CancelToken,
+    settle: &Settle,
+    manifest: &mut Option<Manifest>,
 ) {
-    // For reasons on mac os x we do not see closes?
-    #[cfg(target_os = "macos")]
-    {
-        handle_new_file(&tx, p, source_id, &dir, &latest).await;
+    log::info!("Polling {} every {period:?}", dir.dir.display());
+
+    let mut latest_dir = Option::<PathBuf>::default();
+    let mut known = Scan::of_blocking(&dir.dir, dir.depth_limit()).await;
+
+    if known.is_none() {
+        log::warn!(
+            "Unable to read {}; polling until it can be",
+            dir.dir.display()
+        );
+    }
+
+    loop {
+        tokio::select! {
+            _ = stopper.recv() => return,
+            _ = sleep(settle.scheduler.as_ref(), period) => {}
+        }
+
+        // A share that drops out for a while is not taken to be empty
+        let Some(scan) = Scan::of_blocking(&dir.dir, dir.depth_limit()).await else {
+            log::debug!("Unable to read {}", dir.dir.display());
+            continue;
+        };
+
+        // The first read is what later ones are compared with
+        let Some(before) = known.take() else {
+            known = Some(scan);
+            continue;
+        };
+
+        if dir.organize_by_dir && dir.latest_only {
+            if let Some(newest) = scan.new_dirs(&before).pop() {
+                clear_tag(tx, source_id, cancel).await;
+                latest_dir = Some(newest);
+            }
+        }
+
+        for p in scan.changed_files(&before) {
+            handle_new_file(tx, p, source_id, dir, &latest_dir, cancel, settle, manifest).await;
+        }
+
+        // Files that are gone are not changes to a scan, but a manifest
+        // that was taken away must not be followed
+        let manifest_path = dir.dir.join(manifest::FILE_NAME);
+
+        if manifest.is_some() && !scan.files.contains_key(&manifest_path) {
+            handle_new_file(
+                tx,
+                manifest_path,
+                source_id,
+                dir,
+                &latest_dir,
+                cancel,
+                settle,
+                manifest,
+            )
+            .await;
+        }
+
+        known = Some(scan);
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_new_file(
     tx: &mpsc::Sender<PlatterCommand>,
     p: std::path::PathBuf,
     source_id: Tag,
     dir: &Directory,
     latest: &Option<PathBuf>,
+    cancel: &mut CancelToken,
+    settle: &Settle,
+    manifest: &mut Option<Manifest>,
 ) {
+    if stamp(&p).is_none() {
+        settle.gone(&p);
+
+        if Manifest::is_manifest(&dir.dir, &p) {
+            drop_manifest(tx, source_id, manifest).await;
+        }
+        return;
+    }
+
+    // Settling, or loaded and unchanged since
+    if !settle.wants(&p) {
+        log::debug!("{} has not changed", p.display());
+        return;
+    }
+
+    if Manifest::is_manifest(&dir.dir, &p) {
+        if take_manifest(tx, &p, source_id, manifest, cancel).await {
+            load_existing(dir, tx, source_id, cancel, settle, manifest.as_ref()).await;
+        }
+        return;
+    }
+
+    // Listed files are loaded whatever the filters say; the rest are
+    // ignored
+    if let Some(m) = manifest {
+        if m.entry(&p).is_none() {
+            log::debug!("Skipping {}; not in the manifest", p.display());
+            return;
+        }
+
+        log::info!("Listed file detected: {}", p.display());
+        settle.load(tx, p, source_id, cancel).await;
+        return;
+    }
+
+    if !dir.accepts(&p) {
+        log::debug!("Skipping {}; filtered out", p.display());
+        return;
+    }
+
     log::info!("New file detected: {}", p.display());
 
     if dir.organize_by_dir {
@@ -127,23 +667,104 @@ async fn handle_new_file(
         };
 
         // it is, so lets load this
-        tx.send(PlatterCommand::LoadFile(p.clone(), Some(source_id)))
-            .await
-            .unwrap();
+        settle.load(tx, p, source_id, cancel).await;
         return;
     }
 
     if dir.latest_only {
         log::debug!("Only latest is allowed, clearing");
-        tx.send(PlatterCommand::ClearTag(source_id)).await.unwrap();
+        clear_tag(tx, source_id, cancel).await;
+    }
+
+    settle.load(tx, p, source_id, cancel).await;
+}
+
+/// Follow a new or rewritten manifest. Everything loaded under the old one
+/// is cleared, and the platter state is told how to place the files the new
+/// one lists. Returns false, changing nothing, if the manifest can't be read
+/// or is unchanged.
+async fn take_manifest(
+    tx: &mpsc::Sender<PlatterCommand>,
+    path: &Path,
+    source_id: Tag,
+    manifest: &mut Option<Manifest>,
+    cancel: &mut CancelToken,
+) -> bool {
+    let new = match Manifest::read(path) {
+        Ok(f) => f,
+        Err(e) => {
+            log::error!("{e:#}");
+            return false;
+        }
+    };
+
+    if manifest.as_ref() == Some(&new) {
+        log::debug!("{} is unchanged", path.display());
+        return false;
+    }
+
+    log::info!("Following manifest {}", path.display());
+
+    clear_tag(tx, source_id, cancel).await;
+
+    tx.send(PlatterCommand::Manifest(source_id, Some(new.clone())))
+        .await
+        .unwrap();
+
+    *manifest = Some(new);
+
+    true
+}
+
+/// Stop following a manifest that was removed. Files loaded under it stay
+/// as they are; later ones go through the directory's filters again.
+async fn drop_manifest(
+    tx: &mpsc::Sender<PlatterCommand>,
+    source_id: Tag,
+    manifest: &mut Option<Manifest>,
+) {
+    if manifest.take().is_none() {
+        return;
     }
 
-    tx.send(PlatterCommand::LoadFile(p.clone(), Some(source_id)))
+    log::info!("Manifest removed; no longer following it");
+
+    tx.send(PlatterCommand::Manifest(source_id, None))
         .await
         .unwrap();
 }
 
-async fn load_existing(dir: &Directory, tx: &mpsc::Sender<PlatterCommand>, source_id: Tag) {
+/// Clear a tag, first cancelling any of its imports that are still queued or
+/// in progress, so they can't publish scenes after the clear
+async fn clear_tag(tx: &mpsc::Sender<PlatterCommand>, tag: Tag, cancel: &mut CancelToken) {
+    std::mem::take(cancel).cancel();
+
+    tx.send(PlatterCommand::ClearTag(tag)).await.unwrap();
+}
+
+/// Load files already in the directory; with a manifest, only those it lists
+async fn load_existing(
+    dir: &Directory,
+    tx: &mpsc::Sender<PlatterCommand>,
+    source_id: Tag,
+    cancel: &CancelToken,
+    settle: &Settle,
+    manifest: Option<&Manifest>,
+) {
+    if let Some(m) = manifest {
+        for (path, _) in m.files().filter(|(p, _)| p.is_file()) {
+            settle.loaded(&path);
+            tx.send(PlatterCommand::LoadFile(
+                path,
+                Some(source_id),
+                Some(cancel.clone()),
+            ))
+            .await
+            .unwrap();
+        }
+        return;
+    }
+
     let Ok(paths) = fs::read_dir(&dir.dir) else {
         log::warn!("Unable to read directory: {dir:?}");
         return;
@@ -153,26 +774,152 @@ async fn load_existing(dir: &Directory, tx: &mpsc::Sender<PlatterCommand>, sourc
         let Ok(path) = path else {
             continue;
         };
-        tx.send(PlatterCommand::LoadFile(path.path(), Some(source_id)))
-            .await
-            .unwrap();
+
+        // One that could not be read is still not a model
+        if !dir.accepts(&path.path()) || Manifest::is_manifest(&dir.dir, &path.path()) {
+            continue;
+        }
+
+        settle.loaded(&path.path());
+
+        tx.send(PlatterCommand::LoadFile(
+            path.path(),
+            Some(source_id),
+            Some(cancel.clone()),
+        ))
+        .await
+        .unwrap();
     }
 }
 
-/// Construct a file watcher and channel for notifications
-fn setup_watcher() -> notify::Result<(RecommendedWatcher, mpsc::Receiver<notify::Result<Event>>)> {
-    let (send_from_watcher, recv_from_watcher) = mpsc::channel(16);
+/// Filesystem events on their way from the notification thread to the
+/// watcher loop. The queue is bounded; what happens to events that arrive
+/// when it is full is up to the overflow policy.
+struct EventQueue {
+    state: Mutex<QueueState>,
+    capacity: usize,
+    overflow: Overflow,
 
-    let watcher = RecommendedWatcher::new(
-        move |result| {
-            if send_from_watcher.blocking_send(result).is_err() {
-                log::warn!("Unable to send filesystem notification. Is this during a shutdown?");
+    /// Wakes the notification thread when there is room
+    room: Condvar,
+
+    /// Wakes the watcher loop when there are events
+    ready: tokio::sync::Notify,
+}
+
+#[derive(Default)]
+struct QueueState {
+    events: VecDeque<Event>,
+    dropped: u64,
+
+    /// Set once the watcher loop is gone
+    closed: bool,
+}
+
+impl QueueState {
+    fn drop_one(&mut self) {
+        self.dropped += 1;
+
+        // Don't flood the log when a large copy is in progress
+        if self.dropped == 1 || self.dropped.is_multiple_of(1000) {
+            log::warn!(
+                "Filesystem event queue is full; {} events dropped",
+                self.dropped
+            );
+        }
+    }
+}
+
+impl EventQueue {
+    fn new(options: &WatcherOptions) -> Self {
+        Self {
+            state: Default::default(),
+            capacity: options.event_queue.max(1),
+            overflow: options.overflow,
+            room: Condvar::new(),
+            ready: tokio::sync::Notify::new(),
+        }
+    }
+
+    /// Add an event, following the overflow policy if the queue is full.
+    /// Called from the notification thread, which may be made to wait.
+    fn push(&self, event: Event) {
+        let mut state = self.state.lock().unwrap();
+
+        if self.overflow == Overflow::Coalesce && state.events.contains(&event) {
+            return;
+        }
+
+        while state.events.len() >= self.capacity && !state.closed {
+            match self.overflow {
+                Overflow::Block | Overflow::Coalesce => state = self.room.wait(state).unwrap(),
+                Overflow::Drop => {
+                    state.drop_one();
+                    return;
+                }
+                Overflow::DropOldest => {
+                    state.events.pop_front();
+                    state.drop_one();
+                }
+            }
+        }
+
+        if state.closed {
+            log::warn!("Unable to send filesystem notification. Is this during a shutdown?");
+            return;
+        }
+
+        state.events.push_back(event);
+        drop(state);
+
+        self.ready.notify_one();
+    }
+
+    /// Wait for the next event
+    async fn pop(&self) -> Event {
+        loop {
+            if let Some(event) = self.state.lock().unwrap().events.pop_front() {
+                self.room.notify_one();
+                return event;
             }
+
+            // A wake sent since the check is kept, so it isn't missed
+            self.ready.notified().await;
+        }
+    }
+}
+
+/// The watcher loop's end of an event queue. Dropping it lets go of a
+/// notification thread waiting for room.
+struct EventReceiver(Arc<EventQueue>);
+
+impl EventReceiver {
+    async fn recv(&self) -> Event {
+        self.0.pop().await
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().closed = true;
+        self.0.room.notify_all();
+    }
+}
+
+/// Construct a file watcher and queue for notifications
+fn setup_watcher(options: &WatcherOptions) -> notify::Result<(RecommendedWatcher, EventReceiver)> {
+    let queue = Arc::new(EventQueue::new(options));
+    let sender = queue.clone();
+
+    let watcher = RecommendedWatcher::new(
+        move |result: notify::Result<Event>| match result {
+            Ok(event) => sender.push(event),
+            Err(e) => log::debug!("Filesystem notification error: {e}"),
         },
         Config::default(),
     )?;
 
-    Ok((watcher, recv_from_watcher))
+    Ok((watcher, EventReceiver(queue)))
 }
 
 #[cfg(test)]
@@ -187,7 +934,7 @@ mod test {
     use tempfile::TempDir;
 
     use crate::{
-        arguments::Directory,
+        arguments::{Directory, HumanDuration},
         platter_state::{PlatterCommand, Tag},
     };
 
@@ -208,6 +955,222 @@ mod test {
         new_file_path
     }
 
+    /// A watcher's last command, once it has stopped
+    fn is_unwatched(command: &PlatterCommand) -> bool {
+        matches!(command, PlatterCommand::Unwatched(_))
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_settled() {
+        let test_dir = make_test_dir();
+        let path = test_dir.path().join("growing.obj");
+        let interval = std::time::Duration::from_millis(20);
+
+        assert!(super::wait_until_settled(&path, interval, 2, None)
+            .await
+            .is_none());
+
+        std::fs::write(&path, b"v 0 0 0\n").unwrap();
+
+        // Keep writing for a while; the file only settles once we stop
+        let writer = {
+            let path = path.clone();
+            tokio::spawn(async move {
+                for _ in 0..5 {
+                    tokio::time::sleep(interval).await;
+                    let mut bytes = std::fs::read(&path).unwrap();
+                    bytes.extend(b"v 1 1 1\n");
+                    std::fs::write(&path, bytes).unwrap();
+                }
+            })
+        };
+
+        // Several checks in a row, so a writer held up briefly is waited for
+        let settled = super::wait_until_settled(&path, interval, 5, None).await;
+        writer.await.unwrap();
+
+        assert_eq!(settled, super::stamp(&path));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 48);
+    }
+
+    #[tokio::test]
+    async fn test_event_queue() {
+        use notify::{Event, EventKind};
+
+        use crate::arguments::Overflow;
+
+        let event = |name: &str| Event::new(EventKind::Any).add_path(name.into());
+
+        let queue = |overflow| {
+            super::EventQueue::new(&super::WatcherOptions {
+                event_queue: 2,
+                overflow,
+                ..Default::default()
+            })
+        };
+
+        let contents = |queue: &super::EventQueue| -> Vec<_> {
+            queue.state.lock().unwrap().events.iter().cloned().collect()
+        };
+
+        // A full queue keeps the first events, or the latest
+        let q = queue(Overflow::Drop);
+        ["a", "b", "c"].iter().for_each(|f| q.push(event(f)));
+        assert_eq!(contents(&q), [event("a"), event("b")]);
+        assert_eq!(q.state.lock().unwrap().dropped, 1);
+
+        let q = queue(Overflow::DropOldest);
+        ["a", "b", "c"].iter().for_each(|f| q.push(event(f)));
+        assert_eq!(contents(&q), [event("b"), event("c")]);
+
+        // Repeats of a waiting event are folded into it
+        let q = queue(Overflow::Coalesce);
+        ["a", "a", "b"].iter().for_each(|f| q.push(event(f)));
+        assert_eq!(contents(&q), [event("a"), event("b")]);
+        assert_eq!(q.pop().await, event("a"));
+
+        // Blocking waits for room, and gives up once the receiver is gone
+        let receiver = super::EventReceiver(std::sync::Arc::new(queue(Overflow::Block)));
+        let q = receiver.0.clone();
+        ["a", "b"].iter().for_each(|f| q.push(event(f)));
+
+        let pusher = std::thread::spawn(move || {
+            ["c", "d", "e", "f"].iter().for_each(|f| q.push(event(f)));
+        });
+
+        for f in ["a", "b", "c"] {
+            assert_eq!(receiver.recv().await, event(f));
+        }
+
+        // Whatever was still waiting is let go
+        let q = receiver.0.clone();
+        drop(receiver);
+        pusher.join().unwrap();
+        assert!(contents(&q).len() <= 2);
+    }
+
+    #[test]
+    fn test_event_patterns() {
+        use notify::event::{
+            AccessKind, AccessMode, CreateKind, DataChange, MetadataKind, ModifyKind, RemoveKind,
+        };
+        use notify::EventKind;
+
+        let test_dir = make_test_dir();
+        let path = copy_asset(test_dir.path(), "cube.obj");
+
+        let patterns = [
+            (
+                "linux",
+                vec![
+                    EventKind::Create(CreateKind::File),
+                    EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+                    EventKind::Access(AccessKind::Close(AccessMode::Write)),
+                ],
+            ),
+            (
+                "macos",
+                vec![
+                    EventKind::Create(CreateKind::File),
+                    EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+                    EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)),
+                ],
+            ),
+            (
+                "windows",
+                vec![
+                    EventKind::Create(CreateKind::Any),
+                    EventKind::Modify(ModifyKind::Any),
+                    EventKind::Modify(ModifyKind::Any),
+                ],
+            ),
+        ];
+
+        // However a platform announces a file, it is loaded once; events
+        // while it settles, or after, are duplicates
+        for (os, events) in patterns {
+            let mut files = super::FileStates::default();
+            let mut loads = 0;
+
+            for kind in &events {
+                assert!(super::touches_files(kind), "{os}: {kind:?}");
+                loads += usize::from(files.changed(&path, super::stamp(&path)));
+            }
+
+            files.loaded(&path, super::stamp(&path).unwrap());
+
+            for _ in &events {
+                loads += usize::from(files.changed(&path, super::stamp(&path)));
+            }
+
+            assert_eq!(loads, 1, "{os}");
+        }
+
+        // Reads and new folders are not file changes
+        for kind in [
+            EventKind::Access(AccessKind::Read),
+            EventKind::Access(AccessKind::Close(AccessMode::Read)),
+            EventKind::Create(CreateKind::Folder),
+        ] {
+            assert!(!super::touches_files(&kind), "{kind:?}");
+        }
+        assert!(super::touches_files(&EventKind::Remove(RemoveKind::File)));
+
+        // Without settling, a file being copied is only taken once it is
+        // closed
+        let settle = super::Settle::new(&super::WatcherOptions {
+            settle_interval: std::time::Duration::ZERO,
+            ..Default::default()
+        });
+
+        for kind in [
+            EventKind::Create(CreateKind::File),
+            EventKind::Modify(ModifyKind::Data(DataChange::Any)),
+        ] {
+            assert_eq!(settle.heeds(&kind), cfg!(target_os = "macos"), "{kind:?}");
+        }
+        assert!(settle.heeds(&EventKind::Access(AccessKind::Close(AccessMode::Write))));
+        assert!(settle.heeds(&EventKind::Remove(RemoveKind::File)));
+
+        // A file removed and put back as it was is loaded again
+        settle.loaded(&path);
+        assert!(!settle.wants(&path));
+        let bytes = std::fs::read(&path).unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        settle.gone(&path);
+        std::fs::write(&path, bytes).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(settle.wants(&path));
+
+        let mut files = super::FileStates::default();
+        assert!(files.changed(&path, super::stamp(&path)));
+        files.loaded(&path, super::stamp(&path).unwrap());
+
+        // A rewrite is loaded again
+        std::fs::write(&path, b"v 0 0 0\n").unwrap();
+        assert!(files.changed(&path, super::stamp(&path)));
+        files.loaded(&path, super::stamp(&path).unwrap());
+
+        // A removed file is forgotten, so it is new if it comes back
+        std::fs::remove_file(&path).unwrap();
+        assert!(!files.changed(&path, None));
+        std::fs::write(&path, b"v 0 0 0\n").unwrap();
+        assert!(files.changed(&path, super::stamp(&path)));
+
+        // One that goes away while settling stays settling until its wait
+        // gives up
+        assert!(!files.changed(&path, None));
+        assert!(!files.wants(&path, super::stamp(&path)));
+        files.dropped(&path);
+        assert!(files.wants(&path, super::stamp(&path)));
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_dir_watch() {
@@ -217,7 +1180,16 @@ mod test {
             dir: test_dir.path().into(),
             load_existing: false,
             latest_only: false,
+            sequence: None,
             organize_by_dir: false,
+            transform: Default::default(),
+            ttl: None,
+            policy: Default::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            poll: None,
+            max_depth: None,
+            no_recursive: false,
         };
 
         let (watcher_tx, mut watcher_rx) = tokio::sync::mpsc::channel(16);
@@ -225,7 +1197,12 @@ mod test {
 
         println!("Starting watcher on {}", test_dir.path().display());
 
-        tokio::spawn(super::launch_file_watcher(watcher_tx, setup, stop_rx));
+        tokio::spawn(super::launch_file_watcher(
+            watcher_tx,
+            setup,
+            stop_rx,
+            Default::default(),
+        ));
 
         println!("Watcher up...waiting");
 
@@ -234,16 +1211,16 @@ mod test {
         // copy in a file
         let new_file_path = copy_asset(test_dir.path(), "cube.obj");
 
-        let mut sequence = VecDeque::from([PlatterCommand::LoadFile(new_file_path, None)]);
+        let mut sequence = VecDeque::from([PlatterCommand::LoadFile(new_file_path, None, None)]);
 
         println!("Awaiting commands");
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
 
-        while let Some(command) = watcher_rx.recv().await {
+        while let Some(command) = watcher_rx.recv().await.filter(|f| !is_unwatched(f)) {
             //println!("Next: {command:?}");
             let should_be = sequence.pop_front().expect("expected command underflow");
             match (command, should_be) {
-                (PlatterCommand::LoadFile(x, _), PlatterCommand::LoadFile(y, _)) => {
+                (PlatterCommand::LoadFile(x, ..), PlatterCommand::LoadFile(y, ..)) => {
                     assert_eq!(x, y);
                 }
                 (PlatterCommand::ClearTag(x), PlatterCommand::ClearTag(y)) => {
@@ -259,6 +1236,243 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_scan() {
+        let test_dir = make_test_dir();
+        let a = copy_asset(test_dir.path(), "cube.obj");
+
+        let before = super::Scan::of(test_dir.path(), None).unwrap();
+        assert!(super::Scan::of(test_dir.path(), None)
+            .unwrap()
+            .changed_files(&before)
+            .is_empty());
+
+        let sub = test_dir.path().join("run1");
+        std::fs::create_dir(&sub).unwrap();
+        let b = copy_asset(&sub, "monkey.obj");
+        std::fs::write(&a, b"v 0 0 0\n").unwrap();
+
+        let after = super::Scan::of(test_dir.path(), None).unwrap();
+        assert_eq!(after.new_dirs(&before), [sub]);
+        assert_eq!(after.changed_files(&before), [a.clone(), b]);
+
+        // Subdirectories are beyond a limit of zero
+        let shallow = super::Scan::of(test_dir.path(), Some(0)).unwrap();
+        assert!(shallow.dirs.is_empty());
+        assert_eq!(shallow.changed_files(&before), [a]);
+
+        // A directory that can't be read has no scan, rather than an empty one
+        assert!(super::Scan::of(&test_dir.path().join("missing"), None).is_none());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_poll_watch() {
+        let test_dir = make_test_dir();
+
+        let setup = Directory {
+            dir: test_dir.path().into(),
+            load_existing: false,
+            latest_only: false,
+            sequence: None,
+            organize_by_dir: false,
+            transform: Default::default(),
+            ttl: None,
+            policy: Default::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            poll: Some(HumanDuration(std::time::Duration::from_millis(100))),
+            max_depth: None,
+            no_recursive: false,
+        };
+
+        let (watcher_tx, mut watcher_rx) = tokio::sync::mpsc::channel(16);
+        let (stop_tx, stop_rx) = tokio::sync::broadcast::channel(1);
+
+        tokio::spawn(super::launch_file_watcher(
+            watcher_tx,
+            setup.clone(),
+            stop_rx,
+            Default::default(),
+        ));
+
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+        let path = copy_asset(test_dir.path(), "cube.obj");
+
+        let Some(PlatterCommand::LoadFile(x, ..)) = watcher_rx.recv().await else {
+            panic!("Expected a load");
+        };
+        assert_eq!(x, path);
+
+        stop_tx.send(true).unwrap();
+        assert!(watcher_rx.recv().await.is_some_and(|f| is_unwatched(&f)));
+        assert!(watcher_rx.recv().await.is_none());
+
+        // A directory that shows up later is compared with its first read,
+        // so files already in it are left alone
+        let later = test_dir.path().join("later");
+
+        let (watcher_tx, mut watcher_rx) = tokio::sync::mpsc::channel(16);
+        let (stop_tx, stop_rx) = tokio::sync::broadcast::channel(1);
+
+        tokio::spawn(super::launch_file_watcher(
+            watcher_tx,
+            Directory {
+                dir: later.clone(),
+                ..setup
+            },
+            stop_rx,
+            Default::default(),
+        ));
+
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+        std::fs::create_dir(&later).unwrap();
+        copy_asset(&later, "cube.obj");
+
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+        let path = copy_asset(&later, "monkey.obj");
+
+        let Some(PlatterCommand::LoadFile(x, ..)) = watcher_rx.recv().await else {
+            panic!("Expected a load");
+        };
+        assert_eq!(x, path);
+
+        stop_tx.send(true).unwrap();
+        assert!(watcher_rx.recv().await.is_some_and(|f| is_unwatched(&f)));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_rewrite_watch() {
+        let test_dir = make_test_dir();
+
+        let setup = Directory {
+            dir: test_dir.path().into(),
+            load_existing: false,
+            latest_only: false,
+            sequence: None,
+            organize_by_dir: false,
+            transform: Default::default(),
+            ttl: None,
+            policy: Default::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            poll: None,
+            max_depth: None,
+            no_recursive: false,
+        };
+
+        let (watcher_tx, mut watcher_rx) = tokio::sync::mpsc::channel(16);
+        let (stop_tx, stop_rx) = tokio::sync::broadcast::channel(1);
+
+        tokio::spawn(super::launch_file_watcher(
+            watcher_tx,
+            setup,
+            stop_rx,
+            Default::default(),
+        ));
+
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+        // The same file written twice is loaded under the same tag both
+        // times, so the second load can replace the first scene
+        let path = copy_asset(test_dir.path(), "cube.obj");
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        copy_asset(test_dir.path(), "cube.obj");
+
+        let mut tags = Vec::new();
+
+        while let Some(command) = watcher_rx.recv().await.filter(|f| !is_unwatched(f)) {
+            let PlatterCommand::LoadFile(x, tag, _) = command else {
+                panic!("Expected a load, got {command:?}");
+            };
+            assert_eq!(x, path);
+            tags.push(tag.unwrap());
+
+            if tags.len() == 2 {
+                stop_tx.send(true).unwrap();
+            }
+        }
+
+        assert_eq!(tags[0], tags[1]);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_manifest_watch() {
+        let test_dir = make_test_dir();
+        let cube = copy_asset(test_dir.path(), "cube.obj");
+
+        let setup = Directory {
+            dir: test_dir.path().into(),
+            ..Default::default()
+        };
+
+        let (watcher_tx, mut watcher_rx) = tokio::sync::mpsc::channel(16);
+        let (stop_tx, stop_rx) = tokio::sync::broadcast::channel(1);
+
+        tokio::spawn(super::launch_file_watcher(
+            watcher_tx,
+            setup,
+            stop_rx,
+            Default::default(),
+        ));
+
+        tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+        // Listed files already there are loaded with the manifest; later ones
+        // as they arrive. Unlisted files are ignored.
+        std::fs::write(
+            test_dir.path().join(crate::manifest::FILE_NAME),
+            r#"{"files":[{"path":"cube.obj","name":"Cube"},{"path":"monkey.obj","group":"heads"}]}"#,
+        )
+        .unwrap();
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        copy_asset(test_dir.path(), "cube.glb");
+        let monkey = copy_asset(test_dir.path(), "monkey.obj");
+
+        assert!(matches!(
+            watcher_rx.recv().await,
+            Some(PlatterCommand::ClearTag(_))
+        ));
+
+        let Some(PlatterCommand::Manifest(_, Some(manifest))) = watcher_rx.recv().await else {
+            panic!("Expected the manifest");
+        };
+        assert_eq!(manifest.entry(&cube).unwrap().name.as_deref(), Some("Cube"));
+
+        for path in [cube, monkey] {
+            let Some(PlatterCommand::LoadFile(x, ..)) = watcher_rx.recv().await else {
+                panic!("Expected a load");
+            };
+            assert_eq!(x, path);
+        }
+
+        // Without the manifest, files are taken as they come again
+        std::fs::remove_file(test_dir.path().join(crate::manifest::FILE_NAME)).unwrap();
+
+        assert!(matches!(
+            watcher_rx.recv().await,
+            Some(PlatterCommand::Manifest(_, None))
+        ));
+
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        let cylinder = test_dir.path().join("cylinder.obj");
+        std::fs::copy(get_asset("cube.obj"), &cylinder).unwrap();
+
+        let Some(PlatterCommand::LoadFile(x, ..)) = watcher_rx.recv().await else {
+            panic!("Expected a load");
+        };
+        assert_eq!(x, cylinder);
+
+        stop_tx.send(true).unwrap();
+        assert!(watcher_rx.recv().await.is_some_and(|f| is_unwatched(&f)));
+        assert!(watcher_rx.recv().await.is_none());
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_exclusive_watch() {
@@ -270,7 +1484,16 @@ mod test {
             dir: test_dir.path().into(),
             load_existing: false,
             latest_only: true,
+            sequence: None,
             organize_by_dir: false,
+            transform: Default::default(),
+            ttl: None,
+            policy: Default::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            poll: None,
+            max_depth: None,
+            no_recursive: false,
         };
 
         let (watcher_tx, mut watcher_rx) = tokio::sync::mpsc::channel(16);
@@ -278,7 +1501,12 @@ mod test {
 
         println!("Starting watcher on {}", test_dir.path().display());
 
-        tokio::spawn(super::launch_file_watcher(watcher_tx, setup, stop_rx));
+        tokio::spawn(super::launch_file_watcher(
+            watcher_tx,
+            setup,
+            stop_rx,
+            Default::default(),
+        ));
 
         println!("Watcher up...waiting");
 
@@ -294,23 +1522,25 @@ mod test {
 
         let mut sequence = VecDeque::from([
             PlatterCommand::ClearTag(Tag::new()),
-            PlatterCommand::LoadFile(new_file_path1, None),
+            PlatterCommand::LoadFile(new_file_path1, None, None),
             PlatterCommand::ClearTag(Tag::new()),
-            PlatterCommand::LoadFile(new_file_path2, None),
+            PlatterCommand::LoadFile(new_file_path2, None, None),
         ]);
 
         println!("Awaiting commands");
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
 
         let mut last_tag = Tag::new();
+        let mut last_cancel = None;
         let mut first_clear = true; // we dont know the first tag yet.
 
-        while let Some(command) = watcher_rx.recv().await {
+        while let Some(command) = watcher_rx.recv().await.filter(|f| !is_unwatched(f)) {
             //println!("Next: {command:?}");
             let should_be = sequence.pop_front().expect("expected command underflow");
             match (command, should_be) {
-                (PlatterCommand::LoadFile(x, u), PlatterCommand::LoadFile(y, _)) => {
+                (PlatterCommand::LoadFile(x, u, c), PlatterCommand::LoadFile(y, ..)) => {
                     last_tag = u.unwrap();
+                    last_cancel = c;
                     assert_eq!(x, y);
                 }
                 (PlatterCommand::ClearTag(x), PlatterCommand::ClearTag(_)) => {
@@ -319,6 +1549,9 @@ mod test {
                     } else {
                         // now we know the tag and can test
                         assert_eq!(x, last_tag);
+
+                        // the earlier import must not publish after the clear
+                        assert!(last_cancel.as_ref().unwrap().is_cancelled());
                     }
                 }
                 (a, b) => {
@@ -342,7 +1575,16 @@ mod test {
             dir: test_dir.path().into(),
             load_existing: false,
             latest_only: true,
+            sequence: None,
             organize_by_dir: true,
+            transform: Default::default(),
+            ttl: None,
+            policy: Default::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            poll: None,
+            max_depth: None,
+            no_recursive: false,
         };
 
         let (watcher_tx, mut watcher_rx) = tokio::sync::mpsc::channel(16);
@@ -350,7 +1592,12 @@ mod test {
 
         println!("Starting watcher on {}", test_dir.path().display());
 
-        tokio::spawn(super::launch_file_watcher(watcher_tx, setup, stop_rx));
+        tokio::spawn(super::launch_file_watcher(
+            watcher_tx,
+            setup,
+            stop_rx,
+            Default::default(),
+        ));
 
         println!("Watcher up...waiting");
 
@@ -373,8 +1620,8 @@ mod test {
         let new_file_path2 = copy_asset(d1.as_path(), "monkey.obj");
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
 
-        sequence.push_back(PlatterCommand::LoadFile(new_file_path1, None));
-        sequence.push_back(PlatterCommand::LoadFile(new_file_path2, None));
+        sequence.push_back(PlatterCommand::LoadFile(new_file_path1, None, None));
+        sequence.push_back(PlatterCommand::LoadFile(new_file_path2, None, None));
 
         std::fs::create_dir(&d2).unwrap();
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
@@ -388,19 +1635,19 @@ mod test {
         let new_file_path2 = copy_asset(d2.as_path(), "monkey.obj");
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
 
-        sequence.push_back(PlatterCommand::LoadFile(new_file_path1, None));
-        sequence.push_back(PlatterCommand::LoadFile(new_file_path2, None));
+        sequence.push_back(PlatterCommand::LoadFile(new_file_path1, None, None));
+        sequence.push_back(PlatterCommand::LoadFile(new_file_path2, None, None));
 
         println!("Awaiting commands");
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
 
         let mut known_tags = HashSet::new();
 
-        while let Some(command) = watcher_rx.recv().await {
+        while let Some(command) = watcher_rx.recv().await.filter(|f| !is_unwatched(f)) {
             println!("Next: {command:?}");
             let should_be = sequence.pop_front().expect("expected command underflow");
             match (command, should_be) {
-                (PlatterCommand::LoadFile(x, u), PlatterCommand::LoadFile(y, _)) => {
+                (PlatterCommand::LoadFile(x, u, _), PlatterCommand::LoadFile(y, ..)) => {
                     known_tags.insert(u.unwrap().clone());
                     assert_eq!(x, y);
                 }
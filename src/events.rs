@@ -0,0 +1,130 @@
+//! A bounded history of what platter has done, so late clients can catch up
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Number of events kept before the oldest are dropped
+const DEFAULT_CAPACITY: usize = 256;
+
+/// Something that happened to the set of loaded scenes
+#[derive(Debug, Clone)]
+pub enum EventKind {
+    /// A file was imported as a scene
+    Loaded { path: PathBuf, scene: u32 },
+    /// A scene was imported again from its file, keeping its id
+    Reloaded { path: PathBuf, scene: u32 },
+    /// An image file a scene references changed, and was published again
+    Refreshed { path: PathBuf, scene: u32 },
+    /// A scene was copied as a new scene
+    Duplicated { from: u32, scene: u32 },
+    /// A file could not be imported
+    LoadFailed { path: PathBuf, error: String },
+    /// A scene was removed
+    Removed { scene: u32 },
+    /// A scene outlived its source's TTL, and is about to be removed
+    Expired { scene: u32 },
+    /// A directory watch was requested
+    WatchStarted { path: PathBuf },
+    /// A directory watch was stopped by a client
+    WatchStopped { path: PathBuf },
+    /// Scenes were written to a file
+    Exported { path: PathBuf },
+    /// Scenes could not be written to a file
+    ExportFailed { path: PathBuf, error: String },
+}
+
+impl EventKind {
+    /// A short name for this kind of event
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Loaded { .. } => "loaded",
+            EventKind::Reloaded { .. } => "reloaded",
+            EventKind::Refreshed { .. } => "refreshed",
+            EventKind::Duplicated { .. } => "duplicated",
+            EventKind::LoadFailed { .. } => "load_failed",
+            EventKind::Removed { .. } => "removed",
+            EventKind::Expired { .. } => "expired",
+            EventKind::WatchStarted { .. } => "watch_started",
+            EventKind::WatchStopped { .. } => "watch_stopped",
+            EventKind::Exported { .. } => "exported",
+            EventKind::ExportFailed { .. } => "export_failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// Monotonic sequence number, starting at 1
+    pub seq: u64,
+    pub time: SystemTime,
+    pub kind: EventKind,
+}
+
+/// Ring buffer of recent events
+#[derive(Debug)]
+pub struct EventLog {
+    events: VecDeque<Event>,
+    capacity: usize,
+    next_seq: u64,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            next_seq: 1,
+        }
+    }
+
+    /// Add an event, dropping the oldest if we are full
+    pub fn record(&mut self, kind: EventKind) {
+        log::debug!("Event: {kind:?}");
+
+        if self.events.len() >= self.capacity {
+            self.events.pop_front();
+        }
+
+        self.events.push_back(Event {
+            seq: self.next_seq,
+            time: SystemTime::now(),
+            kind,
+        });
+
+        self.next_seq += 1;
+    }
+
+    /// Get all retained events with a sequence number greater than `seq`
+    pub fn since(&self, seq: u64) -> impl Iterator<Item = &Event> {
+        self.events.iter().filter(move |f| f.seq > seq)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_event_log_bounds() {
+        let mut log = EventLog::new(3);
+
+        for scene in 0..5 {
+            log.record(EventKind::Removed { scene });
+        }
+
+        let seqs: Vec<_> = log.since(0).map(|f| f.seq).collect();
+        assert_eq!(seqs, vec![3, 4, 5]);
+
+        let seqs: Vec<_> = log.since(4).map(|f| f.seq).collect();
+        assert_eq!(seqs, vec![5]);
+
+        assert_eq!(log.since(5).count(), 0);
+    }
+}
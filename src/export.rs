@@ -0,0 +1,1002 @@
+//! Export published scenes to binary glTF.
+//!
+//! This works from what we publish, not from the files scenes came from, so
+//! an export includes the transforms and material changes made during a
+//! session. Components are read in their serialized form, which follows the
+//! NOODLES message spec, and buffer contents are fetched from wherever they
+//! are served.
+//!
+//! Meshes, materials, and base color textures are exported. Each instance of
+//! an instanced mesh becomes a node of its own, without its color. Text,
+//! lights, and helpers such as labels are left out.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use serde_json::json;
+
+use colabrodo_common::common::ComponentID;
+use colabrodo_common::value_tools::Value;
+use colabrodo_server::server_messages::*;
+use colabrodo_server::server_state::*;
+
+/// Serialize a component from one of the state's lists, if it exists
+macro_rules! grab {
+    ($list:expr, $id:expr) => {
+        $list.inspect($id, |f| Value::serialized(f).ok()).flatten()
+    };
+}
+
+/// Components copied out of the server state, so the lock can be released
+/// before buffers are fetched
+#[derive(Debug, Default)]
+pub struct Capture {
+    /// Entities in the order given, with their state
+    entities: Vec<(ComponentID, Value)>,
+    geometries: HashMap<ComponentID, Value>,
+    views: HashMap<ComponentID, Value>,
+    buffers: HashMap<ComponentID, Value>,
+    materials: HashMap<ComponentID, Value>,
+    textures: HashMap<ComponentID, Value>,
+    images: HashMap<ComponentID, Value>,
+}
+
+/// Look up a field of a map
+pub(crate) fn field<'a>(v: &'a Value, key: &str) -> Option<&'a Value> {
+    v.as_map()?
+        .iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+}
+
+pub(crate) fn id(v: Option<&Value>) -> Option<ComponentID> {
+    v?.deserialized().ok()
+}
+
+fn uint(v: Option<&Value>) -> Option<usize> {
+    usize::try_from(v?.as_integer()?).ok()
+}
+
+fn float(v: &Value) -> Option<f64> {
+    match v {
+        Value::Float(f) => Some(*f),
+        Value::Integer(i) => Some(i128::from(*i) as f64),
+        _ => None,
+    }
+}
+
+fn floats(v: Option<&Value>) -> Option<Vec<f64>> {
+    v?.as_array()?.iter().map(float).collect()
+}
+
+fn text<'a>(v: &'a Value, key: &str) -> Option<&'a str> {
+    field(v, key)?.as_text()
+}
+
+/// Whether an entity is one of our helpers, such as a label or shadow
+fn is_helper(entity: &Value) -> bool {
+    field(entity, "tags")
+        .and_then(|f| f.as_array())
+        .is_some_and(|f| {
+            f.iter().any(|t| {
+                t.as_text()
+                    .is_some_and(|t| t.starts_with("platter.helper="))
+            })
+        })
+}
+
+/// Copy the state of some entities, and everything needed to draw them
+pub fn capture(state: &ServerState, entities: &[EntityReference]) -> Capture {
+    let mut ret = Capture::default();
+
+    for ent in entities {
+        let Some(value) = grab!(state.entities, ent.id()) else {
+            continue;
+        };
+
+        if is_helper(&value) {
+            continue;
+        }
+
+        let rep = field(&value, "render_rep");
+
+        if let Some(mesh) = id(rep.and_then(|f| field(f, "mesh"))) {
+            ret.add_geometry(state, mesh);
+        }
+
+        let instances = rep.and_then(|f| field(f, "instances"));
+
+        if let Some(view) = id(instances.and_then(|f| field(f, "view"))) {
+            ret.add_view(state, view);
+        }
+
+        ret.entities.push((ent.id(), value));
+    }
+
+    ret
+}
+
+impl Capture {
+    fn add_geometry(&mut self, state: &ServerState, geom: ComponentID) {
+        if self.geometries.contains_key(&geom) {
+            return;
+        }
+
+        let Some(value) = grab!(state.geometries, geom) else {
+            return;
+        };
+
+        let patches = field(&value, "patches").and_then(|f| f.as_array());
+
+        for patch in patches.into_iter().flatten() {
+            let attributes = field(patch, "attributes").and_then(|f| f.as_array());
+
+            for attr in attributes.into_iter().flatten() {
+                if let Some(view) = id(field(attr, "view")) {
+                    self.add_view(state, view);
+                }
+            }
+
+            if let Some(view) = id(field(patch, "indices").and_then(|f| field(f, "view"))) {
+                self.add_view(state, view);
+            }
+
+            if let Some(mat) = id(field(patch, "material")) {
+                self.add_material(state, mat);
+            }
+        }
+
+        self.geometries.insert(geom, value);
+    }
+
+    fn add_view(&mut self, state: &ServerState, view: ComponentID) {
+        if self.views.contains_key(&view) {
+            return;
+        }
+
+        let Some(value) = grab!(state.buffer_views, view) else {
+            return;
+        };
+
+        if let Some(buffer) = id(field(&value, "source_buffer")) {
+            if let Entry::Vacant(e) = self.buffers.entry(buffer) {
+                if let Some(b) = grab!(state.buffers, buffer) {
+                    e.insert(b);
+                }
+            }
+        }
+
+        self.views.insert(view, value);
+    }
+
+    fn add_material(&mut self, state: &ServerState, mat: ComponentID) {
+        if self.materials.contains_key(&mat) {
+            return;
+        }
+
+        let Some(value) = grab!(state.materials, mat) else {
+            return;
+        };
+
+        let texture = field(&value, "pbr_info")
+            .and_then(|f| field(f, "base_color_texture"))
+            .and_then(|f| id(field(f, "texture")));
+
+        if let Some(tex) = texture {
+            self.add_texture(state, tex);
+        }
+
+        self.materials.insert(mat, value);
+    }
+
+    fn add_texture(&mut self, state: &ServerState, tex: ComponentID) {
+        if self.textures.contains_key(&tex) {
+            return;
+        }
+
+        let Some(value) = grab!(state.textures, tex) else {
+            return;
+        };
+
+        if let Some(image) = id(field(&value, "image")) {
+            if let Some(img) = grab!(state.images, image) {
+                if let Some(view) = id(field(&img, "buffer_source")) {
+                    self.add_view(state, view);
+                }
+                self.images.insert(image, img);
+            }
+        }
+
+        self.textures.insert(tex, value);
+    }
+}
+
+/// glTF component type, element type, and size in bytes of a NOODLES format
+fn format_info(format: &str) -> Option<(u32, &'static str, usize)> {
+    const U8: u32 = 5121;
+    const U16: u32 = 5123;
+    const U32: u32 = 5125;
+    const F32: u32 = 5126;
+
+    Some(match format {
+        "U8" => (U8, "SCALAR", 1),
+        "U16" => (U16, "SCALAR", 2),
+        "U32" => (U32, "SCALAR", 4),
+        "U8VEC4" => (U8, "VEC4", 4),
+        "U16VEC2" => (U16, "VEC2", 4),
+        "VEC2" => (F32, "VEC2", 8),
+        "VEC3" => (F32, "VEC3", 12),
+        "VEC4" => (F32, "VEC4", 16),
+        "MAT3" => (F32, "MAT3", 36),
+        "MAT4" => (F32, "MAT4", 64),
+        _ => return None,
+    })
+}
+
+/// glTF attribute name for a NOODLES semantic, given its element type
+fn attribute_name(semantic: &str, channel: usize, kind: &str) -> Option<String> {
+    Some(match (semantic, kind) {
+        ("POSITION", "VEC3") => "POSITION".into(),
+        ("NORMAL", "VEC3") => "NORMAL".into(),
+        ("TANGENT", "VEC4") => "TANGENT".into(),
+        ("TEXTURE", "VEC2") => format!("TEXCOORD_{channel}"),
+        ("COLOR", "VEC3" | "VEC4") => format!("COLOR_{channel}"),
+        _ => return None,
+    })
+}
+
+fn primitive_mode(kind: &str) -> u32 {
+    match kind {
+        "POINTS" => 0,
+        "LINES" => 1,
+        "LINE_LOOP" => 2,
+        "LINE_STRIP" => 3,
+        "TRIANGLE_STRIP" => 5,
+        _ => 4,
+    }
+}
+
+/// Size of a NOODLES instance, a 4x4 float matrix
+const INSTANCE_SIZE: usize = 64;
+
+/// Builds the glTF document and its binary chunk
+struct Writer<'a, F> {
+    capture: &'a Capture,
+    fetch: F,
+
+    /// Contents of each buffer, fetched as needed
+    buffers: HashMap<ComponentID, Vec<u8>>,
+
+    bin: Vec<u8>,
+    views: Vec<serde_json::Value>,
+    accessors: Vec<serde_json::Value>,
+    meshes: Vec<serde_json::Value>,
+    materials: Vec<serde_json::Value>,
+    textures: Vec<serde_json::Value>,
+    images: Vec<serde_json::Value>,
+
+    mesh_index: HashMap<ComponentID, Option<usize>>,
+    material_index: HashMap<ComponentID, usize>,
+    texture_index: HashMap<ComponentID, Option<usize>>,
+}
+
+impl<F: FnMut(&str) -> Result<Vec<u8>>> Writer<'_, F> {
+    /// Bytes of a NOODLES buffer view
+    fn view_bytes(&mut self, view: ComponentID) -> Result<&[u8]> {
+        let v = self
+            .capture
+            .views
+            .get(&view)
+            .context("Missing buffer view")?;
+
+        let buffer = id(field(v, "source_buffer")).context("Buffer view has no buffer")?;
+        let offset = uint(field(v, "offset")).unwrap_or(0);
+        let length = uint(field(v, "length")).context("Buffer view has no length")?;
+
+        if !self.buffers.contains_key(&buffer) {
+            let b = self
+                .capture
+                .buffers
+                .get(&buffer)
+                .context("Missing buffer")?;
+
+            let bytes = if let Some(bytes) = field(b, "inline_bytes").and_then(|f| f.as_bytes()) {
+                bytes.clone()
+            } else if let Some(url) = text(b, "uri_bytes") {
+                (self.fetch)(url)?
+            } else {
+                anyhow::bail!("Buffer has no content");
+            };
+
+            self.buffers.insert(buffer, bytes);
+        }
+
+        self.buffers[&buffer]
+            .get(offset..offset + length)
+            .context("Buffer view runs past its buffer")
+    }
+
+    /// Append bytes to the binary chunk as a new glTF buffer view
+    fn push_view(&mut self, bytes: &[u8]) -> usize {
+        while !self.bin.len().is_multiple_of(4) {
+            self.bin.push(0);
+        }
+
+        self.views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.bin.len(),
+            "byteLength": bytes.len(),
+        }));
+
+        self.bin.extend_from_slice(bytes);
+
+        self.views.len() - 1
+    }
+
+    /// Copy `count` strided elements out of a view into a packed accessor
+    fn accessor(
+        &mut self,
+        view: ComponentID,
+        format: &str,
+        count: usize,
+        offset: usize,
+        stride: Option<usize>,
+        normalized: bool,
+    ) -> Result<usize> {
+        let (component, kind, size) =
+            format_info(format).with_context(|| format!("Unknown format {format}"))?;
+
+        let stride = stride.filter(|f| *f > 0).unwrap_or(size);
+
+        let src = self.view_bytes(view)?;
+
+        let mut packed = Vec::with_capacity(count * size);
+
+        for i in 0..count {
+            let start = offset + i * stride;
+            let element = src
+                .get(start..start + size)
+                .context("Attribute runs past its buffer view")?;
+            packed.extend_from_slice(element);
+        }
+
+        let mut accessor = json!({
+            "componentType": component,
+            "type": kind,
+            "count": count,
+        });
+
+        // Positions need bounds
+        if format == "VEC3" {
+            let mut min = [f32::MAX; 3];
+            let mut max = [f32::MIN; 3];
+
+            for v in packed.chunks_exact(12) {
+                for c in 0..3 {
+                    let f = f32::from_le_bytes(v[c * 4..c * 4 + 4].try_into().unwrap());
+                    min[c] = min[c].min(f);
+                    max[c] = max[c].max(f);
+                }
+            }
+
+            if count > 0 {
+                accessor["min"] = json!(min);
+                accessor["max"] = json!(max);
+            }
+        }
+
+        if normalized && component != 5126 {
+            accessor["normalized"] = json!(true);
+        }
+
+        accessor["bufferView"] = json!(self.push_view(&packed));
+
+        self.accessors.push(accessor);
+
+        Ok(self.accessors.len() - 1)
+    }
+
+    /// Placements of a mesh's instances as glTF nodes, each with a
+    /// translation, rotation, and scale
+    fn instances(&mut self, instances: &Value) -> Result<Vec<serde_json::Value>> {
+        let view = id(field(instances, "view")).context("Instances have no view")?;
+        let stride = uint(field(instances, "stride"))
+            .filter(|f| *f > 0)
+            .unwrap_or(INSTANCE_SIZE);
+
+        let src = self.view_bytes(view)?;
+
+        let mut ret = Vec::new();
+        let mut start = 0;
+
+        // Columns are position, color, rotation as a quaternion, and scale
+        while let Some(element) = src.get(start..start + INSTANCE_SIZE) {
+            let column = |c: usize, n: usize| -> Vec<f32> {
+                (0..n)
+                    .map(|i| {
+                        let at = (c * 4 + i) * 4;
+                        f32::from_le_bytes(element[at..at + 4].try_into().unwrap())
+                    })
+                    .collect()
+            };
+
+            ret.push(json!({
+                "translation": column(0, 3),
+                "rotation": column(2, 4),
+                "scale": column(3, 3),
+            }));
+
+            start += stride;
+        }
+
+        Ok(ret)
+    }
+
+    fn texture(&mut self, tex: ComponentID) -> Result<Option<usize>> {
+        if let Some(index) = self.texture_index.get(&tex) {
+            return Ok(*index);
+        }
+
+        let capture = self.capture;
+
+        let image = capture
+            .textures
+            .get(&tex)
+            .and_then(|t| id(field(t, "image")))
+            .and_then(|i| capture.images.get(&i));
+
+        let bytes = match image {
+            Some(img) => {
+                if let Some(view) = id(field(img, "buffer_source")) {
+                    Some(self.view_bytes(view)?.to_vec())
+                } else if let Some(url) = text(img, "uri_source") {
+                    Some((self.fetch)(url)?)
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+
+        let mime = bytes.as_deref().and_then(|b| {
+            if b.starts_with(b"\x89PNG") {
+                Some("image/png")
+            } else if b.starts_with(b"\xff\xd8") {
+                Some("image/jpeg")
+            } else {
+                None
+            }
+        });
+
+        let ret = match (bytes, mime) {
+            (Some(bytes), Some(mime)) => {
+                let view = self.push_view(&bytes);
+                self.images
+                    .push(json!({"bufferView": view, "mimeType": mime}));
+                self.textures.push(json!({"source": self.images.len() - 1}));
+                Some(self.textures.len() - 1)
+            }
+            _ => {
+                log::warn!("Leaving out a texture that isn't PNG or JPEG");
+                None
+            }
+        };
+
+        self.texture_index.insert(tex, ret);
+
+        Ok(ret)
+    }
+
+    fn material(&mut self, mat: ComponentID) -> Result<usize> {
+        if let Some(index) = self.material_index.get(&mat) {
+            return Ok(*index);
+        }
+
+        let value = self
+            .capture
+            .materials
+            .get(&mat)
+            .context("Missing material")?;
+
+        let mut pbr = json!({});
+        let mut out = json!({});
+
+        if let Some(info) = field(value, "pbr_info") {
+            if let Some(c) = floats(field(info, "base_color")).filter(|f| f.len() == 4) {
+                pbr["baseColorFactor"] = json!(c);
+            }
+            if let Some(m) = field(info, "metallic").and_then(float) {
+                pbr["metallicFactor"] = json!(m);
+            }
+            if let Some(r) = field(info, "roughness").and_then(float) {
+                pbr["roughnessFactor"] = json!(r);
+            }
+
+            let tex_ref = field(info, "base_color_texture");
+
+            if let Some(tex) = id(tex_ref.and_then(|f| field(f, "texture"))) {
+                if let Some(index) = self.texture(tex)? {
+                    let slot = uint(tex_ref.and_then(|f| field(f, "texture_coord_slot")));
+                    pbr["baseColorTexture"] = json!({
+                        "index": index,
+                        "texCoord": slot.unwrap_or(0),
+                    });
+                }
+            }
+        }
+
+        out["pbrMetallicRoughness"] = pbr;
+
+        if let Some(name) = text(value, "name") {
+            out["name"] = json!(name);
+        }
+        if field(value, "use_alpha").and_then(|f| f.as_bool()) == Some(true) {
+            out["alphaMode"] = json!("BLEND");
+        }
+        if field(value, "double_sided").and_then(|f| f.as_bool()) == Some(true) {
+            out["doubleSided"] = json!(true);
+        }
+
+        self.materials.push(out);
+        let index = self.materials.len() - 1;
+        self.material_index.insert(mat, index);
+
+        Ok(index)
+    }
+
+    /// Convert a geometry to a mesh. Geometries with nothing we can export
+    /// give None.
+    fn mesh(&mut self, geom: ComponentID) -> Result<Option<usize>> {
+        if let Some(index) = self.mesh_index.get(&geom) {
+            return Ok(*index);
+        }
+
+        let capture = self.capture;
+        let value = capture.geometries.get(&geom).context("Missing geometry")?;
+
+        let mut primitives = Vec::new();
+
+        let patches = field(value, "patches").and_then(|f| f.as_array());
+
+        for patch in patches.into_iter().flatten() {
+            let count = uint(field(patch, "vertex_count")).unwrap_or(0);
+
+            let mut attributes = serde_json::Map::new();
+
+            let list = field(patch, "attributes").and_then(|f| f.as_array());
+
+            for attr in list.into_iter().flatten() {
+                let (Some(view), Some(semantic), Some(format)) = (
+                    id(field(attr, "view")),
+                    text(attr, "semantic"),
+                    text(attr, "format"),
+                ) else {
+                    continue;
+                };
+
+                let kind = format_info(format).map(|f| f.1).unwrap_or_default();
+                let channel = uint(field(attr, "channel")).unwrap_or(0);
+
+                let Some(name) = attribute_name(semantic, channel, kind) else {
+                    log::debug!("Leaving out {semantic} attribute of format {format}");
+                    continue;
+                };
+
+                let normalized = field(attr, "normalized").and_then(|f| f.as_bool());
+
+                let index = self.accessor(
+                    view,
+                    format,
+                    count,
+                    uint(field(attr, "offset")).unwrap_or(0),
+                    uint(field(attr, "stride")),
+                    normalized.unwrap_or(semantic == "COLOR"),
+                )?;
+
+                attributes.insert(name, json!(index));
+            }
+
+            if !attributes.contains_key("POSITION") {
+                continue;
+            }
+
+            let mut prim = json!({
+                "attributes": attributes,
+                "mode": primitive_mode(text(patch, "type").unwrap_or_default()),
+            });
+
+            if let Some(indices) = field(patch, "indices") {
+                if let (Some(view), Some(format)) =
+                    (id(field(indices, "view")), text(indices, "format"))
+                {
+                    prim["indices"] = json!(self.accessor(
+                        view,
+                        format,
+                        uint(field(indices, "count")).unwrap_or(0),
+                        uint(field(indices, "offset")).unwrap_or(0),
+                        uint(field(indices, "stride")),
+                        false,
+                    )?);
+                }
+            }
+
+            if let Some(mat) = id(field(patch, "material")) {
+                prim["material"] = json!(self.material(mat)?);
+            }
+
+            primitives.push(prim);
+        }
+
+        let ret = (!primitives.is_empty()).then(|| {
+            let mut mesh = json!({ "primitives": primitives });
+            if let Some(name) = text(value, "name") {
+                mesh["name"] = json!(name);
+            }
+            self.meshes.push(mesh);
+            self.meshes.len() - 1
+        });
+
+        self.mesh_index.insert(geom, ret);
+
+        Ok(ret)
+    }
+}
+
+/// Write captured entities as a GLB, fetching buffers and images served by
+/// URL with `fetch`
+pub fn to_glb(capture: &Capture, fetch: impl FnMut(&str) -> Result<Vec<u8>>) -> Result<Vec<u8>> {
+    let mut w = Writer {
+        capture,
+        fetch,
+        buffers: HashMap::new(),
+        bin: Vec::new(),
+        views: Vec::new(),
+        accessors: Vec::new(),
+        meshes: Vec::new(),
+        materials: Vec::new(),
+        textures: Vec::new(),
+        images: Vec::new(),
+        mesh_index: HashMap::new(),
+        material_index: HashMap::new(),
+        texture_index: HashMap::new(),
+    };
+
+    let node_index: HashMap<ComponentID, usize> = capture
+        .entities
+        .iter()
+        .enumerate()
+        .map(|(i, (ent, _))| (*ent, i))
+        .collect();
+
+    let mut nodes = Vec::new();
+    let mut children = vec![Vec::new(); capture.entities.len()];
+    let mut roots = Vec::new();
+
+    // Instances are children of their entity, and come after every entity
+    let mut placed = Vec::new();
+
+    for (i, (_, value)) in capture.entities.iter().enumerate() {
+        let mut node = json!({});
+
+        if let Some(name) = text(value, "name") {
+            node["name"] = json!(name);
+        }
+
+        if let Some(tf) = floats(field(value, "transform")).filter(|f| f.len() == 16) {
+            node["matrix"] = json!(tf);
+        }
+
+        let rep = field(value, "render_rep");
+        let mesh = id(rep.and_then(|f| field(f, "mesh")));
+
+        if let Some(index) = mesh.map(|f| w.mesh(f)).transpose()?.flatten() {
+            match rep.and_then(|f| field(f, "instances")) {
+                Some(instances) => {
+                    for mut instance in w.instances(instances)? {
+                        instance["mesh"] = json!(index);
+                        children[i].push(capture.entities.len() + placed.len());
+                        placed.push(instance);
+                    }
+                }
+                None => node["mesh"] = json!(index),
+            }
+        }
+
+        match id(field(value, "parent")).and_then(|p| node_index.get(&p)) {
+            Some(parent) => children[*parent].push(i),
+            None => roots.push(i),
+        }
+
+        nodes.push(node);
+    }
+
+    for (node, list) in nodes.iter_mut().zip(children) {
+        if !list.is_empty() {
+            node["children"] = json!(list);
+        }
+    }
+
+    nodes.extend(placed);
+
+    while !w.bin.len().is_multiple_of(4) {
+        w.bin.push(0);
+    }
+
+    let mut doc = json!({
+        "asset": {
+            "version": "2.0",
+            "generator": concat!("platter ", env!("CARGO_PKG_VERSION")),
+        },
+        "scene": 0,
+        "scenes": [{ "nodes": roots }],
+        "nodes": nodes,
+    });
+
+    for (key, list) in [
+        ("meshes", w.meshes),
+        ("materials", w.materials),
+        ("textures", w.textures),
+        ("images", w.images),
+        ("accessors", w.accessors),
+        ("bufferViews", w.views),
+    ] {
+        if !list.is_empty() {
+            doc[key] = json!(list);
+        }
+    }
+
+    if !w.bin.is_empty() {
+        doc["buffers"] = json!([{ "byteLength": w.bin.len() }]);
+    }
+
+    let mut json = serde_json::to_vec(&doc)?;
+
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+
+    let mut total = 12 + 8 + json.len();
+    if !w.bin.is_empty() {
+        total += 8 + w.bin.len();
+    }
+
+    let mut ret = Vec::with_capacity(total);
+
+    ret.extend_from_slice(b"glTF");
+    ret.extend_from_slice(&2u32.to_le_bytes());
+    ret.extend_from_slice(&(total as u32).to_le_bytes());
+
+    ret.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    ret.extend_from_slice(b"JSON");
+    ret.extend_from_slice(&json);
+
+    if !w.bin.is_empty() {
+        ret.extend_from_slice(&(w.bin.len() as u32).to_le_bytes());
+        ret.extend_from_slice(b"BIN\0");
+        ret.extend_from_slice(&w.bin);
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (Value::Text(k.into()), v))
+                .collect(),
+        )
+    }
+
+    fn int(i: usize) -> Value {
+        Value::Integer(i.into())
+    }
+
+    fn cid(i: u64) -> ComponentID {
+        Value::Integer(i.into()).deserialized().unwrap()
+    }
+
+    fn id_value(id: ComponentID) -> Value {
+        Value::serialized(&id).unwrap()
+    }
+
+    #[test]
+    fn test_to_glb() {
+        // A triangle with positions and colors interleaved in a served
+        // buffer, and indices in an inline one
+        let mut vertices = Vec::new();
+        for p in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 2.0, -1.0]] {
+            for c in p {
+                vertices.extend(c.to_le_bytes());
+            }
+            vertices.extend([255, 0, 0, 255]);
+        }
+        let indices: Vec<u8> = [0u16, 1, 2].iter().flat_map(|f| f.to_le_bytes()).collect();
+
+        let (vbuf, ibuf, vview, iview, geom, mat) =
+            (cid(1), cid(2), cid(3), cid(4), cid(5), cid(6));
+
+        // Two instances of the triangle, moved along x and scaled
+        let mut placements = Vec::new();
+        for x in [2.0f32, 4.0] {
+            for f in [
+                x, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 3.0, 3.0, 3.0, 1.0,
+            ] {
+                placements.extend(f.to_le_bytes());
+            }
+        }
+        let (pbuf, pview) = (cid(7), cid(8));
+
+        let mut capture = Capture::default();
+
+        capture.buffers.insert(
+            vbuf,
+            map(vec![("uri_bytes", Value::Text("http://assets/v".into()))]),
+        );
+        capture
+            .buffers
+            .insert(ibuf, map(vec![("inline_bytes", Value::Bytes(indices))]));
+        capture
+            .buffers
+            .insert(pbuf, map(vec![("inline_bytes", Value::Bytes(placements))]));
+
+        for (view, buffer, length) in [
+            (vview, vbuf, vertices.len()),
+            (iview, ibuf, 6),
+            (pview, pbuf, 128),
+        ] {
+            capture.views.insert(
+                view,
+                map(vec![
+                    ("source_buffer", id_value(buffer)),
+                    ("offset", int(0)),
+                    ("length", int(length)),
+                ]),
+            );
+        }
+
+        let attr = |semantic: &str, offset, format: &str| {
+            map(vec![
+                ("view", id_value(vview)),
+                ("semantic", Value::Text(semantic.into())),
+                ("offset", int(offset)),
+                ("stride", int(16)),
+                ("format", Value::Text(format.into())),
+            ])
+        };
+
+        capture.geometries.insert(
+            geom,
+            map(vec![(
+                "patches",
+                Value::Array(vec![map(vec![
+                    (
+                        "attributes",
+                        Value::Array(vec![
+                            attr("POSITION", 0, "VEC3"),
+                            attr("COLOR", 12, "U8VEC4"),
+                        ]),
+                    ),
+                    ("vertex_count", int(3)),
+                    (
+                        "indices",
+                        map(vec![
+                            ("view", id_value(iview)),
+                            ("count", int(3)),
+                            ("format", Value::Text("U16".into())),
+                        ]),
+                    ),
+                    ("type", Value::Text("TRIANGLES".into())),
+                    ("material", id_value(mat)),
+                ])]),
+            )]),
+        );
+
+        capture.materials.insert(
+            mat,
+            map(vec![
+                (
+                    "pbr_info",
+                    map(vec![(
+                        "base_color",
+                        Value::Array((0..4).map(|_| Value::Float(1.0)).collect()),
+                    )]),
+                ),
+                ("use_alpha", Value::Bool(true)),
+            ]),
+        );
+
+        let mut tf = vec![Value::Float(0.0); 16];
+        for i in [0, 5, 10, 15] {
+            tf[i] = Value::Float(1.0);
+        }
+        tf[12] = Value::Float(5.0);
+
+        capture.entities = vec![
+            (
+                cid(10),
+                map(vec![
+                    ("name", Value::Text("root".into())),
+                    ("transform", Value::Array(tf)),
+                ]),
+            ),
+            (
+                cid(11),
+                map(vec![
+                    ("parent", id_value(cid(10))),
+                    ("render_rep", map(vec![("mesh", id_value(geom))])),
+                ]),
+            ),
+            (
+                cid(12),
+                map(vec![(
+                    "render_rep",
+                    map(vec![
+                        ("mesh", id_value(geom)),
+                        ("instances", map(vec![("view", id_value(pview))])),
+                    ]),
+                )]),
+            ),
+        ];
+
+        let mut fetched = Vec::new();
+
+        let glb = to_glb(&capture, |url| {
+            fetched.push(url.to_string());
+            Ok(vertices.clone())
+        })
+        .unwrap();
+
+        assert_eq!(fetched, ["http://assets/v"]);
+
+        let gltf = gltf::Gltf::from_slice(&glb).unwrap();
+
+        let scene = gltf.default_scene().unwrap();
+        let root = scene.nodes().next().unwrap();
+        assert_eq!(root.name(), Some("root"));
+        assert_eq!(root.transform().decomposed().0, [5.0, 0.0, 0.0]);
+
+        // Each instance is a node of its own, sharing the mesh
+        let instanced = scene.nodes().nth(1).unwrap();
+        assert!(instanced.mesh().is_none());
+
+        let placed: Vec<_> = instanced
+            .children()
+            .map(|f| {
+                assert_eq!(f.mesh().unwrap().index(), 0);
+                let (t, _, s) = f.transform().decomposed();
+                (t[0], s[0])
+            })
+            .collect();
+        assert_eq!(placed, [(2.0, 3.0), (4.0, 3.0)]);
+
+        let child = root.children().next().unwrap();
+        let prim = child.mesh().unwrap().primitives().next().unwrap();
+
+        let pos = prim.get(&gltf::Semantic::Positions).unwrap();
+        assert_eq!(pos.count(), 3);
+        assert_eq!(pos.min().unwrap(), serde_json::json!([0.0, 0.0, -1.0]));
+        assert_eq!(pos.max().unwrap(), serde_json::json!([1.0, 2.0, 0.0]));
+        assert!(prim.get(&gltf::Semantic::Colors(0)).unwrap().normalized());
+        assert_eq!(prim.indices().unwrap().count(), 3);
+        assert_eq!(
+            prim.material().alpha_mode(),
+            gltf::material::AlphaMode::Blend
+        );
+
+        // Positions were packed without the colors between them
+        let view = pos.view().unwrap();
+        assert_eq!(view.length(), 36);
+        let bin = gltf.blob.as_ref().unwrap();
+        let start = view.offset() + 24;
+        assert_eq!(
+            f32::from_le_bytes(bin[start + 4..start + 8].try_into().unwrap()),
+            2.0
+        );
+    }
+}
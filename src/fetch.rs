@@ -0,0 +1,258 @@
+//! Download remote resources referenced by imported files
+
+use std::io::Read;
+use std::time::Duration;
+
+use anyhow::Result;
+use base64::Engine;
+
+use crate::import::ImportError;
+
+/// Limits applied when downloading remote content
+#[derive(Debug, Clone)]
+pub struct FetchLimits {
+    /// Give up on a request after this long
+    pub timeout: Duration,
+
+    /// Refuse any resource larger than this, in bytes
+    pub max_size: u64,
+}
+
+/// Determine if a URI points to something we would have to download
+pub fn is_remote(uri: &str) -> bool {
+    uri.starts_with("http://") || uri.starts_with("https://")
+}
+
+/// Determine if a URI carries its content inline
+pub fn is_data(uri: &str) -> bool {
+    uri.starts_with("data:")
+}
+
+/// Decode the content of a `data:` URI, either base64 or percent encoded
+pub fn decode_data(uri: &str) -> Result<Vec<u8>> {
+    let (header, body) = uri
+        .strip_prefix("data:")
+        .and_then(|f| f.split_once(','))
+        .ok_or_else(|| ImportError::UnableToOpenFile("Malformed data URI".into()))?;
+
+    if header.ends_with(";base64") {
+        return base64::engine::general_purpose::STANDARD
+            .decode(body.trim())
+            .map_err(|e| {
+                ImportError::UnableToOpenFile(format!("Bad base64 in data URI: {e}")).into()
+            });
+    }
+
+    let mut ret = Vec::with_capacity(body.len());
+    let mut bytes = body.bytes();
+
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            ret.push(b);
+            continue;
+        }
+
+        let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+
+        let value = std::str::from_utf8(&hex)
+            .ok()
+            .and_then(|f| u8::from_str_radix(f, 16).ok())
+            .ok_or_else(|| ImportError::UnableToOpenFile("Bad escape in data URI".into()))?;
+
+        ret.push(value);
+    }
+
+    Ok(ret)
+}
+
+/// Download a resource, respecting the given limits.
+pub fn fetch(url: &str, limits: &FetchLimits) -> Result<Vec<u8>> {
+    fetch_typed(url, limits, |_| true).map(|f| f.0)
+}
+
+/// Download a model a client pointed us at, refusing content types that
+/// can't be one, such as web pages. Returns the bytes and the content type,
+/// if the server gave one.
+pub fn fetch_model(url: &str, limits: &FetchLimits) -> Result<(Vec<u8>, Option<String>)> {
+    fetch_typed(url, limits, is_model_type)
+}
+
+/// Whether a content type could hold something we import. Generic binary
+/// and text types are allowed, as many servers use them for everything.
+pub fn is_model_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    mime.starts_with("model/")
+        || matches!(
+            mime.as_str(),
+            "application/octet-stream"
+                | "binary/octet-stream"
+                | "application/zip"
+                | "application/x-zip-compressed"
+                | "application/json"
+                | "application/vnd.usdz+zip"
+                | "application/vnd.ms-package.3dmanufacturing-3dmodel+xml"
+                | "image/png"
+                | "image/tiff"
+                | "text/plain"
+                | "text/csv"
+                | "text/tab-separated-values"
+        )
+}
+
+/// Extensions for model content types, for downloads whose URL doesn't
+/// name a file
+fn extension_for(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next()?.trim().to_ascii_lowercase();
+
+    Some(match mime.as_str() {
+        "model/gltf-binary" => "glb",
+        "model/gltf+json" => "gltf",
+        "model/obj" => "obj",
+        "model/stl" => "stl",
+        "model/3mf" => "3mf",
+        "model/vnd.usdz+zip" | "application/vnd.usdz+zip" => "usdz",
+        "application/zip" | "application/x-zip-compressed" => "zip",
+        _ => return None,
+    })
+}
+
+/// A safe file name for a download: the last segment of the URL path, with
+/// an extension from the content type if it has none
+pub fn file_name_for(url: &url::Url, content_type: Option<&str>) -> String {
+    let segment = url
+        .path_segments()
+        .and_then(|mut f| f.next_back())
+        .map(|f| {
+            f.chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>()
+        })
+        .filter(|f| !f.trim_start_matches('.').is_empty())
+        .unwrap_or_else(|| "download".into());
+
+    if std::path::Path::new(&segment).extension().is_some() {
+        return segment;
+    }
+
+    match content_type.and_then(extension_for) {
+        Some(ext) => format!("{segment}.{ext}"),
+        None => segment,
+    }
+}
+
+fn fetch_typed(
+    url: &str,
+    limits: &FetchLimits,
+    accept: fn(&str) -> bool,
+) -> Result<(Vec<u8>, Option<String>)> {
+    log::info!("Fetching remote resource: {url}");
+
+    let response = ureq::AgentBuilder::new()
+        .timeout(limits.timeout)
+        .build()
+        .get(url)
+        .call()
+        .map_err(|e| ImportError::UnableToOpenFile(format!("Unable to fetch {url}: {e}")))?;
+
+    let content_type = response.header("Content-Type").map(|f| f.to_string());
+
+    if let Some(t) = content_type.as_deref().filter(|f| !accept(f)) {
+        return Err(ImportError::UnableToOpenFile(format!(
+            "Remote resource {url} has unsupported content type {t}"
+        ))
+        .into());
+    }
+
+    // Check the advertised size first so we can bail before downloading
+    let advertised = response
+        .header("Content-Length")
+        .and_then(|f| f.parse::<u64>().ok());
+
+    if advertised.is_some_and(|f| f > limits.max_size) {
+        return Err(ImportError::UnableToOpenFile(format!(
+            "Remote resource {url} is larger than the limit of {} bytes",
+            limits.max_size
+        ))
+        .into());
+    }
+
+    // The server may lie, so we also cap the actual read
+    let mut bytes = Vec::with_capacity(advertised.unwrap_or_default() as usize);
+
+    response
+        .into_reader()
+        .take(limits.max_size + 1)
+        .read_to_end(&mut bytes)?;
+
+    if bytes.len() as u64 > limits.max_size {
+        return Err(ImportError::UnableToOpenFile(format!(
+            "Remote resource {url} is larger than the limit of {} bytes",
+            limits.max_size
+        ))
+        .into());
+    }
+
+    log::debug!("Fetched {} bytes from {url}", bytes.len());
+
+    Ok((bytes, content_type))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_data() {
+        assert_eq!(
+            decode_data("data:application/octet-stream;base64,AAEC/w==").unwrap(),
+            vec![0, 1, 2, 255]
+        );
+        assert_eq!(
+            decode_data("data:text/plain,a%20b%FF").unwrap(),
+            b"a b\xff".to_vec()
+        );
+        assert_eq!(decode_data("data:,").unwrap(), Vec::<u8>::new());
+
+        assert!(decode_data("data:;base64,!!!").is_err());
+        assert!(decode_data("data:text/plain,%G0").is_err());
+        assert!(decode_data("data:nothing").is_err());
+    }
+
+    #[test]
+    fn test_download_names() {
+        assert!(is_model_type("model/gltf-binary"));
+        assert!(is_model_type("application/octet-stream; charset=binary"));
+        assert!(!is_model_type("text/html; charset=utf-8"));
+        assert!(!is_model_type("application/javascript"));
+
+        let name = |url: &str, t| file_name_for(&url::Url::parse(url).unwrap(), t);
+
+        assert_eq!(name("https://example.com/a/duck.glb?x=1", None), "duck.glb");
+        assert_eq!(
+            name("https://example.com/my%20model.obj", None),
+            "my_20model.obj"
+        );
+        assert_eq!(
+            name("https://example.com/get/123", Some("model/gltf-binary")),
+            "123.glb"
+        );
+        assert_eq!(name("https://example.com/", None), "download");
+        assert_eq!(name("https://example.com/../..", None), "download");
+        assert_eq!(
+            name("https://example.com/", Some("application/zip")),
+            "download.zip"
+        );
+    }
+}
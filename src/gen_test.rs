@@ -0,0 +1,319 @@
+//! Generated test scenes.
+//!
+//! These are built in memory and published like any imported mesh, so
+//! clients and networks can be benchmarked without large files on hand.
+
+use std::f32::consts::PI;
+use std::path::Path;
+
+use anyhow::Result;
+use nalgebra::{Matrix4, Vector3};
+
+use crate::arguments::TestScene;
+use crate::colored_mesh::{self, ColoredMesh};
+use crate::import::ImportOptions;
+use crate::scene::{Bounds, Scene, SceneObject};
+use colabrodo_server::{server_http::*, server_messages::*, server_state::*};
+
+/// Distance between the centers of neighbouring spheres and stress entities
+const SPACING: f32 = 1.5;
+
+/// Build and publish a test scene
+pub fn generate(
+    kind: &TestScene,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let name = format!("gen-test-{}", kind.name());
+
+    let mut options = options.clone();
+    options.generate_normals = !matches!(kind, TestScene::Grid { .. });
+
+    let parts: Vec<_> = match *kind {
+        TestScene::Grid { size } => vec![(grid(size), Vector3::zeros())],
+        TestScene::Spheres { count, segments } => (0..count)
+            .map(|i| {
+                let mut mesh = ColoredMesh::default();
+                sphere(&mut mesh, 0.5, segments, hue(i));
+
+                let x = (i as f32 - (count as f32 - 1.0) / 2.0) * SPACING;
+                (mesh, Vector3::new(x, 0.5, 0.0))
+            })
+            .collect(),
+        TestScene::Stress { entities, tris } => {
+            let side = (entities as f32).cbrt().ceil().max(1.0) as u32;
+
+            (0..entities)
+                .map(|i| {
+                    let cell = Vector3::new(i % side, (i / side) % side, i / (side * side));
+                    (patch(tris, hue(i)), cell.cast::<f32>() * SPACING)
+                })
+                .collect()
+        }
+    };
+
+    log::info!("Generating {name} with {} entities", parts.len());
+
+    let scenes = parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, (mesh, offset))| {
+            let path = Path::new(&name).join(i.to_string());
+            let scene = colored_mesh::publish_mesh(
+                &mesh,
+                &path,
+                state.clone(),
+                asset_store.clone(),
+                &options,
+            )?;
+            Ok((scene, offset))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut scene = group(&name, scenes, &state, asset_store);
+
+    scene.info.format = Some("gen-test".into());
+    scene.publish_info();
+
+    Ok(scene)
+}
+
+/// Parent a set of single-entity scenes to a new root, each at an offset
+fn group(
+    name: &str,
+    scenes: Vec<(Scene, Vector3<f32>)>,
+    state: &ServerStatePtr,
+    asset_store: AssetStorePtr,
+) -> Scene {
+    let root = state
+        .lock()
+        .unwrap()
+        .entities
+        .new_component(ServerEntityState {
+            name: Some(name.into()),
+            mutable: ServerEntityStateUpdatable {
+                representation: Some(ServerEntityRepresentation::new_null()),
+                ..Default::default()
+            },
+        });
+
+    let mut children = Vec::new();
+    let mut published = Vec::new();
+    let mut materials = Vec::new();
+    let mut bounds: Option<Bounds> = None;
+    let mut triangles = 0;
+
+    for (mut scene, offset) in scenes {
+        let tf = Matrix4::new_translation(&offset);
+
+        // Take the parts; the emptied scene then unpublishes nothing on drop
+        let parts = std::mem::take(&mut scene.root.parts);
+        published.append(&mut scene.published);
+        materials.append(&mut scene.materials);
+
+        for part in &parts {
+            ServerEntityStateUpdatable {
+                parent: Some(root.clone()),
+                transform: Some(tf.as_slice().try_into().unwrap()),
+                ..Default::default()
+            }
+            .patch(part);
+        }
+
+        if let Some(b) = scene.info.bounds.map(|f| f.transformed(&tf)) {
+            bounds = Some(bounds.map_or(b, |f| f.union(&b)));
+        }
+
+        triangles += scene.info.triangles;
+
+        children.push(SceneObject {
+            parts,
+            children: vec![],
+        });
+    }
+
+    let mut ret = Scene::new(
+        SceneObject {
+            parts: vec![root],
+            children,
+        },
+        published,
+        Some(asset_store),
+    );
+
+    ret.materials = materials;
+    ret.info.bounds = bounds;
+    ret.info.triangles = triangles;
+
+    ret
+}
+
+/// A distinct, fairly saturated color for the `i`th entity
+fn hue(i: u32) -> [u8; 4] {
+    let h = (i as f32 * 0.618_034).fract() * 6.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    [r, g, b, 1.0].map(|c: f32| (55.0 + c * 200.0) as u8)
+}
+
+/// Push a vertex, with no color of its own
+fn vertex(mesh: &mut ColoredMesh, p: Vector3<f32>) -> u32 {
+    mesh.vertices.push(p.into());
+    mesh.vertex_colors.push(None);
+    mesh.vertices.len() as u32 - 1
+}
+
+/// A checkerboard in the XZ plane, one unit per cell, facing up
+fn grid(size: u32) -> ColoredMesh {
+    let mut mesh = ColoredMesh::default();
+    let half = size as f32 / 2.0;
+
+    for z in 0..=size {
+        for x in 0..=size {
+            vertex(
+                &mut mesh,
+                Vector3::new(x as f32 - half, 0.0, z as f32 - half),
+            );
+        }
+    }
+
+    let at = |x: u32, z: u32| z * (size + 1) + x;
+
+    for z in 0..size {
+        for x in 0..size {
+            let c = if (x + z) % 2 == 0 { 200 } else { 90 };
+            mesh.faces.push((
+                vec![at(x, z), at(x, z + 1), at(x + 1, z + 1), at(x + 1, z)],
+                Some([c, c, c, 255]),
+            ));
+        }
+    }
+
+    mesh
+}
+
+/// A UV sphere about the origin
+fn sphere(mesh: &mut ColoredMesh, radius: f32, segments: u32, color: [u8; 4]) {
+    let segments = segments.max(3);
+    let rings = (segments / 2).max(2);
+
+    let top = vertex(mesh, Vector3::new(0.0, radius, 0.0));
+    let first = top + 1;
+
+    for ring in 1..rings {
+        let phi = PI * ring as f32 / rings as f32;
+        for seg in 0..segments {
+            let theta = 2.0 * PI * seg as f32 / segments as f32;
+            let dir = Vector3::new(phi.sin() * theta.cos(), phi.cos(), phi.sin() * theta.sin());
+            vertex(mesh, dir * radius);
+        }
+    }
+
+    let bottom = vertex(mesh, Vector3::new(0.0, -radius, 0.0));
+
+    let at = |ring: u32, seg: u32| first + (ring - 1) * segments + seg % segments;
+    let color = Some(color);
+
+    for seg in 0..segments {
+        mesh.faces
+            .push((vec![top, at(1, seg + 1), at(1, seg)], color));
+
+        for ring in 1..rings - 1 {
+            mesh.faces.push((
+                vec![
+                    at(ring, seg),
+                    at(ring, seg + 1),
+                    at(ring + 1, seg + 1),
+                    at(ring + 1, seg),
+                ],
+                color,
+            ));
+        }
+
+        mesh.faces.push((
+            vec![bottom, at(rings - 1, seg), at(rings - 1, seg + 1)],
+            color,
+        ));
+    }
+}
+
+/// A gently rippled unit square with exactly `tris` triangles
+fn patch(tris: u32, color: [u8; 4]) -> ColoredMesh {
+    let mut mesh = ColoredMesh::default();
+
+    let quads = tris.div_ceil(2).max(1);
+    let w = (quads as f32).sqrt().ceil() as u32;
+    let h = quads.div_ceil(w);
+
+    for z in 0..=h {
+        for x in 0..=w {
+            let (u, v) = (x as f32 / w as f32, z as f32 / h as f32);
+            let y = 0.05 * (u * 2.0 * PI).sin() * (v * 2.0 * PI).cos();
+            vertex(&mut mesh, Vector3::new(u - 0.5, y, v - 0.5));
+        }
+    }
+
+    let at = |x: u32, z: u32| z * (w + 1) + x;
+    let color = Some(color);
+
+    'outer: for z in 0..h {
+        for x in 0..w {
+            for f in [
+                vec![at(x, z), at(x, z + 1), at(x + 1, z + 1)],
+                vec![at(x, z), at(x + 1, z + 1), at(x + 1, z)],
+            ] {
+                if mesh.faces.len() as u32 == tris {
+                    break 'outer;
+                }
+                mesh.faces.push((f, color));
+            }
+        }
+    }
+
+    mesh
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Normal of a face, from its first three corners
+    fn normal(mesh: &ColoredMesh, face: &[u32]) -> Vector3<f32> {
+        let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(mesh.vertices[face[i] as usize]));
+        (b - a).cross(&(c - a))
+    }
+
+    #[test]
+    fn test_shapes() {
+        let g = grid(4);
+        assert_eq!(g.vertices.len(), 25);
+        assert_eq!(g.faces.len(), 16);
+        assert!(g.faces.iter().all(|f| normal(&g, &f.0).y > 0.0));
+
+        // Every face of the sphere points away from its center
+        let mut s = ColoredMesh::default();
+        sphere(&mut s, 1.0, 8, [255; 4]);
+        assert_eq!(s.vertices.len(), 2 + 3 * 8);
+        assert_eq!(s.vertex_colors.len(), s.vertices.len());
+
+        for (face, _) in &s.faces {
+            let center: Vector3<f32> = face
+                .iter()
+                .map(|i| Vector3::from(s.vertices[*i as usize]))
+                .sum();
+            assert!(normal(&s, face).dot(&center) > 0.0, "{face:?}");
+        }
+
+        for tris in [1, 7, 1000] {
+            assert_eq!(patch(tris, [255; 4]).faces.len() as u32, tris);
+        }
+    }
+}
@@ -0,0 +1,548 @@
+//! Packing triangle meshes into buffers for publishing.
+//!
+//! Indices are stored in the narrowest format that can address every vertex.
+//! Most meshes in a scene are small, so this saves half or more of the index
+//! data over always using 32 bits. glTF buffers are republished as authored,
+//! so they keep whatever widths the exporter chose.
+//!
+//! Before packing, zero-area and repeated triangles are dropped. Scans and
+//! CAD exports are full of them, and they upset normal generation in some
+//! clients. Vertices may also be welded, for exports that give every face
+//! its own corners. NaN and infinite positions are always scrubbed, as they
+//! break bounds and rendering in clients.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::AddAssign;
+use std::path::Path;
+
+use colabrodo_common::{components::*, types::Format};
+use colabrodo_server::{
+    server_bufferbuilder::*, server_http::AssetStorePtr, server_messages::*, server_state::*,
+};
+use nalgebra::Vector3;
+
+use crate::import::{self, BufferData, ImportOptions};
+
+/// What [`prepare_mesh`] removed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cleanup {
+    /// Triangles with no area
+    pub degenerate: u64,
+    /// Triangles covering the same corners, with the same winding, as an
+    /// earlier one
+    pub duplicate: u64,
+    /// Vertices merged into another by welding
+    pub welded: u64,
+    /// Vertices or points with NaN or infinite positions, dropped along
+    /// with their triangles
+    pub nonfinite: u64,
+}
+
+impl Cleanup {
+    pub fn is_empty(&self) -> bool {
+        *self == Cleanup::default()
+    }
+}
+
+impl AddAssign for Cleanup {
+    fn add_assign(&mut self, rhs: Self) {
+        self.degenerate += rhs.degenerate;
+        self.duplicate += rhs.duplicate;
+        self.welded += rhs.welded;
+        self.nonfinite += rhs.nonfinite;
+    }
+}
+
+impl std::fmt::Display for Cleanup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts = [
+            (self.nonfinite, "non-finite vertices scrubbed"),
+            (self.degenerate, "degenerate triangles removed"),
+            (self.duplicate, "duplicate triangles removed"),
+            (self.welded, "vertices welded"),
+        ];
+
+        let parts: Vec<_> = parts
+            .iter()
+            .filter(|(n, _)| *n > 0)
+            .map(|(n, what)| format!("{n} {what}"))
+            .collect();
+
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+/// Drop vertices with NaN or infinite positions, along with their
+/// triangles, and zero bad normals. Returns the number of vertices dropped.
+pub fn scrub(vertex: &mut Vec<VertexTexture>, faces: &mut Vec<[u32; 3]>) -> u64 {
+    let finite = |v: &[f32; 3]| v.iter().all(|f| f.is_finite());
+
+    for v in vertex.iter_mut().filter(|v| !finite(&v.normal)) {
+        v.normal = [0.0; 3];
+    }
+
+    if vertex.iter().all(|v| finite(&v.position)) {
+        return 0;
+    }
+
+    // New index of each vertex, if it survives
+    let mut next = 0;
+    let remap: Vec<Option<u32>> = vertex
+        .iter()
+        .map(|v| {
+            finite(&v.position).then(|| {
+                next += 1;
+                next - 1
+            })
+        })
+        .collect();
+
+    let before = vertex.len();
+    vertex.retain(|v| finite(&v.position));
+
+    faces.retain_mut(|face| match face.map(|i| remap[i as usize]) {
+        [Some(a), Some(b), Some(c)] => {
+            *face = [a, b, c];
+            true
+        }
+        _ => false,
+    });
+
+    (before - vertex.len()) as u64
+}
+
+/// How close vertex attributes must be for welding to merge them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeldOptions {
+    pub position: f32,
+    pub normal: f32,
+    /// In texture coordinates, where the image spans 0 to 1
+    pub texture: f32,
+}
+
+/// Snap a value to a grid of `epsilon` spacing. Without a spacing, values
+/// must match exactly.
+fn quantize(v: f32, epsilon: f32) -> i64 {
+    if epsilon > 0.0 {
+        (v / epsilon).round() as i64
+    } else {
+        v.to_bits() as i64
+    }
+}
+
+/// Merge vertices whose attributes land in the same grid cells, and point
+/// faces at the survivors. Returns the number of vertices removed.
+///
+/// Values just either side of a cell boundary stay apart, so this can miss
+/// some merges a true distance check would make. It never merges vertices
+/// further apart than one cell.
+pub fn weld(vertex: &mut Vec<VertexTexture>, faces: &mut [[u32; 3]], options: &WeldOptions) -> u64 {
+    let mut cells = HashMap::<[i64; 8], u32>::new();
+    let mut welded = Vec::with_capacity(vertex.len());
+
+    let remap: Vec<u32> = vertex
+        .iter()
+        .map(|v| {
+            let p = v.position.map(|f| quantize(f, options.position));
+            let n = v.normal.map(|f| quantize(f, options.normal));
+            let t = v
+                .texture
+                .map(|f| quantize(f as f32 / u16::MAX as f32, options.texture));
+
+            let key = [p[0], p[1], p[2], n[0], n[1], n[2], t[0], t[1]];
+
+            *cells.entry(key).or_insert_with(|| {
+                welded.push(*v);
+                welded.len() as u32 - 1
+            })
+        })
+        .collect();
+
+    for face in faces.iter_mut() {
+        *face = face.map(|i| remap[i as usize]);
+    }
+
+    let removed = (vertex.len() - welded.len()) as u64;
+
+    *vertex = welded;
+
+    removed
+}
+
+/// Remove degenerate and duplicate triangles in place.
+///
+/// Duplicates are found by position, so unshared vertices do not hide them.
+/// A copy with the opposite winding is a back face, and is kept.
+pub fn clean_faces(faces: &mut Vec<[u32; 3]>, position: impl Fn(u32) -> [f32; 3]) -> Cleanup {
+    let mut ret = Cleanup::default();
+    let mut seen = HashSet::<[[u32; 3]; 3]>::new();
+
+    faces.retain(|face| {
+        let [a, b, c] = face.map(|i| Vector3::from(position(i)));
+        let (ab, ac) = (b - a, c - a);
+
+        // Compare against the edge lengths, so tiny triangles are not
+        // mistaken for slivers
+        let limit = f32::EPSILON * ab.norm() * ac.norm();
+
+        if ab.cross(&ac).norm() <= limit {
+            ret.degenerate += 1;
+            return false;
+        }
+
+        let mut key = face.map(|i| position(i).map(f32::to_bits));
+        let first = (0..3).min_by_key(|i| key[*i]).unwrap();
+        key.rotate_left(first);
+
+        if !seen.insert(key) {
+            ret.duplicate += 1;
+            return false;
+        }
+
+        true
+    });
+
+    ret
+}
+
+/// Weld and clean a textured mesh, as asked for in the options
+pub fn prepare_mesh(
+    vertex: &mut Vec<VertexTexture>,
+    faces: &mut Vec<[u32; 3]>,
+    options: &ImportOptions,
+) -> Cleanup {
+    let mut ret = Cleanup {
+        nonfinite: scrub(vertex, faces),
+        ..Default::default()
+    };
+
+    // Welding can collapse slivers, so it goes before cleaning
+    if let Some(weld_options) = &options.weld {
+        ret.welded = weld(vertex, faces, weld_options);
+    }
+
+    if !options.keep_degenerate {
+        ret += clean_faces(faces, |i| vertex[i as usize].position);
+    }
+
+    ret
+}
+
+/// Narrowest index format that can address `vertex_count` vertices
+pub fn index_format(vertex_count: usize) -> Format {
+    if vertex_count <= u8::MAX as usize + 1 {
+        Format::U8
+    } else if vertex_count <= u16::MAX as usize + 1 {
+        Format::U16
+    } else {
+        Format::U32
+    }
+}
+
+/// Append indices to `out` in an index format from [`index_format`]
+pub fn pack_indices(indices: impl IntoIterator<Item = u32>, format: Format, out: &mut Vec<u8>) {
+    for i in indices {
+        match format {
+            Format::U8 => out.push(i as u8),
+            Format::U16 => out.extend_from_slice(&(i as u16).to_le_bytes()),
+            _ => out.extend_from_slice(&i.to_le_bytes()),
+        }
+    }
+}
+
+/// Size of a packed vertex: position, normal, then texture coordinates
+const VERTEX_STRIDE: usize = 28;
+
+/// A textured triangle mesh
+pub struct TriangleMesh<'a> {
+    pub name: Option<String>,
+    pub vertex: &'a [VertexTexture],
+    pub faces: &'a [[u32; 3]],
+}
+
+impl TriangleMesh<'_> {
+    fn format(&self) -> Format {
+        index_format(self.vertex.len())
+    }
+
+    /// Pack vertices, then indices, into one buffer
+    pub fn pack_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(self.pack_size() as usize);
+
+        for v in self.vertex {
+            v.position
+                .iter()
+                .chain(v.normal.iter())
+                .for_each(|f| ret.extend_from_slice(&f.to_le_bytes()));
+            v.texture
+                .iter()
+                .for_each(|f| ret.extend_from_slice(&f.to_le_bytes()));
+        }
+
+        pack_indices(
+            self.faces.iter().flatten().copied(),
+            self.format(),
+            &mut ret,
+        );
+
+        ret
+    }
+
+    /// Pack this mesh and publish its buffer. This can run before the
+    /// server state is locked; the geometry is built from the result.
+    pub fn publish(
+        &self,
+        asset_store: &AssetStorePtr,
+        source: &Path,
+        options: &ImportOptions,
+        published: &mut Vec<uuid::Uuid>,
+    ) -> PackedMesh {
+        let bytes = self.pack_bytes();
+
+        PackedMesh {
+            name: self.name.clone(),
+            data: import::publish_buffer(asset_store, source, &bytes, options, published),
+            vertex_count: self.vertex.len() as u64,
+            index_count: (self.faces.len() * 3) as u32,
+            format: self.format(),
+        }
+    }
+
+    fn pack_size(&self) -> u64 {
+        let index_size = match self.format() {
+            Format::U8 => 1,
+            Format::U16 => 2,
+            _ => 4,
+        };
+
+        (self.vertex.len() * VERTEX_STRIDE + self.faces.len() * 3 * index_size) as u64
+    }
+}
+
+/// A [`TriangleMesh`] packed into a published buffer
+#[derive(Debug, Clone)]
+pub struct PackedMesh {
+    pub name: Option<String>,
+    pub data: BufferData,
+    vertex_count: u64,
+    index_count: u32,
+    format: Format,
+}
+
+impl PackedMesh {
+    /// Create the buffer and a geometry reading from it
+    pub fn build_geometry(
+        &self,
+        state: &mut ServerState,
+        material: MaterialReference,
+    ) -> GeometryReference {
+        let vertex_size = self.vertex_count * VERTEX_STRIDE as u64;
+
+        let buffer = self.data.create(state);
+
+        let view = state.buffer_views.new_component(ServerBufferViewState {
+            name: None,
+            source_buffer: buffer,
+            view_type: BufferViewType::Geometry,
+            offset: 0,
+            length: self.data.size(),
+        });
+
+        let attribute = |semantic, offset: u32, format, normalized| ServerGeometryAttribute {
+            view: view.clone(),
+            semantic,
+            channel: None,
+            offset: Some(offset),
+            stride: Some(VERTEX_STRIDE as u32),
+            format,
+            normalized: Some(normalized),
+            minimum_value: None,
+            maximum_value: None,
+        };
+
+        state.geometries.new_component(ServerGeometryState {
+            name: self.name.clone(),
+            patches: vec![ServerGeometryPatch {
+                attributes: vec![
+                    attribute(AttributeSemantic::Position, 0, Format::VEC3, false),
+                    attribute(AttributeSemantic::Normal, 12, Format::VEC3, false),
+                    attribute(AttributeSemantic::Texture, 24, Format::U16VEC2, true),
+                ],
+                vertex_count: self.vertex_count,
+                indices: Some(ServerGeometryIndex {
+                    view: view.clone(),
+                    count: self.index_count,
+                    offset: Some(vertex_size as u32),
+                    stride: None,
+                    format: self.format,
+                }),
+                patch_type: PrimitiveType::Triangles,
+                material,
+            }],
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_index_format() {
+        assert_eq!(index_format(3), Format::U8);
+        assert_eq!(index_format(256), Format::U8);
+        assert_eq!(index_format(257), Format::U16);
+        assert_eq!(index_format(65536), Format::U16);
+        assert_eq!(index_format(65537), Format::U32);
+
+        let mut out = Vec::new();
+        pack_indices([1, 258], Format::U16, &mut out);
+        assert_eq!(out, vec![1, 0, 2, 1]);
+    }
+
+    #[test]
+    fn test_pack_triangle() {
+        let vertex = [VertexTexture {
+            position: [1.0, 2.0, 3.0],
+            normal: [0.0, 0.0, 1.0],
+            texture: [0, u16::MAX],
+        }; 3];
+
+        let mesh = TriangleMesh {
+            name: None,
+            vertex: &vertex,
+            faces: &[[0, 1, 2]],
+        };
+
+        let bytes = mesh.pack_bytes();
+
+        assert_eq!(bytes.len() as u64, mesh.pack_size());
+        assert_eq!(bytes.len(), 3 * VERTEX_STRIDE + 3);
+        assert_eq!(&bytes[24..28], &[0, 0, 255, 255]);
+        assert_eq!(&bytes[bytes.len() - 3..], &[0, 1, 2]);
+    }
+
+    #[test]
+    fn test_clean_faces() {
+        let position = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [2.0, 0.0, 0.0],
+            // Same place as the first vertex
+            [0.0, 0.0, 0.0],
+        ];
+
+        let mut faces = vec![
+            [0, 1, 2],
+            // Repeated index
+            [0, 0, 1],
+            // Collinear
+            [0, 1, 3],
+            // The first, rotated and through another vertex
+            [1, 2, 4],
+            // The first, flipped
+            [0, 2, 1],
+        ];
+
+        let cleanup = clean_faces(&mut faces, |i| position[i as usize]);
+
+        assert_eq!(faces, vec![[0, 1, 2], [0, 2, 1]]);
+        assert_eq!(
+            cleanup,
+            Cleanup {
+                degenerate: 2,
+                duplicate: 1,
+                ..Default::default()
+            }
+        );
+
+        // Small, but not degenerate
+        let small = [[0.0, 0.0, 0.0], [1e-6, 0.0, 0.0], [0.0, 1e-6, 0.0]];
+        let mut faces = vec![[0, 1, 2]];
+        assert!(clean_faces(&mut faces, |i| small[i as usize]).is_empty());
+    }
+
+    #[test]
+    fn test_scrub() {
+        let corner = |position| VertexTexture {
+            position,
+            normal: [0.0, 0.0, 1.0],
+            texture: [0, 0],
+        };
+
+        let mut vertex = vec![
+            corner([0.0, 0.0, 0.0]),
+            corner([1.0, 0.0, 0.0]),
+            corner([0.0, 1.0, 0.0]),
+            corner([f32::NAN, 1.0, 0.0]),
+        ];
+        vertex[1].normal = [f32::INFINITY, 0.0, 0.0];
+
+        let mut faces = vec![[0, 1, 2], [1, 2, 3]];
+
+        assert_eq!(scrub(&mut vertex, &mut faces), 1);
+        assert_eq!(faces, vec![[0, 1, 2]]);
+        assert_eq!(vertex.len(), 3);
+        assert_eq!(vertex[1].normal, [0.0; 3]);
+
+        // Later vertices move down to fill the gap
+        let mut vertex = vec![
+            corner([f32::NEG_INFINITY, 0.0, 0.0]),
+            corner([0.0, 0.0, 0.0]),
+            corner([1.0, 0.0, 0.0]),
+            corner([0.0, 1.0, 0.0]),
+        ];
+        let mut faces = vec![[1, 2, 3], [0, 1, 2]];
+
+        assert_eq!(scrub(&mut vertex, &mut faces), 1);
+        assert_eq!(faces, vec![[0, 1, 2]]);
+
+        let cleanup = Cleanup {
+            nonfinite: 1,
+            duplicate: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            cleanup.to_string(),
+            "1 non-finite vertices scrubbed, 2 duplicate triangles removed"
+        );
+    }
+
+    #[test]
+    fn test_weld() {
+        let corner = |x: f32, y: f32| VertexTexture {
+            position: [x, y, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            texture: [0, 0],
+        };
+
+        // Two triangles of a quad, each with its own corners
+        let mut vertex = vec![
+            corner(0.0, 0.0),
+            corner(1.0, 0.0),
+            corner(1.0, 1.0),
+            corner(0.0, 0.0),
+            corner(1.0, 1.0 + 1e-7),
+            corner(0.0, 1.0),
+        ];
+        let mut faces = [[0, 1, 2], [3, 4, 5]];
+
+        let options = WeldOptions {
+            position: 1e-5,
+            normal: 1e-3,
+            texture: 1e-4,
+        };
+
+        assert_eq!(weld(&mut vertex, &mut faces, &options), 2);
+        assert_eq!(vertex.len(), 4);
+        assert_eq!(faces, [[0, 1, 2], [0, 2, 3]]);
+
+        // Different texture coordinates keep a seam
+        let mut vertex = vec![corner(0.0, 0.0), corner(0.0, 0.0)];
+        vertex[1].texture = [u16::MAX, 0];
+        let mut faces = [[0, 1, 1]];
+
+        assert_eq!(weld(&mut vertex, &mut faces, &options), 0);
+    }
+}
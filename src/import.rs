@@ -1,16 +1,44 @@
-use std::{fmt::Display, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
 
 use anyhow::Result;
+use clap::ValueEnum;
+use nalgebra::Matrix4;
+use serde::Deserialize;
 
-use colabrodo_server::{server_http::AssetStorePtr, server_state::ServerStatePtr};
+use colabrodo_server::{
+    server_http::{self, create_asset_id, remove_asset, Asset, AssetStorePtr},
+    server_messages::{BufferReference, BufferState},
+    server_state::{ServerState, ServerStatePtr},
+};
 
-use crate::scene::Scene;
+use crate::capabilities::Capabilities;
+use crate::color::ColorSpace;
+use crate::colormap::Colormap;
+use crate::fetch::FetchLimits;
+use crate::geometry::WeldOptions;
+use crate::import_csv::TableOptions;
+use crate::import_heightmap::HeightmapOptions;
+use crate::import_xyz::PointColumn;
+use crate::scene::{Bounds, Scene};
+use crate::scratch::{ScratchDir, ScratchSpace};
 
 #[derive(Debug)]
 pub enum ImportError {
     UnableToOpenFile(String),
     UnknownFileFormat(String),
     UnableToImport(String),
+    Cancelled(String),
 }
 
 impl Display for ImportError {
@@ -21,26 +49,1521 @@ impl Display for ImportError {
 
 impl std::error::Error for ImportError {}
 
+/// Options that control how files are imported
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// If set, resources referenced by remote URIs are downloaded and
+    /// republished through our own asset store.
+    pub fetch_remote: Option<FetchLimits>,
+
+    /// If set, importers must produce stable ordering, and asset ids are
+    /// derived from the source path and content instead of being random.
+    pub deterministic: bool,
+
+    /// If set, meshes without normals get smooth normals computed for them
+    pub generate_normals: bool,
+
+    /// If set, zero-area and duplicate triangles are published as found
+    pub keep_degenerate: bool,
+
+    /// If set, nearly identical vertices are merged
+    pub weld: Option<WeldOptions>,
+
+    /// A mesh placed by at least this many nodes is published once, and
+    /// drawn with instances. Zero turns this off.
+    pub instance_threshold: usize,
+
+    /// If set, textures are scaled down so neither side is longer than this
+    /// many pixels
+    pub max_texture_size: Option<u32>,
+
+    /// Where importers may put temporary files
+    pub scratch: Option<ScratchSpace>,
+
+    /// Buffers smaller than this many bytes are sent inline. Larger assets
+    /// are served from scratch space rather than memory.
+    pub size_large_limit: u64,
+
+    /// Column layout for text point clouds. If unset, it is guessed from
+    /// the number of columns.
+    pub point_columns: Option<Vec<PointColumn>>,
+
+    /// If set, material colors are multiplied by this. Textures are kept,
+    /// as they are multiplied by the base color too.
+    pub tint: Option<[f32; 3]>,
+
+    /// How grayscale images are turned into terrain
+    pub heightmap: HeightmapOptions,
+
+    /// Colors for scalar data
+    pub colormap: Colormap,
+
+    /// How table rows are turned into points or glyphs
+    pub table: TableOptions,
+
+    /// Optional component types importers may produce
+    pub capabilities: Capabilities,
+
+    /// Set while importing a file extracted from this archive
+    pub archive: Option<PathBuf>,
+
+    /// Which way is up in the source content
+    pub up_axis: UpAxis,
+
+    /// Units of the source content. If set, content is converted to meters.
+    pub units: Option<Units>,
+
+    /// Color space of vertex and point colors in the source content
+    pub vertex_colors: ColorSpace,
+
+    /// Move the center of each scene's bounds to the origin
+    pub center: bool,
+
+    /// Scale each scene so its largest side is this long
+    pub fit: Option<f32>,
+
+    /// Sizes of published assets, filled in as ids are handed out
+    pub asset_sizes: AssetSizes,
+
+    /// Most bytes of assets to publish, over all scenes
+    pub asset_limit: Option<u64>,
+
+    /// If set and cancelled, the import is abandoned and anything it
+    /// published is removed
+    pub cancel: Option<CancelToken>,
+
+    /// Assets handed to this import. Each import gets its own, so one that
+    /// fails removes only what it published, and sweeps leave its assets
+    /// alone until its scene owns them.
+    pub claim: Option<AssetClaim>,
+}
+
+/// Flag to abandon imports that are no longer wanted, such as those for a
+/// tag that has since been cleared
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Assets handed to one import while it runs. Claimed assets count as owned,
+/// so they are not swept before the import's scene is added. The claim is
+/// released once the last clone is dropped.
+#[derive(Debug, Clone)]
+pub struct AssetClaim(Arc<ClaimList>);
+
+#[derive(Debug)]
+struct ClaimList {
+    sizes: AssetSizes,
+
+    /// Held assets, and whether this import recorded them
+    held: Mutex<HashMap<uuid::Uuid, bool>>,
+}
+
+impl AssetClaim {
+    /// Let go of every held asset
+    pub fn release(&self) {
+        let held = std::mem::take(&mut *self.0.held.lock().unwrap());
+        self.0.sizes.0.lock().unwrap().release(held.keys());
+    }
+}
+
+impl Drop for ClaimList {
+    fn drop(&mut self) {
+        let held = std::mem::take(self.held.get_mut().unwrap());
+        self.sizes.0.lock().unwrap().release(held.keys());
+    }
+}
+
+/// What a published asset holds, in the order clients should fetch them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AssetKind {
+    /// Vertex, index, and instance buffers; nothing shows without these
+    Geometry,
+    Image,
+}
+
+/// Size in bytes of each published asset, the color space of images, and
+/// where assets are served from, shared between importers and the scene list.
+/// Served assets are also indexed by content, so identical content is
+/// published once.
+#[derive(Debug, Clone, Default)]
+pub struct AssetSizes(Arc<Mutex<AssetTable>>);
+
+#[derive(Debug, Default)]
+struct AssetTable {
+    entries: HashMap<uuid::Uuid, AssetEntry>,
+
+    /// Served assets by a hash of their content
+    by_content: HashMap<uuid::Uuid, uuid::Uuid>,
+
+    /// Number of running imports holding each asset
+    claimed: HashMap<uuid::Uuid, usize>,
+}
+
+#[derive(Debug, Clone)]
+struct AssetEntry {
+    size: u64,
+    color_space: Option<ColorSpace>,
+    served: Option<(url::Url, AssetKind)>,
+    content: Option<uuid::Uuid>,
+
+    /// Set once the asset has been found by its content, so other imports
+    /// may be using it
+    shared: bool,
+
+    /// Scratch space holding the asset, if it is served from disk. Removed
+    /// when the asset is forgotten.
+    spool: Option<Arc<ScratchDir>>,
+}
+
+/// Hash of asset content, for finding identical assets
+fn content_hash(bytes: &[u8]) -> uuid::Uuid {
+    uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_OID, bytes)
+}
+
+/// Start hashing a version 5 UUID in a namespace, for content that arrives
+/// in pieces
+fn v5_hasher(namespace: &uuid::Uuid) -> sha1_smol::Sha1 {
+    let mut ret = sha1_smol::Sha1::new();
+    ret.update(namespace.as_bytes());
+    ret
+}
+
+/// The UUID hashed so far, the same as [`uuid::Uuid::new_v5`] gives for
+/// all of the content at once
+fn v5_uuid(hasher: &sha1_smol::Sha1) -> uuid::Uuid {
+    let digest = hasher.digest().bytes();
+    uuid::Builder::from_sha1_bytes(digest[..16].try_into().unwrap()).into_uuid()
+}
+
+impl AssetSizes {
+    /// Start a claim for a new import
+    pub fn claim(&self) -> AssetClaim {
+        AssetClaim(Arc::new(ClaimList {
+            sizes: self.clone(),
+            held: Default::default(),
+        }))
+    }
+
+    /// Record a new asset and the hash of its content, so it can be shared
+    /// once served
+    fn record(
+        &self,
+        asset: uuid::Uuid,
+        bytes: u64,
+        content: uuid::Uuid,
+        claim: Option<&AssetClaim>,
+    ) {
+        let mut table = self.0.lock().unwrap();
+
+        table.entries.insert(
+            asset,
+            AssetEntry {
+                size: bytes,
+                color_space: None,
+                served: None,
+                content: Some(content),
+                shared: false,
+                spool: None,
+            },
+        );
+
+        if let Some(claim) = claim {
+            table.hold(claim, asset, true);
+        }
+    }
+
+    /// A served asset with this content hash, if there is one. It is held
+    /// by the claim before anything can remove it.
+    fn find_content(&self, content: &uuid::Uuid, claim: Option<&AssetClaim>) -> Option<uuid::Uuid> {
+        let mut table = self.0.lock().unwrap();
+
+        let id = *table.by_content.get(content)?;
+
+        if let Some(entry) = table.entries.get_mut(&id) {
+            entry.shared = true;
+        }
+
+        if let Some(claim) = claim {
+            table.hold(claim, id, false);
+        }
+
+        Some(id)
+    }
+
+    /// Note where an asset is served from, and what it holds
+    pub fn note_served(&self, asset: &uuid::Uuid, url: url::Url, kind: AssetKind) {
+        let mut table = self.0.lock().unwrap();
+
+        let Some(entry) = table.entries.get_mut(asset) else {
+            return;
+        };
+
+        entry.served = Some((url, kind));
+
+        if let Some(content) = entry.content {
+            table.by_content.insert(content, *asset);
+        }
+    }
+
+    /// Keep the scratch space an asset is served from until it is forgotten
+    fn keep_spool(&self, asset: &uuid::Uuid, dir: ScratchDir) {
+        if let Some(entry) = self.0.lock().unwrap().entries.get_mut(asset) {
+            entry.spool = Some(Arc::new(dir));
+        }
+    }
+
+    /// URL of an asset, if it is already served
+    fn served_url(&self, asset: &uuid::Uuid) -> Option<url::Url> {
+        let table = self.0.lock().unwrap();
+        Some(table.entries.get(asset)?.served.as_ref()?.0.clone())
+    }
+
+    /// URLs of served assets, in the order a client should fetch them:
+    /// geometry before images, then smallest first so something shows as
+    /// soon as possible.
+    pub fn prefetch_order<'a>(
+        &self,
+        assets: impl IntoIterator<Item = &'a uuid::Uuid>,
+    ) -> Vec<url::Url> {
+        let table = self.0.lock().unwrap();
+
+        let mut ret: Vec<_> = assets
+            .into_iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .filter_map(|f| {
+                let entry = table.entries.get(f)?;
+                let (url, kind) = entry.served.clone()?;
+                Some(((kind, entry.size, url.clone()), url))
+            })
+            .collect();
+
+        ret.sort_by(|a, b| a.0.cmp(&b.0));
+
+        ret.into_iter().map(|f| f.1).collect()
+    }
+
+    pub fn get(&self, asset: &uuid::Uuid) -> Option<u64> {
+        self.0.lock().unwrap().entries.get(asset).map(|f| f.size)
+    }
+
+    /// Note the color space of an image asset
+    pub fn tag_color_space(&self, asset: &uuid::Uuid, space: ColorSpace) {
+        if let Some(entry) = self.0.lock().unwrap().entries.get_mut(asset) {
+            entry.color_space = Some(space);
+        }
+    }
+
+    /// Color space of an image asset. None for other assets.
+    pub fn color_space(&self, asset: &uuid::Uuid) -> Option<ColorSpace> {
+        self.0.lock().unwrap().entries.get(asset)?.color_space
+    }
+
+    /// Total size of all published assets
+    pub fn total(&self) -> u64 {
+        self.0
+            .lock()
+            .unwrap()
+            .entries
+            .values()
+            .map(|f| f.size)
+            .sum()
+    }
+
+    /// Release a failed import's claim, and forget the assets it recorded.
+    /// Those since found by their content, or held by other imports, are
+    /// kept, as they may be in use. Returns the assets forgotten.
+    fn abandon(&self, claim: &AssetClaim) -> Vec<uuid::Uuid> {
+        let held = std::mem::take(&mut *claim.0.held.lock().unwrap());
+
+        let mut table = self.0.lock().unwrap();
+
+        table.release(held.keys());
+
+        let mut ret: Vec<_> = held
+            .into_iter()
+            .filter(|(id, recorded)| {
+                *recorded
+                    && !table.claimed.contains_key(id)
+                    && table.entries.get(id).is_some_and(|e| !e.shared)
+            })
+            .map(|f| f.0)
+            .collect();
+
+        ret.sort();
+
+        for asset in &ret {
+            table.remove(asset);
+        }
+
+        ret
+    }
+
+    /// Is a running import holding this asset?
+    pub fn is_claimed(&self, asset: &uuid::Uuid) -> bool {
+        self.0.lock().unwrap().claimed.contains_key(asset)
+    }
+
+    /// Ids and sizes of recorded assets not in `owned`, nor held by a
+    /// running import, smallest id first
+    pub fn unowned(&self, owned: &HashSet<uuid::Uuid>) -> Vec<(uuid::Uuid, u64)> {
+        let table = self.0.lock().unwrap();
+
+        let mut ret: Vec<_> = table
+            .entries
+            .iter()
+            .filter(|(id, _)| !owned.contains(id) && !table.claimed.contains_key(id))
+            .map(|(id, entry)| (*id, entry.size))
+            .collect();
+
+        ret.sort();
+
+        ret
+    }
+
+    /// Drop the sizes of assets that are no longer published
+    pub fn forget<'a>(&self, assets: impl IntoIterator<Item = &'a uuid::Uuid>) {
+        let mut table = self.0.lock().unwrap();
+
+        for asset in assets {
+            table.remove(asset);
+        }
+    }
+
+    /// Drop an asset unless a running import holds it. Returns whether it
+    /// was dropped. Checked under one lock, so an import can't take the
+    /// asset between the check and the removal.
+    pub fn forget_unclaimed(&self, asset: &uuid::Uuid) -> bool {
+        let mut table = self.0.lock().unwrap();
+
+        if table.claimed.contains_key(asset) {
+            return false;
+        }
+
+        table.remove(asset);
+        true
+    }
+}
+
+impl AssetTable {
+    /// Hold an asset for an import, once
+    fn hold(&mut self, claim: &AssetClaim, asset: uuid::Uuid, recorded: bool) {
+        let mut held = claim.0.held.lock().unwrap();
+
+        if held.contains_key(&asset) {
+            return;
+        }
+
+        held.insert(asset, recorded);
+        *self.claimed.entry(asset).or_default() += 1;
+    }
+
+    /// Let go of assets an import held
+    fn release<'a>(&mut self, assets: impl IntoIterator<Item = &'a uuid::Uuid>) {
+        for asset in assets {
+            if let Some(count) = self.claimed.get_mut(asset) {
+                *count -= 1;
+                if *count == 0 {
+                    self.claimed.remove(asset);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, asset: &uuid::Uuid) {
+        let content = self.entries.remove(asset).and_then(|f| f.content);
+
+        if let Some(content) = content {
+            if self.by_content.get(&content) == Some(asset) {
+                self.by_content.remove(&content);
+            }
+        }
+    }
+}
+
+/// Serve an asset from the http server, noting its URL and kind for
+/// prefetch hints. Assets shared with earlier imports are already served,
+/// and are left alone.
+///
+/// Large assets are written to scratch space and served from the file, so
+/// the server doesn't keep a second copy of the importer's buffers in
+/// memory for as long as the scene lives.
+pub fn add_asset(
+    asset_store: AssetStorePtr,
+    id: uuid::Uuid,
+    bytes: &[u8],
+    kind: AssetKind,
+    options: &ImportOptions,
+) -> url::Url {
+    if let Some(url) = options.asset_sizes.served_url(&id) {
+        return url;
+    }
+
+    let (asset, spool) = match spool(id, bytes, options) {
+        Some((path, dir)) => (Asset::new_on_disk(&path), Some(dir)),
+        None => (Asset::new_from_slice(bytes), None),
+    };
+
+    let url = server_http::add_asset(asset_store, id, asset);
+    options.asset_sizes.note_served(&id, url.clone(), kind);
+
+    if let Some(dir) = spool {
+        options.asset_sizes.keep_spool(&id, dir);
+    }
+
+    url
+}
+
+/// Where the bytes of a buffer come from
+#[derive(Debug, Clone, PartialEq)]
+pub enum BufferData {
+    /// Small enough to send with the buffer itself
+    Inline(Vec<u8>),
+
+    /// Served by the asset server, with the size in bytes
+    Url(url::Url, u64),
+}
+
+impl BufferData {
+    /// Size in bytes
+    pub fn size(&self) -> u64 {
+        match self {
+            BufferData::Inline(bytes) => bytes.len() as u64,
+            BufferData::Url(_, size) => *size,
+        }
+    }
+
+    /// Create a buffer component holding these bytes
+    pub fn create(&self, state: &mut ServerState) -> BufferReference {
+        let buffer = match self {
+            BufferData::Inline(bytes) => BufferState::new_from_bytes(bytes.clone()),
+            BufferData::Url(url, size) => BufferState::new_from_url(url, *size),
+        };
+
+        state.buffers.new_component(buffer)
+    }
+}
+
+/// Publish the bytes of a buffer. Buffers smaller than the large size limit
+/// are kept to be sent inline. Larger ones are served by the asset server,
+/// and their asset is added to `published`.
+pub fn publish_buffer(
+    asset_store: &AssetStorePtr,
+    source: &Path,
+    bytes: &[u8],
+    options: &ImportOptions,
+    published: &mut Vec<uuid::Uuid>,
+) -> BufferData {
+    if (bytes.len() as u64) < options.size_large_limit {
+        return BufferData::Inline(bytes.to_vec());
+    }
+
+    let id = asset_id(source, bytes, options);
+
+    published.push(id);
+
+    let url = add_asset(asset_store.clone(), id, bytes, AssetKind::Geometry, options);
+
+    BufferData::Url(url, bytes.len() as u64)
+}
+
+/// Write an asset over the large size limit to scratch space. Returns None,
+/// and the asset is kept in memory, for small assets, or if there is no
+/// scratch space to use.
+fn spool(id: uuid::Uuid, bytes: &[u8], options: &ImportOptions) -> Option<(PathBuf, ScratchDir)> {
+    if bytes.len() as u64 <= options.size_large_limit {
+        return None;
+    }
+
+    let scratch = options.scratch.as_ref()?;
+
+    let res = scratch.allocate("asset").and_then(|mut dir| {
+        let path = dir.write(&id.to_string(), bytes)?;
+        Ok((path, dir))
+    });
+
+    match res {
+        Ok(x) => Some(x),
+        Err(e) => {
+            log::warn!("Unable to write asset {id} to scratch space, keeping it in memory: {e}");
+            None
+        }
+    }
+}
+
+/// A large asset written a piece at a time. Pieces go straight to scratch
+/// space, and the asset's id is worked out as they go by, so the whole of it
+/// is never held in memory.
+pub struct AssetWriter {
+    dir: ScratchDir,
+    path: PathBuf,
+    file: BufWriter<File>,
+
+    /// Hash of the content, as [`content_hash`] takes it
+    content: sha1_smol::Sha1,
+
+    /// Hash for a deterministic id, if ids are deterministic
+    named: Option<sha1_smol::Sha1>,
+
+    len: u64,
+}
+
+impl AssetWriter {
+    /// Start an asset for content from `source`. Returns None if there is
+    /// no scratch space to write to.
+    pub fn new(source: &Path, options: &ImportOptions) -> Result<Option<Self>> {
+        let Some(scratch) = options.scratch.as_ref() else {
+            return Ok(None);
+        };
+
+        let dir = scratch.allocate("asset")?;
+        let path = dir.file_path("asset.bin")?;
+        let file = BufWriter::new(File::create(&path)?);
+
+        Ok(Some(Self {
+            dir,
+            path,
+            file,
+            content: v5_hasher(&uuid::Uuid::NAMESPACE_OID),
+            named: id_namespace(source, options).map(|f| v5_hasher(&f)),
+            len: 0,
+        }))
+    }
+
+    /// Bytes written so far
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append bytes to the asset. Returns false, having written nothing, if
+    /// the scratch quota can't hold them.
+    pub fn append(&mut self, bytes: &[u8]) -> Result<bool> {
+        if let Err(e) = self.dir.reserve(bytes.len() as u64) {
+            log::debug!("Unable to append to {}: {e}", self.path.display());
+            return Ok(false);
+        }
+
+        self.file.write_all(bytes)?;
+
+        self.content.update(bytes);
+        if let Some(named) = &mut self.named {
+            named.update(bytes);
+        }
+
+        self.len += bytes.len() as u64;
+
+        Ok(true)
+    }
+
+    /// Serve the asset from its file, and add it to `published`. Content
+    /// identical to an asset already served shares that asset instead, and
+    /// the file is dropped.
+    pub fn finish(
+        self,
+        asset_store: &AssetStorePtr,
+        kind: AssetKind,
+        options: &ImportOptions,
+        published: &mut Vec<uuid::Uuid>,
+    ) -> Result<BufferData> {
+        let Self {
+            dir,
+            path,
+            mut file,
+            content,
+            named,
+            len,
+        } = self;
+
+        file.flush()?;
+        drop(file);
+
+        let id = register_asset(v5_uuid(&content), len, options, || {
+            named.map_or_else(create_asset_id, |f| v5_uuid(&f))
+        });
+
+        published.push(id);
+
+        let url = match options.asset_sizes.served_url(&id) {
+            Some(url) => url,
+            None => {
+                let url =
+                    server_http::add_asset(asset_store.clone(), id, Asset::new_on_disk(&path));
+                options.asset_sizes.note_served(&id, url.clone(), kind);
+                options.asset_sizes.keep_spool(&id, dir);
+                url
+            }
+        };
+
+        Ok(BufferData::Url(url, len))
+    }
+}
+
+/// The up direction of source content. NOODLES scenes are Y-up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpAxis {
+    #[default]
+    Y,
+    /// Common for CAD and scan data
+    Z,
+}
+
+/// Units content may be authored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Units {
+    /// As declared by the file, or guessed from its size
+    Auto,
+    Micrometers,
+    Millimeters,
+    Centimeters,
+    Meters,
+    Inches,
+    Feet,
+}
+
+impl Units {
+    /// Length of one unit, in meters
+    fn meters(&self) -> Option<f32> {
+        Some(match self {
+            Units::Auto => return None,
+            Units::Micrometers => 1e-6,
+            Units::Millimeters => 1e-3,
+            Units::Centimeters => 1e-2,
+            Units::Meters => 1.0,
+            Units::Inches => 0.0254,
+            Units::Feet => 0.3048,
+        })
+    }
+}
+
+/// Length of one unit, in meters, given units as recorded in scene info
+fn meters_per_unit(units: &str) -> Option<f32> {
+    if let Some(u) = Units::from_str(units, true).ok().and_then(|f| f.meters()) {
+        return Some(u);
+    }
+
+    // Odd USD scales are recorded as a number of meters
+    units.strip_suffix(" meters")?.parse().ok()
+}
+
+/// Matrix that brings content to Y-up meters, as far as the options ask
+fn conversion(options: &ImportOptions, units: Option<&str>) -> Matrix4<f32> {
+    let rotate = match options.up_axis {
+        UpAxis::Y => Matrix4::identity(),
+        // x, y, z to x, z, -y
+        UpAxis::Z => Matrix4::new(
+            1.0, 0.0, 0.0, 0.0, //
+            0.0, 0.0, 1.0, 0.0, //
+            0.0, -1.0, 0.0, 0.0, //
+            0.0, 0.0, 0.0, 1.0,
+        ),
+    };
+
+    let scale = match options.units {
+        None => None,
+        Some(Units::Auto) => units.and_then(meters_per_unit),
+        Some(u) => u.meters(),
+    };
+
+    match scale {
+        Some(s) => Matrix4::new_scaling(s) * rotate,
+        None => rotate,
+    }
+}
+
+/// Matrix that centers and fits content with the given bounds, as far as
+/// the options ask
+fn framing(options: &ImportOptions, bounds: &Bounds) -> Matrix4<f32> {
+    let center = match options.center {
+        true => Matrix4::new_translation(&-bounds.center()),
+        false => Matrix4::identity(),
+    };
+
+    let size = bounds.extent().max();
+
+    match options.fit {
+        // A single point or a flat line has no size to fit
+        Some(fit) if size > 0.0 => Matrix4::new_scaling(fit / size) * center,
+        _ => center,
+    }
+}
+
+impl ImportOptions {
+    /// Has the import these options are for been cancelled?
+    pub fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|f| f.is_cancelled())
+    }
+
+    /// Apply the tint, if any, to a material base color
+    pub fn tinted(&self, color: [f32; 4]) -> [f32; 4] {
+        match self.tint {
+            Some([r, g, b]) => [color[0] * r, color[1] * g, color[2] * b, color[3]],
+            None => color,
+        }
+    }
+}
+
+/// Lowercase extension of a path. Extensions that are not valid UTF-8 can't
+/// name a format we know, so they are treated as missing.
+pub fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|f| f.to_str())
+        .map(|f| f.to_lowercase())
+}
+
+/// A readable name for content imported from `path`. File names need not be
+/// valid UTF-8; these are only for display, so they are converted lossily.
+pub fn display_name(path: &Path) -> Option<String> {
+    path.file_stem().map(|f| f.to_string_lossy().into_owned())
+}
+
+/// Create an id for an asset published while importing `source`.
+///
+/// In deterministic mode the same source and content always give the same
+/// id, so scripted clients can rely on asset URLs between runs.
+///
+/// Content identical to an asset already served gets that asset's id, so it
+/// is published once. Scenes then list the same id, and the asset stays
+/// until the last of them is dropped.
+pub fn asset_id(source: &Path, bytes: &[u8], options: &ImportOptions) -> uuid::Uuid {
+    register_asset(content_hash(bytes), bytes.len() as u64, options, || {
+        make_asset_id(source, bytes, options)
+    })
+}
+
+/// Id for content with the given hash and size: that of an asset already
+/// served with the same content, or else a new one from `make`
+fn register_asset(
+    content: uuid::Uuid,
+    len: u64,
+    options: &ImportOptions,
+    make: impl FnOnce() -> uuid::Uuid,
+) -> uuid::Uuid {
+    let claim = options.claim.as_ref();
+
+    if let Some(id) = options.asset_sizes.find_content(&content, claim) {
+        return id;
+    }
+
+    let id = make();
+    options.asset_sizes.record(id, len, content, claim);
+    id
+}
+
+fn make_asset_id(source: &Path, bytes: &[u8], options: &ImportOptions) -> uuid::Uuid {
+    match id_namespace(source, options) {
+        Some(namespace) => uuid::Uuid::new_v5(&namespace, bytes),
+        None => create_asset_id(),
+    }
+}
+
+/// Namespace for the ids of assets from a source, if ids are deterministic
+fn id_namespace(source: &Path, options: &ImportOptions) -> Option<uuid::Uuid> {
+    if !options.deterministic {
+        return None;
+    }
+
+    // Extracted files land somewhere new each run; use the archive instead
+    let source = options.archive.as_deref().unwrap_or(source);
+
+    // Use the raw path, so names that differ only in invalid UTF-8 don't
+    // collide
+    Some(uuid::Uuid::new_v5(
+        &uuid::Uuid::NAMESPACE_URL,
+        source.as_os_str().as_encoded_bytes(),
+    ))
+}
+
+/// Signature shared by all importers
+type ImportFn = fn(&Path, ServerStatePtr, AssetStorePtr, &ImportOptions) -> Result<Scene>;
+
+/// Find the importer for a format, given as a lowercase extension. Images
+/// are only imported as heightmaps if asked for.
+fn importer_for(format: &str, options: &ImportOptions) -> Option<ImportFn> {
+    match format {
+        "gltf" | "glb" => Some(crate::import_gltf::import_file),
+        "obj" => Some(crate::import_obj::import_file),
+        "usd" | "usda" | "usdz" => Some(crate::import_usd::import_file),
+        "3mf" => Some(crate::import_3mf::import_file),
+        "csv" | "tsv" => Some(crate::import_csv::import_file),
+        "e57" => Some(crate::import_e57::import_file),
+        "png" | "tif" | "tiff" if options.heightmap.enabled => {
+            Some(crate::import_heightmap::import_file)
+        }
+        // LAZ goes to the LAS importer too, which refuses its compressed
+        // points with a clear error, rather than leaving the file unknown
+        "las" | "laz" => Some(crate::import_las::import_file),
+        "off" => Some(crate::import_off::import_file),
+        "vtk" | "vtu" => Some(crate::import_vtk::import_file),
+        "xyz" | "pts" | "pcd" => Some(crate::import_xyz::import_file),
+        "ply" => Some(crate::import_ply::import_file),
+        "splat" => Some(crate::import_splat::import_file),
+        "zip" => Some(crate::import_zip::import_file),
+        #[cfg(feature = "assimp")]
+        "fbx" | "dae" => Some(crate::import_assimp::import_file),
+        _ => None,
+    }
+}
+
 /// Attempt to import a geometry file.
+///
+/// The format is determined by extension. If that fails, we take a look at the
+/// contents of the file.
 pub fn import_file(
     path: &Path,
     state: ServerStatePtr,
     asset_store: AssetStorePtr,
+    options: &ImportOptions,
 ) -> Result<Scene> {
-    let ext = path.extension().and_then(|f| f.to_str()).ok_or_else(|| {
-        ImportError::UnknownFileFormat(format!(
-            "Unable to determine extension from path: {}",
-            path.display()
-        ))
-    })?;
-
-    match ext {
-        "gltf" | "glb" => crate::import_gltf::import_file(path, state, asset_store),
-        "obj" => crate::import_obj::import_file(path, state, asset_store),
-        _ => Err(ImportError::UnknownFileFormat(format!(
-            "File {} does not have a known extension",
-            path.display()
-        ))
-        .into()),
+    if options.cancelled() {
+        return Err(cancelled(path).into());
+    }
+
+    check_capacity(path, options)?;
+
+    // Imports run side by side, so each keeps its own list of what it
+    // recorded to clean up after
+    let options = &ImportOptions {
+        claim: Some(
+            options
+                .claim
+                .clone()
+                .unwrap_or_else(|| options.asset_sizes.claim()),
+        ),
+        ..options.clone()
+    };
+
+    // Parsing can't be interrupted, so a cancel that arrives part way through
+    // throws the finished scene away. Its assets are removed below.
+    let res = import_file_inner(path, state, asset_store.clone(), options).and_then(|mut scene| {
+        if options.cancelled() {
+            scene.published.clear();
+            drop(scene);
+            return Err(cancelled(path).into());
+        }
+        Ok(scene)
+    });
+
+    // Importers publish as they go; don't leave a failed import's assets
+    // behind
+    if let (Err(_), Some(claim)) = (&res, &options.claim) {
+        let leaked = options.asset_sizes.abandon(claim);
+
+        if !leaked.is_empty() {
+            log::info!(
+                "Removing {} assets from failed import of {}",
+                leaked.len(),
+                path.display()
+            );
+        }
+
+        for id in leaked {
+            remove_asset(asset_store.clone(), id);
+        }
+    }
+
+    res
+}
+
+fn cancelled(path: &Path) -> ImportError {
+    ImportError::Cancelled(format!("Import of {} was cancelled", path.display()))
+}
+
+/// Estimate what importing a file will publish, and refuse before anything
+/// is published if it will not fit.
+///
+/// The estimate is the size of the file. Packed geometry is usually about
+/// as large as its source; compressed formats can grow well beyond it.
+fn check_capacity(path: &Path, options: &ImportOptions) -> Result<(), ImportError> {
+    let estimate = std::fs::metadata(path).map(|f| f.len()).unwrap_or(0);
+
+    if let Some(limit) = options.asset_limit {
+        let used = options.asset_sizes.total();
+
+        if used.saturating_add(estimate) > limit {
+            return Err(ImportError::UnableToImport(format!(
+                "{} needs about {estimate} bytes of assets, but {used} of the {limit} byte limit are in use",
+                path.display()
+            )));
+        }
+    }
+
+    if let Some(available) = available_memory() {
+        if estimate > available {
+            return Err(ImportError::UnableToImport(format!(
+                "{} needs about {estimate} bytes of assets, but only {available} bytes of memory are available",
+                path.display()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Memory available for new allocations, where the platform tells us
+fn available_memory() -> Option<u64> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+
+    let info = std::fs::read_to_string("/proc/meminfo").ok()?;
+
+    let line = info.lines().find(|f| f.starts_with("MemAvailable:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+
+    Some(kb * 1024)
+}
+
+fn import_file_inner(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let ext = extension(path);
+
+    let (format, importer) = match ext.as_deref().and_then(|f| importer_for(f, options)) {
+        Some(importer) => (ext.unwrap(), importer),
+        None => {
+            let sniffed = sniff_file(path).ok_or_else(|| {
+                ImportError::UnknownFileFormat(format!(
+                    "File {} does not have a known extension or content",
+                    path.display()
+                ))
+            })?;
+
+            log::info!(
+                "Detected {} as {sniffed:?} from its contents",
+                path.display()
+            );
+
+            let importer = importer_for(sniffed.extension(), options).ok_or_else(|| {
+                ImportError::UnknownFileFormat(format!(
+                    "File {} looks like {sniffed:?}, which is not supported",
+                    path.display()
+                ))
+            })?;
+
+            (sniffed.extension().to_string(), importer)
+        }
+    };
+
+    let mut scene = importer(path, state, asset_store, options)?;
+
+    scene.info.source = Some(path.to_path_buf());
+    scene.info.format = Some(format);
+    scene.info.imported = Some(SystemTime::now());
+
+    if scene.info.units.is_none() {
+        scene.info.units = scene.info.bounds.as_ref().map(guess_units);
+    }
+
+    let mut tf = conversion(options, scene.info.units.as_deref());
+
+    if let Some(b) = &scene.info.bounds {
+        tf = framing(options, &b.transformed(&tf)) * tf;
+    }
+
+    scene.set_conversion(tf);
+
+    if !scene.info.cleanup.is_empty() {
+        log::info!("Cleaned up {}: {}", path.display(), scene.info.cleanup);
+    }
+
+    scene.publish_info();
+
+    Ok(scene)
+}
+
+/// File formats we can recognize by their contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SniffedFormat {
+    Glb,
+    Gltf,
+    Obj,
+    Stl,
+    Ply,
+    Off,
+    Zip,
+}
+
+impl SniffedFormat {
+    /// The usual extension for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SniffedFormat::Glb => "glb",
+            SniffedFormat::Gltf => "gltf",
+            SniffedFormat::Obj => "obj",
+            SniffedFormat::Stl => "stl",
+            SniffedFormat::Ply => "ply",
+            SniffedFormat::Off => "off",
+            SniffedFormat::Zip => "zip",
+        }
+    }
+}
+
+/// Read the start of a file and try to determine its format
+fn sniff_file(path: &Path) -> Option<SniffedFormat> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let file_len = file.metadata().ok()?.len();
+
+    let mut head = Vec::with_capacity(1024);
+    (&mut file).take(1024).read_to_end(&mut head).ok()?;
+
+    sniff_format(&head, file_len)
+}
+
+/// Determine a file format from the first bytes of a file and its total length
+fn sniff_format(head: &[u8], file_len: u64) -> Option<SniffedFormat> {
+    if head.starts_with(b"glTF") {
+        return Some(SniffedFormat::Glb);
+    }
+
+    if head.starts_with(b"PK\x03\x04") {
+        return Some(SniffedFormat::Zip);
+    }
+
+    if head.starts_with(b"ply\n") || head.starts_with(b"ply\r\n") {
+        return Some(SniffedFormat::Ply);
+    }
+
+    // Binary STL is an 80 byte header, a triangle count, and 50 bytes per
+    // triangle. Check this before looking at text, as the header is
+    // arbitrary and might even start with 'solid'.
+    if head.len() >= 84 {
+        let count = u32::from_le_bytes(head[80..84].try_into().unwrap()) as u64;
+        if count > 0 && file_len == 84 + count * 50 {
+            return Some(SniffedFormat::Stl);
+        }
+    }
+
+    let text = String::from_utf8_lossy(head);
+    let trimmed = text.trim_start();
+
+    if trimmed.starts_with("solid") && text.contains("facet") {
+        return Some(SniffedFormat::Stl);
+    }
+
+    // OFF starts with a keyword like OFF, COFF or NOFF
+    let keyword = trimmed.split_whitespace().next().unwrap_or_default();
+    if keyword.ends_with("OFF") && keyword.len() <= 7 {
+        return Some(SniffedFormat::Off);
+    }
+
+    if trimmed.starts_with('{') && text.contains("\"asset\"") {
+        return Some(SniffedFormat::Gltf);
+    }
+
+    // OBJ has no header, but almost every file will have vertex lines near
+    // the start.
+    let is_obj = text.lines().any(|l| {
+        let mut parts = l.split_whitespace();
+        matches!(parts.next(), Some("v") | Some("vn") | Some("vt"))
+            && parts.next().is_some_and(|f| f.parse::<f32>().is_ok())
+    });
+
+    if is_obj {
+        return Some(SniffedFormat::Obj);
+    }
+
+    None
+}
+
+/// Guess the units of content that does not declare them, based on its size.
+///
+/// Meshes that span thousands of units are most likely authored in
+/// millimeters (typical for CAD).
+fn guess_units(bounds: &Bounds) -> String {
+    if bounds.extent().max() > 1000.0 {
+        "millimeters".into()
+    } else {
+        "meters".into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nalgebra::Vector4;
+
+    #[test]
+    fn test_conversion() {
+        let mut options = ImportOptions::default();
+        assert_eq!(
+            conversion(&options, Some("millimeters")),
+            Matrix4::identity()
+        );
+
+        options.up_axis = UpAxis::Z;
+        options.units = Some(Units::Auto);
+
+        let m = conversion(&options, Some("millimeters"));
+        let up = m * Vector4::new(0.0, 0.0, 1000.0, 1.0);
+        assert!((up - Vector4::new(0.0, 1.0, 0.0, 1.0)).norm() < 1e-6);
+
+        options.units = Some(Units::Inches);
+        let m = conversion(&options, Some("millimeters"));
+        assert!((m[(1, 2)] - 0.0254).abs() < 1e-6);
+
+        assert_eq!(meters_per_unit("centimeters"), Some(0.01));
+        assert_eq!(meters_per_unit("0.5 meters"), Some(0.5));
+        assert_eq!(meters_per_unit("cubits"), None);
+    }
+
+    #[test]
+    fn test_framing() {
+        let bounds = Bounds::from_points([[10.0, 0.0, 0.0], [14.0, 2.0, 1.0]].iter()).unwrap();
+
+        let mut options = ImportOptions::default();
+        assert_eq!(framing(&options, &bounds), Matrix4::identity());
+
+        options.center = true;
+        options.fit = Some(2.0);
+
+        let framed = bounds.transformed(&framing(&options, &bounds));
+        assert!((framed.min - nalgebra::vector![-1.0, -0.5, -0.25]).norm() < 1e-6);
+        assert!((framed.max - nalgebra::vector![1.0, 0.5, 0.25]).norm() < 1e-6);
+
+        let point = Bounds::from_points([[1.0, 1.0, 1.0]].iter()).unwrap();
+        let framed = point.transformed(&framing(&options, &point));
+        assert_eq!(framed.min, nalgebra::Vector3::zeros());
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(
+            sniff_format(b"glTF\x02\x00\x00\x00", 100),
+            Some(SniffedFormat::Glb)
+        );
+        assert_eq!(
+            sniff_format(b"PK\x03\x04rest", 100),
+            Some(SniffedFormat::Zip)
+        );
+        assert_eq!(
+            sniff_format(b"ply\nformat ascii 1.0\n", 100),
+            Some(SniffedFormat::Ply)
+        );
+        assert_eq!(
+            sniff_format(b"solid cube\n  facet normal 0 0 1\n", 100),
+            Some(SniffedFormat::Stl)
+        );
+        assert_eq!(
+            sniff_format(b"{\n  \"asset\": {\"version\": \"2.0\"}", 100),
+            Some(SniffedFormat::Gltf)
+        );
+        assert_eq!(
+            sniff_format(b"# comment\no cube\nv 1.0 0.0 0.0\n", 100),
+            Some(SniffedFormat::Obj)
+        );
+        assert_eq!(
+            sniff_format(b"COFF\n3 1 0\n", 100),
+            Some(SniffedFormat::Off)
+        );
+        assert_eq!(sniff_format(b"hello world", 100), None);
+
+        // binary stl with 2 triangles
+        let mut stl = vec![0u8; 84];
+        stl[80] = 2;
+        assert_eq!(sniff_format(&stl, 84 + 100), Some(SniffedFormat::Stl));
+        assert_eq!(sniff_format(&stl, 84 + 101), None);
+    }
+
+    #[test]
+    fn test_importer_for() {
+        let mut options = ImportOptions::default();
+
+        assert!(importer_for("glb", &options).is_some());
+        assert!(importer_for("txt", &options).is_none());
+        assert!(importer_for("laz", &options).is_some());
+
+        // Images are textures unless heightmaps are asked for
+        assert!(importer_for("png", &options).is_none());
+
+        options.heightmap.enabled = true;
+        assert!(importer_for("png", &options).is_some());
+        assert!(importer_for("tiff", &options).is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+
+        // Latin-1 names, as left behind by old archives
+        let a = dir.path().join(OsStr::from_bytes(b"caf\xe9.OFF"));
+        let b = dir.path().join(OsStr::from_bytes(b"caf\xe8"));
+
+        std::fs::write(&a, "OFF\n3 1 0\n").unwrap();
+        std::fs::write(&b, "OFF\n3 1 0\n").unwrap();
+
+        assert_eq!(extension(&a).as_deref(), Some("off"));
+        assert_eq!(extension(&b), None);
+        assert_eq!(display_name(&a).as_deref(), Some("caf\u{FFFD}"));
+        assert_eq!(sniff_file(&b), Some(SniffedFormat::Off));
+
+        // Lossy names are the same, but ids must not be
+        let options = ImportOptions {
+            deterministic: true,
+            ..Default::default()
+        };
+        assert_ne!(
+            asset_id(&a.with_extension(""), b"x", &options),
+            asset_id(&b, b"x", &options)
+        );
+    }
+
+    #[test]
+    fn test_check_capacity() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("a.obj");
+        std::fs::write(&path, [b' '; 100]).unwrap();
+
+        let mut options = ImportOptions {
+            asset_limit: Some(150),
+            ..Default::default()
+        };
+
+        assert!(check_capacity(&path, &options).is_ok());
+
+        asset_id(&path, &[0; 60], &options);
+        assert!(check_capacity(&path, &options).is_err());
+
+        options.asset_limit = None;
+        assert!(check_capacity(&path, &options).is_ok());
+    }
+
+    #[test]
+    fn test_asset_sizes() {
+        let options = ImportOptions::default();
+
+        let a = asset_id(Path::new("a.obj"), &[0; 10], &options);
+        let b = asset_id(Path::new("a.obj"), &[0; 3], &options);
+
+        assert_eq!(options.asset_sizes.get(&a), Some(10));
+        assert_eq!(options.asset_sizes.get(&b), Some(3));
+
+        options.asset_sizes.forget([&a]);
+        assert_eq!(options.asset_sizes.get(&a), None);
+        assert_eq!(options.asset_sizes.get(&b), Some(3));
+
+        options.asset_sizes.tag_color_space(&b, ColorSpace::Linear);
+        assert_eq!(
+            options.asset_sizes.color_space(&b),
+            Some(ColorSpace::Linear)
+        );
+        assert_eq!(options.asset_sizes.color_space(&a), None);
+
+        let c = asset_id(Path::new("a.obj"), &[0; 5], &options);
+        let d = asset_id(Path::new("a.obj"), &[0; 1], &options);
+        let url = |f: &str| url::Url::parse(&format!("http://localhost/{f}")).unwrap();
+
+        options
+            .asset_sizes
+            .note_served(&b, url("b"), AssetKind::Image);
+        options
+            .asset_sizes
+            .note_served(&c, url("c"), AssetKind::Geometry);
+        options
+            .asset_sizes
+            .note_served(&d, url("d"), AssetKind::Image);
+
+        // Geometry first, then the smaller image; forgotten assets are left out
+        assert_eq!(
+            options.asset_sizes.prefetch_order([&a, &b, &c, &d]),
+            [url("c"), url("d"), url("b")]
+        );
+    }
+
+    #[test]
+    fn test_shared_assets() {
+        let options = ImportOptions::default();
+        let url = url::Url::parse("http://localhost/a").unwrap();
+
+        // Content is only shared once it is served
+        let a = asset_id(Path::new("a.obj"), b"mesh", &options);
+        assert_ne!(asset_id(Path::new("a.obj"), b"mesh", &options), a);
+
+        options
+            .asset_sizes
+            .note_served(&a, url.clone(), AssetKind::Geometry);
+
+        // The same content from anywhere gets the same asset
+        assert_eq!(asset_id(Path::new("b.obj"), b"mesh", &options), a);
+        assert_ne!(asset_id(Path::new("a.obj"), b"other", &options), a);
+        assert_eq!(options.asset_sizes.served_url(&a), Some(url));
+
+        // Listed twice, fetched once
+        assert_eq!(options.asset_sizes.prefetch_order([&a, &a]).len(), 1);
+
+        options.asset_sizes.forget([&a]);
+        assert_ne!(asset_id(Path::new("a.obj"), b"mesh", &options), a);
+    }
+
+    #[test]
+    fn test_spool() {
+        let root = tempfile::TempDir::new().unwrap();
+
+        let mut options = ImportOptions {
+            size_large_limit: 4,
+            ..Default::default()
+        };
+
+        // Without scratch space, everything stays in memory
+        assert!(spool(create_asset_id(), &[0; 8], &options).is_none());
+
+        options.scratch = Some(ScratchSpace::new(Some(root.path()), 64).unwrap());
+
+        let small = asset_id(Path::new("a.obj"), &[0; 4], &options);
+        assert!(spool(small, &[0; 4], &options).is_none());
+
+        let large = asset_id(Path::new("a.obj"), &[1; 8], &options);
+        let (path, dir) = spool(large, &[1; 8], &options).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), [1; 8]);
+
+        // Over the quota
+        let huge = asset_id(Path::new("a.obj"), &[2; 128], &options);
+        assert!(spool(huge, &[2; 128], &options).is_none());
+
+        // The file lives until the asset is forgotten
+        options.asset_sizes.keep_spool(&large, dir);
+        assert!(path.exists());
+
+        options.asset_sizes.forget([&large]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_asset_writer() {
+        let root = tempfile::TempDir::new().unwrap();
+
+        let mut options = ImportOptions {
+            deterministic: true,
+            ..Default::default()
+        };
+
+        // Without scratch space, there is nowhere to write
+        assert!(AssetWriter::new(Path::new("a.xyz"), &options)
+            .unwrap()
+            .is_none());
+
+        options.scratch = Some(ScratchSpace::new(Some(root.path()), 8).unwrap());
+
+        let mut writer = AssetWriter::new(Path::new("a.xyz"), &options)
+            .unwrap()
+            .unwrap();
+        assert!(writer.is_empty());
+
+        assert!(writer.append(&[1, 2, 3]).unwrap());
+        assert!(writer.append(&[4, 5]).unwrap());
+
+        // Over the quota, nothing is written
+        assert!(!writer.append(&[0; 4]).unwrap());
+        assert_eq!(writer.len(), 5);
+
+        // Hashed in pieces, the same as all at once
+        let bytes = [1, 2, 3, 4, 5];
+        assert_eq!(v5_uuid(&writer.content), content_hash(&bytes));
+        assert_eq!(
+            v5_uuid(writer.named.as_ref().unwrap()),
+            make_asset_id(Path::new("a.xyz"), &bytes, &options)
+        );
+
+        writer.file.flush().unwrap();
+        assert_eq!(std::fs::read(&writer.path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_unowned_assets() {
+        let options = ImportOptions::default();
+
+        let a = asset_id(Path::new("a.obj"), &[0; 10], &options);
+        let b = asset_id(Path::new("a.obj"), &[0; 3], &options);
+
+        let owned = HashSet::from([a]);
+        assert_eq!(options.asset_sizes.unowned(&owned), [(b, 3)]);
+
+        options.asset_sizes.forget([&b]);
+        assert!(options.asset_sizes.unowned(&owned).is_empty());
+        assert_eq!(options.asset_sizes.unowned(&HashSet::new()), [(a, 10)]);
+    }
+
+    #[test]
+    fn test_abandon() {
+        let options = ImportOptions::default();
+        let url = url::Url::parse("http://localhost/a").unwrap();
+
+        let first = ImportOptions {
+            claim: Some(options.asset_sizes.claim()),
+            ..options.clone()
+        };
+        let second = ImportOptions {
+            claim: Some(options.asset_sizes.claim()),
+            ..options.clone()
+        };
+
+        let a = asset_id(Path::new("a.obj"), b"a", &first);
+        let b = asset_id(Path::new("a.obj"), b"b", &first);
+        let c = asset_id(Path::new("c.obj"), b"c", &second);
+
+        // The second import shares one of the first's
+        options
+            .asset_sizes
+            .note_served(&b, url, AssetKind::Geometry);
+        assert_eq!(asset_id(Path::new("c.obj"), b"b", &second), b);
+
+        // Only what the first recorded, and nobody else uses, goes
+        let sizes = &options.asset_sizes;
+        assert_eq!(sizes.abandon(first.claim.as_ref().unwrap()), [a]);
+        assert_eq!(sizes.get(&a), None);
+        assert_eq!(sizes.get(&b), Some(1));
+        assert_eq!(sizes.get(&c), Some(1));
+
+        assert!(sizes.abandon(first.claim.as_ref().unwrap()).is_empty());
+
+        // Held assets are not swept until the claim is released
+        assert!(sizes.is_claimed(&b) && sizes.is_claimed(&c));
+        assert!(sizes.unowned(&HashSet::new()).is_empty());
+
+        drop(second);
+        assert!(!sizes.is_claimed(&b));
+        assert_eq!(sizes.unowned(&HashSet::new()).len(), 2);
+    }
+
+    #[test]
+    fn test_forget_unclaimed() {
+        let options = ImportOptions::default();
+
+        let running = ImportOptions {
+            claim: Some(options.asset_sizes.claim()),
+            ..options.clone()
+        };
+
+        let a = asset_id(Path::new("a.obj"), b"a", &running);
+
+        // Held by a running import, so removal is refused
+        let sizes = &options.asset_sizes;
+        assert!(!sizes.forget_unclaimed(&a));
+        assert_eq!(sizes.get(&a), Some(1));
+
+        drop(running);
+        assert!(sizes.forget_unclaimed(&a));
+        assert_eq!(sizes.get(&a), None);
     }
 }
@@ -0,0 +1,601 @@
+//! Import 3D Manufacturing Format (3MF) packages
+
+use std::{collections::HashMap, io::Read, path::Path};
+
+use anyhow::Result;
+use nalgebra::{Matrix4, Vector3};
+
+use crate::color;
+use crate::geometry::{self, Cleanup, TriangleMesh};
+use crate::import::{self, ImportError, ImportOptions};
+use crate::prepared::{Prepared, PreparedEntity, PreparedMaterial};
+use crate::scene::{Bounds, Scene};
+
+use colabrodo_server::{
+    server_bufferbuilder::*, server_http::*, server_messages::*, server_state::*,
+};
+
+const MODEL_REL_TYPE: &str = "http://schemas.microsoft.com/3dmanufacturing/2013/01/3dmodel";
+const DEFAULT_MODEL_PATH: &str = "3D/3dmodel.model";
+
+/// A material, as a linear RGBA color
+type Color = [f32; 4];
+
+#[derive(Debug, Default)]
+struct MeshData {
+    vertices: Vec<[f32; 3]>,
+    /// Triangle indices, along with the color of the triangle, if any
+    triangles: Vec<([u32; 3], Option<usize>)>,
+}
+
+#[derive(Debug)]
+struct Component {
+    object: u32,
+    transform: Matrix4<f32>,
+}
+
+#[derive(Debug, Default)]
+struct Object {
+    name: Option<String>,
+    mesh: Option<MeshData>,
+    components: Vec<Component>,
+}
+
+#[derive(Debug, Default)]
+struct Model {
+    unit: Option<String>,
+    /// All distinct colors used by triangles
+    colors: Vec<Color>,
+    objects: HashMap<u32, Object>,
+    /// Top level items to build, with their transforms
+    build: Vec<Component>,
+}
+
+/// Parse a 3MF color, given as sRGB "#RRGGBB" or "#RRGGBBAA"
+fn parse_color(s: &str) -> Option<Color> {
+    let hex = s.strip_prefix('#')?;
+
+    if hex.len() != 6 && hex.len() != 8 {
+        return None;
+    }
+
+    let mut ret = [1.0; 4];
+
+    for (i, c) in ret.iter_mut().enumerate().take(hex.len() / 2) {
+        let v = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()? as f32 / 255.0;
+
+        // Alpha is already linear
+        *c = if i == 3 { v } else { color::srgb_to_linear(v) };
+    }
+
+    Some(ret)
+}
+
+/// Parse a 3MF transform. These are 4x3, in row-vector order.
+fn parse_transform(s: Option<&str>) -> Matrix4<f32> {
+    let Some(s) = s else {
+        return Matrix4::identity();
+    };
+
+    let v: Vec<f32> = s
+        .split_whitespace()
+        .filter_map(|f| f.parse().ok())
+        .collect();
+
+    if v.len() != 12 {
+        log::warn!("Ignoring malformed transform {s}");
+        return Matrix4::identity();
+    }
+
+    // Transposing a row-vector matrix; each row of three becomes a column
+    let mut m = Matrix4::identity();
+    for col in 0..4 {
+        for row in 0..3 {
+            m[(row, col)] = v[col * 3 + row];
+        }
+    }
+    m
+}
+
+fn attr<T: std::str::FromStr>(node: &roxmltree::Node, name: &str) -> Option<T> {
+    node.attribute(name).and_then(|f| f.parse().ok())
+}
+
+/// Parse the contents of a model part
+fn parse_model(xml: &str) -> Result<Model> {
+    let doc = roxmltree::Document::parse(xml)?;
+
+    let root = doc.root_element();
+
+    let mut model = Model {
+        unit: root.attribute("unit").map(|f| f.to_string()),
+        ..Default::default()
+    };
+
+    // Property groups map to a list of indices into our color list
+    let mut groups = HashMap::<u32, Vec<usize>>::new();
+
+    let resources = root
+        .children()
+        .find(|f| f.tag_name().name() == "resources")
+        .ok_or_else(|| ImportError::UnableToImport("Model has no resources".into()))?;
+
+    for res in resources.children().filter(|f| f.is_element()) {
+        let Some(id) = attr::<u32>(&res, "id") else {
+            continue;
+        };
+
+        match res.tag_name().name() {
+            "basematerials" | "colorgroup" => {
+                let list = res
+                    .children()
+                    .filter(|f| f.is_element())
+                    .map(|f| {
+                        let c = f
+                            .attribute("displaycolor")
+                            .or(f.attribute("color"))
+                            .and_then(parse_color)
+                            .unwrap_or([1.0; 4]);
+                        model.colors.push(c);
+                        model.colors.len() - 1
+                    })
+                    .collect();
+                groups.insert(id, list);
+            }
+            "object" => {
+                let mut obj = Object {
+                    name: res.attribute("name").map(|f| f.to_string()),
+                    ..Default::default()
+                };
+
+                // Object level default property
+                let default_prop = attr::<u32>(&res, "pid").and_then(|pid| {
+                    groups
+                        .get(&pid)?
+                        .get(attr::<usize>(&res, "pindex").unwrap_or(0))
+                        .copied()
+                });
+
+                for part in res.children().filter(|f| f.is_element()) {
+                    match part.tag_name().name() {
+                        "mesh" => {
+                            let mut mesh = MeshData::default();
+                            for list in part.children().filter(|f| f.is_element()) {
+                                match list.tag_name().name() {
+                                    "vertices" => {
+                                        mesh.vertices.extend(
+                                            list.children().filter(|f| f.is_element()).map(|v| {
+                                                [
+                                                    attr(&v, "x").unwrap_or_default(),
+                                                    attr(&v, "y").unwrap_or_default(),
+                                                    attr(&v, "z").unwrap_or_default(),
+                                                ]
+                                            }),
+                                        );
+                                    }
+                                    "triangles" => {
+                                        for t in list.children().filter(|f| f.is_element()) {
+                                            let (Some(v1), Some(v2), Some(v3)) =
+                                                (attr(&t, "v1"), attr(&t, "v2"), attr(&t, "v3"))
+                                            else {
+                                                continue;
+                                            };
+
+                                            let prop = match attr::<u32>(&t, "pid") {
+                                                Some(pid) => groups
+                                                    .get(&pid)
+                                                    .and_then(|g| {
+                                                        g.get(attr(&t, "p1").unwrap_or(0))
+                                                    })
+                                                    .copied(),
+                                                None => default_prop,
+                                            };
+
+                                            mesh.triangles.push(([v1, v2, v3], prop));
+                                        }
+                                    }
+                                    _ => (),
+                                }
+                            }
+                            obj.mesh = Some(mesh);
+                        }
+                        "components" => {
+                            obj.components.extend(
+                                part.children().filter(|f| f.is_element()).filter_map(|c| {
+                                    Some(Component {
+                                        object: attr(&c, "objectid")?,
+                                        transform: parse_transform(c.attribute("transform")),
+                                    })
+                                }),
+                            );
+                        }
+                        _ => (),
+                    }
+                }
+
+                model.objects.insert(id, obj);
+            }
+            _ => (),
+        }
+    }
+
+    if let Some(build) = root.children().find(|f| f.tag_name().name() == "build") {
+        model.build = build
+            .children()
+            .filter(|f| f.is_element())
+            .filter_map(|c| {
+                Some(Component {
+                    object: attr(&c, "objectid")?,
+                    transform: parse_transform(c.attribute("transform")),
+                })
+            })
+            .collect();
+    }
+
+    Ok(model)
+}
+
+/// Find the model part, by way of the package relationships
+fn find_model_part(archive: &mut zip::ZipArchive<std::fs::File>) -> String {
+    let mut rels = String::new();
+
+    let found = archive
+        .by_name("_rels/.rels")
+        .ok()
+        .and_then(|mut f| f.read_to_string(&mut rels).ok())
+        .and_then(|_| {
+            let doc = roxmltree::Document::parse(&rels).ok()?;
+            doc.descendants()
+                .find(|f| f.attribute("Type") == Some(MODEL_REL_TYPE))
+                .and_then(|f| f.attribute("Target"))
+                .map(|f| f.trim_start_matches('/').to_string())
+        });
+
+    found.unwrap_or_else(|| DEFAULT_MODEL_PATH.to_string())
+}
+
+/// Pack the triangles of a mesh with a given color into vertices and faces
+fn pack_mesh(
+    mesh: &MeshData,
+    color: Option<usize>,
+    smooth: bool,
+) -> (Vec<VertexTexture>, Vec<[u32; 3]>) {
+    let tris = mesh
+        .triangles
+        .iter()
+        .filter(|f| f.1 == color)
+        .map(|f| f.0)
+        .filter(|t| t.iter().all(|i| (*i as usize) < mesh.vertices.len()));
+
+    let face_normal = |t: &[u32; 3]| {
+        let [a, b, c] = t.map(|i| Vector3::from(mesh.vertices[i as usize]));
+        (b - a).cross(&(c - a))
+    };
+
+    let mut verts = Vec::new();
+    let mut faces = Vec::new();
+
+    if smooth {
+        let mut remap = HashMap::<u32, u32>::new();
+        let mut accum = Vec::<Vector3<f32>>::new();
+
+        for t in tris {
+            let n = face_normal(&t);
+            faces.push(t.map(|i| {
+                let id = *remap.entry(i).or_insert_with(|| {
+                    verts.push(VertexTexture {
+                        position: mesh.vertices[i as usize],
+                        normal: [0.0; 3],
+                        texture: [0; 2],
+                    });
+                    accum.push(Vector3::zeros());
+                    verts.len() as u32 - 1
+                });
+                accum[id as usize] += n;
+                id
+            }));
+        }
+
+        for (v, n) in verts.iter_mut().zip(accum) {
+            v.normal = n.try_normalize(f32::EPSILON).unwrap_or_default().into();
+        }
+    } else {
+        // Flat shading needs a vertex per corner
+        for t in tris {
+            let n: [f32; 3] = face_normal(&t)
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_default()
+                .into();
+            let base = verts.len() as u32;
+            verts.extend(t.map(|i| VertexTexture {
+                position: mesh.vertices[i as usize],
+                normal: n,
+                texture: [0; 2],
+            }));
+            faces.push([base, base + 1, base + 2]);
+        }
+    }
+
+    (verts, faces)
+}
+
+struct Converter<'a> {
+    prepared: Prepared,
+    asset_store: AssetStorePtr,
+    source: &'a Path,
+    options: &'a ImportOptions,
+    model: &'a Model,
+
+    materials: HashMap<Option<usize>, usize>,
+    /// Meshes for each object, split by color
+    geometry: HashMap<u32, Vec<(usize, Bounds, u64)>>,
+
+    bounds: Option<Bounds>,
+    triangles: u64,
+    /// Counted once per object, however often it is instanced
+    cleanup: Cleanup,
+}
+
+impl<'a> Converter<'a> {
+    fn material(&mut self, color: Option<usize>) -> usize {
+        let c = color
+            .and_then(|f| self.model.colors.get(f).copied())
+            .unwrap_or([1.0; 4]);
+
+        *self.materials.entry(color).or_insert_with(|| {
+            self.prepared.add_material(PreparedMaterial {
+                pbr: PBRInfo {
+                    base_color: self.options.tinted(c),
+                    metallic: Some(0.0),
+                    roughness: Some(1.0),
+                    ..Default::default()
+                },
+                use_alpha: (c[3] < 1.0).then_some(true),
+                ..Default::default()
+            })
+        })
+    }
+
+    /// Pack (or reuse) the meshes for a mesh object
+    fn object_geometry(&mut self, id: u32) -> Result<Vec<(usize, Bounds, u64)>> {
+        if let Some(g) = self.geometry.get(&id) {
+            return Ok(g.clone());
+        }
+
+        let Some(mesh) = self.model.objects.get(&id).and_then(|f| f.mesh.as_ref()) else {
+            return Ok(vec![]);
+        };
+
+        let mut colors: Vec<_> = mesh.triangles.iter().map(|f| f.1).collect();
+        colors.sort();
+        colors.dedup();
+
+        let mut ret = Vec::new();
+
+        for color in colors {
+            let (mut verts, mut faces) = pack_mesh(mesh, color, self.options.generate_normals);
+
+            self.cleanup += geometry::prepare_mesh(&mut verts, &mut faces, self.options);
+
+            let Some(bounds) = Bounds::from_points(verts.iter().map(|f| &f.position)) else {
+                continue;
+            };
+
+            let material = self.material(color);
+
+            let source = TriangleMesh {
+                name: None,
+                vertex: &verts,
+                faces: &faces,
+            };
+
+            let mesh = self.prepared.add_mesh(
+                &source,
+                material,
+                self.source,
+                &self.asset_store,
+                self.options,
+            );
+
+            ret.push((mesh, bounds, faces.len() as u64));
+        }
+
+        self.geometry.insert(id, ret.clone());
+
+        Ok(ret)
+    }
+
+    /// Instance an object under a parent entity. Depth guards against
+    /// component cycles.
+    fn instance(
+        &mut self,
+        component: &Component,
+        parent: Option<usize>,
+        parent_tf: &Matrix4<f32>,
+        depth: usize,
+    ) -> Result<()> {
+        if depth > 32 {
+            return Err(ImportError::UnableToImport("Component nesting is too deep".into()).into());
+        }
+
+        let Some(obj) = self.model.objects.get(&component.object) else {
+            log::warn!("Missing object {}", component.object);
+            return Ok(());
+        };
+
+        let world = parent_tf * component.transform;
+
+        let tf: [f32; 16] = component.transform.as_slice().try_into().unwrap();
+
+        let entity = self.prepared.add_entity(PreparedEntity {
+            name: obj.name.clone(),
+            parent,
+            transform: Some(tf),
+            mesh: None,
+        });
+
+        // Entities have one representation, so each color gets a child
+        for (mesh, bounds, count) in self.object_geometry(component.object)? {
+            let b = bounds.transformed(&world);
+            self.bounds = Some(self.bounds.map_or(b, |f| f.union(&b)));
+            self.triangles += count;
+
+            self.prepared.add_entity(PreparedEntity {
+                parent: Some(entity),
+                mesh: Some(mesh),
+                ..Default::default()
+            });
+        }
+
+        for child in &obj.components {
+            self.instance(child, Some(entity), &world, depth + 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Import a 3MF package
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)
+        .map_err(|e| ImportError::UnableToOpenFile(format!("Bad 3MF package: {e}")))?;
+
+    let part = find_model_part(&mut archive);
+
+    let mut xml = String::new();
+    archive
+        .by_name(&part)
+        .map_err(|e| ImportError::UnableToImport(format!("Missing model part {part}: {e}")))?
+        .read_to_string(&mut xml)?;
+
+    let model = parse_model(&xml)
+        .map_err(|e| ImportError::UnableToImport(format!("Unable to parse 3MF model: {e}")))?;
+
+    let mut prepared = Prepared::default();
+
+    // Group all build items so the scene can be moved as one
+    let root = prepared.add_entity(PreparedEntity {
+        name: import::display_name(path),
+        ..Default::default()
+    });
+
+    let mut converter = Converter {
+        prepared,
+        asset_store: asset_store.clone(),
+        source: path,
+        options,
+        model: &model,
+        materials: HashMap::new(),
+        geometry: HashMap::new(),
+        bounds: None,
+        triangles: 0,
+        cleanup: Cleanup::default(),
+    };
+
+    for item in &model.build {
+        converter.instance(item, Some(root), &Matrix4::identity(), 0)?;
+    }
+
+    let Converter {
+        prepared,
+        bounds,
+        triangles,
+        cleanup,
+        ..
+    } = converter;
+
+    let mut scene = prepared.publish(&mut state.lock().unwrap(), asset_store);
+
+    scene.info.bounds = bounds;
+    scene.info.triangles = triangles;
+    scene.info.cleanup = cleanup;
+
+    // 3MF defaults to millimeters
+    scene.info.units = Some(match model.unit.as_deref().unwrap_or("millimeter") {
+        "micron" => "micrometers".into(),
+        "millimeter" => "millimeters".into(),
+        "centimeter" => "centimeters".into(),
+        "meter" => "meters".into(),
+        "inch" => "inches".into(),
+        "foot" => "feet".into(),
+        x => x.to_string(),
+    });
+
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MODEL: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<model unit="millimeter" xmlns="http://schemas.microsoft.com/3dmanufacturing/core/2015/02">
+  <resources>
+    <basematerials id="1">
+      <base name="Red" displaycolor="#FF0000" />
+      <base name="Clear" displaycolor="#FFFFFF80" />
+    </basematerials>
+    <object id="2" type="model" pid="1" pindex="0">
+      <mesh>
+        <vertices>
+          <vertex x="0" y="0" z="0" />
+          <vertex x="1" y="0" z="0" />
+          <vertex x="0" y="1" z="0" />
+          <vertex x="0" y="0" z="1" />
+        </vertices>
+        <triangles>
+          <triangle v1="0" v2="2" v3="1" />
+          <triangle v1="0" v2="1" v3="3" pid="1" p1="1" />
+        </triangles>
+      </mesh>
+    </object>
+    <object id="3" type="model">
+      <components>
+        <component objectid="2" transform="1 0 0 0 1 0 0 0 1 10 20 30" />
+      </components>
+    </object>
+  </resources>
+  <build>
+    <item objectid="3" />
+  </build>
+</model>"##;
+
+    #[test]
+    fn test_parse_model() {
+        let model = parse_model(MODEL).unwrap();
+
+        assert_eq!(model.unit.as_deref(), Some("millimeter"));
+        assert_eq!(model.colors.len(), 2);
+        assert_eq!(model.colors[0], [1.0, 0.0, 0.0, 1.0]);
+        assert!((model.colors[1][3] - 128.0 / 255.0).abs() < 0.001);
+
+        let mesh = model.objects[&2].mesh.as_ref().unwrap();
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.triangles[0], ([0, 2, 1], Some(0)));
+        assert_eq!(mesh.triangles[1], ([0, 1, 3], Some(1)));
+
+        let component = &model.objects[&3].components[0];
+        assert_eq!(component.object, 2);
+        assert_eq!(
+            component.transform.column(3).as_slice(),
+            &[10.0, 20.0, 30.0, 1.0]
+        );
+
+        assert_eq!(model.build.len(), 1);
+    }
+
+    #[test]
+    fn test_pack_mesh_flat() {
+        let model = parse_model(MODEL).unwrap();
+        let mesh = model.objects[&2].mesh.as_ref().unwrap();
+
+        let (verts, faces) = pack_mesh(mesh, Some(0), false);
+        assert_eq!(faces, vec![[0, 1, 2]]);
+        assert_eq!(verts[0].normal, [0.0, 0.0, -1.0]);
+    }
+}
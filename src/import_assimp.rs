@@ -0,0 +1,363 @@
+//! Import through assimp, for formats we do not have a native importer for
+
+use std::{path::Path, rc::Rc};
+
+use anyhow::Result;
+
+use russimp::{
+    material::{Material, PropertyTypeInfo, TextureType},
+    mesh::Mesh,
+    node::Node,
+    scene::{PostProcess, Scene as AiScene},
+    Matrix4x4,
+};
+
+use crate::color;
+use crate::geometry::{self, Cleanup, TriangleMesh};
+use crate::import::{self, ImportError, ImportOptions};
+use crate::prepared::{Prepared, PreparedEntity, PreparedMaterial};
+use crate::scene::{Bounds, Scene};
+use crate::texture;
+
+use colabrodo_server::{
+    server_bufferbuilder::*, server_http::*, server_messages::*, server_state::*,
+};
+
+/// Import a file with assimp
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let mut flags = vec![
+        PostProcess::Triangulate,
+        PostProcess::JoinIdenticalVertices,
+        PostProcess::SortByPrimitiveType,
+        // NOODLES texture coordinates have the origin at the top
+        PostProcess::FlipUVs,
+    ];
+
+    if options.generate_normals {
+        flags.push(PostProcess::GenerateSmoothNormals);
+    }
+
+    // Assimp only takes UTF-8 paths. Other files are read into memory, which
+    // works as long as they don't refer to other files.
+    let ai_scene = match path.to_str() {
+        Some(name) => AiScene::from_file(name, flags),
+        None => {
+            log::warn!(
+                "{} is not a UTF-8 path; external references will not be found",
+                path.display()
+            );
+            let bytes = std::fs::read(path)?;
+            let hint = import::extension(path).unwrap_or_default();
+            AiScene::from_buffer(&bytes, flags, &hint)
+        }
+    }
+    .map_err(|e| {
+        ImportError::UnableToImport(format!("Assimp could not import {}: {e}", path.display()))
+    })?;
+
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("./"));
+
+    let Some(node) = &ai_scene.root else {
+        return Err(ImportError::UnableToImport(format!(
+            "{} does not contain a scene",
+            path.display()
+        ))
+        .into());
+    };
+
+    let mut prepared = Prepared::default();
+
+    let materials: Vec<_> = ai_scene
+        .materials
+        .iter()
+        .map(|f| {
+            let material =
+                convert_material(&mut prepared, f, base_dir, &asset_store, path, options);
+            prepared.add_material(material)
+        })
+        .collect();
+
+    // Shared by meshes whose material is missing
+    let mut fallback = None;
+
+    let mut bounds = Option::<Bounds>::None;
+    let mut triangles = 0;
+    let mut cleanup = Cleanup::default();
+
+    let mut meshes = Vec::new();
+
+    for mesh in &ai_scene.meshes {
+        let Some(mut verts) = pack_mesh(mesh) else {
+            // Points and lines are sorted into their own meshes; skip them
+            meshes.push(None);
+            continue;
+        };
+
+        cleanup += geometry::prepare_mesh(&mut verts.0, &mut verts.1, options);
+
+        triangles += verts.1.len() as u64;
+
+        let source = TriangleMesh {
+            name: Some(mesh.name.clone()),
+            vertex: &verts.0,
+            faces: &verts.1,
+        };
+
+        let material = match materials.get(mesh.material_index as usize) {
+            Some(m) => *m,
+            None => {
+                *fallback.get_or_insert_with(|| prepared.add_material(default_material(options)))
+            }
+        };
+
+        meshes.push(Some(prepared.add_mesh(
+            &source,
+            material,
+            path,
+            &asset_store,
+            options,
+        )));
+    }
+
+    convert_node(
+        &mut prepared,
+        node,
+        None,
+        &nalgebra::Matrix4::identity(),
+        &ai_scene.meshes,
+        &meshes,
+        &mut bounds,
+    );
+
+    let mut scene = prepared.publish(&mut state.lock().unwrap(), asset_store);
+
+    scene.info.bounds = bounds;
+    scene.info.triangles = triangles;
+    scene.info.cleanup = cleanup;
+
+    Ok(scene)
+}
+
+/// Assimp matrices are row-major; NOODLES wants column-major
+fn convert_matrix(m: &Matrix4x4) -> nalgebra::Matrix4<f32> {
+    nalgebra::Matrix4::new(
+        m.a1, m.a2, m.a3, m.a4, //
+        m.b1, m.b2, m.b3, m.b4, //
+        m.c1, m.c2, m.c3, m.c4, //
+        m.d1, m.d2, m.d3, m.d4,
+    )
+}
+
+/// Recursively convert each assimp node to an entity.
+///
+/// Meshes on a node become child entities, as a NOODLES entity can only have
+/// one representation. Entities are added in order, with the root node
+/// first.
+fn convert_node(
+    prepared: &mut Prepared,
+    node: &Rc<Node>,
+    parent: Option<usize>,
+    parent_tf: &nalgebra::Matrix4<f32>,
+    ai_meshes: &[Mesh],
+    meshes: &[Option<usize>],
+    bounds: &mut Option<Bounds>,
+) {
+    let local = convert_matrix(&node.transformation);
+    let world = parent_tf * local;
+
+    let tf: [f32; 16] = local.as_slice().try_into().unwrap();
+
+    let entity = prepared.add_entity(PreparedEntity {
+        name: Some(node.name.clone()),
+        parent,
+        transform: Some(tf),
+        mesh: None,
+    });
+
+    for mesh_id in &node.meshes {
+        let Some(Some(mesh)) = meshes.get(*mesh_id as usize) else {
+            continue;
+        };
+
+        let ai_mesh = &ai_meshes[*mesh_id as usize];
+
+        let points: Vec<_> = ai_mesh.vertices.iter().map(|f| [f.x, f.y, f.z]).collect();
+
+        if let Some(b) = Bounds::from_points(points.iter()) {
+            let b = b.transformed(&world);
+            *bounds = Some(bounds.map_or(b, |f| f.union(&b)));
+        }
+
+        prepared.add_entity(PreparedEntity {
+            name: Some(ai_mesh.name.clone()),
+            parent: Some(entity),
+            mesh: Some(*mesh),
+            ..Default::default()
+        });
+    }
+
+    for child in node.children.borrow().iter() {
+        convert_node(
+            prepared,
+            child,
+            Some(entity),
+            &world,
+            ai_meshes,
+            meshes,
+            bounds,
+        );
+    }
+}
+
+/// Pack a triangle mesh into vertices and faces. Returns None if the mesh has
+/// no triangles.
+fn pack_mesh(mesh: &Mesh) -> Option<(Vec<VertexTexture>, Vec<[u32; 3]>)> {
+    let faces: Vec<[u32; 3]> = mesh
+        .faces
+        .iter()
+        .filter_map(|f| f.0.as_slice().try_into().ok())
+        .collect();
+
+    if faces.is_empty() {
+        return None;
+    }
+
+    let uvs = mesh.texture_coords.first().and_then(|f| f.as_ref());
+
+    let verts = mesh
+        .vertices
+        .iter()
+        .enumerate()
+        .map(|(i, p)| VertexTexture {
+            position: [p.x, p.y, p.z],
+            normal: mesh
+                .normals
+                .get(i)
+                .map(|n| [n.x, n.y, n.z])
+                .unwrap_or_default(),
+            texture: uvs
+                .and_then(|f| f.get(i))
+                .map(|t| {
+                    [
+                        (t.x.clamp(0.0, 1.0) * (65536.0 - 1.0)) as u16,
+                        (t.y.clamp(0.0, 1.0) * (65536.0 - 1.0)) as u16,
+                    ]
+                })
+                .unwrap_or_default(),
+        })
+        .collect();
+
+    Some((verts, faces))
+}
+
+/// Find a float property on an assimp material
+fn float_property<'a>(mat: &'a Material, key: &str) -> Option<&'a [f32]> {
+    mat.properties.iter().find_map(|f| match &f.data {
+        PropertyTypeInfo::FloatArray(v) if f.key == key => Some(v.as_slice()),
+        _ => None,
+    })
+}
+
+/// Find the file name of a texture on an assimp material
+fn texture_property(mat: &Material, semantic: TextureType) -> Option<&str> {
+    mat.properties.iter().find_map(|f| match &f.data {
+        PropertyTypeInfo::String(s) if f.key == "$tex.file" && f.semantic == semantic => {
+            Some(s.as_str())
+        }
+        _ => None,
+    })
+}
+
+fn default_material(options: &ImportOptions) -> PreparedMaterial {
+    PreparedMaterial {
+        pbr: PBRInfo {
+            base_color: options.tinted([1.0, 1.0, 1.0, 1.0]),
+            metallic: Some(0.0),
+            roughness: Some(1.0),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Convert an assimp material to a NOODLES material.
+///
+/// Only the base color, a few PBR factors, and the base color texture are
+/// carried over.
+fn convert_material(
+    prepared: &mut Prepared,
+    mat: &Material,
+    base_dir: &Path,
+    asset_store: &AssetStorePtr,
+    source: &Path,
+    options: &ImportOptions,
+) -> PreparedMaterial {
+    let mut base_color = [1.0, 1.0, 1.0, 1.0];
+
+    // Prefer the PBR color if the exporter wrote one
+    if let Some(c) = float_property(mat, "$clr.base").or(float_property(mat, "$clr.diffuse")) {
+        for (dst, src) in base_color.iter_mut().zip(c) {
+            *dst = *src;
+        }
+    }
+
+    if let Some(opacity) = float_property(mat, "$mat.opacity").and_then(|f| f.first()) {
+        base_color[3] *= opacity;
+    }
+
+    let emissive = float_property(mat, "$clr.emissive")
+        .and_then(|f| f.get(0..3))
+        .map(|f| [f[0], f[1], f[2]])
+        .filter(|f| f.iter().any(|c| *c > 0.0));
+
+    let texture = texture_property(mat, TextureType::BaseColor)
+        .or(texture_property(mat, TextureType::Diffuse))
+        .and_then(|f| {
+            if f.starts_with('*') {
+                log::warn!("Embedded texture {f} is not supported yet");
+                return None;
+            }
+
+            // Exporters often write absolute or backslashed paths
+            let f = f.replace('\\', "/");
+
+            let bytes = match std::fs::read(base_dir.join(&f))
+                .map_err(anyhow::Error::from)
+                .and_then(|f| color::color_texture(f, None))
+            {
+                Ok(x) => texture::fit(x, options),
+                Err(e) => {
+                    log::warn!("Unable to read texture {f}: {e}");
+                    return None;
+                }
+            };
+
+            Some(prepared.add_texture(Some(f), &bytes, source, asset_store, options))
+        });
+
+    PreparedMaterial {
+        name: None,
+        pbr: PBRInfo {
+            base_color: options.tinted(base_color),
+            metallic: Some(
+                float_property(mat, "$mat.metallicFactor")
+                    .and_then(|f| f.first().copied())
+                    .unwrap_or(0.0),
+            ),
+            roughness: Some(
+                float_property(mat, "$mat.roughnessFactor")
+                    .and_then(|f| f.first().copied())
+                    .unwrap_or(1.0),
+            ),
+            ..Default::default()
+        },
+        texture,
+        emissive,
+        use_alpha: (base_color[3] < 1.0).then_some(true),
+    }
+}
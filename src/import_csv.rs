@@ -0,0 +1,483 @@
+//! Import tabular data (CSV/TSV) as point clouds or instanced glyphs
+
+use std::{
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::Result;
+use nalgebra::{Matrix4, Vector3};
+
+use crate::capabilities::Capability;
+use crate::color::{self, ColorSpace};
+use crate::colormap::Colormap;
+use crate::geometry::TriangleMesh;
+use crate::import::{self, ImportError, ImportOptions};
+use crate::points::{PointChunk, PointCloud, POINTS_PER_CHUNK};
+use crate::scene::{Bounds, Scene, SceneObject};
+use colabrodo_common::components::*;
+use colabrodo_server::{
+    server_bufferbuilder::*, server_http::*, server_messages::*, server_state::*,
+};
+
+/// How to turn table rows into content
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableOptions {
+    /// Names of the x, y, and z columns
+    pub position: [String; 3],
+
+    /// Column to color rows by, using the import colormap. Rows are white
+    /// if unset.
+    pub color_column: Option<String>,
+
+    /// If set, rows become instanced cubes of this size instead of points
+    pub glyph_size: Option<f32>,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        Self {
+            position: ["x".into(), "y".into(), "z".into()],
+            color_column: None,
+            glyph_size: None,
+        }
+    }
+}
+
+/// Split a line on a delimiter, honoring double quotes
+fn split_record(line: &str, delimiter: char) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => ret.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+
+    ret.push(field);
+
+    ret.iter_mut().for_each(|f| *f = f.trim().to_string());
+
+    ret
+}
+
+/// Pick the delimiter that splits the header the most
+fn guess_delimiter(header: &str) -> char {
+    [',', '\t', ';']
+        .into_iter()
+        .max_by_key(|d| header.matches(*d).count())
+        .unwrap()
+}
+
+/// The rows we care about
+#[derive(Debug, Default)]
+struct Table {
+    positions: Vec<[f64; 3]>,
+    values: Vec<f32>,
+    /// Rows skipped for NaN or infinite positions
+    nonfinite: u64,
+}
+
+fn read_table<R: BufRead>(reader: R, options: &TableOptions) -> Result<Table> {
+    let mut lines = reader.lines();
+
+    let header = loop {
+        match lines.next() {
+            Some(line) => {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    break line;
+                }
+            }
+            None => return Err(ImportError::UnableToImport("Table is empty".into()).into()),
+        }
+    };
+
+    let delimiter = guess_delimiter(&header);
+    let names = split_record(&header, delimiter);
+
+    let find = |name: &str| {
+        names
+            .iter()
+            .position(|f| f.eq_ignore_ascii_case(name))
+            .ok_or_else(|| ImportError::UnableToImport(format!("Missing column '{name}'")))
+    };
+
+    let axes = [
+        find(&options.position[0])?,
+        find(&options.position[1])?,
+        find(&options.position[2])?,
+    ];
+
+    let color = options.color_column.as_deref().map(find).transpose()?;
+
+    let mut table = Table::default();
+
+    for line in lines {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = split_record(&line, delimiter);
+
+        let parse = |i: usize| record.get(i).and_then(|f| f.parse::<f64>().ok());
+
+        let (Some(x), Some(y), Some(z)) = (parse(axes[0]), parse(axes[1]), parse(axes[2])) else {
+            log::debug!("Skipping row: {line}");
+            continue;
+        };
+
+        if ![x, y, z].iter().all(|f| f.is_finite()) {
+            table.nonfinite += 1;
+            continue;
+        }
+
+        table.positions.push([x, y, z]);
+
+        if let Some(c) = color {
+            table.values.push(parse(c).map_or(f32::NAN, |f| f as f32));
+        }
+    }
+
+    if table.positions.is_empty() {
+        return Err(ImportError::UnableToImport("No rows found".into()).into());
+    }
+
+    Ok(table)
+}
+
+impl Table {
+    /// Color each row by its value, stretched over the range of the column
+    fn colors(&self, colormap: Colormap) -> Vec<[u8; 4]> {
+        if self.values.is_empty() {
+            return vec![[255; 4]; self.positions.len()];
+        }
+
+        colormap.colorize(&self.values)
+    }
+}
+
+/// A cube of the given size, centered on the origin, with a vertex per face
+/// corner
+fn cube(size: f32) -> (Vec<VertexTexture>, Vec<[u32; 3]>) {
+    let h = size / 2.0;
+
+    let mut verts = Vec::new();
+    let mut faces = Vec::new();
+
+    for axis in 0..3 {
+        for sign in [-1.0f32, 1.0] {
+            let mut n = [0.0; 3];
+            n[axis] = sign;
+
+            // Two directions across the face, ordered so it faces outward
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            let (u, v) = if sign > 0.0 { (u, v) } else { (v, u) };
+
+            let base = verts.len() as u32;
+
+            for (a, b) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let mut p = [0.0; 3];
+                p[axis] = sign * h;
+                p[u] = a * h;
+                p[v] = b * h;
+                verts.push(VertexTexture {
+                    position: p,
+                    normal: n,
+                    texture: [0; 2],
+                });
+            }
+
+            faces.push([base, base + 1, base + 2]);
+            faces.push([base, base + 2, base + 3]);
+        }
+    }
+
+    (verts, faces)
+}
+
+/// Pack rows as NOODLES instances: a matrix whose columns are position,
+/// color, rotation, and scale
+fn pack_instances(positions: &[[f32; 3]], colors: &[[u8; 4]], space: ColorSpace) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(positions.len() * 64);
+
+    for (p, c) in positions.iter().zip(colors) {
+        let color = color::vertex_color(*c, space).map(|f| f as f32 / 255.0);
+        let columns = [
+            [p[0], p[1], p[2], 1.0],
+            color,
+            [0.0, 0.0, 0.0, 1.0],
+            [1.0, 1.0, 1.0, 1.0],
+        ];
+
+        for v in columns.iter().flatten() {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    bytes
+}
+
+/// Publish rows as instanced cubes under a group placed at `origin`. The
+/// state is only locked once the cube and instances are packed.
+#[allow(clippy::too_many_arguments)]
+fn publish_glyphs(
+    state: &ServerStatePtr,
+    asset_store: AssetStorePtr,
+    path: &Path,
+    options: &ImportOptions,
+    positions: &[[f32; 3]],
+    colors: &[[u8; 4]],
+    origin: &Matrix4<f32>,
+    size: f32,
+) -> Result<Scene> {
+    options.capabilities.require(Capability::Instances)?;
+
+    let mut published = Vec::new();
+
+    let (verts, faces) = cube(size);
+
+    let source = TriangleMesh {
+        name: None,
+        vertex: &verts,
+        faces: &faces,
+    };
+
+    let mesh = source.publish(&asset_store, path, options, &mut published);
+
+    let instances = pack_instances(positions, colors, options.vertex_colors);
+    let size_bytes = instances.len() as u64;
+    let instances = import::publish_buffer(&asset_store, path, &instances, options, &mut published);
+
+    let mut state = state.lock().unwrap();
+
+    // Instance colors multiply the material
+    let material = state.materials.new_component(ServerMaterialState {
+        name: None,
+        mutable: ServerMaterialStateUpdatable {
+            pbr_info: Some(PBRInfo {
+                base_color: options.tinted([1.0; 4]),
+                metallic: Some(0.0),
+                roughness: Some(1.0),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    });
+
+    let geom = mesh.build_geometry(&mut state, material.clone());
+
+    let buffer = instances.create(&mut state);
+
+    let view = state.buffer_views.new_component(ServerBufferViewState {
+        name: None,
+        source_buffer: buffer,
+        view_type: BufferViewType::Unknown,
+        offset: 0,
+        length: size_bytes,
+    });
+
+    let local = Bounds::from_points(positions.iter()).map(|b| {
+        let pad = Vector3::repeat(size / 2.0);
+        Bounds {
+            min: b.min - pad,
+            max: b.max + pad,
+        }
+    });
+
+    let root = state.entities.new_component(ServerEntityState {
+        name: import::display_name(path),
+        mutable: Default::default(),
+    });
+
+    let glyphs = state.entities.new_component(ServerEntityState {
+        name: None,
+        mutable: ServerEntityStateUpdatable {
+            parent: Some(root.clone()),
+            transform: Some(origin.as_slice().try_into().unwrap()),
+            representation: Some(ServerEntityRepresentation::new_render(
+                RenderRepresentation {
+                    mesh: geom,
+                    instances: Some(ServerGeometryInstance {
+                        view,
+                        stride: None,
+                        bb: local.map(|b| BoundingBox {
+                            min: b.min.into(),
+                            max: b.max.into(),
+                        }),
+                    }),
+                },
+            )),
+            ..Default::default()
+        },
+    });
+
+    let mut scene = Scene::new(
+        SceneObject {
+            parts: vec![root, glyphs],
+            children: vec![],
+        },
+        published,
+        Some(asset_store),
+    );
+
+    scene.materials.push(material);
+
+    scene.info.bounds = local.map(|b| b.transformed(origin));
+    scene.info.triangles = (faces.len() * positions.len()) as u64;
+
+    Ok(scene)
+}
+
+/// Import a CSV or TSV table
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let file = BufReader::new(std::fs::File::open(path)?);
+
+    let table = read_table(file, &options.table)?;
+
+    let colors = table.colors(options.colormap);
+
+    // Keep positions relative to the first row, so large coordinates
+    // survive the trip to f32
+    let first = table.positions[0];
+    let origin = Matrix4::new_translation(&Vector3::from(first.map(|f| f as f32)));
+
+    let positions: Vec<[f32; 3]> = table
+        .positions
+        .iter()
+        .map(|p| [0, 1, 2].map(|a| (p[a] - first[a]) as f32))
+        .collect();
+
+    log::info!("Table with {} rows", positions.len());
+
+    let mut scene = match options.table.glyph_size {
+        Some(size) => publish_glyphs(
+            &state,
+            asset_store,
+            path,
+            options,
+            &positions,
+            &colors,
+            &origin,
+            size,
+        )?,
+        None => publish_points(
+            &state,
+            asset_store,
+            path,
+            options,
+            &positions,
+            &colors,
+            origin,
+        )?,
+    };
+
+    scene.info.cleanup.nonfinite = table.nonfinite;
+
+    Ok(scene)
+}
+
+/// Publish rows as a point cloud. The state is only locked once the points
+/// are packed.
+fn publish_points(
+    state: &ServerStatePtr,
+    asset_store: AssetStorePtr,
+    path: &Path,
+    options: &ImportOptions,
+    positions: &[[f32; 3]],
+    colors: &[[u8; 4]],
+    origin: Matrix4<f32>,
+) -> Result<Scene> {
+    let mut cloud = PointCloud::new(asset_store, path, options)?;
+    cloud.start(origin);
+
+    for (p, c) in positions
+        .chunks(POINTS_PER_CHUNK)
+        .zip(colors.chunks(POINTS_PER_CHUNK))
+    {
+        let mut chunk = PointChunk::with_capacity(p.len());
+
+        for (p, c) in p.iter().zip(c) {
+            chunk.push(*p, *c);
+        }
+
+        cloud.publish(chunk)?;
+    }
+
+    cloud.finish(&mut state.lock().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_record() {
+        assert_eq!(split_record("a, b,c", ','), vec!["a", "b", "c"]);
+        assert_eq!(
+            split_record("\"x, y\",\"say \"\"hi\"\"\",3", ','),
+            vec!["x, y", "say \"hi\"", "3"]
+        );
+        assert_eq!(guess_delimiter("x\ty\tz"), '\t');
+        assert_eq!(guess_delimiter("x;y;z"), ';');
+    }
+
+    #[test]
+    fn test_read_table() {
+        let src = "X,Y,Z,temp,label\n1,2,3,10,a\n4,5,6,,b\nbad,row\nNaN,1,1,50,d\n7,8,9,30,c\n";
+
+        let options = TableOptions {
+            color_column: Some("temp".into()),
+            ..Default::default()
+        };
+
+        let table = read_table(src.as_bytes(), &options).unwrap();
+
+        assert_eq!(
+            table.positions,
+            vec![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0], [7.0, 8.0, 9.0]]
+        );
+        assert_eq!(table.nonfinite, 1);
+
+        let colors = table.colors(Colormap::Gray);
+        assert_eq!(colors[0], [0, 0, 0, 255]);
+        assert_eq!(colors[1], [128, 128, 128, 255]);
+        assert_eq!(colors[2], [255, 255, 255, 255]);
+
+        let options = TableOptions {
+            color_column: Some("missing".into()),
+            ..Default::default()
+        };
+        assert!(read_table(src.as_bytes(), &options).is_err());
+    }
+
+    #[test]
+    fn test_cube() {
+        let (verts, faces) = cube(2.0);
+        assert_eq!(verts.len(), 24);
+        assert_eq!(faces.len(), 12);
+
+        // Every triangle winds to face along its normal
+        for [a, b, c] in faces {
+            let p = |i: u32| Vector3::from(verts[i as usize].position);
+            let n = Vector3::from(verts[a as usize].normal);
+            assert!((p(b) - p(a)).cross(&(p(c) - p(a))).dot(&n) > 0.0);
+        }
+    }
+}
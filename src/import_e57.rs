@@ -0,0 +1,644 @@
+//! Import E57 scans
+//!
+//! E57 files are a paged binary container: every page ends in a checksum,
+//! which readers skip to get the logical content. An XML section describes
+//! each scan, and point data lives in bit-packed "compressed vector"
+//! sections.
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::Result;
+use nalgebra::{Matrix4, Quaternion, Translation3, UnitQuaternion};
+
+use crate::import::{ImportError, ImportOptions};
+use crate::points::{PointChunk, PointCloud, POINTS_PER_CHUNK};
+use crate::scene::Scene;
+use colabrodo_server::{server_http::*, server_state::*};
+
+const SIGNATURE: &[u8] = b"ASTM-E57";
+
+/// Bytes at the end of each page holding a checksum
+const CHECKSUM_SIZE: u64 = 4;
+
+/// Reads the logical content of an E57 file, skipping page checksums
+struct PagedReader<R> {
+    inner: R,
+    page_size: u64,
+    /// Current physical offset
+    offset: u64,
+}
+
+impl<R: Read + Seek> PagedReader<R> {
+    fn new(inner: R, page_size: u64) -> Self {
+        Self {
+            inner,
+            page_size,
+            offset: 0,
+        }
+    }
+
+    fn seek_physical(&mut self, offset: u64) -> Result<()> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.offset = offset;
+        Ok(())
+    }
+
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        let data_size = self.page_size - CHECKSUM_SIZE;
+
+        while !buf.is_empty() {
+            let in_page = self.offset % self.page_size;
+
+            if in_page >= data_size {
+                self.seek_physical(self.offset - in_page + self.page_size)?;
+                continue;
+            }
+
+            let n = ((data_size - in_page) as usize).min(buf.len());
+            let (now, rest) = buf.split_at_mut(n);
+            self.inner.read_exact(now)?;
+            self.offset += n as u64;
+            buf = rest;
+        }
+
+        Ok(())
+    }
+
+    fn read_vec(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut ret = vec![0; len];
+        self.read_exact(&mut ret)?;
+        Ok(ret)
+    }
+}
+
+fn truncated() -> anyhow::Error {
+    ImportError::UnableToImport("Truncated E57 data".into()).into()
+}
+
+fn le_u16(b: &[u8], at: usize) -> Result<u16> {
+    b.get(at..at + 2)
+        .map(|f| u16::from_le_bytes(f.try_into().unwrap()))
+        .ok_or_else(truncated)
+}
+
+fn le_u64(b: &[u8], at: usize) -> Result<u64> {
+    b.get(at..at + 8)
+        .map(|f| u64::from_le_bytes(f.try_into().unwrap()))
+        .ok_or_else(truncated)
+}
+
+/// How a single field of a point record is encoded
+#[derive(Debug, Clone, PartialEq)]
+enum FieldKind {
+    Float {
+        bits: u32,
+    },
+    Integer {
+        min: i64,
+        bits: u32,
+    },
+    Scaled {
+        min: i64,
+        bits: u32,
+        scale: f64,
+        offset: f64,
+    },
+}
+
+impl FieldKind {
+    fn bits(&self) -> u32 {
+        match self {
+            FieldKind::Float { bits }
+            | FieldKind::Integer { bits, .. }
+            | FieldKind::Scaled { bits, .. } => *bits,
+        }
+    }
+
+    fn decode(&self, raw: u64) -> f64 {
+        match self {
+            FieldKind::Float { bits: 32 } => f32::from_bits(raw as u32) as f64,
+            FieldKind::Float { .. } => f64::from_bits(raw),
+            FieldKind::Integer { min, .. } => (*min + raw as i64) as f64,
+            FieldKind::Scaled {
+                min, scale, offset, ..
+            } => (*min + raw as i64) as f64 * scale + offset,
+        }
+    }
+}
+
+/// Number of bits needed to store every integer in min..=max
+fn bits_for_range(min: i64, max: i64) -> u32 {
+    let span = max.abs_diff(min);
+    u64::BITS - span.leading_zeros()
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    kind: FieldKind,
+    /// Decoded range, for normalizing colors and intensity
+    range: (f64, f64),
+}
+
+#[derive(Debug)]
+struct ScanInfo {
+    name: Option<String>,
+    pose: Matrix4<f32>,
+    offset: u64,
+    count: u64,
+    fields: Vec<Field>,
+}
+
+fn child<'a, 'i>(node: roxmltree::Node<'a, 'i>, name: &str) -> Option<roxmltree::Node<'a, 'i>> {
+    node.children().find(|f| f.tag_name().name() == name)
+}
+
+fn child_f64(node: roxmltree::Node, name: &str) -> Option<f64> {
+    child(node, name)?.text()?.trim().parse().ok()
+}
+
+fn attr<T: std::str::FromStr>(node: roxmltree::Node, name: &str) -> Option<T> {
+    node.attribute(name)?.trim().parse().ok()
+}
+
+fn parse_field(node: roxmltree::Node) -> Option<Field> {
+    let name = node.tag_name().name().to_string();
+
+    let (kind, range) = match node.attribute("type")? {
+        "Float" => {
+            let bits = if node.attribute("precision") == Some("single") {
+                32
+            } else {
+                64
+            };
+            // Float intensities are conventionally 0 - 1 when unbounded
+            let range = (
+                attr(node, "minimum").unwrap_or(0.0),
+                attr(node, "maximum").unwrap_or(1.0),
+            );
+            (FieldKind::Float { bits }, range)
+        }
+        "Integer" => {
+            let min: i64 = attr(node, "minimum")?;
+            let max: i64 = attr(node, "maximum")?;
+            let bits = bits_for_range(min, max);
+            (FieldKind::Integer { min, bits }, (min as f64, max as f64))
+        }
+        "ScaledInteger" => {
+            let min: i64 = attr(node, "minimum")?;
+            let max: i64 = attr(node, "maximum")?;
+            let scale = attr(node, "scale").unwrap_or(1.0);
+            let offset = attr(node, "offset").unwrap_or(0.0);
+            let bits = bits_for_range(min, max);
+            (
+                FieldKind::Scaled {
+                    min,
+                    bits,
+                    scale,
+                    offset,
+                },
+                (min as f64 * scale + offset, max as f64 * scale + offset),
+            )
+        }
+        _ => return None,
+    };
+
+    Some(Field { name, kind, range })
+}
+
+fn parse_pose(node: Option<roxmltree::Node>) -> Matrix4<f32> {
+    let Some(node) = node else {
+        return Matrix4::identity();
+    };
+
+    let rotation = child(node, "rotation").map_or(UnitQuaternion::identity(), |r| {
+        UnitQuaternion::from_quaternion(Quaternion::new(
+            child_f64(r, "w").unwrap_or(1.0) as f32,
+            child_f64(r, "x").unwrap_or_default() as f32,
+            child_f64(r, "y").unwrap_or_default() as f32,
+            child_f64(r, "z").unwrap_or_default() as f32,
+        ))
+    });
+
+    let translation = child(node, "translation").map_or(Translation3::identity(), |t| {
+        Translation3::new(
+            child_f64(t, "x").unwrap_or_default() as f32,
+            child_f64(t, "y").unwrap_or_default() as f32,
+            child_f64(t, "z").unwrap_or_default() as f32,
+        )
+    });
+
+    translation.to_homogeneous() * rotation.to_homogeneous()
+}
+
+/// Find every scan described by the XML section
+fn parse_scans(xml: &str) -> Result<Vec<ScanInfo>> {
+    let doc = roxmltree::Document::parse(xml)?;
+
+    let Some(data3d) = child(doc.root_element(), "data3D") else {
+        return Ok(vec![]);
+    };
+
+    let mut ret = Vec::new();
+
+    for scan in data3d.children().filter(|f| f.is_element()) {
+        let Some(points) = child(scan, "points") else {
+            continue;
+        };
+
+        if points.attribute("type") != Some("CompressedVector") {
+            continue;
+        }
+
+        let (Some(offset), Some(count)) = (attr(points, "fileOffset"), attr(points, "recordCount"))
+        else {
+            continue;
+        };
+
+        let fields = child(points, "prototype")
+            .map(|p| {
+                p.children()
+                    .filter(|f| f.is_element())
+                    .filter_map(|f| {
+                        let ret = parse_field(f);
+                        if ret.is_none() {
+                            log::warn!("Unsupported E57 field {}", f.tag_name().name());
+                        }
+                        ret
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        ret.push(ScanInfo {
+            name: child(scan, "name")
+                .and_then(|f| f.text())
+                .map(|f| f.trim().to_string()),
+            pose: parse_pose(child(scan, "pose")),
+            offset,
+            count,
+            fields,
+        });
+    }
+
+    Ok(ret)
+}
+
+/// Unpacks values from a bytestream, least significant bit first. Bytes
+/// arrive a packet at a time, and values may straddle packets.
+#[derive(Debug, Default)]
+struct BitStream {
+    bytes: VecDeque<u8>,
+    /// Bits of the front byte already consumed
+    bit: u32,
+}
+
+impl BitStream {
+    fn extend(&mut self, b: &[u8]) {
+        self.bytes.extend(b);
+    }
+
+    fn available(&self) -> u64 {
+        (self.bytes.len() as u64 * 8).saturating_sub(self.bit as u64)
+    }
+
+    fn next(&mut self, bits: u32) -> Option<u64> {
+        if bits == 0 {
+            return Some(0);
+        }
+
+        if self.available() < bits as u64 {
+            return None;
+        }
+
+        let mut ret = 0u64;
+        let mut have = 0;
+
+        while have < bits {
+            let byte = *self.bytes.front().unwrap() as u64 >> self.bit;
+            let take = (8 - self.bit).min(bits - have);
+            ret |= (byte & ((1 << take) - 1)) << have;
+            have += take;
+            self.bit += take;
+
+            if self.bit == 8 {
+                self.bytes.pop_front();
+                self.bit = 0;
+            }
+        }
+
+        Some(ret)
+    }
+}
+
+/// Where each kind of value lives in a record
+#[derive(Debug, Default)]
+struct Layout {
+    cartesian: Option<[usize; 3]>,
+    spherical: Option<[usize; 3]>,
+    invalid: Option<usize>,
+    color: Option<[usize; 3]>,
+    intensity: Option<usize>,
+}
+
+impl Layout {
+    fn new(fields: &[Field]) -> Self {
+        let find = |name: &str| fields.iter().position(|f| f.name == name);
+        let find3 = |a, b, c| Some([find(a)?, find(b)?, find(c)?]);
+
+        Self {
+            cartesian: find3("cartesianX", "cartesianY", "cartesianZ"),
+            spherical: find3("sphericalRange", "sphericalAzimuth", "sphericalElevation"),
+            invalid: find("cartesianInvalidState").or(find("sphericalInvalidState")),
+            color: find3("colorRed", "colorGreen", "colorBlue"),
+            intensity: find("intensity"),
+        }
+    }
+}
+
+/// Turn one decoded record into a point, or None if it is marked invalid
+fn make_point(record: &[f64], fields: &[Field], layout: &Layout) -> Option<([f32; 3], [u8; 4])> {
+    if layout.invalid.is_some_and(|i| record[i] != 0.0) {
+        return None;
+    }
+
+    let position = if let Some([x, y, z]) = layout.cartesian {
+        [record[x], record[y], record[z]]
+    } else {
+        let [r, az, el] = layout.spherical?;
+        let (r, az, el) = (record[r], record[az], record[el]);
+        [
+            r * el.cos() * az.cos(),
+            r * el.cos() * az.sin(),
+            r * el.sin(),
+        ]
+    };
+
+    let normalize = |i: usize| {
+        let (lo, hi) = fields[i].range;
+        if hi > lo {
+            ((record[i] - lo) / (hi - lo) * 255.0).clamp(0.0, 255.0) as u8
+        } else {
+            255
+        }
+    };
+
+    let color = if let Some(c) = layout.color {
+        c.map(normalize)
+    } else if let Some(i) = layout.intensity {
+        [normalize(i); 3]
+    } else {
+        [255; 3]
+    };
+
+    Some((
+        position.map(|f| f as f32),
+        [color[0], color[1], color[2], 255],
+    ))
+}
+
+/// Read the points of a scan, handing chunks to `f`
+fn read_scan<R: Read + Seek>(
+    reader: &mut PagedReader<R>,
+    scan: &ScanInfo,
+    chunk_size: usize,
+    mut f: impl FnMut(PointChunk) -> Result<()>,
+) -> Result<()> {
+    let layout = Layout::new(&scan.fields);
+
+    if layout.cartesian.is_none() && layout.spherical.is_none() {
+        return Err(ImportError::UnableToImport("E57 scan has no coordinates".into()).into());
+    }
+
+    // Compressed vector section header
+    reader.seek_physical(scan.offset)?;
+    let header = reader.read_vec(32)?;
+
+    if header[0] != 1 {
+        return Err(ImportError::UnableToImport("Bad E57 compressed vector section".into()).into());
+    }
+
+    reader.seek_physical(le_u64(&header, 16)?)?;
+
+    let mut streams: Vec<BitStream> = scan.fields.iter().map(|_| BitStream::default()).collect();
+
+    let mut record = vec![0.0; scan.fields.len()];
+    let mut done = 0;
+    let mut chunk = PointChunk::with_capacity(chunk_size.min(scan.count as usize));
+
+    while done < scan.count {
+        let head = reader.read_vec(4)?;
+        let packet_length = le_u16(&head, 2)? as usize + 1;
+        let rest = reader.read_vec(packet_length.saturating_sub(4))?;
+
+        // Index and empty packets carry no points
+        if head[0] != 1 {
+            continue;
+        }
+
+        let stream_count = le_u16(&rest, 0)? as usize;
+
+        if stream_count != streams.len() {
+            return Err(ImportError::UnableToImport(format!(
+                "E57 packet has {stream_count} streams, expected {}",
+                streams.len()
+            ))
+            .into());
+        }
+
+        let mut at = 2 + stream_count * 2;
+        for (i, stream) in streams.iter_mut().enumerate() {
+            let len = le_u16(&rest, 2 + i * 2)? as usize;
+            stream.extend(rest.get(at..at + len).ok_or_else(truncated)?);
+            at += len;
+        }
+
+        // Pull out every complete record
+        while done < scan.count {
+            if streams
+                .iter()
+                .zip(&scan.fields)
+                .any(|(s, f)| s.available() < f.kind.bits() as u64)
+            {
+                break;
+            }
+
+            for ((value, stream), field) in record.iter_mut().zip(&mut streams).zip(&scan.fields) {
+                *value = field.kind.decode(stream.next(field.kind.bits()).unwrap());
+            }
+
+            done += 1;
+
+            if let Some((p, c)) = make_point(&record, &scan.fields, &layout) {
+                chunk.push(p, c);
+            }
+
+            if chunk.len() == chunk_size {
+                f(std::mem::take(&mut chunk))?;
+            }
+        }
+    }
+
+    if !chunk.is_empty() {
+        f(chunk)?;
+    }
+
+    Ok(())
+}
+
+/// Import an E57 file, with one entity per scan
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let mut file = std::fs::File::open(path)?;
+
+    let mut header = [0; 48];
+    file.read_exact(&mut header)?;
+
+    if &header[0..8] != SIGNATURE {
+        return Err(ImportError::UnableToImport("Not an E57 file".into()).into());
+    }
+
+    let xml_offset = le_u64(&header, 24)?;
+    let xml_length = le_u64(&header, 32)?;
+    let page_size = le_u64(&header, 40)?;
+
+    if page_size <= CHECKSUM_SIZE {
+        return Err(ImportError::UnableToImport("Bad E57 page size".into()).into());
+    }
+
+    let mut reader = PagedReader::new(std::io::BufReader::new(file), page_size);
+
+    reader.seek_physical(xml_offset)?;
+    let xml = String::from_utf8(reader.read_vec(xml_length as usize)?)?;
+
+    let scans = parse_scans(&xml)?;
+
+    log::info!("E57 with {} scans", scans.len());
+
+    let mut cloud = PointCloud::new(asset_store, path, options)?;
+
+    for scan in &scans {
+        cloud.begin_group(scan.name.clone(), scan.pose, None);
+        read_scan(&mut reader, scan, POINTS_PER_CHUNK, |chunk| {
+            cloud.publish(chunk)
+        })?;
+    }
+
+    let mut scene = cloud.finish(&mut state.lock().unwrap())?;
+
+    // E57 coordinates are always meters
+    scene.info.units = Some("meters".into());
+
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_paged_reader() {
+        // Pages of 8 bytes: 4 data, 4 checksum
+        let data = b"abcdXXXXefghXXXXij";
+        let mut reader = PagedReader::new(Cursor::new(&data[..]), 8);
+
+        reader.seek_physical(2).unwrap();
+        assert_eq!(reader.read_vec(6).unwrap(), b"cdefgh");
+        assert_eq!(reader.read_vec(2).unwrap(), b"ij");
+
+        // Short packets are an error, not a panic
+        assert_eq!(le_u16(b"\x01\x02\x03", 1).unwrap(), 0x0302);
+        assert!(le_u16(b"\x01\x02\x03", 2).is_err());
+        assert!(le_u64(&[0; 7], 0).is_err());
+    }
+
+    #[test]
+    fn test_bitstream() {
+        // 3 bit values 1, 2, 3, 4, 5 packed LSB first
+        let mut packed = 0u32;
+        for (i, v) in [1u32, 2, 3, 4, 5].iter().enumerate() {
+            packed |= v << (i * 3);
+        }
+
+        let bytes = packed.to_le_bytes();
+
+        let mut s = BitStream::default();
+        s.extend(&bytes[..1]);
+        assert_eq!(s.next(3), Some(1));
+        assert_eq!(s.next(3), Some(2));
+        // The third value straddles the byte boundary
+        assert_eq!(s.next(3), None);
+        s.extend(&bytes[1..]);
+        assert_eq!(s.next(3), Some(3));
+        assert_eq!(s.next(3), Some(4));
+        assert_eq!(s.next(3), Some(5));
+
+        assert_eq!(bits_for_range(0, 255), 8);
+        assert_eq!(bits_for_range(-1, 1), 2);
+        assert_eq!(bits_for_range(5, 5), 0);
+    }
+
+    #[test]
+    fn test_parse_scans() {
+        let xml = r#"<?xml version="1.0"?>
+<e57Root type="Structure">
+  <data3D type="Vector" allowHeterogeneousChildren="1">
+    <vectorChild type="Structure">
+      <name type="String"><![CDATA[Station 1]]></name>
+      <pose type="Structure">
+        <rotation type="Structure">
+          <w type="Float">0.70710678</w>
+          <x type="Float">0</x>
+          <y type="Float">0</y>
+          <z type="Float">0.70710678</z>
+        </rotation>
+        <translation type="Structure">
+          <x type="Float">10</x>
+          <y type="Float">0</y>
+          <z type="Float">0</z>
+        </translation>
+      </pose>
+      <points type="CompressedVector" fileOffset="1024" recordCount="3">
+        <prototype type="Structure">
+          <cartesianX type="Float" precision="single"/>
+          <cartesianY type="Float" precision="single"/>
+          <cartesianZ type="ScaledInteger" minimum="-1000" maximum="1000" scale="0.001"/>
+          <colorRed type="Integer" minimum="0" maximum="255"/>
+        </prototype>
+      </points>
+    </vectorChild>
+  </data3D>
+</e57Root>"#;
+
+        let scans = parse_scans(xml).unwrap();
+        assert_eq!(scans.len(), 1);
+
+        let scan = &scans[0];
+        assert_eq!(scan.name.as_deref(), Some("Station 1"));
+        assert_eq!(scan.offset, 1024);
+        assert_eq!(scan.count, 3);
+        assert_eq!(scan.fields.len(), 4);
+        assert_eq!(scan.fields[0].kind, FieldKind::Float { bits: 32 });
+        assert_eq!(scan.fields[2].kind.bits(), 11);
+        assert!((scan.fields[2].kind.decode(1500) - 0.5).abs() < 1e-9);
+
+        // A quarter turn about z, then moved along x
+        let p = scan
+            .pose
+            .transform_point(&nalgebra::Point3::new(1.0, 0.0, 0.0));
+        assert!((p - nalgebra::Point3::new(10.0, 1.0, 0.0)).norm() < 1e-5);
+
+        let layout = Layout::new(&scan.fields);
+        assert_eq!(layout.cartesian, Some([0, 1, 2]));
+        assert!(layout.color.is_none());
+    }
+}
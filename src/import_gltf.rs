@@ -1,11 +1,21 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
 use anyhow::Result;
 
-use crate::scene::{Scene, SceneObject};
+use crate::capabilities::Capability;
+use crate::color::ColorSpace;
+use crate::dir_watcher;
+use crate::fetch;
+use crate::import::{self, AssetKind, ImportError, ImportOptions};
+use crate::scene::{Bounds, Reference, Scene, SceneObject, Viewpoint};
+use crate::texture;
 use colabrodo_common::{components::*, types::Format};
 use colabrodo_server::{server_http::*, server_messages::*, server_state::*};
 use gltf;
+use nalgebra::{Matrix3, Matrix4, Rotation3, UnitQuaternion, Vector3};
 
 /// Trait to convert GLTF enums and values to corresponding NOODLES values
 trait ToNoodles {
@@ -17,10 +27,16 @@ trait ToNoodles {
 impl ToNoodles for gltf::texture::MinFilter {
     type Value = MinFilter;
     fn into_noodles(self) -> Self::Value {
+        // NOODLES only has one mipmapped mode. Keep the choice of whether
+        // to mipmap at all, and let pixel-art style nearest sampling stay
+        // crisp.
         match self {
             gltf::texture::MinFilter::Nearest => MinFilter::Nearest,
             gltf::texture::MinFilter::Linear => MinFilter::Linear,
-            _ => MinFilter::LinearMipmapLinear,
+            gltf::texture::MinFilter::NearestMipmapNearest => MinFilter::Nearest,
+            gltf::texture::MinFilter::LinearMipmapNearest
+            | gltf::texture::MinFilter::NearestMipmapLinear
+            | gltf::texture::MinFilter::LinearMipmapLinear => MinFilter::LinearMipmapLinear,
         }
     }
 }
@@ -106,15 +122,37 @@ impl<'a> ToNoodles for gltf::accessor::Accessor<'a> {
 
 // =============================================================================
 
+/// Convert a KHR_texture_transform into a column-major 3x3 matrix
+fn texture_transform_matrix(tf: &gltf::texture::TextureTransform) -> [f32; 9] {
+    let [ox, oy] = tf.offset();
+    let [sx, sy] = tf.scale();
+    let (sin, cos) = tf.rotation().sin_cos();
+
+    let translation = Matrix3::new(1.0, 0.0, ox, 0.0, 1.0, oy, 0.0, 0.0, 1.0);
+    let rotation = Matrix3::new(cos, sin, 0.0, -sin, cos, 0.0, 0.0, 0.0, 1.0);
+    let scale = Matrix3::new(sx, 0.0, 0.0, 0.0, sy, 0.0, 0.0, 0.0, 1.0);
+
+    (translation * rotation * scale)
+        .as_slice()
+        .try_into()
+        .unwrap()
+}
+
 /// Build a NOODLES texture reference from a list of NOODLES textures from a GLTF 'texture reference'.
 fn fetch_texture_by_info(
     tex_list: &[TextureReference],
     gltf_tex: &gltf::texture::Info,
 ) -> ServerTextureRef {
+    let tf = gltf_tex.texture_transform();
+
     ServerTextureRef {
         texture: tex_list[gltf_tex.texture().index()].clone(),
-        transform: None,
-        texture_coord_slot: Some(gltf_tex.tex_coord()),
+        transform: tf.as_ref().map(texture_transform_matrix),
+        // The transform extension is allowed to override the coord slot
+        texture_coord_slot: Some(
+            tf.and_then(|f| f.tex_coord())
+                .unwrap_or_else(|| gltf_tex.tex_coord()),
+        ),
     }
 }
 
@@ -143,12 +181,12 @@ fn fetch_occ_texture(
 }
 
 /// Create a default material if a GLTF material is missing
-fn make_default_material(state: &mut ServerState) -> MaterialReference {
+fn make_default_material(state: &mut ServerState, options: &ImportOptions) -> MaterialReference {
     state.materials.new_component(ServerMaterialState {
         name: Some("Default".into()),
         mutable: ServerMaterialStateUpdatable {
             pbr_info: Some(PBRInfo {
-                base_color: [1.0; 4],
+                base_color: options.tinted([1.0; 4]),
                 metallic: Some(1.0),
                 roughness: Some(1.0),
                 ..Default::default()
@@ -275,15 +313,45 @@ fn convert_geometry_patch(
     })
 }
 
+/// Convert a GLTF punctual light to a NOODLES light
+fn convert_light(light: &gltf::khr_lights_punctual::Light) -> ServerLightState {
+    // NOODLES uses a negative range to indicate an unlimited range
+    let range = light.range().unwrap_or(-1.0);
+
+    ServerLightState {
+        name: light.name().map(|f| f.to_string()),
+        light_type: match light.kind() {
+            gltf::khr_lights_punctual::Kind::Directional => {
+                LightType::Directional(DirectionalLight { range })
+            }
+            gltf::khr_lights_punctual::Kind::Point => LightType::Point(PointLight { range }),
+            gltf::khr_lights_punctual::Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => LightType::Spot(SpotLight {
+                range,
+                inner_cone_angle_rad: inner_cone_angle,
+                outer_cone_angle_rad: outer_cone_angle,
+            }),
+        },
+        mutable: ServerLightStateUpdatable {
+            color: Some(light.color()),
+            intensity: Some(light.intensity()),
+        },
+    }
+}
+
 /// Recursively convert each GLTF node.
 ///
-/// Takes the NOODLES state to add entities, corresponding GLTF node, an optional NOODLES parent to use, a list of meshes to refer to, and a mapping of GLTF node id to NOODLES entity reference (updated during this call)
+/// Takes the NOODLES state to add entities, corresponding GLTF node, an optional NOODLES parent to use, a list of meshes and lights to refer to, and a mapping of GLTF node id to NOODLES entity reference (updated during this call)
 fn recursive_convert_node(
     state: &mut ServerState,
     node: &gltf::Node,
     parent: Option<EntityReference>,
     n_meshes: &[GeometryReference],
+    n_lights: &[LightReference],
     n_nodes: &mut HashMap<usize, EntityReference>,
+    instanced: &HashSet<usize>,
 ) -> EntityReference {
     // If the node already exists, return it
     if let Some(e) = n_nodes.get(&node.index()) {
@@ -332,6 +400,12 @@ fn recursive_convert_node(
             parent,
             transform: Some(tf),
             representation: rep,
+            lights: node
+                .light()
+                .and_then(|f| n_lights.get(f.index()))
+                .map(|f| vec![f.clone()]),
+            // NOODLES has no cameras, so we just mark the entity
+            tags: node.camera().map(|_| vec!["platter.camera".to_string()]),
             ..Default::default()
         },
     });
@@ -339,9 +413,21 @@ fn recursive_convert_node(
     // Update the node mapping
     n_nodes.insert(node.index(), new_ent.clone());
 
-    // Build all children
+    // Build all children, except those drawn as instances
     for child in node.children() {
-        recursive_convert_node(state, &child, Some(new_ent.clone()), n_meshes, n_nodes);
+        if instanced.contains(&child.index()) {
+            continue;
+        }
+
+        recursive_convert_node(
+            state,
+            &child,
+            Some(new_ent.clone()),
+            n_meshes,
+            n_lights,
+            n_nodes,
+            instanced,
+        );
     }
 
     new_ent
@@ -352,36 +438,140 @@ pub fn import_file(
     path: &Path,
     state: ServerStatePtr,
     asset_store: AssetStorePtr,
+    options: &ImportOptions,
 ) -> Result<Scene> {
-    let mut lock = state.lock().unwrap();
-
     let mut published = Vec::<uuid::Uuid>::new();
 
-    // Import and fetch whatever buffers we can. Note that this will NOT fetch
-    // remote data hosted on external URIs. We will pass those along.
-    let (gltf, buffers) = decode_gltf(path)?;
+    // Import and fetch whatever buffers we can. Remote buffers are only
+    // downloaded if the user asks for it. This is the slow part, so it
+    // happens before the state is locked.
+    let (gltf, buffers) = decode_gltf(path, options)?;
 
-    log::debug!("Starting NOODLES conversion:");
-    let n_buffers: Vec<_> = buffers
+    check_draco(&gltf)?;
+
+    // Unconditionally publish each buffer as a noodles buffer.
+    let data: Vec<_> = buffers
         .iter()
+        .map(|f| {
+            import::publish_buffer(&asset_store, path, f.0.as_slice(), options, &mut published)
+        })
+        .collect();
+
+    // glTF color textures are sRGB; the rest hold data, and are linear
+    let color_images: HashSet<usize> = gltf
+        .materials()
+        .flat_map(|f| {
+            [
+                f.pbr_metallic_roughness().base_color_texture(),
+                f.emissive_texture(),
+            ]
+        })
+        .flatten()
+        .filter_map(|f| texture_image(&f.texture(), options).ok())
+        .collect();
+
+    // KTX2 images from KHR_texture_basisu that no texture shows, as clients
+    // get the fallback image instead
+    let shown: HashSet<usize> = gltf
+        .textures()
+        .filter_map(|f| texture_image(&f, options).ok())
+        .collect();
+
+    let unshown: HashSet<usize> = gltf
+        .textures()
+        .filter_map(|f| basisu_image(&f))
+        .filter(|f| !shown.contains(f))
+        .collect();
+
+    // Read, fetch, and fit images before the state is locked, as remote
+    // images can take a while to arrive. Images we publish ourselves get an
+    // asset; the rest are passed to clients as they are.
+    let images: Vec<_> = gltf
+        .images()
         .enumerate()
-        .map(|(i, f)| {
-            let id = create_asset_id();
+        .map(|(i, img)| {
+            if unshown.contains(&i) {
+                return Ok(None);
+            }
 
-            // Unconditionally publish each buffer as a noodles buffer.
+            let color_space = if color_images.contains(&i) {
+                ColorSpace::Srgb
+            } else {
+                ColorSpace::Linear
+            };
 
-            published.push(id);
+            let mut file = None;
 
-            let res = add_asset(
-                asset_store.clone(),
-                id,
-                Asset::new_from_slice(f.0.as_slice()),
-            );
+            let bytes = match img.source() {
+                // Embedded images that clients can't decode, or that are too
+                // large for them, can't stay in their buffer; they are
+                // transcoded or scaled down and published on their own
+                gltf::image::Source::View { view, .. } => {
+                    let bytes = &buffers[view.buffer().index()][view.offset()..][..view.length()];
+
+                    texture::prepare(bytes, options).unwrap_or_else(|e| {
+                        log::warn!("Unable to prepare image {i}: {e}");
+                        None
+                    })
+                }
+                gltf::image::Source::Uri { uri, .. } => {
+                    file = image_file(uri, path)?;
+
+                    // Republish the image so clients do not need to reach
+                    // the original host, or decode huge URIs.
+                    image_bytes(uri, file.as_deref(), options)?.map(|f| texture::fit(f, options))
+                }
+            };
+
+            let asset = bytes.map(|bytes| {
+                let id = import::asset_id(path, &bytes, options);
+
+                published.push(id);
+                options.asset_sizes.tag_color_space(&id, color_space);
+
+                let url =
+                    import::add_asset(asset_store.clone(), id, &bytes, AssetKind::Image, options);
+
+                (id, url)
+            });
+
+            Ok(Some((asset, file, color_space)))
+        })
+        .collect::<Result<_>>()?;
+
+    drop(buffers);
+
+    let plan = match options.capabilities.has(Capability::Instances) {
+        true => plan_instances(&gltf, options.instance_threshold),
+        false => InstancePlan::default(),
+    };
+
+    // Instance placements are packed and published up front, too
+    let instance_data: Vec<_> = plan
+        .meshes
+        .iter()
+        .map(|(_, _, placements)| {
+            let bytes: Vec<u8> = placements
+                .iter()
+                .filter_map(instance_columns)
+                .flat_map(|f| f.into_iter().flatten())
+                .flat_map(f32::to_le_bytes)
+                .collect();
+
+            import::publish_buffer(&asset_store, path, &bytes, options, &mut published)
+        })
+        .collect();
 
+    let mut lock = state.lock().unwrap();
+
+    log::debug!("Starting NOODLES conversion:");
+    let n_buffers: Vec<_> = data
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
             log::debug!("Adding buffer {i}");
 
-            lock.buffers
-                .new_component(BufferState::new_from_url(&res, f.len() as u64))
+            f.create(&mut lock)
         })
         .collect();
 
@@ -411,35 +601,65 @@ pub fn import_file(
 
     log::debug!("Added {} buffer views", n_buffer_views.len());
 
+    // Images read from files of their own, by image index, so edits to the
+    // files can be published again
+    let mut references = HashMap::<usize, Reference>::new();
+
     let n_images: Vec<_> = gltf
         .images()
+        .zip(images)
         .enumerate()
-        .map(|(_i, img)| {
-            let new_state = ServerImageState {
-                name: img.name().map(|f| f.to_string()),
-                source: match img.source() {
-                    gltf::image::Source::View { view, .. } => {
-                        ImageSource::new_buffer(n_buffer_views[view.index()].clone())
-                    }
-                    gltf::image::Source::Uri { uri, .. } => {
-                        ImageSource::new_uri(uri.parse().unwrap())
-                    }
-                },
+        .map(|(i, (img, image))| {
+            let Some((asset, file, color_space)) = image else {
+                return Ok(None);
+            };
+
+            let source = match (img.source(), &asset) {
+                (_, Some((_, url))) => ImageSource::new_uri(url.clone()),
+                (gltf::image::Source::View { view, .. }, None) => {
+                    ImageSource::new_buffer(n_buffer_views[view.index()].clone())
+                }
+                (gltf::image::Source::Uri { uri, .. }, None) => ImageSource::new_uri(uri.parse()?),
             };
 
-            lock.images.new_component(new_state)
+            let image = lock.images.new_component(ServerImageState {
+                name: img.name().map(|f| f.to_string()),
+                source,
+            });
+
+            if let (Some(file), Some((asset, _))) = (file, asset) {
+                references.insert(
+                    i,
+                    Reference {
+                        stamp: dir_watcher::stamp(&file),
+                        path: file,
+                        image: image.clone(),
+                        asset,
+                        textures: Vec::new(),
+                        color_space,
+                    },
+                );
+            }
+
+            Ok(Some(image))
         })
-        .collect();
+        .collect::<Result<Vec<_>>>()?;
 
-    log::debug!("Added {} images", n_images.len());
+    log::debug!("Added {} images", n_images.iter().flatten().count());
 
     let n_samplers: Vec<_> = gltf
         .samplers()
         .map(|f| {
             lock.samplers.new_component(SamplerState {
                 name: f.name().map(|f| f.to_string()),
-                mag_filter: f.mag_filter().map(|f| f.into_noodles()),
-                min_filter: f.min_filter().map(|f| f.into_noodles()),
+                mag_filter: Some(
+                    f.mag_filter()
+                        .map_or(MagFilter::Linear, |f| f.into_noodles()),
+                ),
+                min_filter: Some(
+                    f.min_filter()
+                        .map_or(MinFilter::LinearMipmapLinear, |f| f.into_noodles()),
+                ),
                 wrap_s: Some(f.wrap_s().into_noodles()),
                 wrap_t: Some(f.wrap_t().into_noodles()),
             })
@@ -448,31 +668,70 @@ pub fn import_file(
 
     log::debug!("Added {} samplers", n_samplers.len());
 
+    // Textures without a sampler use the glTF defaults: repeat, with
+    // filtering left to the implementation. Spell that out rather than
+    // leaving clients to guess.
+    let n_default_sampler = gltf
+        .textures()
+        .any(|f| f.sampler().index().is_none())
+        .then(|| {
+            lock.samplers.new_component(SamplerState {
+                name: Some("Default".into()),
+                mag_filter: Some(MagFilter::Linear),
+                min_filter: Some(MinFilter::LinearMipmapLinear),
+                wrap_s: Some(SamplerMode::Repeat),
+                wrap_t: Some(SamplerMode::Repeat),
+            })
+        });
+
     let n_texture: Vec<_> = gltf
         .textures()
         .map(|f| {
             log::debug!("Adding texture: {:?}", f.index());
-            lock.textures.new_component(ServerTextureState {
+            Ok(lock.textures.new_component(ServerTextureState {
                 name: f.name().map(|f| f.to_string()),
-                image: n_images[f.source().index()].clone(),
-                sampler: f
-                    .sampler()
-                    .index()
-                    .and_then(|id| n_samplers.get(id).cloned()),
-            })
+                image: n_images
+                    .get(texture_image(&f, options)?)
+                    .cloned()
+                    .flatten()
+                    .ok_or_else(|| ImportError::UnableToImport("Bad texture image".into()))?,
+                sampler: match f.sampler().index() {
+                    Some(id) => n_samplers.get(id).cloned(),
+                    None => n_default_sampler.clone(),
+                },
+            }))
         })
-        .collect();
+        .collect::<Result<_>>()?;
 
     log::debug!("Added {} textures", n_texture.len());
 
+    for (texture, n_tex) in gltf.textures().zip(&n_texture) {
+        if let Some(r) = texture_image(&texture, options)
+            .ok()
+            .and_then(|f| references.get_mut(&f))
+        {
+            r.textures.push(n_tex.clone());
+        }
+    }
+
     let n_material: Vec<_> = gltf
         .materials()
         .map(|f| {
+            // NOODLES has no way to express the index of refraction; the
+            // metallic-roughness model assumes the glTF default of 1.5.
+            if let Some(ior) = f.ior() {
+                log::debug!("Material IOR {ior} has no NOODLES equivalent, ignoring");
+            }
+
+            // Emissive strength scales the emissive factor, which is
+            // otherwise limited to [0, 1].
+            let emissive_strength = f.emissive_strength().unwrap_or(1.0);
+
             lock.materials.new_component(ServerMaterialState {
                 name: f.name().map(|f| f.to_string()),
                 mutable: ServerMaterialStateUpdatable {
                     pbr_info: Some(PBRInfo {
-                        base_color: f.pbr_metallic_roughness().base_color_factor(),
+                        base_color: options.tinted(f.pbr_metallic_roughness().base_color_factor()),
                         base_color_texture: f
                             .pbr_metallic_roughness()
                             .base_color_texture()
@@ -493,7 +752,7 @@ pub fn import_file(
                     emissive_texture: f
                         .emissive_texture()
                         .map(|tex| fetch_texture_by_info(&n_texture, &tex)),
-                    emissive_factor: Some(f.emissive_factor()),
+                    emissive_factor: Some(f.emissive_factor().map(|c| c * emissive_strength)),
                     use_alpha: match f.alpha_mode() {
                         gltf::material::AlphaMode::Opaque => None,
                         gltf::material::AlphaMode::Mask => Some(true),
@@ -530,7 +789,7 @@ pub fn import_file(
                             .map(|f| n_material[f].clone())
                             .unwrap_or_else(|| {
                                 if n_default_mat.is_none() {
-                                    n_default_mat = Some(make_default_material(&mut lock))
+                                    n_default_mat = Some(make_default_material(&mut lock, options))
                                 }
                                 n_default_mat.clone().unwrap()
                             });
@@ -546,36 +805,585 @@ pub fn import_file(
 
     log::debug!("Added {}/{} meshes", n_geoms.len(), gltf.meshes().len());
 
+    // Nodes with a light are kept even if lights are disabled; they just
+    // lose the light
+    let n_lights: Vec<_> = gltf
+        .lights()
+        .filter(|_| options.capabilities.has(Capability::Lights))
+        .map(|lights| {
+            lights
+                .map(|f| lock.lights.new_component(convert_light(&f)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    log::debug!("Added {} lights", n_lights.len());
+
     let mut n_nodes = HashMap::<usize, EntityReference>::new();
 
-    for node in gltf.nodes() {
-        recursive_convert_node(&mut lock, &node, None, &n_geoms, &mut n_nodes);
+    for node in gltf.nodes().filter(|f| !plan.nodes.contains(&f.index())) {
+        recursive_convert_node(
+            &mut lock,
+            &node,
+            None,
+            &n_geoms,
+            &n_lights,
+            &mut n_nodes,
+            &plan.nodes,
+        );
     }
 
     log::debug!("Added {} nodes", n_nodes.len());
 
+    let mut n_instanced = Vec::new();
+
+    // Instances hang from the entity of their parent node, or the scene root
+    let root = gltf.nodes().find_map(|n| n_nodes.get(&n.index()).cloned());
+
+    for ((mesh, parent, placements), data) in plan.meshes.iter().zip(instance_data) {
+        let buffer = data.create(&mut lock);
+
+        let view = lock.buffer_views.new_component(ServerBufferViewState {
+            name: None,
+            source_buffer: buffer,
+            view_type: BufferViewType::Unknown,
+            offset: 0,
+            length: data.size(),
+        });
+
+        let source = gltf.meshes().nth(*mesh);
+
+        let bounds = source.as_ref().and_then(|m| {
+            placements
+                .iter()
+                .flat_map(|tf| {
+                    m.primitives().map(|p| {
+                        let bb = p.bounding_box();
+                        Bounds {
+                            min: bb.min.into(),
+                            max: bb.max.into(),
+                        }
+                        .transformed(tf)
+                    })
+                })
+                .reduce(|a, b| a.union(&b))
+        });
+
+        n_instanced.push(
+            lock.entities.new_component(ServerEntityState {
+                name: source.and_then(|m| m.name()).map(|f| f.to_string()),
+                mutable: ServerEntityStateUpdatable {
+                    parent: parent
+                        .and_then(|p| n_nodes.get(&p).cloned())
+                        .or_else(|| root.clone()),
+                    representation: Some(ServerEntityRepresentation::new_render(
+                        RenderRepresentation {
+                            mesh: n_geoms[*mesh].clone(),
+                            instances: Some(ServerGeometryInstance {
+                                view,
+                                stride: None,
+                                bb: bounds.map(|b| BoundingBox {
+                                    min: b.min.into(),
+                                    max: b.max.into(),
+                                }),
+                            }),
+                        },
+                    )),
+                    ..Default::default()
+                },
+            }),
+        );
+    }
+
+    if !plan.meshes.is_empty() {
+        log::debug!(
+            "Drew {} nodes as instances of {} meshes",
+            plan.nodes.len(),
+            plan.meshes.len()
+        );
+    }
+
     let root = SceneObject {
         parts: gltf
             .nodes()
-            .enumerate()
-            .map(|(i, _n)| n_nodes.get(&i).unwrap().clone())
+            .filter_map(|n| n_nodes.get(&n.index()).cloned())
+            .chain(n_instanced)
             .collect(),
         children: vec![],
     };
 
-    Ok(Scene::new(root, published, Some(asset_store)))
+    let mut scene = Scene::new(root, published, Some(asset_store));
+
+    scene.materials = n_material.into_iter().chain(n_default_mat).collect();
+    scene.references = references.into_values().collect();
+
+    let (bounds, triangles) = measure_nodes(&gltf);
+
+    scene.info.bounds = bounds;
+    scene.info.triangles = triangles;
+    scene.viewpoints = collect_viewpoints(&gltf);
+
+    // GLTF is always in meters
+    scene.info.units = Some("meters".into());
+
+    Ok(scene)
+}
+
+/// Meshes placed by enough nodes to draw with instances
+#[derive(Debug, Default)]
+struct InstancePlan {
+    /// Index of each mesh and the parent of the nodes placing it, with the
+    /// transform of each node, relative to that parent. Nodes at the top of
+    /// the scene have no parent. A mesh placed under several parents gets a
+    /// group for each.
+    meshes: Vec<(usize, Option<usize>, Vec<Matrix4<f32>>)>,
+
+    /// Nodes drawn as instances, which get no entity of their own
+    nodes: HashSet<usize>,
+}
+
+/// Find meshes placed by at least `threshold` nodes. Only leaf nodes with
+/// nothing but a mesh are counted, so nothing else is lost by dropping their
+/// entities, and only those whose transform an instance can express.
+fn plan_instances(gltf: &gltf::Document, threshold: usize) -> InstancePlan {
+    let mut plan = InstancePlan::default();
+
+    if threshold == 0 {
+        return plan;
+    }
+
+    let parents: HashMap<usize, usize> = gltf
+        .nodes()
+        .flat_map(|p| p.children().map(move |c| (c.index(), p.index())))
+        .collect();
+
+    let mut uses = BTreeMap::<usize, Vec<(usize, Matrix4<f32>)>>::new();
+    let mut seen = HashSet::new();
+
+    visit_nodes(gltf, |node, _| {
+        let Some(mesh) = node.mesh() else {
+            return;
+        };
+
+        let plain = node.children().len() == 0
+            && node.camera().is_none()
+            && node.light().is_none()
+            && node.skin().is_none()
+            && node.weights().is_none();
+
+        let local = Matrix4::from(node.transform().matrix());
+
+        if plain && instance_columns(&local).is_some() && seen.insert(node.index()) {
+            uses.entry(mesh.index())
+                .or_default()
+                .push((node.index(), local));
+        }
+    });
+
+    for (mesh, list) in uses {
+        if list.len() < threshold {
+            continue;
+        }
+
+        let mut groups = BTreeMap::<Option<usize>, Vec<Matrix4<f32>>>::new();
+
+        for (node, local) in list {
+            plan.nodes.insert(node);
+            groups
+                .entry(parents.get(&node).copied())
+                .or_default()
+                .push(local);
+        }
+
+        plan.meshes.extend(
+            groups
+                .into_iter()
+                .map(|(parent, placements)| (mesh, parent, placements)),
+        );
+    }
+
+    plan
+}
+
+/// Columns of a NOODLES instance placing a mesh by a transform: position,
+/// color, rotation as a quaternion, and scale. Gives None for transforms
+/// that are not a rotation and a positive scale, such as shears and mirrors.
+fn instance_columns(tf: &Matrix4<f32>) -> Option<[[f32; 4]; 4]> {
+    let linear: Matrix3<f32> = tf.fixed_view::<3, 3>(0, 0).into();
+
+    let scale = Vector3::from_iterator(linear.column_iter().map(|c| c.norm()));
+
+    if scale.iter().any(|f| !(*f > 1e-8 && f.is_finite())) {
+        return None;
+    }
+
+    let rotation = Matrix3::from_columns(&[
+        linear.column(0) / scale.x,
+        linear.column(1) / scale.y,
+        linear.column(2) / scale.z,
+    ]);
+
+    let error = (rotation.transpose() * rotation - Matrix3::identity())
+        .abs()
+        .max();
+
+    if error > 1e-3 || rotation.determinant() < 0.0 {
+        return None;
+    }
+
+    let q = UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(rotation));
+    let t = tf.column(3);
+
+    Some([
+        [t.x, t.y, t.z, 1.0],
+        [1.0; 4],
+        [q.i, q.j, q.k, q.w],
+        [scale.x, scale.y, scale.z, 1.0],
+    ])
+}
+
+/// Compute the bounds and triangle count of a GLTF document by walking the
+/// node hierarchy.
+fn measure_nodes(gltf: &gltf::Document) -> (Option<Bounds>, u64) {
+    let mut bounds = Option::<Bounds>::None;
+    let mut triangles = 0;
+
+    visit_nodes(gltf, |node, tf| {
+        let Some(mesh) = node.mesh() else {
+            return;
+        };
+
+        for prim in mesh.primitives() {
+            let bb = prim.bounding_box();
+            let b = Bounds {
+                min: bb.min.into(),
+                max: bb.max.into(),
+            }
+            .transformed(tf);
+
+            bounds = Some(bounds.map_or(b, |f| f.union(&b)));
+
+            let count = prim
+                .indices()
+                .or_else(|| prim.get(&gltf::Semantic::Positions))
+                .map(|f| f.count() as u64)
+                .unwrap_or_default();
+
+            triangles += match prim.mode() {
+                gltf::mesh::Mode::Triangles => count / 3,
+                gltf::mesh::Mode::TriangleStrip | gltf::mesh::Mode::TriangleFan => {
+                    count.saturating_sub(2)
+                }
+                _ => 0,
+            };
+        }
+    });
+
+    (bounds, triangles)
+}
+
+/// Collect the cameras of a GLTF document as viewpoints
+fn collect_viewpoints(gltf: &gltf::Document) -> Vec<Viewpoint> {
+    let mut ret = Vec::new();
+
+    visit_nodes(gltf, |node, tf| {
+        let Some(camera) = node.camera() else {
+            return;
+        };
+
+        ret.push(Viewpoint {
+            name: camera.name().or_else(|| node.name()).map(|f| f.to_string()),
+            transform: *tf,
+            yfov: match camera.projection() {
+                gltf::camera::Projection::Perspective(p) => Some(p.yfov()),
+                gltf::camera::Projection::Orthographic(_) => None,
+            },
+        });
+    });
+
+    ret
+}
+
+/// Visit every node in the hierarchy, along with its transform relative to
+/// the scene root
+fn visit_nodes(gltf: &gltf::Document, mut f: impl FnMut(&gltf::Node, &Matrix4<f32>)) {
+    let children: std::collections::HashSet<_> = gltf
+        .nodes()
+        .flat_map(|f| f.children().map(|c| c.index()))
+        .collect();
+
+    for node in gltf.nodes().filter(|n| !children.contains(&n.index())) {
+        recursive_visit_node(&node, Matrix4::identity(), &mut f);
+    }
+}
+
+fn recursive_visit_node(
+    node: &gltf::Node,
+    parent_tf: Matrix4<f32>,
+    f: &mut impl FnMut(&gltf::Node, &Matrix4<f32>),
+) {
+    let tf = parent_tf * Matrix4::from(node.transform().matrix());
+
+    f(node, &tf);
+
+    for child in node.children() {
+        recursive_visit_node(&child, tf, f);
+    }
+}
+
+const DRACO_EXTENSION: &str = "KHR_draco_mesh_compression";
+
+const BASISU_EXTENSION: &str = "KHR_texture_basisu";
+
+/// Check for Draco mesh compression.
+///
+/// We do not have a Draco decoder. Compressed primitives have no usable
+/// buffer views, so importing them would just publish empty geometry. If the
+/// file provides uncompressed fallbacks (the extension is used, but not
+/// required), we can use those instead.
+fn check_draco(gltf: &gltf::Document) -> Result<()> {
+    if gltf.extensions_required().any(|f| f == DRACO_EXTENSION) {
+        return Err(ImportError::UnableToImport(format!(
+            "{DRACO_EXTENSION} is not supported. Decompress the file first, for example with `gltf-transform copy`"
+        ))
+        .into());
+    }
+
+    if gltf.extensions_used().any(|f| f == DRACO_EXTENSION) {
+        log::info!("File uses {DRACO_EXTENSION}, using uncompressed fallback data");
+    }
+
+    Ok(())
+}
+
+/// Index of the KTX2 image of a texture using `KHR_texture_basisu`
+fn basisu_image(texture: &gltf::Texture) -> Option<usize> {
+    texture
+        .extension_value(BASISU_EXTENSION)
+        .and_then(|f| f.get("source")?.as_u64())
+        .map(|f| f as usize)
+}
+
+/// Index of the image a texture shows. The fallback image is preferred,
+/// unless the `ktx2` capability is enabled; then the KTX2 image from
+/// `KHR_texture_basisu` is. KTX2 images without a fallback are transcoded
+/// as they are published, if clients can't decode them.
+fn texture_image(texture: &gltf::Texture, options: &ImportOptions) -> Result<usize> {
+    let ktx2 = basisu_image(texture);
+
+    let fallback = texture.source().map(|f| f.index());
+
+    let image = match options.capabilities.has(Capability::Ktx2) {
+        true => ktx2.or(fallback),
+        false => fallback.or(ktx2),
+    };
+
+    image.ok_or_else(|| {
+        ImportError::UnableToImport(format!("Texture {} has no image", texture.index())).into()
+    })
+}
+
+/// The file an image URI refers to, if it is a relative reference to a file
+/// next to the source
+fn image_file(uri: &str, path: &Path) -> Result<Option<PathBuf>> {
+    // Relative references are percent encoded paths
+    let Err(url::ParseError::RelativeUrlWithoutBase) = url::Url::parse(uri) else {
+        return Ok(None);
+    };
+
+    let base = std::fs::canonicalize(path)?;
+
+    let file = url::Url::from_file_path(&base)
+        .ok()
+        .and_then(|f| f.join(uri).ok())
+        .and_then(|f| f.to_file_path().ok())
+        .ok_or_else(|| ImportError::UnableToOpenFile(format!("Bad image URI {uri}")))?;
+
+    // Watched by its real path, if it has one
+    Ok(Some(file.canonicalize().unwrap_or(file)))
+}
+
+/// Get the content of an image URI that we should publish ourselves: inline
+/// data, files next to the source, and remote images if we are allowed to
+/// fetch them. Anything else is passed to clients as is.
+fn image_bytes(uri: &str, file: Option<&Path>, options: &ImportOptions) -> Result<Option<Vec<u8>>> {
+    if fetch::is_data(uri) {
+        return Ok(Some(fetch::decode_data(uri)?));
+    }
+
+    if fetch::is_remote(uri) {
+        return match &options.fetch_remote {
+            Some(limits) => Ok(Some(fetch::fetch(uri, limits)?)),
+            None => Ok(None),
+        };
+    }
+
+    if let Some(file) = file {
+        let bytes = std::fs::read(file).map_err(|e| {
+            ImportError::UnableToOpenFile(format!("Unable to read image {}: {e}", file.display()))
+        })?;
+
+        return Ok(Some(bytes));
+    }
+
+    Ok(None)
 }
 
 type Decode = (gltf::Document, Vec<gltf::buffer::Data>);
 
-fn decode_gltf(path: &Path) -> Result<Decode, gltf::Error> {
+fn decode_gltf(path: &Path, options: &ImportOptions) -> Result<Decode> {
     let base = path.parent().unwrap_or_else(|| Path::new("./"));
     let file = std::fs::File::open(path).map_err(gltf::Error::Io)?;
     let reader = std::io::BufReader::new(file);
 
-    let doc = gltf::Gltf::from_reader(reader)?;
+    let mut doc = gltf::Gltf::from_reader_without_validation(reader)?;
+
+    // The gltf crate refuses files that require extensions it doesn't know,
+    // but KTX2 textures are handled here
+    let mut json = doc.document.into_json();
+    json.extensions_required.retain(|f| f != BASISU_EXTENSION);
+    doc.document = gltf::Document::from_json(json)?;
+
+    let mut buffers = Vec::new();
+
+    for buffer in doc.document.buffers() {
+        let data = match (buffer.source(), &options.fetch_remote) {
+            (gltf::buffer::Source::Uri(uri), Some(limits)) if fetch::is_remote(uri) => {
+                gltf::buffer::Data(fetch::fetch(uri, limits)?)
+            }
+            (source, _) => {
+                gltf::buffer::Data::from_source_and_blob(source, Some(base), &mut doc.blob)?
+            }
+        };
+
+        if data.len() < buffer.length() {
+            return Err(ImportError::UnableToImport(format!(
+                "Buffer {} is too short: expected {} bytes, got {}",
+                buffer.index(),
+                buffer.length(),
+                data.len()
+            ))
+            .into());
+        }
 
-    let buffers = gltf::import_buffers(&doc.document, Some(base), doc.blob)?;
+        buffers.push(data);
+    }
 
     Ok((doc.document, buffers))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::capabilities::Capabilities;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_plan_instances() {
+        let json = r#"{
+            "asset": {"version": "2.0"},
+            "buffers": [{"byteLength": 36, "uri": "tri.bin"}],
+            "bufferViews": [{"buffer": 0, "byteLength": 36}],
+            "accessors": [{
+                "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]
+            }],
+            "meshes": [
+                {"primitives": [{"attributes": {"POSITION": 0}}]},
+                {"primitives": [{"attributes": {"POSITION": 0}}]}
+            ],
+            "nodes": [
+                {"children": [1, 2, 3], "translation": [0, 5, 0]},
+                {"mesh": 0, "translation": [1, 0, 0]},
+                {"mesh": 0, "translation": [2, 0, 0], "scale": [2, 2, 2]},
+                {"mesh": 0, "scale": [1, -1, 1]},
+                {"mesh": 0},
+                {"mesh": 1}
+            ],
+            "scenes": [{"nodes": [0, 4, 5]}]
+        }"#;
+
+        let gltf = gltf::Gltf::from_slice(json.as_bytes()).unwrap();
+
+        let plan = plan_instances(&gltf, 2);
+
+        // The mirrored node keeps its own entity, as does the mesh used once
+        assert_eq!(plan.nodes, HashSet::from([1, 2, 4]));
+        assert_eq!(plan.meshes.len(), 2);
+
+        let (mesh, parent, placements) = &plan.meshes[1];
+        assert_eq!((*mesh, *parent), (0, Some(0)));
+
+        // Placements are relative to the parent node
+        let columns: Vec<_> = placements.iter().filter_map(instance_columns).collect();
+        assert_eq!(columns[0][0], [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(columns[1][0], [2.0, 0.0, 0.0, 1.0]);
+        assert_eq!(columns[1][3], [2.0, 2.0, 2.0, 1.0]);
+
+        // The node at the top of the scene is a group of its own
+        let (mesh, parent, placements) = &plan.meshes[0];
+        assert_eq!((*mesh, *parent, placements.len()), (0, None, 1));
+
+        assert!(plan_instances(&gltf, 4).meshes.is_empty());
+        assert!(plan_instances(&gltf, 0).nodes.is_empty());
+    }
+
+    #[test]
+    fn test_instance_columns() {
+        let tf = Matrix4::new_translation(&Vector3::new(1.0, 2.0, 3.0))
+            * Matrix4::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2)
+            * Matrix4::new_nonuniform_scaling(&Vector3::new(3.0, 1.0, 2.0));
+
+        let [position, color, rotation, scale] = instance_columns(&tf).unwrap();
+
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+
+        assert_relative_eq!(position[..], [1.0, 2.0, 3.0, 1.0][..], epsilon = 1e-5);
+        assert_eq!(color, [1.0; 4]);
+        assert_relative_eq!(rotation[..], [0.0, half, 0.0, half][..], epsilon = 1e-5);
+        assert_relative_eq!(scale[..], [3.0, 1.0, 2.0, 1.0][..], epsilon = 1e-5);
+
+        // Shears and collapsed axes can't be expressed
+        let mut shear = Matrix4::identity();
+        shear[(0, 1)] = 1.0;
+        assert!(instance_columns(&shear).is_none());
+        assert!(instance_columns(&Matrix4::new_scaling(0.0)).is_none());
+    }
+
+    #[test]
+    fn test_basisu_textures() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("textured.gltf");
+
+        std::fs::write(
+            &path,
+            r#"{
+                "asset": {"version": "2.0"},
+                "extensionsUsed": ["KHR_texture_basisu"],
+                "extensionsRequired": ["KHR_texture_basisu"],
+                "images": [{"uri": "a.png"}, {"uri": "a.ktx2"}, {"uri": "b.ktx2"}],
+                "textures": [
+                    {"source": 0, "extensions": {"KHR_texture_basisu": {"source": 1}}},
+                    {"extensions": {"KHR_texture_basisu": {"source": 2}}}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        // Files requiring the extension can be read
+        let (gltf, _) = decode_gltf(&path, &ImportOptions::default()).unwrap();
+        let textures: Vec<_> = gltf.textures().collect();
+
+        // The fallback is used where there is one, unless clients take KTX2
+        let mut options = ImportOptions::default();
+        assert_eq!(texture_image(&textures[0], &options).unwrap(), 0);
+        assert_eq!(texture_image(&textures[1], &options).unwrap(), 2);
+
+        options.capabilities = Capabilities::new(&[Capability::Ktx2], &[]);
+        assert_eq!(texture_image(&textures[0], &options).unwrap(), 1);
+        assert_eq!(texture_image(&textures[1], &options).unwrap(), 2);
+
+        assert_eq!(basisu_image(&textures[0]), Some(1));
+        assert_eq!(basisu_image(&textures[1]), Some(2));
+    }
+}
@@ -0,0 +1,246 @@
+//! Import grayscale images as terrain meshes
+
+use std::path::Path;
+
+use anyhow::Result;
+use nalgebra::Vector3;
+
+use crate::geometry::{self, TriangleMesh};
+use crate::import::{self, ImportError, ImportOptions};
+use crate::prepared::{Prepared, PreparedEntity, PreparedMaterial};
+use crate::scene::{Bounds, Scene};
+use colabrodo_server::{
+    server_bufferbuilder::*, server_http::*, server_messages::*, server_state::*,
+};
+
+/// Larger images are sampled down to keep the mesh to about this many
+/// vertices
+const MAX_VERTICES: usize = 1 << 22;
+
+/// How to turn pixels into terrain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeightmapOptions {
+    /// Whether images are imported as heightmaps at all
+    pub enabled: bool,
+
+    /// Horizontal distance between neighboring pixels
+    pub spacing: f32,
+
+    /// Height of a full intensity pixel. Floating point images are used as
+    /// is, and multiplied by this.
+    pub scale: f32,
+}
+
+impl Default for HeightmapOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            spacing: 1.0,
+            scale: 1.0,
+        }
+    }
+}
+
+/// A grid of heights, row by row
+struct Grid {
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+}
+
+impl Grid {
+    fn from_image(image: &image::DynamicImage) -> Result<Self> {
+        // Height needs one channel; colored images are most likely textures
+        if image.color().has_color() {
+            return Err(ImportError::UnableToImport(
+                "Only grayscale images can be used as heightmaps".into(),
+            )
+            .into());
+        }
+
+        let luma = image.to_luma32f();
+
+        let pixels = luma.width() as usize * luma.height() as usize;
+        let step = ((pixels as f64 / MAX_VERTICES as f64).sqrt().ceil() as usize).max(1);
+
+        if step > 1 {
+            log::info!("Heightmap is large, using every {step} pixels");
+        }
+
+        let xs: Vec<u32> = (0..luma.width()).step_by(step).collect();
+        let ys: Vec<u32> = (0..luma.height()).step_by(step).collect();
+
+        let values = ys
+            .iter()
+            .flat_map(|y| xs.iter().map(|x| luma.get_pixel(*x, *y).0[0]))
+            .collect();
+
+        Ok(Self {
+            width: xs.len(),
+            height: ys.len(),
+            values,
+        })
+    }
+
+    fn at(&self, x: usize, y: usize) -> f32 {
+        self.values[y * self.width + x]
+    }
+}
+
+/// Triangulate a grid. Columns run along +x and rows along +z, with height
+/// on +y.
+fn pack_grid(grid: &Grid, spacing: f32, scale: f32) -> (Vec<VertexTexture>, Vec<[u32; 3]>) {
+    let (w, h) = (grid.width, grid.height);
+
+    let uv = |i: usize, n: usize| match n {
+        1 => 0,
+        _ => ((i as f32 / (n - 1) as f32) * u16::MAX as f32) as u16,
+    };
+
+    let mut verts = Vec::with_capacity(w * h);
+
+    for y in 0..h {
+        for x in 0..w {
+            // Central differences, falling back to one side at the edges
+            let (x0, x1) = (x.saturating_sub(1), (x + 1).min(w - 1));
+            let (y0, y1) = (y.saturating_sub(1), (y + 1).min(h - 1));
+
+            let dx = match x1 - x0 {
+                0 => 0.0,
+                d => (grid.at(x1, y) - grid.at(x0, y)) * scale / (d as f32 * spacing),
+            };
+            let dz = match y1 - y0 {
+                0 => 0.0,
+                d => (grid.at(x, y1) - grid.at(x, y0)) * scale / (d as f32 * spacing),
+            };
+
+            verts.push(VertexTexture {
+                position: [
+                    x as f32 * spacing,
+                    grid.at(x, y) * scale,
+                    y as f32 * spacing,
+                ],
+                normal: Vector3::new(-dx, 1.0, -dz).normalize().into(),
+                texture: [uv(x, w), uv(y, h)],
+            });
+        }
+    }
+
+    let mut faces = Vec::with_capacity(w.saturating_sub(1) * h.saturating_sub(1) * 2);
+
+    for y in 0..h.saturating_sub(1) {
+        for x in 0..w.saturating_sub(1) {
+            let i = (y * w + x) as u32;
+            let (right, down) = (i + 1, i + w as u32);
+            faces.push([i, down, right]);
+            faces.push([right, down, down + 1]);
+        }
+    }
+
+    (verts, faces)
+}
+
+/// Import a grayscale image as a terrain mesh
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let image = image::open(path)
+        .map_err(|e| ImportError::UnableToOpenFile(format!("Unable to read image: {e}")))?;
+
+    let grid = Grid::from_image(&image)?;
+
+    if grid.width < 2 || grid.height < 2 {
+        return Err(ImportError::UnableToImport("Heightmap is too small".into()).into());
+    }
+
+    let HeightmapOptions { spacing, scale, .. } = options.heightmap;
+
+    let (mut verts, mut faces) = pack_grid(&grid, spacing, scale);
+
+    // Float TIFFs often mark missing samples with NaN
+    let nonfinite = geometry::scrub(&mut verts, &mut faces);
+
+    let bounds = Bounds::from_points(verts.iter().map(|f| &f.position));
+
+    let source = TriangleMesh {
+        name: None,
+        vertex: &verts,
+        faces: &faces,
+    };
+
+    let mut prepared = Prepared::default();
+
+    let material = prepared.add_material(PreparedMaterial {
+        pbr: PBRInfo {
+            base_color: options.tinted([0.8, 0.8, 0.8, 1.0]),
+            metallic: Some(0.0),
+            roughness: Some(1.0),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
+    let mesh = prepared.add_mesh(&source, material, path, &asset_store, options);
+
+    prepared.add_entity(PreparedEntity {
+        name: import::display_name(path),
+        mesh: Some(mesh),
+        ..Default::default()
+    });
+
+    let mut scene = prepared.publish(&mut state.lock().unwrap(), asset_store);
+
+    scene.info.bounds = bounds;
+    scene.info.triangles = faces.len() as u64;
+    scene.info.cleanup.nonfinite = nonfinite;
+
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pack_grid() {
+        // A ramp rising along x
+        let grid = Grid {
+            width: 3,
+            height: 2,
+            values: vec![0.0, 0.5, 1.0, 0.0, 0.5, 1.0],
+        };
+
+        let (verts, faces) = pack_grid(&grid, 2.0, 4.0);
+
+        assert_eq!(verts.len(), 6);
+        assert_eq!(faces.len(), 4);
+
+        assert_eq!(verts[5].position, [4.0, 4.0, 2.0]);
+        assert_eq!(verts[5].texture, [u16::MAX, u16::MAX]);
+
+        // Slope of 1, so the normal leans back 45 degrees
+        let n = verts[1].normal;
+        assert!((n[0] + std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-5);
+        assert!((n[1] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-5);
+        assert_eq!(n[2], 0.0);
+
+        // Triangles face up
+        let p = |i: u32| Vector3::from(verts[i as usize].position);
+        for [a, b, c] in faces {
+            assert!((p(b) - p(a)).cross(&(p(c) - p(a))).y > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_reject_color() {
+        let image = image::DynamicImage::new_rgb8(4, 4);
+        assert!(Grid::from_image(&image).is_err());
+
+        let image = image::DynamicImage::new_luma16(4, 4);
+        let grid = Grid::from_image(&image).unwrap();
+        assert_eq!((grid.width, grid.height), (4, 4));
+    }
+}
@@ -0,0 +1,336 @@
+//! Import LAS point clouds
+
+use std::{
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::Result;
+use nalgebra::{Matrix4, Vector3};
+
+use crate::import::{ImportError, ImportOptions};
+use crate::points::{PointChunk, PointCloud, POINTS_PER_CHUNK};
+use crate::scene::Scene;
+use colabrodo_server::{server_http::*, server_state::*};
+
+/// The parts of the public header block we need
+#[derive(Debug)]
+struct LasHeader {
+    version: (u8, u8),
+    point_offset: u32,
+    point_format: u8,
+    record_length: u16,
+    point_count: u64,
+    scale: [f64; 3],
+    offset: [f64; 3],
+    min: [f64; 3],
+}
+
+fn read_u16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(b[at..at + 2].try_into().unwrap())
+}
+
+fn read_u32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn read_i32(b: &[u8], at: usize) -> i32 {
+    i32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+fn read_f64(b: &[u8], at: usize) -> f64 {
+    f64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+impl LasHeader {
+    /// Size of a 1.0 - 1.2 header; later versions append to it
+    const MIN_SIZE: usize = 227;
+
+    fn parse(b: &[u8]) -> Result<Self> {
+        if b.len() < Self::MIN_SIZE || &b[0..4] != b"LASF" {
+            return Err(ImportError::UnableToImport("Not a LAS file".into()).into());
+        }
+
+        let version = (b[24], b[25]);
+        let point_format = b[104];
+
+        // LAZ sets the top bits of the format to flag compression
+        if point_format & 0xC0 != 0 {
+            return Err(ImportError::UnableToImport(
+                "LAZ is unsupported: compressed point data can't be decoded yet; \
+                 decompress to LAS first"
+                    .into(),
+            )
+            .into());
+        }
+
+        let xyz = |at: usize| [read_f64(b, at), read_f64(b, at + 8), read_f64(b, at + 16)];
+
+        // Bounds are stored as max x, min x, max y, ...
+        let min = [read_f64(b, 187), read_f64(b, 203), read_f64(b, 219)];
+
+        // 1.4 moved the count to a 64 bit field, leaving the legacy count
+        // zero for large clouds
+        let mut point_count = read_u32(b, 107) as u64;
+
+        if version >= (1, 4) && b.len() >= 255 {
+            point_count = point_count.max(read_u64(b, 247));
+        }
+
+        Ok(Self {
+            version,
+            point_offset: read_u32(b, 96),
+            point_format,
+            record_length: read_u16(b, 105),
+            point_count,
+            scale: xyz(131),
+            offset: xyz(155),
+            min,
+        })
+    }
+
+    /// Offset of the RGB fields in a point record, if the format has them
+    fn color_offset(&self) -> Option<usize> {
+        match self.point_format {
+            2 => Some(20),
+            3 | 5 => Some(28),
+            7 | 8 | 10 => Some(30),
+            _ => None,
+        }
+    }
+
+    /// Smallest record for this point format
+    fn min_record_length(&self) -> Option<usize> {
+        Some(match self.point_format {
+            0 => 20,
+            1 => 28,
+            2 => 26,
+            3 => 34,
+            4 => 57,
+            5 => 63,
+            6 => 30,
+            7 => 36,
+            8 => 38,
+            9 => 59,
+            10 => 67,
+            _ => return None,
+        })
+    }
+}
+
+/// Read point records, handing chunks to `f`. Positions are made relative
+/// to `origin` so they survive the trip to f32.
+fn read_points<R: Read>(
+    mut reader: R,
+    header: &LasHeader,
+    origin: &[f64; 3],
+    chunk_size: usize,
+    mut f: impl FnMut(PointChunk) -> Result<()>,
+) -> Result<()> {
+    let record_length = header.record_length as usize;
+
+    if header.min_record_length().is_none_or(|f| record_length < f) {
+        return Err(ImportError::UnableToImport(format!(
+            "Unsupported point format {} with record length {record_length}",
+            header.point_format
+        ))
+        .into());
+    }
+
+    let color_offset = header.color_offset();
+
+    let capacity = chunk_size.min(header.point_count as usize);
+
+    let mut record = vec![0; record_length];
+    let mut chunk = PointChunk::with_capacity(capacity);
+
+    // Colors are 16 bit, but plenty of writers store 8 bit values in them.
+    // We can't tell until we have seen some, so hold colors wide until the
+    // end of each chunk.
+    let mut wide = Vec::<[u16; 3]>::new();
+
+    let mut flush = |mut chunk: PointChunk, wide: &mut Vec<[u16; 3]>| {
+        let eight_bit = wide.iter().all(|c| c.iter().all(|v| *v <= 255));
+
+        for (i, c) in wide.drain(..).enumerate() {
+            let [r, g, b] = c.map(|v| if eight_bit { v as u8 } else { (v >> 8) as u8 });
+            chunk.set_color(i, [r, g, b, 255]);
+        }
+
+        f(chunk)
+    };
+
+    for _ in 0..header.point_count {
+        reader.read_exact(&mut record)?;
+
+        let mut position = [0.0; 3];
+
+        for (axis, p) in position.iter_mut().enumerate() {
+            let raw = read_i32(&record, axis * 4) as f64;
+            *p = (raw * header.scale[axis] + header.offset[axis] - origin[axis]) as f32;
+        }
+
+        // Colors are matched to points by index, so skip dropped points
+        if !chunk.push(position, [0; 4]) {
+            continue;
+        }
+
+        wide.push(match color_offset {
+            Some(at) => [
+                read_u16(&record, at),
+                read_u16(&record, at + 2),
+                read_u16(&record, at + 4),
+            ],
+            None => {
+                // Without color, show intensity as gray
+                [read_u16(&record, 12); 3]
+            }
+        });
+
+        if chunk.len() == chunk_size {
+            let next = PointChunk::with_capacity(capacity);
+            flush(std::mem::replace(&mut chunk, next), &mut wide)?;
+        }
+    }
+
+    if !chunk.is_empty() {
+        flush(chunk, &mut wide)?;
+    }
+
+    Ok(())
+}
+
+/// Import a LAS point cloud
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let mut file = BufReader::new(std::fs::File::open(path)?);
+
+    let mut header_bytes = vec![0; 375];
+    let read = file.read(&mut header_bytes)?;
+    header_bytes.truncate(read);
+
+    let header = LasHeader::parse(&header_bytes)?;
+
+    log::info!(
+        "LAS {}.{}, format {}, {} points",
+        header.version.0,
+        header.version.1,
+        header.point_format,
+        header.point_count
+    );
+
+    file.seek(SeekFrom::Start(header.point_offset as u64))?;
+
+    let origin = header.min;
+
+    let tf = Matrix4::new_translation(&Vector3::from(origin.map(|f| f as f32)));
+
+    let mut cloud = PointCloud::new(asset_store, path, options)?;
+    cloud.start(tf);
+
+    read_points(&mut file, &header, &origin, POINTS_PER_CHUNK, |chunk| {
+        cloud.publish(chunk)
+    })?;
+
+    let mut scene = cloud.finish(&mut state.lock().unwrap())?;
+
+    // LAS coordinates are almost always projected, in meters
+    scene.info.units = Some("meters".into());
+
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::points::POINT_STRIDE;
+
+    /// Build a small LAS 1.2 file with point format 2
+    fn make_las(points: &[([i32; 3], u16, [u16; 3])]) -> Vec<u8> {
+        let mut b = vec![0; LasHeader::MIN_SIZE];
+        b[0..4].copy_from_slice(b"LASF");
+        b[24] = 1;
+        b[25] = 2;
+        b[94..96].copy_from_slice(&(LasHeader::MIN_SIZE as u16).to_le_bytes());
+        b[96..100].copy_from_slice(&(LasHeader::MIN_SIZE as u32).to_le_bytes());
+        b[104] = 2;
+        b[105..107].copy_from_slice(&26u16.to_le_bytes());
+        b[107..111].copy_from_slice(&(points.len() as u32).to_le_bytes());
+
+        for at in [131, 139, 147] {
+            b[at..at + 8].copy_from_slice(&0.01f64.to_le_bytes());
+        }
+        b[155..163].copy_from_slice(&1000.0f64.to_le_bytes());
+        b[187..195].copy_from_slice(&1000.0f64.to_le_bytes());
+
+        for (xyz, intensity, rgb) in points {
+            let mut rec = vec![0; 26];
+            for (i, v) in xyz.iter().enumerate() {
+                rec[i * 4..i * 4 + 4].copy_from_slice(&v.to_le_bytes());
+            }
+            rec[12..14].copy_from_slice(&intensity.to_le_bytes());
+            for (i, v) in rgb.iter().enumerate() {
+                rec[20 + i * 2..22 + i * 2].copy_from_slice(&v.to_le_bytes());
+            }
+            b.extend(rec);
+        }
+
+        b
+    }
+
+    #[test]
+    fn test_read_points() {
+        let data = make_las(&[
+            ([0, 0, 0], 10, [65535, 0, 0]),
+            ([100, 200, 300], 20, [0, 32768, 0]),
+            ([50, 50, 50], 30, [0, 0, 65535]),
+        ]);
+
+        let header = LasHeader::parse(&data).unwrap();
+        assert_eq!(header.point_count, 3);
+        assert_eq!(header.color_offset(), Some(20));
+
+        let mut chunks = Vec::new();
+
+        read_points(
+            &data[header.point_offset as usize..],
+            &header,
+            &header.min,
+            2,
+            |chunk| {
+                chunks.push(chunk);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].len(), 2);
+        assert_eq!(chunks[1].len(), 1);
+
+        let bounds = chunks[0].bounds.unwrap();
+        assert_eq!(bounds.max, Vector3::new(1.0, 2.0, 3.0));
+
+        // Second point: 1.0, 2.0, 3.0 from the origin, 16 bit green
+        let p = &chunks[0].bytes[POINT_STRIDE..];
+        let y = f32::from_le_bytes(p[4..8].try_into().unwrap());
+        assert!((y - 2.0).abs() < 1e-5);
+        assert_eq!(&p[12..16], &[0, 128, 0, 255]);
+    }
+
+    #[test]
+    fn test_reject_laz() {
+        let mut data = make_las(&[]);
+        data[104] |= 0x80;
+        let err = LasHeader::parse(&data).unwrap_err();
+        assert!(err.to_string().contains("LAZ is unsupported"), "{err}");
+    }
+}
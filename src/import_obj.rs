@@ -3,7 +3,7 @@ use std::{
     fs::File,
     io::{BufRead, BufReader},
     mem::take,
-    path::Path,
+    path::{Path, PathBuf},
     str::SplitWhitespace,
 };
 
@@ -11,9 +11,13 @@ use anyhow::{Context, Result};
 
 use nalgebra::Vector3;
 
-use crate::scene::{Scene, SceneObject};
+use crate::color;
+use crate::geometry::{self, Cleanup, TriangleMesh};
+use crate::import::ImportOptions;
+use crate::prepared::{Prepared, PreparedEntity, PreparedMaterial};
+use crate::scene::{Bounds, Scene};
+use crate::texture;
 
-use colabrodo_common::components::*;
 use colabrodo_server::{
     server_bufferbuilder::*, server_http::*, server_messages::*, server_state::*,
 };
@@ -23,6 +27,7 @@ pub fn import_file(
     path: &Path,
     state: ServerStatePtr,
     asset_store: AssetStorePtr,
+    options: &ImportOptions,
 ) -> Result<Scene> {
     let file = File::open(path)?;
     let mut buf_reader = BufReader::new(file);
@@ -44,68 +49,275 @@ pub fn import_file(
         wfobj.handle(&line);
     }
 
-    let all_objs = pack_wf_state(wfobj);
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("./"));
 
-    let mut lock = state.lock().unwrap();
+    let mtl_defs = load_mtl_libs(base_dir, &wfobj.mtl_libs);
 
-    let published = Vec::<uuid::Uuid>::new();
+    let mut all_objs = pack_wf_state(wfobj, options.generate_normals);
 
-    let mut root = SceneObject {
-        parts: vec![],
-        children: vec![],
-    };
+    if options.deterministic {
+        all_objs.sort_by(|a, b| (&a.name, &a.material).cmp(&(&b.name, &b.material)));
+    }
 
-    for sub_obj in all_objs {
-        let source = VertexSource {
-            name: None,
-            vertex: &sub_obj.verts,
-            index: IndexType::Triangles(&sub_obj.faces),
-        };
+    let mut prepared = Prepared::default();
 
-        let bytes = source.pack_bytes().context("Packing bytes")?;
+    // Materials are added lazily, as they are used
+    let mut materials = HashMap::<Option<String>, usize>::new();
 
-        let asset_id = create_asset_id();
+    let mut bounds = Option::<Bounds>::None;
+    let mut triangles = 0;
+    let mut cleanup = Cleanup::default();
 
-        let url = add_asset(
-            asset_store.clone(),
-            asset_id,
-            Asset::new_from_slice(&bytes.bytes),
-        );
+    for mut sub_obj in all_objs {
+        cleanup += geometry::prepare_mesh(&mut sub_obj.verts, &mut sub_obj.faces, options);
 
-        let material = lock.materials.new_component(ServerMaterialState {
+        if let Some(b) = Bounds::from_points(sub_obj.verts.iter().map(|f| &f.position)) {
+            bounds = Some(bounds.map_or(b, |f| f.union(&b)));
+        }
+        triangles += sub_obj.faces.len() as u64;
+
+        let material = *materials
+            .entry(sub_obj.material.clone())
+            .or_insert_with(|| {
+                let def = sub_obj.material.as_ref().and_then(|f| {
+                    let def = mtl_defs.get(f);
+                    if def.is_none() {
+                        log::warn!("Material {f} is not defined in any material library");
+                    }
+                    def
+                });
+
+                let material =
+                    prepare_material(&mut prepared, def, base_dir, &asset_store, path, options);
+                prepared.add_material(material)
+            });
+
+        let source = TriangleMesh {
             name: None,
-            mutable: ServerMaterialStateUpdatable {
-                pbr_info: Some(PBRInfo {
-                    base_color: [1.0, 1.0, 1.0, 1.0],
-                    metallic: Some(0.0),
-                    roughness: Some(1.0),
-                    ..Default::default()
-                }),
-                ..Default::default()
-            },
-        });
+            vertex: &sub_obj.verts,
+            faces: &sub_obj.faces,
+        };
 
-        let geom_ref = source
-            .build_geometry(&mut lock, BufferRepresentation::Url(url), material)
-            .context("Building geometry")?;
+        let mesh = prepared.add_mesh(&source, material, path, &asset_store, options);
 
-        let entity = lock.entities.new_component(ServerEntityState {
+        prepared.add_entity(PreparedEntity {
             name: Some(sub_obj.name),
-            mutable: ServerEntityStateUpdatable {
-                representation: Some(ServerEntityRepresentation::new_render(
-                    RenderRepresentation {
-                        mesh: geom_ref,
-                        instances: None,
-                    },
-                )),
-                ..Default::default()
-            },
+            mesh: Some(mesh),
+            ..Default::default()
         });
+    }
+
+    let mut scene = prepared.publish(&mut state.lock().unwrap(), asset_store);
+
+    scene.info.bounds = bounds;
+    scene.info.triangles = triangles;
+    scene.info.cleanup = cleanup;
+
+    Ok(scene)
+}
+
+/// A material, as described by an MTL file
+#[derive(Debug, Clone, PartialEq)]
+struct MtlMaterial {
+    /// Diffuse color
+    kd: [f32; 3],
+    /// Specular color
+    ks: [f32; 3],
+    /// Specular exponent
+    ns: Option<f32>,
+    /// Emissive color
+    ke: [f32; 3],
+    /// Opacity
+    d: f32,
+    /// PBR extension: roughness
+    pr: Option<f32>,
+    /// PBR extension: metallic
+    pm: Option<f32>,
+    /// Diffuse texture, relative to the MTL file
+    map_kd: Option<String>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            kd: [1.0; 3],
+            ks: [0.0; 3],
+            ns: None,
+            ke: [0.0; 3],
+            d: 1.0,
+            pr: None,
+            pm: None,
+            map_kd: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    /// Approximate the roughness of this material.
+    ///
+    /// Uses the PBR extension if present, otherwise converts the Phong
+    /// exponent. Materials without a specular color are fully rough.
+    fn roughness(&self) -> f32 {
+        if let Some(pr) = self.pr {
+            return pr.clamp(0.0, 1.0);
+        }
+
+        if self.ks.iter().all(|f| *f <= 0.0) {
+            return 1.0;
+        }
+
+        match self.ns {
+            Some(ns) => (2.0 / (ns.max(0.0) + 2.0)).sqrt(),
+            None => 1.0,
+        }
+    }
+}
+
+fn parse_mtl_floats<const N: usize>(line: SplitWhitespace, default: [f32; N]) -> [f32; N] {
+    let mut ret = default;
+    for (i, f) in line.take(N).enumerate() {
+        ret[i] = f.parse().unwrap_or(default[i]);
+    }
+    ret
+}
+
+/// Parse an MTL file into a map of material name to definition
+fn parse_mtl(reader: impl BufRead) -> HashMap<String, MtlMaterial> {
+    let mut ret = HashMap::new();
+
+    let mut current: Option<(String, MtlMaterial)> = None;
 
-        root.parts.push(entity);
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            break;
+        };
+
+        let mut iter = line.split_whitespace();
+
+        let Some(directive) = iter.next() else {
+            continue;
+        };
+
+        if directive == "newmtl" {
+            if let Some((name, mat)) = current.take() {
+                ret.insert(name, mat);
+            }
+            let name = iter.collect::<Vec<_>>().join(" ");
+            current = Some((name, MtlMaterial::default()));
+            continue;
+        }
+
+        let Some((_, mat)) = current.as_mut() else {
+            continue;
+        };
+
+        match directive {
+            "Kd" => mat.kd = parse_mtl_floats(iter, mat.kd),
+            "Ks" => mat.ks = parse_mtl_floats(iter, mat.ks),
+            "Ke" => mat.ke = parse_mtl_floats(iter, mat.ke),
+            "Ns" => mat.ns = iter.next().and_then(|f| f.parse().ok()),
+            "d" => mat.d = parse_mtl_floats(iter, [mat.d])[0],
+            "Tr" => mat.d = 1.0 - parse_mtl_floats(iter, [1.0 - mat.d])[0],
+            "Pr" => mat.pr = iter.next().and_then(|f| f.parse().ok()),
+            "Pm" => mat.pm = iter.next().and_then(|f| f.parse().ok()),
+            // Texture options come before the file name; we just take the last item
+            "map_Kd" => mat.map_kd = iter.last().map(|f| f.to_string()),
+            _ => (),
+        }
     }
 
-    Ok(Scene::new(root, published, Some(asset_store)))
+    if let Some((name, mat)) = current.take() {
+        ret.insert(name, mat);
+    }
+
+    ret
+}
+
+/// Load all referenced MTL libraries
+fn load_mtl_libs(base_dir: &Path, libs: &[String]) -> HashMap<String, (MtlMaterial, PathBuf)> {
+    let mut ret = HashMap::new();
+
+    for lib in libs {
+        let lib_path = base_dir.join(lib);
+
+        let file = match File::open(&lib_path) {
+            Ok(x) => x,
+            Err(e) => {
+                log::warn!(
+                    "Unable to open material library {}: {e}",
+                    lib_path.display()
+                );
+                continue;
+            }
+        };
+
+        let lib_dir = lib_path.parent().unwrap_or(base_dir).to_path_buf();
+
+        for (name, mat) in parse_mtl(BufReader::new(file)) {
+            ret.insert(name, (mat, lib_dir.clone()));
+        }
+    }
+
+    ret
+}
+
+/// Read a texture image from disk, and publish it
+fn publish_texture(
+    prepared: &mut Prepared,
+    path: &Path,
+    asset_store: &AssetStorePtr,
+    source: &Path,
+    options: &ImportOptions,
+) -> Result<usize> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Reading texture {}", path.display()))?;
+
+    // Only diffuse maps are used, which are color textures
+    let bytes = color::color_texture(bytes, None)?;
+    let bytes = texture::fit(bytes, options);
+
+    let name = path.file_name().map(|f| f.to_string_lossy().to_string());
+
+    Ok(prepared.add_texture(name, &bytes, source, asset_store, options))
+}
+
+/// Prepare a NOODLES material from an MTL definition, or a default material
+/// if there is none.
+fn prepare_material(
+    prepared: &mut Prepared,
+    def: Option<&(MtlMaterial, PathBuf)>,
+    base_dir: &Path,
+    asset_store: &AssetStorePtr,
+    source: &Path,
+    options: &ImportOptions,
+) -> PreparedMaterial {
+    let (mat, dir) = match def {
+        Some((mat, dir)) => (mat.clone(), dir.as_path()),
+        None => (MtlMaterial::default(), base_dir),
+    };
+
+    let texture = mat.map_kd.as_ref().and_then(|f| {
+        match publish_texture(prepared, &dir.join(f), asset_store, source, options) {
+            Ok(texture) => Some(texture),
+            Err(e) => {
+                log::warn!("Unable to load texture: {e:?}");
+                None
+            }
+        }
+    });
+
+    PreparedMaterial {
+        name: None,
+        pbr: PBRInfo {
+            base_color: options.tinted([mat.kd[0], mat.kd[1], mat.kd[2], mat.d]),
+            metallic: Some(mat.pm.unwrap_or(0.0)),
+            roughness: Some(mat.roughness()),
+            ..Default::default()
+        },
+        texture,
+        emissive: mat.ke.iter().any(|f| *f > 0.0).then_some(mat.ke),
+        use_alpha: (mat.d < 1.0).then_some(true),
+    }
 }
 
 type WFFunc = fn(obj: &mut WFObjectState, line: SplitWhitespace) -> Option<()>;
@@ -239,14 +451,19 @@ impl FaceDef {
 
 #[derive(Debug, Clone)]
 enum FaceMarker {
-    Def(FaceDef),
+    /// A face corner, along with the smoothing group of the face, if one was
+    /// given. Group 0 is flat shaded.
+    Def(FaceDef, Option<u32>),
     End,
 }
 
 fn handle_f(obj: &mut WFObjectState, line: SplitWhitespace) -> Option<()> {
     // slightly awkward here to avoid double borrow of obj
     obj.last_face_list.extend(line.map(|f| {
-        FaceMarker::Def(FaceDef::new(f).sanitize(&obj.vert_list, &obj.normal_list, &obj.tex_list))
+        FaceMarker::Def(
+            FaceDef::new(f).sanitize(&obj.vert_list, &obj.normal_list, &obj.tex_list),
+            obj.last_smoothing,
+        )
     }));
     obj.last_face_list.push(FaceMarker::End);
 
@@ -256,6 +473,32 @@ fn handle_f(obj: &mut WFObjectState, line: SplitWhitespace) -> Option<()> {
 fn handle_o(obj: &mut WFObjectState, mut line: SplitWhitespace) -> Option<()> {
     obj.push_object();
     obj.last_name = line.next().unwrap_or("Unknown").to_string();
+    obj.last_group = None;
+    Some(())
+}
+
+fn handle_g(obj: &mut WFObjectState, line: SplitWhitespace) -> Option<()> {
+    obj.push_object();
+    let group = line.collect::<Vec<_>>().join(" ");
+    obj.last_group = (!group.is_empty()).then_some(group);
+    Some(())
+}
+
+fn handle_s(obj: &mut WFObjectState, mut line: SplitWhitespace) -> Option<()> {
+    // 'off' and unparseable groups are treated as flat shading
+    obj.last_smoothing = Some(line.next()?.parse().unwrap_or(0));
+    Some(())
+}
+
+fn handle_mtllib(obj: &mut WFObjectState, line: SplitWhitespace) -> Option<()> {
+    obj.mtl_libs.push(line.collect::<Vec<_>>().join(" "));
+    Some(())
+}
+
+fn handle_usemtl(obj: &mut WFObjectState, line: SplitWhitespace) -> Option<()> {
+    // Faces are split by material, so finish off the faces so far
+    obj.push_object();
+    obj.last_material = Some(line.collect::<Vec<_>>().join(" "));
     Some(())
 }
 
@@ -266,8 +509,14 @@ struct WFObjectState {
     normal_list: Vec<[f32; 3]>,
     tex_list: Vec<[f32; 3]>,
 
-    obj_face_list: HashMap<String, Vec<FaceMarker>>,
+    mtl_libs: Vec<String>,
+
+    /// Faces, keyed by object name and material
+    obj_face_list: HashMap<(String, Option<String>), Vec<FaceMarker>>,
     last_name: String,
+    last_group: Option<String>,
+    last_material: Option<String>,
+    last_smoothing: Option<u32>,
     last_face_list: Vec<FaceMarker>,
 }
 
@@ -280,14 +529,22 @@ impl WFObjectState {
         fn_map.insert("vt".to_string(), handle_vt);
         fn_map.insert("f".to_string(), handle_f);
         fn_map.insert("o".to_string(), handle_o);
+        fn_map.insert("g".to_string(), handle_g);
+        fn_map.insert("s".to_string(), handle_s);
+        fn_map.insert("mtllib".to_string(), handle_mtllib);
+        fn_map.insert("usemtl".to_string(), handle_usemtl);
 
         Self {
             fn_map,
             vert_list: Default::default(),
             normal_list: Default::default(),
             tex_list: Default::default(),
+            mtl_libs: Default::default(),
             obj_face_list: Default::default(),
             last_name: Default::default(),
+            last_group: Default::default(),
+            last_material: Default::default(),
+            last_smoothing: Default::default(),
             last_face_list: Default::default(),
         }
     }
@@ -306,14 +563,23 @@ impl WFObjectState {
             return;
         }
 
-        let mut name = self.last_name.as_str();
+        // Groups are more specific than objects, so prefer them
+        let mut name = self
+            .last_group
+            .as_deref()
+            .unwrap_or(self.last_name.as_str());
         if name.is_empty() {
             name = "Unknown";
         }
 
         let local_vec = take(&mut self.last_face_list);
 
-        self.obj_face_list.insert(name.to_string(), local_vec);
+        // An object may be split up by materials, and come back to a
+        // material used earlier, so extend instead of replace.
+        self.obj_face_list
+            .entry((name.to_string(), self.last_material.clone()))
+            .or_default()
+            .extend(local_vec);
     }
 }
 
@@ -331,9 +597,10 @@ fn assemble_vertex(obj: &WFObjectState, f: FaceDef) -> VertexTexture {
             .t
             .map(|x| {
                 let source = obj.tex_list[x as usize];
+                // OBJ texture coordinates have the origin at the bottom
                 [
                     (source[0] * (65536.0 - 1.0)) as u16,
-                    (source[1] * (65536.0 - 1.0)) as u16,
+                    ((1.0 - source[1]) * (65536.0 - 1.0)) as u16,
                 ]
             })
             .unwrap_or([0, 0]),
@@ -374,17 +641,146 @@ fn compute_quad(indicies: &[u32], vs: &[VertexTexture]) -> ([u32; 3], [u32; 3])
     (f1, f2)
 }
 
+/// Triangulate a polygon with 5 or more corners by ear clipping.
+///
+/// The polygon is projected onto the plane given by its Newell normal. If
+/// no ear can be found (self-intersecting or degenerate input), the rest is
+/// fanned.
+fn triangulate_polygon(indicies: &[u32], vs: &[VertexTexture]) -> Vec<[u32; 3]> {
+    let positions: Vec<_> = indicies
+        .iter()
+        .map(|f| Vector3::from(vs[*f as usize].position))
+        .collect();
+
+    let mut normal = Vector3::<f32>::zeros();
+
+    for (i, a) in positions.iter().enumerate() {
+        let b = positions[(i + 1) % positions.len()];
+        normal.x += (a.y - b.y) * (a.z + b.z);
+        normal.y += (a.z - b.z) * (a.x + b.x);
+        normal.z += (a.x - b.x) * (a.y + b.y);
+    }
+
+    // Drop the dominant axis to get a 2D polygon with the same winding
+    let (ax, ay) = match normal.iamax() {
+        0 if normal.x > 0.0 => (1, 2),
+        0 => (2, 1),
+        1 if normal.y > 0.0 => (2, 0),
+        1 => (0, 2),
+        _ if normal.z > 0.0 => (0, 1),
+        _ => (1, 0),
+    };
+
+    let flat: Vec<[f32; 2]> = positions.iter().map(|f| [f[ax], f[ay]]).collect();
+
+    let cross = |a: [f32; 2], b: [f32; 2], c: [f32; 2]| {
+        (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+    };
+
+    let mut remaining: Vec<usize> = (0..indicies.len()).collect();
+    let mut ret = Vec::with_capacity(indicies.len() - 2);
+
+    while remaining.len() > 3 {
+        let n = remaining.len();
+
+        let ear = (0..n).find(|&i| {
+            let (p, c, nx) = (
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            );
+            let (a, b, d) = (flat[p], flat[c], flat[nx]);
+
+            // must be convex
+            if cross(a, b, d) <= 0.0 {
+                return false;
+            }
+
+            // and contain no other corner
+            !remaining.iter().any(|&o| {
+                o != p
+                    && o != c
+                    && o != nx
+                    && cross(a, b, flat[o]) >= 0.0
+                    && cross(b, d, flat[o]) >= 0.0
+                    && cross(d, a, flat[o]) >= 0.0
+            })
+        });
+
+        let Some(i) = ear else {
+            break;
+        };
+
+        ret.push([
+            indicies[remaining[(i + n - 1) % n]],
+            indicies[remaining[i]],
+            indicies[remaining[(i + 1) % n]],
+        ]);
+
+        remaining.remove(i);
+    }
+
+    for i in 1..remaining.len() - 1 {
+        ret.push([
+            indicies[remaining[0]],
+            indicies[remaining[i]],
+            indicies[remaining[i + 1]],
+        ]);
+    }
+
+    ret
+}
+
+/// How a vertex is shared between faces for normal generation
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+enum ShadingKey {
+    /// Normal comes from the file, or is left empty
+    Given,
+    /// Shared by all faces in the smoothing group
+    Smooth(u32),
+    /// Unique to a single face
+    Flat(usize),
+}
+
+/// Compute area weighted normals for vertices that did not come with one
+fn generate_normals(verts: &mut [VertexTexture], faces: &[[u32; 3]], needs_normal: &[bool]) {
+    let mut accum = vec![Vector3::<f32>::zeros(); verts.len()];
+
+    for face in faces {
+        let [a, b, c] = face.map(|f| Vector3::from(verts[f as usize].position));
+
+        // Not normalized, so larger faces contribute more
+        let n = (b - a).cross(&(c - a));
+
+        for i in face {
+            accum[*i as usize] += n;
+        }
+    }
+
+    for ((v, n), needs) in verts.iter_mut().zip(accum).zip(needs_normal) {
+        if *needs {
+            v.normal = n.try_normalize(f32::EPSILON).unwrap_or_default().into();
+        }
+    }
+}
+
 struct PackedObj {
     name: String,
+    material: Option<String>,
     verts: Vec<VertexTexture>,
     faces: Vec<[u32; 3]>,
 }
 
-fn pack_wf_state(mut obj: WFObjectState) -> Vec<PackedObj> {
+/// Pack parsed faces into triangle meshes.
+///
+/// Missing normals are generated for faces in a smoothing group. Faces with no
+/// smoothing information are only given normals if `smooth_missing` is set.
+fn pack_wf_state(mut obj: WFObjectState, smooth_missing: bool) -> Vec<PackedObj> {
     let mut vert_list = Vec::<VertexTexture>::new();
     let mut faces = Vec::<[u32; 3]>::new();
 
-    let mut face_remapper = HashMap::<FaceDef, u32>::new();
+    let mut face_remapper = HashMap::<(FaceDef, ShadingKey), u32>::new();
+    let mut needs_normal = Vec::<bool>::new();
 
     let mut counter;
 
@@ -394,22 +790,42 @@ fn pack_wf_state(mut obj: WFObjectState) -> Vec<PackedObj> {
 
     let mut ret = Vec::<PackedObj>::new();
 
-    for (name, this_obj_faces) in take(&mut obj.obj_face_list) {
+    for ((name, material), this_obj_faces) in take(&mut obj.obj_face_list) {
         this_face_cache.clear();
         counter = 0;
         vert_list.clear();
         faces.clear();
+        face_remapper.clear();
+        needs_normal.clear();
+
+        let mut face_index = 0;
 
         for face in this_obj_faces {
             match face {
-                FaceMarker::Def(face) => {
-                    this_face_cache.push(*face_remapper.entry(face.clone()).or_insert_with(|| {
-                        vert_list.push(assemble_vertex(&obj, face.clone()));
-
-                        let place = counter;
-                        counter += 1;
-                        place
-                    }));
+                FaceMarker::Def(face, smoothing) => {
+                    // Vertices without normals are only shared within a
+                    // smoothing group, so the generated normals don't bleed
+                    // across hard edges
+                    let key = match (face.n, smoothing) {
+                        (Some(_), _) => ShadingKey::Given,
+                        (None, Some(0)) => ShadingKey::Flat(face_index),
+                        (None, Some(s)) => ShadingKey::Smooth(s),
+                        // 0 is not a real smoothing group, so we can use it
+                        // to smooth everything else together
+                        (None, None) if smooth_missing => ShadingKey::Smooth(0),
+                        (None, None) => ShadingKey::Given,
+                    };
+
+                    this_face_cache.push(*face_remapper.entry((face.clone(), key)).or_insert_with(
+                        || {
+                            needs_normal.push(key != ShadingKey::Given);
+                            vert_list.push(assemble_vertex(&obj, face.clone()));
+
+                            let place = counter;
+                            counter += 1;
+                            place
+                        },
+                    ));
                 }
                 FaceMarker::End => {
                     if this_face_cache.len() == 3 {
@@ -420,15 +836,23 @@ fn pack_wf_state(mut obj: WFObjectState) -> Vec<PackedObj> {
 
                         faces.push(f1);
                         faces.push(f2);
+                    } else if this_face_cache.len() > 4 {
+                        faces.extend(triangulate_polygon(&this_face_cache, &vert_list));
                     }
 
                     this_face_cache.clear();
+                    face_index += 1;
                 }
             }
         }
 
+        if needs_normal.iter().any(|f| *f) {
+            generate_normals(&mut vert_list, &faces, &needs_normal);
+        }
+
         ret.push(PackedObj {
             name,
+            material,
             verts: take(&mut vert_list),
             faces: take(&mut faces),
         })
@@ -436,3 +860,154 @@ fn pack_wf_state(mut obj: WFObjectState) -> Vec<PackedObj> {
 
     ret
 }
+
+#[cfg(test)]
+mod test {
+    use super::{pack_wf_state, parse_mtl, triangulate_polygon, MtlMaterial, WFObjectState};
+    use colabrodo_server::server_bufferbuilder::VertexTexture;
+
+    #[test]
+    fn test_parse_mtl() {
+        let src = "# comment
+newmtl Red Paint
+Kd 1.0 0.0 0.0
+Ks 0.5 0.5 0.5
+Ns 98.0
+d 0.5
+map_Kd -s 1 1 1 textures/red.png
+
+newmtl Plain
+Kd 0.2 0.2 0.2
+";
+
+        let mats = parse_mtl(src.as_bytes());
+
+        assert_eq!(mats.len(), 2);
+
+        let red = &mats["Red Paint"];
+        assert_eq!(red.kd, [1.0, 0.0, 0.0]);
+        assert_eq!(red.d, 0.5);
+        assert_eq!(red.map_kd.as_deref(), Some("textures/red.png"));
+        assert!((red.roughness() - 0.1414).abs() < 0.001);
+
+        let plain = &mats["Plain"];
+        assert_eq!(
+            plain,
+            &MtlMaterial {
+                kd: [0.2, 0.2, 0.2],
+                ..Default::default()
+            }
+        );
+        assert_eq!(plain.roughness(), 1.0);
+    }
+
+    #[test]
+    fn test_groups_and_smoothing() {
+        let src = "v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 1
+g first
+s 1
+f 1 2 3
+f 1 3 4
+g second
+s off
+f 1 2 3
+f 1 3 4
+";
+
+        let mut wfobj = WFObjectState::new();
+        for line in src.lines() {
+            wfobj.handle(line);
+        }
+
+        let mut objs = pack_wf_state(wfobj, false);
+        objs.sort_by(|a, b| a.name.cmp(&b.name));
+
+        assert_eq!(objs.len(), 2);
+        assert_eq!(objs[0].name, "first");
+        assert_eq!(objs[1].name, "second");
+
+        // smooth faces share vertices, flat faces do not
+        assert_eq!(objs[0].verts.len(), 4);
+        assert_eq!(objs[1].verts.len(), 6);
+
+        for obj in &objs {
+            for v in &obj.verts {
+                let len = v.normal.iter().map(|f| f * f).sum::<f32>().sqrt();
+                assert!((len - 1.0).abs() < 0.0001);
+            }
+        }
+
+        // shared edge vertex is averaged in the smooth group, but not in the flat one
+        assert!(objs[0].verts[0].normal[1] < 0.0);
+        assert_eq!(objs[1].verts[0].normal, [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_triangulate_concave() {
+        // An L shape, counter clockwise, in the XZ plane. Starts at a corner
+        // that cannot see the whole polygon, so a naive fan would fail.
+        let points = [
+            [1.0, 0.0, -2.0],
+            [1.0, 0.0, -1.0],
+            [2.0, 0.0, -1.0],
+            [2.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0],
+            [0.0, 0.0, -2.0],
+        ];
+
+        let verts: Vec<_> = points
+            .iter()
+            .map(|f| VertexTexture {
+                position: *f,
+                normal: [0.0; 3],
+                texture: [0; 2],
+            })
+            .collect();
+
+        let indicies: Vec<u32> = (0..verts.len() as u32).collect();
+
+        let tris = triangulate_polygon(&indicies, &verts);
+
+        assert_eq!(tris.len(), 4);
+
+        // total area should match the L
+        let area: f32 = tris
+            .iter()
+            .map(|t| {
+                let [a, b, c] = t.map(|i| points[i as usize]);
+                ((b[0] - a[0]) * (c[2] - a[2]) - (b[2] - a[2]) * (c[0] - a[0])).abs() / 2.0
+            })
+            .sum();
+
+        assert!((area - 3.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_generate_normals_option() {
+        let src = "v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 1
+f 1 2 3
+f 1 3 4
+";
+
+        let pack = |generate| {
+            let mut wfobj = WFObjectState::new();
+            for line in src.lines() {
+                wfobj.handle(line);
+            }
+            pack_wf_state(wfobj, generate).pop().unwrap()
+        };
+
+        let plain = pack(false);
+        assert!(plain.verts.iter().all(|v| v.normal == [0.0; 3]));
+
+        let generated = pack(true);
+        assert_eq!(generated.verts.len(), 4);
+        assert!(generated.verts.iter().all(|v| v.normal != [0.0; 3]));
+    }
+}
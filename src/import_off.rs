@@ -0,0 +1,246 @@
+//! Import Object File Format (OFF) meshes, including the COFF variant with
+//! vertex colors
+
+use std::{
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::Result;
+
+use crate::colored_mesh::{publish_mesh, ColoredMesh};
+use crate::import::{ImportError, ImportOptions};
+use crate::scene::Scene;
+use colabrodo_server::{server_http::*, server_state::*};
+
+/// Which optional fields each vertex line carries, from the header keyword
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct OffHeader {
+    texture: bool,
+    color: bool,
+    normal: bool,
+}
+
+impl OffHeader {
+    /// Parse a keyword such as `OFF`, `COFF` or `STCNOFF`
+    fn parse(keyword: &str) -> Option<Self> {
+        let mut prefix = keyword.strip_suffix("OFF")?;
+        let mut ret = Self::default();
+
+        if let Some(rest) = prefix.strip_prefix("ST") {
+            ret.texture = true;
+            prefix = rest;
+        }
+        if let Some(rest) = prefix.strip_prefix('C') {
+            ret.color = true;
+            prefix = rest;
+        }
+        if let Some(rest) = prefix.strip_prefix('N') {
+            ret.normal = true;
+            prefix = rest;
+        }
+
+        // 4OFF and nOFF describe higher dimensional points
+        prefix.is_empty().then_some(ret)
+    }
+}
+
+/// Parse a color given as 3 or 4 components. Integers are 0-255, anything
+/// with a decimal point is 0-1. A lone colormap index is ignored.
+fn parse_color(fields: &[&str]) -> Result<Option<[u8; 4]>> {
+    if fields.len() < 3 {
+        return Ok(None);
+    }
+
+    let is_float = fields.iter().any(|f| f.contains(['.', 'e', 'E']));
+
+    let mut ret = [255u8; 4];
+
+    for (c, f) in ret.iter_mut().zip(fields.iter().take(4)) {
+        let v: f32 = f
+            .parse()
+            .map_err(|_| ImportError::UnableToImport(format!("Bad color component {f}")))?;
+
+        *c = if is_float {
+            (v.clamp(0.0, 1.0) * 255.0).round() as u8
+        } else {
+            v.clamp(0.0, 255.0) as u8
+        };
+    }
+
+    Ok(Some(ret))
+}
+
+fn parse_f32(s: &str) -> Result<f32> {
+    s.parse()
+        .map_err(|_| ImportError::UnableToImport(format!("Bad number {s}")).into())
+}
+
+fn parse_off<R: BufRead>(reader: R) -> Result<ColoredMesh> {
+    // Comments may follow data on any line
+    let mut lines = reader
+        .lines()
+        .map(|l| l.map(|f| f.split('#').next().unwrap_or_default().trim().to_string()))
+        .filter(|l| !matches!(l, Ok(f) if f.is_empty()));
+
+    let mut next_line = || -> Result<String> {
+        lines
+            .next()
+            .ok_or_else(|| ImportError::UnableToImport("Unexpected end of file".into()))?
+            .map_err(Into::into)
+    };
+
+    // The keyword is optional, and the counts may share its line
+    let first = next_line()?;
+    let mut tokens: Vec<&str> = first.split_whitespace().collect();
+
+    let mut header = OffHeader::default();
+
+    if tokens[0].ends_with("OFF") {
+        if tokens.contains(&"BINARY") {
+            return Err(ImportError::UnableToImport("Binary OFF is not supported".into()).into());
+        }
+
+        header = OffHeader::parse(tokens[0]).ok_or_else(|| {
+            ImportError::UnableToImport(format!("Unsupported OFF variant {}", tokens[0]))
+        })?;
+
+        tokens.remove(0);
+    }
+
+    let counts_line;
+
+    if tokens.is_empty() {
+        counts_line = next_line()?;
+        tokens = counts_line.split_whitespace().collect();
+    }
+
+    let count = |i: usize| -> Result<usize> {
+        tokens
+            .get(i)
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| ImportError::UnableToImport("Bad OFF element counts".into()).into())
+    };
+
+    let vertex_count = count(0)?;
+    let face_count = count(1)?;
+
+    let mut mesh = ColoredMesh::default();
+
+    for _ in 0..vertex_count {
+        let line = next_line()?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        if fields.len() < 3 {
+            return Err(ImportError::UnableToImport(format!("Bad vertex line {line}")).into());
+        }
+
+        mesh.vertices.push([
+            parse_f32(fields[0])?,
+            parse_f32(fields[1])?,
+            parse_f32(fields[2])?,
+        ]);
+
+        // Supplied normals are skipped; we compute our own
+        let mut rest = &fields[3..];
+
+        if header.normal {
+            rest = rest.get(3..).unwrap_or_default();
+        }
+
+        if header.texture {
+            rest = &rest[..rest.len().saturating_sub(2)];
+        }
+
+        let color = match header.color {
+            true => parse_color(rest)?,
+            false => None,
+        };
+
+        mesh.vertex_colors.push(color);
+    }
+
+    for _ in 0..face_count {
+        let line = next_line()?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        let n: usize = fields
+            .first()
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| ImportError::UnableToImport(format!("Bad face line {line}")))?;
+
+        if fields.len() < n + 1 {
+            return Err(ImportError::UnableToImport(format!("Bad face line {line}")).into());
+        }
+
+        let mut indices = Vec::with_capacity(n);
+
+        for f in &fields[1..n + 1] {
+            let i: u32 = f
+                .parse()
+                .ok()
+                .filter(|i| (*i as usize) < vertex_count)
+                .ok_or_else(|| ImportError::UnableToImport(format!("Bad vertex index {f}")))?;
+            indices.push(i);
+        }
+
+        mesh.faces.push((indices, parse_color(&fields[n + 1..])?));
+    }
+
+    Ok(mesh)
+}
+
+/// Import an OFF mesh
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let file = BufReader::new(std::fs::File::open(path)?);
+
+    let mesh = parse_off(file)?;
+
+    publish_mesh(&mesh, path, state, asset_store, options)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const COFF: &str = "COFF
+# a colored square and a triangle
+5 2 0
+0 0 0 255 0 0
+1 0 0 0 255 0 255
+1 1 0 0 0 255
+0 1 0 1.0 1.0 1.0 0.5
+0 0 1 10 20 30
+4 0 1 2 3
+3 0 1 4
+";
+
+    #[test]
+    fn test_parse_coff() {
+        let mesh = parse_off(COFF.as_bytes()).unwrap();
+
+        assert_eq!(mesh.vertices.len(), 5);
+        assert_eq!(mesh.vertex_colors[0], Some([255, 0, 0, 255]));
+        assert_eq!(mesh.vertex_colors[3], Some([255, 255, 255, 128]));
+        assert_eq!(mesh.faces.len(), 2);
+        assert_eq!(mesh.faces[0].0, vec![0, 1, 2, 3]);
+        assert!(mesh.has_alpha());
+    }
+
+    #[test]
+    fn test_parse_face_colors() {
+        let src = "OFF 3 1 0\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2 0.0 0.5 1.0\n";
+        let mesh = parse_off(src.as_bytes()).unwrap();
+
+        assert_eq!(mesh.vertex_colors, vec![None; 3]);
+        assert_eq!(mesh.faces[0].1, Some([0, 128, 255, 255]));
+
+        assert!(parse_off("4OFF\n1 0 0\n0 0 0 0\n".as_bytes()).is_err());
+        assert!(parse_off("OFF\n1 1 0\n0 0 0\n3 0 1 2\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,553 @@
+//! Import PLY meshes and point clouds, in ascii or binary
+//!
+//! Faces become a mesh; a PLY with only vertices is a point cloud. Gaussian
+//! splat PLYs are handed on to [`crate::import_splat`]. Elements other than
+//! vertices and faces, such as edges or materials, are skipped.
+
+use std::{io::Read, path::Path};
+
+use anyhow::Result;
+
+use crate::colored_mesh::{publish_mesh, ColoredMesh};
+use crate::import::{ImportError, ImportOptions};
+use crate::points::{PointChunk, PointCloud, POINTS_PER_CHUNK};
+use crate::scene::Scene;
+use colabrodo_server::{server_http::*, server_state::*};
+
+fn bad(msg: impl Into<String>) -> anyhow::Error {
+    ImportError::UnableToImport(msg.into()).into()
+}
+
+/// How the body of a PLY is written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "char" | "int8" => PlyType::I8,
+            "uchar" | "uint8" => PlyType::U8,
+            "short" | "int16" => PlyType::I16,
+            "ushort" | "uint16" => PlyType::U16,
+            "int" | "int32" => PlyType::I32,
+            "uint" | "uint32" => PlyType::U32,
+            "float" | "float32" => PlyType::F32,
+            "double" | "float64" => PlyType::F64,
+            _ => return Err(bad(format!("Unknown PLY type {s}"))),
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, PlyType::F32 | PlyType::F64)
+    }
+
+    /// Read a value from the start of `b`, which must be long enough
+    pub fn read(&self, b: &[u8], little: bool) -> f64 {
+        macro_rules! from {
+            ($t:ty) => {{
+                let b = b[..std::mem::size_of::<$t>()].try_into().unwrap();
+                (if little {
+                    <$t>::from_le_bytes(b)
+                } else {
+                    <$t>::from_be_bytes(b)
+                }) as f64
+            }};
+        }
+
+        match self {
+            PlyType::I8 => b[0] as i8 as f64,
+            PlyType::U8 => b[0] as f64,
+            PlyType::I16 => from!(i16),
+            PlyType::U16 => from!(u16),
+            PlyType::I32 => from!(i32),
+            PlyType::U32 => from!(u32),
+            PlyType::F32 => from!(f32),
+            PlyType::F64 => from!(f64),
+        }
+    }
+}
+
+/// A property of a PLY element
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlyProperty {
+    pub name: String,
+    pub kind: PlyType,
+
+    /// Type of the item count, for list properties
+    pub list: Option<PlyType>,
+}
+
+/// An element of a PLY, such as its vertices or faces
+#[derive(Debug)]
+pub struct PlyElement {
+    pub name: String,
+    pub count: usize,
+    pub properties: Vec<PlyProperty>,
+}
+
+impl PlyElement {
+    /// Whether rows vary in size, having list properties
+    pub fn has_lists(&self) -> bool {
+        self.properties.iter().any(|f| f.list.is_some())
+    }
+
+    /// Bytes per row, for elements without list properties
+    pub fn stride(&self) -> usize {
+        self.properties.iter().map(|f| f.kind.size()).sum()
+    }
+
+    /// Byte offset and type of a named property, for elements without list
+    /// properties
+    pub fn find(&self, name: &str) -> Option<(usize, PlyType)> {
+        let mut offset = 0;
+        for p in &self.properties {
+            if p.name == name {
+                return Some((offset, p.kind));
+            }
+            offset += p.kind.size();
+        }
+        None
+    }
+
+    /// Index and type of the first property with one of these names
+    fn index(&self, names: &[&str]) -> Option<(usize, PlyType)> {
+        self.properties
+            .iter()
+            .position(|f| names.contains(&f.name.as_str()))
+            .map(|i| (i, self.properties[i].kind))
+    }
+}
+
+#[derive(Debug)]
+pub struct PlyHeader {
+    pub format: PlyFormat,
+
+    /// Bytes up to and including `end_header`
+    pub length: usize,
+
+    /// Elements, in the order their rows are stored
+    pub elements: Vec<PlyElement>,
+}
+
+impl PlyHeader {
+    pub fn element(&self, name: &str) -> Option<&PlyElement> {
+        self.elements.iter().find(|f| f.name == name)
+    }
+}
+
+pub fn parse_header(bytes: &[u8]) -> Result<PlyHeader> {
+    let end = bytes
+        .windows(b"end_header".len())
+        .position(|f| f == b"end_header")
+        .ok_or_else(|| bad("PLY header is not terminated"))?;
+
+    // The body starts after the line ending, whichever it is
+    let length = bytes[end..]
+        .iter()
+        .position(|f| *f == b'\n')
+        .map(|f| end + f + 1)
+        .ok_or_else(|| bad("PLY header is not terminated"))?;
+
+    let text = String::from_utf8_lossy(&bytes[..end]);
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+
+    for line in text.lines() {
+        let parts: Vec<_> = line.split_whitespace().collect();
+
+        match parts.as_slice() {
+            ["format", f, ..] => {
+                format = Some(match *f {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(bad(format!("Unknown PLY format {f}"))),
+                });
+            }
+            ["element", name, n] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: n
+                    .parse()
+                    .map_err(|_| bad(format!("Bad PLY element count {n}")))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, kind, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| bad("PLY property outside of an element"))?;
+
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    kind: PlyType::parse(kind)?,
+                    list: Some(PlyType::parse(count)?),
+                });
+            }
+            ["property", kind, name] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| bad("PLY property outside of an element"))?;
+
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    kind: PlyType::parse(kind)?,
+                    list: None,
+                });
+            }
+            _ => (),
+        }
+    }
+
+    Ok(PlyHeader {
+        format: format.ok_or_else(|| bad("PLY has no format"))?,
+        length,
+        elements,
+    })
+}
+
+/// The values of one row of an element. List properties are flattened,
+/// so each property is found through `starts`.
+#[derive(Default)]
+struct Row {
+    values: Vec<f64>,
+
+    /// Where each property starts in `values`, and where the last ends
+    starts: Vec<usize>,
+}
+
+impl Row {
+    fn get(&self, property: usize) -> f64 {
+        self.values[self.starts[property]]
+    }
+
+    fn list(&self, property: usize) -> &[f64] {
+        &self.values[self.starts[property]..self.starts[property + 1]]
+    }
+}
+
+/// Reads the rows of each element in turn
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary {
+        bytes: &'a [u8],
+        at: usize,
+        little: bool,
+    },
+}
+
+impl<'a> Body<'a> {
+    fn new(header: &PlyHeader, bytes: &'a [u8]) -> Result<Self> {
+        let body = &bytes[header.length..];
+
+        Ok(match header.format {
+            PlyFormat::Ascii => Body::Ascii(
+                std::str::from_utf8(body)
+                    .map_err(|_| bad("ascii PLY is not text"))?
+                    .split_ascii_whitespace(),
+            ),
+            format => Body::Binary {
+                bytes: body,
+                at: 0,
+                little: format == PlyFormat::BinaryLittleEndian,
+            },
+        })
+    }
+
+    fn value(&mut self, kind: PlyType) -> Result<f64> {
+        match self {
+            Body::Ascii(tokens) => {
+                let t = tokens.next().ok_or_else(|| bad("PLY is truncated"))?;
+                t.parse().map_err(|_| bad(format!("Bad number {t}")))
+            }
+            Body::Binary { bytes, at, little } => {
+                let b = bytes.get(*at..).filter(|f| f.len() >= kind.size());
+                let b = b.ok_or_else(|| bad("PLY is truncated"))?;
+                *at += kind.size();
+                Ok(kind.read(b, *little))
+            }
+        }
+    }
+
+    /// Read every row of an element
+    fn rows(&mut self, element: &PlyElement, mut f: impl FnMut(&Row) -> Result<()>) -> Result<()> {
+        let mut row = Row::default();
+
+        for _ in 0..element.count {
+            row.values.clear();
+            row.starts.clear();
+
+            for p in &element.properties {
+                row.starts.push(row.values.len());
+
+                let n = match p.list {
+                    Some(count) => self.value(count)? as usize,
+                    None => 1,
+                };
+
+                for _ in 0..n {
+                    row.values.push(self.value(p.kind)?);
+                }
+            }
+
+            row.starts.push(row.values.len());
+            f(&row)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where the color properties of an element are, if it has them
+struct ColorProperties([Option<(usize, PlyType)>; 4]);
+
+impl ColorProperties {
+    fn new(element: &PlyElement) -> Self {
+        Self([
+            element.index(&["red", "r", "diffuse_red"]),
+            element.index(&["green", "g", "diffuse_green"]),
+            element.index(&["blue", "b", "diffuse_blue"]),
+            element.index(&["alpha", "a"]),
+        ])
+    }
+
+    /// The color of a row. Floats are 0-1, integers 0-255.
+    fn read(&self, row: &Row) -> Option<[u8; 4]> {
+        self.0[..3].iter().all(Option::is_some).then(|| {
+            self.0.map(|f| match f {
+                Some((i, kind)) if kind.is_float() => {
+                    (row.get(i).clamp(0.0, 1.0) * 255.0).round() as u8
+                }
+                Some((i, _)) => row.get(i).clamp(0.0, 255.0) as u8,
+                None => 255,
+            })
+        })
+    }
+}
+
+/// Read the vertices of a PLY, and its faces if it has any
+fn parse_mesh(header: &PlyHeader, bytes: &[u8]) -> Result<ColoredMesh> {
+    let mut body = Body::new(header, bytes)?;
+    let mut mesh = ColoredMesh::default();
+
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => {
+                let find = |name: &str| {
+                    element
+                        .index(&[name])
+                        .map(|f| f.0)
+                        .ok_or_else(|| bad(format!("PLY vertices have no {name}")))
+                };
+
+                let position = [find("x")?, find("y")?, find("z")?];
+                let color = ColorProperties::new(element);
+
+                body.rows(element, |row| {
+                    mesh.vertices.push(position.map(|i| row.get(i) as f32));
+                    mesh.vertex_colors.push(color.read(row));
+                    Ok(())
+                })?;
+            }
+            "face" => {
+                let indices = element
+                    .index(&["vertex_indices", "vertex_index"])
+                    .map(|f| f.0)
+                    .filter(|i| element.properties[*i].list.is_some())
+                    .ok_or_else(|| bad("PLY faces have no vertex_indices list"))?;
+
+                let color = ColorProperties::new(element);
+                let vertex_count = mesh.vertices.len();
+
+                body.rows(element, |row| {
+                    let face = row
+                        .list(indices)
+                        .iter()
+                        .map(|f| {
+                            Some(*f as u32)
+                                .filter(|i| *f >= 0.0 && (*i as usize) < vertex_count)
+                                .ok_or_else(|| bad(format!("Bad vertex index {f}")))
+                        })
+                        .collect::<Result<_>>()?;
+
+                    mesh.faces.push((face, color.read(row)));
+                    Ok(())
+                })?;
+            }
+            name => {
+                log::debug!("Skipping PLY element {name}");
+                body.rows(element, |_| Ok(()))?;
+            }
+        }
+    }
+
+    Ok(mesh)
+}
+
+/// Publish PLY vertices as points
+fn publish_points(mesh: &ColoredMesh, cloud: &mut PointCloud) -> Result<()> {
+    for (vertices, colors) in mesh
+        .vertices
+        .chunks(POINTS_PER_CHUNK)
+        .zip(mesh.vertex_colors.chunks(POINTS_PER_CHUNK))
+    {
+        let mut chunk = PointChunk::with_capacity(vertices.len());
+
+        for (v, c) in vertices.iter().zip(colors) {
+            chunk.push(*v, c.unwrap_or([255; 4]));
+        }
+
+        cloud.publish(chunk)?;
+    }
+
+    Ok(())
+}
+
+/// Import a PLY mesh, point cloud, or Gaussian splat
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)?.read_to_end(&mut bytes)?;
+
+    let header = parse_header(&bytes)?;
+
+    if header
+        .element("vertex")
+        .is_some_and(crate::import_splat::is_splat_ply)
+    {
+        return crate::import_splat::import_file(path, state, asset_store, options);
+    }
+
+    let mesh = parse_mesh(&header, &bytes)?;
+
+    if header.element("face").is_some() {
+        return publish_mesh(&mesh, path, state, asset_store, options);
+    }
+
+    log::info!("PLY without faces, with {} points", mesh.vertices.len());
+
+    let mut cloud = PointCloud::new(asset_store, path, options)?;
+    publish_points(&mesh, &mut cloud)?;
+    cloud.finish(&mut state.lock().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ascii_mesh() {
+        let bytes = b"ply\r\n\
+            format ascii 1.0\r\n\
+            comment a colored square\r\n\
+            element vertex 4\r\n\
+            property float x\r\n\
+            property float y\r\n\
+            property float z\r\n\
+            property uchar red\r\n\
+            property uchar green\r\n\
+            property uchar blue\r\n\
+            element face 1\r\n\
+            property list uchar int vertex_indices\r\n\
+            element edge 1\r\n\
+            property int vertex1\r\n\
+            property int vertex2\r\n\
+            end_header\r\n\
+            0 0 0 255 0 0\r\n\
+            1 0 0 0 255 0\r\n\
+            1 1 0 0 0 255\r\n\
+            0 1 0 10 20 30\r\n\
+            4 0 1 2 3\r\n\
+            0 1\r\n";
+
+        let header = parse_header(bytes).unwrap();
+        assert_eq!(header.format, PlyFormat::Ascii);
+        assert_eq!(header.elements.len(), 3);
+        assert!(header.element("face").unwrap().has_lists());
+
+        let mesh = parse_mesh(&header, bytes).unwrap();
+        assert_eq!(mesh.vertices[2], [1.0, 1.0, 0.0]);
+        assert_eq!(mesh.vertex_colors[3], Some([10, 20, 30, 255]));
+        assert_eq!(mesh.faces, vec![(vec![0, 1, 2, 3], None)]);
+    }
+
+    #[test]
+    fn test_binary_mesh() {
+        let mut bytes = b"ply\n\
+            format binary_big_endian 1.0\n\
+            element vertex 3\n\
+            property double x\n\
+            property double y\n\
+            property double z\n\
+            element face 1\n\
+            property list uchar uint vertex_indices\n\
+            property float red\n\
+            property float green\n\
+            property float blue\n\
+            end_header\n"
+            .to_vec();
+
+        for v in [[0.0f64, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            v.iter().for_each(|f| bytes.extend(f.to_be_bytes()));
+        }
+
+        bytes.push(3);
+        [0u32, 1, 2]
+            .iter()
+            .for_each(|f| bytes.extend(f.to_be_bytes()));
+        [1.0f32, 0.5, 0.0]
+            .iter()
+            .for_each(|f| bytes.extend(f.to_be_bytes()));
+
+        let header = parse_header(&bytes).unwrap();
+        let mesh = parse_mesh(&header, &bytes).unwrap();
+
+        assert_eq!(mesh.vertices[1], [1.0, 0.0, 0.0]);
+        assert_eq!(mesh.vertex_colors, vec![None; 3]);
+        assert_eq!(mesh.faces, vec![(vec![0, 1, 2], Some([255, 128, 0, 255]))]);
+
+        // Indices past the vertices, and short files, fail
+        let last = bytes.len() - 13;
+        bytes[last] = 7;
+        assert!(parse_mesh(&header, &bytes).is_err());
+        assert!(parse_mesh(&header, &bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_bad_header() {
+        assert!(parse_header(b"ply\nformat ascii 1.0\n").is_err());
+        assert!(parse_header(b"ply\nformat binary 1.0\nend_header\n").is_err());
+        assert!(parse_header(
+            b"ply\nformat ascii 1.0\nelement vertex 1\nproperty quad x\nend_header\n"
+        )
+        .is_err());
+    }
+}
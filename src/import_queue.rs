@@ -0,0 +1,250 @@
+//! Scheduling of imports.
+//!
+//! Loads wait here, rather than in the command queue, so they can go in order
+//! of their source's priority: a model a client asked for goes ahead of a
+//! directory backfill. Each source may also be limited in how many of its
+//! imports run at once, under a global limit. Reloads, refreshes of
+//! referenced images, generated scenes, and copies convert or copy content
+//! too, so they wait here as well. Other commands don't wait on loads at all.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::arguments::ImportPolicy;
+use crate::platter_state::{source_override, PlatterCommand};
+
+/// Where an import came from, for counting imports per source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SourceKey {
+    /// One of the configured sources, by index
+    Source(usize),
+    /// A client asked for a URL
+    Url,
+    /// Anything else, such as a file outside every source
+    Other,
+}
+
+struct Job {
+    priority: i32,
+    seq: u64,
+    key: SourceKey,
+    command: PlatterCommand,
+}
+
+pub struct ImportQueue {
+    /// Paths of sources, and the index of each in `policies`
+    sources: Vec<(PathBuf, usize)>,
+    policies: Vec<ImportPolicy>,
+    url_priority: i32,
+
+    /// Most imports running at once, over all sources
+    limit: usize,
+
+    pending: Vec<Job>,
+    running: HashMap<SourceKey, usize>,
+    next_seq: u64,
+}
+
+impl ImportQueue {
+    /// Create a queue for sources at the given paths. Paths should be
+    /// canonical, as those of loads are compared against them.
+    pub fn new(
+        sources: Vec<(Vec<PathBuf>, ImportPolicy)>,
+        url_priority: i32,
+        limit: usize,
+    ) -> Self {
+        let mut policies = Vec::new();
+        let mut paths = Vec::new();
+
+        for (list, policy) in sources {
+            paths.extend(list.into_iter().map(|f| (f, policies.len())));
+            policies.push(policy);
+        }
+
+        Self {
+            sources: paths,
+            policies,
+            url_priority,
+            limit: limit.max(1),
+            pending: Vec::new(),
+            running: HashMap::new(),
+            next_seq: 0,
+        }
+    }
+
+    /// Whether a command is a load, or other heavy work, that should be
+    /// queued here
+    pub fn is_import(command: &PlatterCommand) -> bool {
+        matches!(
+            command,
+            PlatterCommand::LoadFile(..)
+                | PlatterCommand::LoadUrl(..)
+                | PlatterCommand::Reload(..)
+                | PlatterCommand::Refresh(..)
+                | PlatterCommand::Generate(..)
+                | PlatterCommand::Duplicate(..)
+        )
+    }
+
+    fn key_for(&self, command: &PlatterCommand) -> SourceKey {
+        match command {
+            PlatterCommand::LoadFile(path, ..) | PlatterCommand::Refresh(path) => {
+                source_override(&self.sources, Some(path.as_path()))
+                    .map_or(SourceKey::Other, SourceKey::Source)
+            }
+            PlatterCommand::LoadUrl(..) => SourceKey::Url,
+            _ => SourceKey::Other,
+        }
+    }
+
+    fn policy(&self, key: SourceKey) -> ImportPolicy {
+        match key {
+            SourceKey::Source(i) => self.policies[i],
+            SourceKey::Url => ImportPolicy {
+                priority: self.url_priority,
+                concurrency: None,
+            },
+            SourceKey::Other => ImportPolicy::default(),
+        }
+    }
+
+    /// Queue a load
+    pub fn push(&mut self, command: PlatterCommand) {
+        let key = self.key_for(&command);
+
+        self.pending.push(Job {
+            priority: self.policy(key).priority,
+            seq: self.next_seq,
+            key,
+            command,
+        });
+
+        self.next_seq += 1;
+    }
+
+    /// Take the next load that may start now: the highest priority, then
+    /// the oldest, of those whose source is under its limit. The load counts
+    /// as running until [`Self::finish`] is called with its key.
+    pub fn next(&mut self) -> Option<(SourceKey, PlatterCommand)> {
+        if self.running.values().sum::<usize>() >= self.limit {
+            return None;
+        }
+
+        let (index, _) = self
+            .pending
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| {
+                let limit = self.policy(job.key).concurrency.unwrap_or(usize::MAX);
+                self.running.get(&job.key).copied().unwrap_or_default() < limit.max(1)
+            })
+            .max_by_key(|(_, job)| (job.priority, std::cmp::Reverse(job.seq)))?;
+
+        let job = self.pending.remove(index);
+
+        *self.running.entry(job.key).or_default() += 1;
+
+        Some((job.key, job.command))
+    }
+
+    /// Note that a load has finished
+    pub fn finish(&mut self, key: SourceKey) {
+        if let Some(count) = self.running.get_mut(&key) {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    /// Drop every load that has not started
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Number of loads waiting to start
+    pub fn waiting(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    fn load_path(command: &PlatterCommand) -> Option<&Path> {
+        match command {
+            PlatterCommand::LoadFile(path, ..) => Some(path),
+            _ => None,
+        }
+    }
+
+    fn load(path: &str) -> PlatterCommand {
+        PlatterCommand::LoadFile(path.into(), None, None)
+    }
+
+    #[test]
+    fn test_import_queue() {
+        let policy = |priority, concurrency| ImportPolicy {
+            priority,
+            concurrency,
+        };
+
+        let mut queue = ImportQueue::new(
+            vec![
+                (vec!["/bulk".into()], policy(-1, Some(1))),
+                (vec!["/a.glb".into(), "/b.glb".into()], policy(5, None)),
+            ],
+            10,
+            2,
+        );
+
+        queue.push(load("/bulk/1.obj"));
+        queue.push(load("/bulk/2.obj"));
+        queue.push(load("/elsewhere.obj"));
+        queue.push(load("/a.glb"));
+        queue.push(PlatterCommand::LoadUrl(
+            url::Url::parse("https://example.com/c.glb").unwrap(),
+        ));
+
+        // The URL, then the named source
+        let (url, c) = queue.next().unwrap();
+        assert_eq!(url, SourceKey::Url);
+        assert!(matches!(c, PlatterCommand::LoadUrl(..)));
+
+        let (named, c) = queue.next().unwrap();
+        assert_eq!(named, SourceKey::Source(1));
+        assert_eq!(load_path(&c), Some(Path::new("/a.glb")));
+
+        // Two running is the global limit
+        assert!(queue.next().is_none());
+
+        queue.finish(url);
+        let (other, c) = queue.next().unwrap();
+        assert_eq!(other, SourceKey::Other);
+        assert_eq!(load_path(&c), Some(Path::new("/elsewhere.obj")));
+
+        queue.finish(named);
+        let (bulk, c) = queue.next().unwrap();
+        assert_eq!(bulk, SourceKey::Source(0));
+        assert_eq!(load_path(&c), Some(Path::new("/bulk/1.obj")));
+
+        // The bulk source runs one at a time
+        queue.finish(other);
+        assert!(queue.next().is_none());
+        assert_eq!(queue.waiting(), 1);
+
+        queue.finish(bulk);
+        let (_, c) = queue.next().unwrap();
+        assert_eq!(load_path(&c), Some(Path::new("/bulk/2.obj")));
+
+        queue.push(load("/a.glb"));
+        queue.clear();
+        assert_eq!(queue.waiting(), 0);
+
+        // Other heavy work is queued too, by source where it has a path
+        let refresh = PlatterCommand::Refresh("/bulk/tex.png".into());
+        assert!(ImportQueue::is_import(&refresh));
+        assert!(ImportQueue::is_import(&PlatterCommand::Duplicate(0)));
+        assert!(!ImportQueue::is_import(&PlatterCommand::Remove(0)));
+        assert_eq!(queue.key_for(&refresh), SourceKey::Source(0));
+    }
+}
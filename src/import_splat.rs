@@ -0,0 +1,286 @@
+//! Import Gaussian splats, from `.splat` files or splat PLYs
+//!
+//! NOODLES has no splat primitive. The raw file is published on the asset
+//! server and advertised with `platter.splat.*` tags, so clients that can
+//! render splats fetch it directly. Everyone else sees the splat centers as
+//! a colored point cloud.
+
+use std::{io::Read, path::Path};
+
+use anyhow::Result;
+use nalgebra::Matrix4;
+
+use crate::import::{ImportError, ImportOptions};
+use crate::import_ply::{self, PlyElement, PlyFormat, PlyHeader, PlyType};
+use crate::points::{PointChunk, PointCloud, POINTS_PER_CHUNK};
+use crate::scene::Scene;
+use colabrodo_server::{server_http::*, server_state::*};
+
+/// Record size of the `.splat` format: position and scale as f32x3, then
+/// RGBA and a quantized rotation as u8x4
+const SPLAT_STRIDE: usize = 32;
+
+/// Zeroth order spherical harmonic, for turning `f_dc` into a color
+const SH_C0: f32 = 0.282_094_8;
+
+/// The vertex element of a splat PLY. Splat files have only vertices,
+/// stored as fixed size records.
+fn splat_vertices(header: &PlyHeader) -> Result<&PlyElement> {
+    if header.format != PlyFormat::BinaryLittleEndian {
+        return Err(
+            ImportError::UnableToImport("Splat PLY must be binary_little_endian".into()).into(),
+        );
+    }
+
+    let vertex = header
+        .elements
+        .first()
+        .filter(|f| f.name == "vertex")
+        .ok_or_else(|| ImportError::UnableToImport("Splat PLY must start with vertices".into()))?;
+
+    if vertex.has_lists() {
+        return Err(ImportError::UnableToImport(
+            "Splat PLY vertices cannot have list properties".into(),
+        )
+        .into());
+    }
+
+    for f in &header.elements[1..] {
+        log::warn!("Ignoring PLY element {}", f.name);
+    }
+
+    Ok(vertex)
+}
+
+/// Splat PLYs carry spherical harmonic color coefficients
+pub fn is_splat_ply(vertex: &PlyElement) -> bool {
+    vertex.find("f_dc_0").is_some() && vertex.find("opacity").is_some()
+}
+
+/// Turn `.splat` records into fallback points
+fn splat_points(
+    bytes: &[u8],
+    chunk_size: usize,
+    mut f: impl FnMut(PointChunk) -> Result<()>,
+) -> Result<()> {
+    for records in bytes.chunks(chunk_size * SPLAT_STRIDE) {
+        let mut chunk = PointChunk::with_capacity(records.len() / SPLAT_STRIDE);
+
+        for r in records.chunks_exact(SPLAT_STRIDE) {
+            let v = |i: usize| f32::from_le_bytes(r[i * 4..i * 4 + 4].try_into().unwrap());
+            chunk.push([v(0), v(1), v(2)], r[24..28].try_into().unwrap());
+        }
+
+        f(chunk)?;
+    }
+
+    Ok(())
+}
+
+/// Turn splat PLY vertices into fallback points
+fn ply_points(
+    header: &PlyHeader,
+    bytes: &[u8],
+    chunk_size: usize,
+    mut f: impl FnMut(PointChunk) -> Result<()>,
+) -> Result<()> {
+    let vertex = splat_vertices(header)?;
+
+    let find = |name: &str| {
+        vertex
+            .find(name)
+            .ok_or_else(|| ImportError::UnableToImport(format!("Splat PLY has no {name}")))
+    };
+
+    let position = [find("x")?, find("y")?, find("z")?];
+    let dc = [find("f_dc_0")?, find("f_dc_1")?, find("f_dc_2")?];
+    let opacity = find("opacity")?;
+
+    let stride = vertex.stride();
+    let body = &bytes[header.length..];
+
+    let records = stride
+        .checked_mul(vertex.count)
+        .and_then(|f| body.get(..f))
+        .ok_or_else(|| ImportError::UnableToImport("Splat PLY is truncated".into()))?;
+
+    for records in records.chunks(chunk_size * stride) {
+        let mut chunk = PointChunk::with_capacity(records.len() / stride);
+
+        for r in records.chunks_exact(stride) {
+            let read = |(offset, kind): (usize, PlyType)| kind.read(&r[offset..], true) as f32;
+
+            let [red, green, blue] =
+                dc.map(|f| ((0.5 + SH_C0 * read(f)).clamp(0.0, 1.0) * 255.0) as u8);
+
+            // Opacity is stored before a sigmoid
+            let alpha = 1.0 / (1.0 + (-read(opacity)).exp());
+
+            chunk.push(
+                position.map(read),
+                [red, green, blue, (alpha * 255.0) as u8],
+            );
+        }
+
+        f(chunk)?;
+    }
+
+    Ok(())
+}
+
+/// Import a Gaussian splat file
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)?.read_to_end(&mut bytes)?;
+
+    let header = if bytes.starts_with(b"ply") {
+        let header = import_ply::parse_header(&bytes)?;
+
+        if !is_splat_ply(splat_vertices(&header)?) {
+            return Err(ImportError::UnableToImport("Not a Gaussian splat PLY".into()).into());
+        }
+
+        Some(header)
+    } else {
+        if bytes.len() % SPLAT_STRIDE != 0 {
+            return Err(ImportError::UnableToImport(format!(
+                "Splat file size is not a multiple of {SPLAT_STRIDE}"
+            ))
+            .into());
+        }
+        None
+    };
+
+    let count = header
+        .as_ref()
+        .map_or(bytes.len() / SPLAT_STRIDE, |f| f.elements[0].count);
+
+    log::info!("Gaussian splat with {count} splats");
+
+    let mut cloud = PointCloud::new(asset_store, path, options)?;
+
+    let url = cloud.publish_asset(&bytes);
+
+    let mut tags = vec![
+        format!(
+            "platter.splat.format={}",
+            if header.is_some() { "ply" } else { "splat" }
+        ),
+        format!("platter.splat.url={url}"),
+        format!("platter.splat.count={count}"),
+    ];
+
+    if let Some(h) = &header {
+        let names: Vec<_> = h.elements[0]
+            .properties
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect();
+        tags.push(format!("platter.splat.properties={}", names.join(",")));
+    }
+
+    cloud.begin_group(Some("Splats".into()), Matrix4::identity(), Some(tags));
+
+    match &header {
+        Some(h) => ply_points(h, &bytes, POINTS_PER_CHUNK, |chunk| cloud.publish(chunk))?,
+        None => splat_points(&bytes, POINTS_PER_CHUNK, |chunk| cloud.publish(chunk))?,
+    }
+
+    cloud.finish(&mut state.lock().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_splat_ply() {
+        let mut bytes = b"ply\n\
+            format binary_little_endian 1.0\n\
+            element vertex 2\n\
+            property float x\n\
+            property float y\n\
+            property float z\n\
+            property float f_dc_0\n\
+            property float f_dc_1\n\
+            property float f_dc_2\n\
+            property float opacity\n\
+            end_header\n"
+            .to_vec();
+
+        for v in [
+            [1.0, 2.0, 3.0, 0.0, 0.0, 0.0, 0.0],
+            [4.0, 5.0, 6.0, 10.0, -10.0, 0.0, 10.0],
+        ] {
+            for f in v {
+                bytes.extend_from_slice(&(f as f32).to_le_bytes());
+            }
+        }
+
+        let header = import_ply::parse_header(&bytes).unwrap();
+        let vertex = splat_vertices(&header).unwrap();
+        assert_eq!(vertex.count, 2);
+        assert_eq!(vertex.stride(), 28);
+        assert!(is_splat_ply(vertex));
+
+        let mut chunks = Vec::new();
+        ply_points(&header, &bytes, 16, |c| {
+            chunks.push(c);
+            Ok(())
+        })
+        .unwrap();
+
+        let chunk = &chunks[0];
+        assert_eq!(chunk.len(), 2);
+
+        // Zero coefficients are mid gray, at half opacity
+        assert_eq!(&chunk.bytes[12..16], &[127, 127, 127, 127]);
+        // Large coefficients saturate
+        assert_eq!(&chunk.bytes[16 + 12..16 + 16], &[255, 0, 127, 254]);
+    }
+
+    #[test]
+    fn test_plain_ply_is_not_splat() {
+        let bytes = b"ply\n\
+            format ascii 1.0\n\
+            element vertex 0\n\
+            end_header\n";
+        let header = import_ply::parse_header(bytes).unwrap();
+        assert!(splat_vertices(&header).is_err());
+
+        let bytes = b"ply\n\
+            format binary_little_endian 1.0\n\
+            element vertex 0\n\
+            property float x\n\
+            end_header\n";
+        let header = import_ply::parse_header(bytes).unwrap();
+        assert!(!is_splat_ply(splat_vertices(&header).unwrap()));
+    }
+
+    #[test]
+    fn test_splat_points() {
+        let mut bytes = Vec::new();
+        for i in 0..3 {
+            for f in [i as f32, 0.0, 0.0, 1.0, 1.0, 1.0] {
+                bytes.extend_from_slice(&f.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[255, 0, 0, 255, 128, 128, 128, 128]);
+        }
+
+        let mut chunks = Vec::new();
+        splat_points(&bytes, 2, |c| {
+            chunks.push(c);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].len(), 1);
+        assert_eq!(&chunks[0].bytes[12..16], &[255, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,1179 @@
+//! Import ASCII USD layers (.usda), either on their own or packaged as USDZ.
+//!
+//! Only a practical subset is handled: the prim hierarchy with common xform
+//! ops, polygon meshes with normals and texture coordinates, and
+//! UsdPreviewSurface materials. Binary crate files (.usdc) are not supported.
+
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use nalgebra::{Matrix4, Rotation3, UnitQuaternion, Vector3};
+
+use crate::color::{self, ColorSpace};
+use crate::geometry::{self, Cleanup, TriangleMesh};
+use crate::import::{self, ImportError, ImportOptions};
+use crate::prepared::{Prepared, PreparedEntity, PreparedMaterial};
+use crate::scene::{Bounds, Scene};
+use crate::texture;
+
+use colabrodo_server::{
+    server_bufferbuilder::*, server_http::*, server_messages::*, server_state::*,
+};
+
+// =============================================================================
+// Parsing
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    Asset(String),
+    Path(String),
+    Punct(char),
+    Newline,
+}
+
+/// Split a USDA layer into tokens.
+///
+/// Newlines are significant for property statements, but only outside of
+/// brackets and parentheses.
+fn tokenize(src: &str) -> Result<Vec<Token>> {
+    let mut ret = Vec::new();
+    let mut chars = src.chars().peekable();
+    let mut depth = 0i32;
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                chars.next();
+                if depth == 0 && ret.last() != Some(&Token::Newline) {
+                    ret.push(Token::Newline);
+                }
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => while chars.next_if(|f| *f != '\n').is_some() {},
+            '"' | '\'' => {
+                chars.next();
+                // Triple quoted strings are used for docs
+                let triple = chars.clone().take(2).all(|f| f == c);
+                if triple {
+                    chars.next();
+                    chars.next();
+                }
+                let mut s = String::new();
+                loop {
+                    let Some(n) = chars.next() else {
+                        anyhow::bail!("Unterminated string");
+                    };
+                    if n == '\\' {
+                        if let Some(e) = chars.next() {
+                            s.push(e);
+                        }
+                        continue;
+                    }
+                    if n == c {
+                        if !triple {
+                            break;
+                        }
+                        if chars.clone().take(2).all(|f| f == c) {
+                            chars.next();
+                            chars.next();
+                            break;
+                        }
+                    }
+                    s.push(n);
+                }
+                ret.push(Token::Str(s));
+            }
+            '@' => {
+                chars.next();
+                let s: String = std::iter::from_fn(|| chars.next_if(|f| *f != '@')).collect();
+                chars.next();
+                ret.push(Token::Asset(s));
+            }
+            '<' => {
+                chars.next();
+                let s: String = std::iter::from_fn(|| chars.next_if(|f| *f != '>')).collect();
+                chars.next();
+                ret.push(Token::Path(s));
+            }
+            '(' | '[' | '{' => {
+                chars.next();
+                depth += 1;
+                ret.push(Token::Punct(c));
+            }
+            ')' | ']' | '}' => {
+                chars.next();
+                depth = (depth - 1).max(0);
+                ret.push(Token::Punct(c));
+            }
+            '=' | ',' | ';' | ':' => {
+                chars.next();
+                ret.push(Token::Punct(c));
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                let s: String = std::iter::from_fn(|| {
+                    chars.next_if(|f| f.is_ascii_alphanumeric() || "-+.".contains(*f))
+                })
+                .collect();
+                match s.as_str() {
+                    "-inf" => ret.push(Token::Number(f64::NEG_INFINITY)),
+                    _ => ret.push(Token::Number(
+                        s.parse().with_context(|| format!("Bad number {s}"))?,
+                    )),
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                // Namespaced names like primvars:st and inputs:file.connect
+                let s: String = std::iter::from_fn(|| {
+                    chars.next_if(|f| f.is_alphanumeric() || "_:.".contains(*f))
+                })
+                .collect();
+                ret.push(Token::Ident(s));
+            }
+            _ => {
+                // Nothing else matters to us
+                chars.next();
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum UsdValue {
+    Number(f64),
+    Str(String),
+    Asset(String),
+    Path(String),
+    Ident(String),
+    /// Tuples and arrays are stored the same way
+    List(Vec<UsdValue>),
+    None,
+}
+
+impl UsdValue {
+    fn as_f32(&self) -> Option<f32> {
+        match self {
+            UsdValue::Number(x) => Some(*x as f32),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            UsdValue::Str(x) | UsdValue::Ident(x) | UsdValue::Asset(x) | UsdValue::Path(x) => {
+                Some(x)
+            }
+            _ => None,
+        }
+    }
+
+    fn as_list(&self) -> Option<&[UsdValue]> {
+        match self {
+            UsdValue::List(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Flatten nested lists of numbers
+    fn floats(&self) -> Vec<f32> {
+        match self {
+            UsdValue::Number(x) => vec![*x as f32],
+            UsdValue::List(x) => x.iter().flat_map(|f| f.floats()).collect(),
+            _ => vec![],
+        }
+    }
+
+    fn ints(&self) -> Vec<i64> {
+        self.floats().into_iter().map(|f| f as i64).collect()
+    }
+
+    fn vec3(&self) -> Option<[f32; 3]> {
+        self.floats().try_into().ok()
+    }
+
+    /// Interpret as a list of fixed size tuples
+    fn tuples<const N: usize>(&self) -> Vec<[f32; N]> {
+        self.floats()
+            .chunks_exact(N)
+            .map(|f| f.try_into().unwrap())
+            .collect()
+    }
+}
+
+/// An attribute or relationship on a prim
+#[derive(Debug, Clone, Default)]
+struct UsdProperty {
+    value: Option<UsdValue>,
+    metadata: HashMap<String, UsdValue>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Prim {
+    type_name: Option<String>,
+    name: String,
+    properties: HashMap<String, UsdProperty>,
+    children: Vec<Prim>,
+}
+
+impl Prim {
+    fn value(&self, name: &str) -> Option<&UsdValue> {
+        self.properties.get(name).and_then(|f| f.value.as_ref())
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let ret = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        ret
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        self.skip_newlines();
+        match self.next() {
+            Some(Token::Punct(x)) if x == c => Ok(()),
+            t => anyhow::bail!("Expected '{c}', found {t:?}"),
+        }
+    }
+
+    /// Skip a balanced group, assuming the opening token is next
+    fn skip_group(&mut self) {
+        let mut depth = 0;
+        while let Some(t) = self.next() {
+            match t {
+                Token::Punct('(' | '[' | '{') => depth += 1,
+                Token::Punct(')' | ']' | '}') => {
+                    depth -= 1;
+                    if depth <= 0 {
+                        return;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<UsdValue> {
+        Ok(match self.next() {
+            Some(Token::Number(x)) => UsdValue::Number(x),
+            Some(Token::Str(x)) => UsdValue::Str(x),
+            Some(Token::Asset(x)) => UsdValue::Asset(x),
+            Some(Token::Path(x)) => UsdValue::Path(x),
+            Some(Token::Ident(x)) if x == "None" => UsdValue::None,
+            Some(Token::Ident(x)) => UsdValue::Ident(x),
+            Some(Token::Punct(open @ ('(' | '['))) => {
+                let close = if open == '(' { ')' } else { ']' };
+                let mut list = Vec::new();
+                loop {
+                    self.skip_newlines();
+                    match self.peek() {
+                        Some(Token::Punct(c)) if *c == close => {
+                            self.pos += 1;
+                            break;
+                        }
+                        Some(Token::Punct(',')) => {
+                            self.pos += 1;
+                        }
+                        None => anyhow::bail!("Unterminated list"),
+                        _ => list.push(self.parse_value()?),
+                    }
+                }
+                UsdValue::List(list)
+            }
+            Some(Token::Punct('{')) => {
+                // Dictionaries and time samples; we only use default values
+                self.pos -= 1;
+                self.skip_group();
+                UsdValue::None
+            }
+            t => anyhow::bail!("Unexpected {t:?} in value"),
+        })
+    }
+
+    /// Parse a parenthesized metadata block, assuming the '(' is next
+    fn parse_metadata(&mut self) -> Result<HashMap<String, UsdValue>> {
+        let mut ret = HashMap::new();
+
+        self.expect('(')?;
+
+        loop {
+            self.skip_newlines();
+            match self.next() {
+                Some(Token::Punct(')')) | None => break,
+                Some(Token::Punct(_)) => (),
+                Some(Token::Ident(mut key)) => {
+                    if matches!(
+                        key.as_str(),
+                        "prepend" | "append" | "delete" | "add" | "reorder"
+                    ) {
+                        if let Some(Token::Ident(k)) = self.next() {
+                            key = k;
+                        }
+                    }
+                    if self.peek() == Some(&Token::Punct('=')) {
+                        self.pos += 1;
+                        self.skip_newlines();
+                        let value = self.parse_value()?;
+                        ret.insert(key, value);
+                    }
+                }
+                // Doc strings and the like
+                Some(_) => (),
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Parse the body of a prim, assuming the '{' is next
+    fn parse_prim_body(&mut self, prim: &mut Prim) -> Result<()> {
+        self.expect('{')?;
+
+        loop {
+            self.skip_newlines();
+
+            let Some(t) = self.peek().cloned() else {
+                anyhow::bail!("Unterminated prim {}", prim.name);
+            };
+
+            match t {
+                Token::Punct('}') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Token::Ident(x) if matches!(x.as_str(), "def" | "over" | "class") => {
+                    let child = self.parse_prim()?;
+                    if x == "def" {
+                        prim.children.push(child);
+                    }
+                }
+                Token::Ident(x) if x == "variantSet" => {
+                    // Variants are not composed; skip the whole block
+                    while !matches!(self.peek(), Some(Token::Punct('{')) | None) {
+                        self.pos += 1;
+                    }
+                    self.skip_group();
+                }
+                _ => self.parse_property(prim)?,
+            }
+        }
+    }
+
+    /// Parse a property statement, up to the end of the line
+    fn parse_property(&mut self, prim: &mut Prim) -> Result<()> {
+        let mut head = Vec::new();
+
+        while let Some(t) = self.peek() {
+            match t {
+                Token::Newline | Token::Punct('=') | Token::Punct('(') | Token::Punct('}') => break,
+                _ => head.push(self.next().unwrap()),
+            }
+        }
+
+        // The name is the last identifier before the value
+        let name = head.iter().rev().find_map(|f| match f {
+            Token::Ident(x) => Some(x.clone()),
+            _ => None,
+        });
+
+        let mut prop = UsdProperty::default();
+
+        if self.peek() == Some(&Token::Punct('=')) {
+            self.pos += 1;
+            prop.value = Some(self.parse_value()?);
+        }
+
+        if self.peek() == Some(&Token::Punct('(')) {
+            prop.metadata = self.parse_metadata()?;
+        }
+
+        if let Some(name) = name {
+            // Don't let a declaration without a value overwrite a value
+            match prim.properties.get_mut(&name) {
+                Some(existing) if prop.value.is_none() => existing.metadata.extend(prop.metadata),
+                _ => {
+                    prim.properties.insert(name, prop);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse a prim, assuming the specifier is next
+    fn parse_prim(&mut self) -> Result<Prim> {
+        self.next();
+
+        let mut prim = Prim::default();
+
+        match self.next() {
+            Some(Token::Ident(t)) => {
+                prim.type_name = Some(t);
+                match self.next() {
+                    Some(Token::Str(n)) => prim.name = n,
+                    t => anyhow::bail!("Expected prim name, found {t:?}"),
+                }
+            }
+            Some(Token::Str(n)) => prim.name = n,
+            t => anyhow::bail!("Expected prim type or name, found {t:?}"),
+        }
+
+        self.skip_newlines();
+
+        if self.peek() == Some(&Token::Punct('(')) {
+            self.parse_metadata()?;
+        }
+
+        self.parse_prim_body(&mut prim)?;
+
+        Ok(prim)
+    }
+}
+
+/// A parsed layer
+#[derive(Debug, Default)]
+struct Layer {
+    metadata: HashMap<String, UsdValue>,
+    prims: Vec<Prim>,
+}
+
+fn parse_usda(src: &str) -> Result<Layer> {
+    if !src.starts_with("#usda") {
+        return Err(ImportError::UnknownFileFormat("Missing #usda header".into()).into());
+    }
+
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        pos: 0,
+    };
+
+    let mut layer = Layer::default();
+
+    parser.skip_newlines();
+
+    if parser.peek() == Some(&Token::Punct('(')) {
+        layer.metadata = parser.parse_metadata()?;
+    }
+
+    loop {
+        parser.skip_newlines();
+        match parser.peek() {
+            None => break,
+            Some(Token::Ident(x)) if matches!(x.as_str(), "def" | "over" | "class") => {
+                let is_def = x == "def";
+                let prim = parser.parse_prim()?;
+                if is_def {
+                    layer.prims.push(prim);
+                }
+            }
+            t => anyhow::bail!("Unexpected {t:?} at top level"),
+        }
+    }
+
+    Ok(layer)
+}
+
+// =============================================================================
+// Conversion
+
+/// Where to find the layer and anything it references
+enum Package {
+    Directory(PathBuf),
+    Zip(zip::ZipArchive<std::fs::File>),
+}
+
+impl Package {
+    fn read(&mut self, name: &str) -> Result<Vec<u8>> {
+        let mut ret = Vec::new();
+        match self {
+            Package::Directory(dir) => {
+                ret = std::fs::read(dir.join(name))?;
+            }
+            Package::Zip(archive) => {
+                archive
+                    .by_name(name.trim_start_matches("./"))?
+                    .read_to_end(&mut ret)?;
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// Compute the local transform of a prim from its xform ops
+fn prim_transform(prim: &Prim) -> Matrix4<f32> {
+    let Some(order) = prim.value("xformOpOrder").and_then(|f| f.as_list()) else {
+        return Matrix4::identity();
+    };
+
+    let mut ret = Matrix4::identity();
+
+    for op in order.iter().filter_map(|f| f.as_str()) {
+        let (inverse, op) = match op.strip_prefix("!invert!") {
+            Some(x) => (true, x),
+            None => (false, op),
+        };
+
+        let Some(value) = prim.value(op) else {
+            continue;
+        };
+
+        let kind = op.split(':').nth(1).unwrap_or_default();
+
+        let m = match kind {
+            "transform" => {
+                // USD uses row vectors, so this is already column-major
+                let v = value.floats();
+                if v.len() != 16 {
+                    continue;
+                }
+                Matrix4::from_column_slice(&v)
+            }
+            "translate" => value
+                .vec3()
+                .map(|t| Matrix4::new_translation(&t.into()))
+                .unwrap_or_else(Matrix4::identity),
+            "scale" => value
+                .vec3()
+                .map(|s| Matrix4::new_nonuniform_scaling(&s.into()))
+                .unwrap_or_else(Matrix4::identity),
+            "rotateXYZ" => value
+                .vec3()
+                .map(|r| {
+                    let [x, y, z] = r.map(|f| f.to_radians());
+                    (Rotation3::from_axis_angle(&Vector3::z_axis(), z)
+                        * Rotation3::from_axis_angle(&Vector3::y_axis(), y)
+                        * Rotation3::from_axis_angle(&Vector3::x_axis(), x))
+                    .to_homogeneous()
+                })
+                .unwrap_or_else(Matrix4::identity),
+            "rotateX" | "rotateY" | "rotateZ" => {
+                let angle = value.as_f32().unwrap_or_default().to_radians();
+                let axis = match kind {
+                    "rotateX" => Vector3::x_axis(),
+                    "rotateY" => Vector3::y_axis(),
+                    _ => Vector3::z_axis(),
+                };
+                Rotation3::from_axis_angle(&axis, angle).to_homogeneous()
+            }
+            "orient" => {
+                // Stored as (real, i, j, k)
+                let q = value.floats();
+                if q.len() != 4 {
+                    continue;
+                }
+                UnitQuaternion::from_quaternion(nalgebra::Quaternion::new(q[0], q[1], q[2], q[3]))
+                    .to_homogeneous()
+            }
+            _ => {
+                log::debug!("Unsupported xform op {op}");
+                continue;
+            }
+        };
+
+        let m = if inverse {
+            m.try_inverse().unwrap_or_else(Matrix4::identity)
+        } else {
+            m
+        };
+
+        ret *= m;
+    }
+
+    ret
+}
+
+/// Interpolation of a primvar
+fn interpolation(prim: &Prim, name: &str) -> String {
+    prim.properties
+        .get(name)
+        .and_then(|f| f.metadata.get("interpolation"))
+        .and_then(|f| f.as_str())
+        .unwrap_or("vertex")
+        .to_string()
+}
+
+/// Find the index into primvar data for a given face corner
+fn primvar_index(
+    interp: &str,
+    indices: Option<&[i64]>,
+    point: usize,
+    corner: usize,
+    face: usize,
+) -> usize {
+    let i = match interp {
+        "faceVarying" => corner,
+        "uniform" => face,
+        "constant" => 0,
+        _ => point,
+    };
+
+    match indices {
+        Some(list) => list.get(i).copied().unwrap_or_default() as usize,
+        None => i,
+    }
+}
+
+/// Convert a mesh prim into vertices and triangles
+fn pack_mesh(prim: &Prim) -> Option<(Vec<VertexTexture>, Vec<[u32; 3]>)> {
+    let points = prim.value("points")?.tuples::<3>();
+    let counts = prim.value("faceVertexCounts")?.ints();
+    let indices = prim.value("faceVertexIndices")?.ints();
+
+    let normals = prim
+        .value("normals")
+        .or(prim.value("primvars:normals"))
+        .map(|f| f.tuples::<3>());
+    let normal_interp = if prim.value("normals").is_some() {
+        interpolation(prim, "normals")
+    } else {
+        interpolation(prim, "primvars:normals")
+    };
+
+    let st = prim.value("primvars:st").map(|f| f.tuples::<2>());
+    let st_interp = interpolation(prim, "primvars:st");
+    let st_indices = prim.value("primvars:st:indices").map(|f| f.ints());
+
+    let left_handed = prim
+        .value("orientation")
+        .and_then(|f| f.as_str())
+        .is_some_and(|f| f == "leftHanded");
+
+    let mut verts = Vec::new();
+    let mut faces = Vec::new();
+    let mut remap = HashMap::<(usize, Option<usize>, Option<usize>), u32>::new();
+
+    let mut corner = 0;
+
+    for (face, count) in counts.iter().enumerate() {
+        let count = *count as usize;
+        let mut polygon = Vec::with_capacity(count);
+
+        for c in corner..corner + count {
+            let point = indices.get(c).map(|f| *f as usize)?;
+
+            if point >= points.len() {
+                return None;
+            }
+
+            let n = normals
+                .as_ref()
+                .map(|_| primvar_index(&normal_interp, None, point, c, face));
+            let t = st
+                .as_ref()
+                .map(|_| primvar_index(&st_interp, st_indices.as_deref(), point, c, face));
+
+            let id = *remap.entry((point, n, t)).or_insert_with(|| {
+                verts.push(VertexTexture {
+                    position: points[point],
+                    normal: n
+                        .and_then(|i| normals.as_ref()?.get(i).copied())
+                        .unwrap_or_default(),
+                    texture: t
+                        .and_then(|i| st.as_ref()?.get(i).copied())
+                        .map(|uv| {
+                            // USD texture coordinates have the origin at the bottom
+                            [
+                                (uv[0].clamp(0.0, 1.0) * (65536.0 - 1.0)) as u16,
+                                ((1.0 - uv[1]).clamp(0.0, 1.0) * (65536.0 - 1.0)) as u16,
+                            ]
+                        })
+                        .unwrap_or_default(),
+                });
+                verts.len() as u32 - 1
+            });
+
+            polygon.push(id);
+        }
+
+        corner += count;
+
+        for i in 1..polygon.len().saturating_sub(1) {
+            if left_handed {
+                faces.push([polygon[0], polygon[i + 1], polygon[i]]);
+            } else {
+                faces.push([polygon[0], polygon[i], polygon[i + 1]]);
+            }
+        }
+    }
+
+    (!faces.is_empty()).then_some((verts, faces))
+}
+
+struct Converter<'a> {
+    prepared: Prepared,
+    asset_store: AssetStorePtr,
+    package: Package,
+    source: &'a Path,
+    options: &'a ImportOptions,
+
+    /// Every prim, by path
+    prims: HashMap<String, &'a Prim>,
+
+    materials: HashMap<String, usize>,
+    default_material: Option<usize>,
+
+    bounds: Option<Bounds>,
+    triangles: u64,
+    cleanup: Cleanup,
+}
+
+impl<'a> Converter<'a> {
+    fn index_prims(prims: &mut HashMap<String, &'a Prim>, prim: &'a Prim, parent: &str) {
+        let path = format!("{parent}/{}", prim.name);
+        for child in &prim.children {
+            Self::index_prims(prims, child, &path);
+        }
+        prims.insert(path, prim);
+    }
+
+    /// Follow a connection to the prim that provides it
+    fn connected(&self, shader: &Prim, input: &str) -> Option<&'a Prim> {
+        let path = shader
+            .value(&format!("{input}.connect"))
+            .and_then(|f| match f {
+                UsdValue::List(l) => l.first(),
+                x => Some(x),
+            })
+            .and_then(|f| f.as_str())?;
+
+        // Strip the property from the path
+        let prim_path = path.split('.').next()?;
+        self.prims.get(prim_path).copied()
+    }
+
+    /// Publish a color texture, converting it to sRGB if it is linear
+    fn publish_texture(&mut self, file: &str, declared: Option<ColorSpace>) -> Result<usize> {
+        let bytes = self
+            .package
+            .read(file)
+            .with_context(|| format!("Reading texture {file}"))?;
+
+        let bytes = color::color_texture(bytes, declared)?;
+        let bytes = texture::fit(bytes, self.options);
+
+        Ok(self.prepared.add_texture(
+            Some(file.to_string()),
+            &bytes,
+            self.source,
+            &self.asset_store,
+            self.options,
+        ))
+    }
+
+    /// Find the UsdPreviewSurface of a material prim
+    fn surface_shader(&self, material: &'a Prim) -> Option<&'a Prim> {
+        if let Some(s) = self.connected(material, "outputs:surface") {
+            return Some(s);
+        }
+
+        material
+            .children
+            .iter()
+            .find(|f| f.value("info:id").and_then(|f| f.as_str()) == Some("UsdPreviewSurface"))
+    }
+
+    fn convert_material(&mut self, path: &str) -> usize {
+        if let Some(m) = self.materials.get(path) {
+            return *m;
+        }
+
+        let mut pbr = PBRInfo {
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            metallic: Some(0.0),
+            roughness: Some(0.5),
+            ..Default::default()
+        };
+        let mut emissive = None;
+        let mut texture = None;
+
+        let shader = self
+            .prims
+            .get(path)
+            .copied()
+            .and_then(|f| self.surface_shader(f));
+
+        if let Some(shader) = shader {
+            if let Some(c) = shader.value("inputs:diffuseColor").and_then(|f| f.vec3()) {
+                pbr.base_color[..3].copy_from_slice(&c);
+            }
+
+            if let Some(o) = shader.value("inputs:opacity").and_then(|f| f.as_f32()) {
+                pbr.base_color[3] = o;
+            }
+
+            if let Some(m) = shader.value("inputs:metallic").and_then(|f| f.as_f32()) {
+                pbr.metallic = Some(m);
+            }
+
+            if let Some(r) = shader.value("inputs:roughness").and_then(|f| f.as_f32()) {
+                pbr.roughness = Some(r);
+            }
+
+            emissive = shader
+                .value("inputs:emissiveColor")
+                .and_then(|f| f.vec3())
+                .filter(|f| f.iter().any(|c| *c > 0.0));
+
+            let reader = self.connected(shader, "inputs:diffuseColor");
+
+            let texture_file = reader
+                .and_then(|f| f.value("inputs:file"))
+                .and_then(|f| f.as_str())
+                .map(|f| f.to_string());
+
+            // Raw data is linear; "auto" leaves it to the image
+            let declared = reader
+                .and_then(|f| f.value("inputs:sourceColorSpace"))
+                .and_then(|f| f.as_str())
+                .and_then(|f| match f {
+                    "raw" => Some(ColorSpace::Linear),
+                    "sRGB" => Some(ColorSpace::Srgb),
+                    _ => None,
+                });
+
+            if let Some(file) = texture_file {
+                match self.publish_texture(&file, declared) {
+                    Ok(t) => texture = Some(t),
+                    Err(e) => log::warn!("Unable to load texture: {e:?}"),
+                }
+            }
+        }
+
+        let use_alpha = (pbr.base_color[3] < 1.0).then_some(true);
+
+        pbr.base_color = self.options.tinted(pbr.base_color);
+
+        let ret = self.prepared.add_material(PreparedMaterial {
+            name: Some(path.to_string()),
+            pbr,
+            texture,
+            emissive,
+            use_alpha,
+        });
+
+        self.materials.insert(path.to_string(), ret);
+
+        ret
+    }
+
+    fn default_material(&mut self) -> usize {
+        *self.default_material.get_or_insert_with(|| {
+            self.prepared.add_material(PreparedMaterial {
+                pbr: PBRInfo {
+                    base_color: self.options.tinted([1.0, 1.0, 1.0, 1.0]),
+                    metallic: Some(0.0),
+                    roughness: Some(1.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+        })
+    }
+
+    fn convert_mesh(&mut self, prim: &Prim, world: &Matrix4<f32>) -> Result<Option<usize>> {
+        let Some((mut verts, mut faces)) = pack_mesh(prim) else {
+            log::warn!("Skipping mesh {} without usable faces", prim.name);
+            return Ok(None);
+        };
+
+        self.cleanup += geometry::prepare_mesh(&mut verts, &mut faces, self.options);
+
+        if let Some(b) = Bounds::from_points(verts.iter().map(|f| &f.position)) {
+            let b = b.transformed(world);
+            self.bounds = Some(self.bounds.map_or(b, |f| f.union(&b)));
+        }
+
+        self.triangles += faces.len() as u64;
+
+        let material = match prim.value("material:binding").and_then(|f| f.as_str()) {
+            Some(path) => self.convert_material(path),
+            None => self.default_material(),
+        };
+
+        let source = TriangleMesh {
+            name: Some(prim.name.clone()),
+            vertex: &verts,
+            faces: &faces,
+        };
+
+        Ok(Some(self.prepared.add_mesh(
+            &source,
+            material,
+            self.source,
+            &self.asset_store,
+            self.options,
+        )))
+    }
+
+    fn convert_prim(&mut self, prim: &Prim, parent: usize, parent_tf: &Matrix4<f32>) -> Result<()> {
+        // Materials and shaders are only used through bindings
+        if matches!(prim.type_name.as_deref(), Some("Material" | "Shader")) {
+            return Ok(());
+        }
+
+        let visible = prim.value("visibility").and_then(|f| f.as_str()) != Some("invisible");
+
+        if !visible {
+            return Ok(());
+        }
+
+        let local = prim_transform(prim);
+        let world = parent_tf * local;
+
+        let mesh = match prim.type_name.as_deref() {
+            Some("Mesh") => self.convert_mesh(prim, &world)?,
+            _ => None,
+        };
+
+        let tf: [f32; 16] = local.as_slice().try_into().unwrap();
+
+        let entity = self.prepared.add_entity(PreparedEntity {
+            name: Some(prim.name.clone()),
+            parent: Some(parent),
+            transform: Some(tf),
+            mesh,
+        });
+
+        for child in &prim.children {
+            self.convert_prim(child, entity, &world)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Read the root layer of a file, returning the text and a way to get at
+/// anything it references.
+fn open_layer(path: &Path) -> Result<(String, Package)> {
+    let ext = import::extension(path).unwrap_or_default();
+
+    if ext == "usdz" {
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)
+            .map_err(|e| ImportError::UnableToOpenFile(format!("Bad USDZ package: {e}")))?;
+
+        // The first file in a package is the root layer
+        let mut first = archive
+            .by_index(0)
+            .map_err(|e| ImportError::UnableToImport(format!("Empty USDZ package: {e}")))?;
+
+        if !first.name().ends_with(".usda") {
+            return Err(ImportError::UnableToImport(format!(
+                "USDZ root layer {} is not ASCII USD; only .usda layers are supported",
+                first.name()
+            ))
+            .into());
+        }
+
+        let mut src = String::new();
+        first.read_to_string(&mut src)?;
+        drop(first);
+
+        return Ok((src, Package::Zip(archive)));
+    }
+
+    let src = std::fs::read(path)?;
+
+    if src.starts_with(b"PXR-USDC") {
+        return Err(ImportError::UnableToImport(format!(
+            "{} is a binary USD crate file; only ASCII USD is supported",
+            path.display()
+        ))
+        .into());
+    }
+
+    let dir = path
+        .parent()
+        .unwrap_or_else(|| Path::new("./"))
+        .to_path_buf();
+
+    Ok((String::from_utf8(src)?, Package::Directory(dir)))
+}
+
+/// Import a USD layer
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let (src, package) = open_layer(path)?;
+
+    let layer = parse_usda(&src)
+        .map_err(|e| ImportError::UnableToImport(format!("Unable to parse USD: {e}")))?;
+
+    let mut prims = HashMap::new();
+    for prim in &layer.prims {
+        Converter::index_prims(&mut prims, prim, "");
+    }
+
+    // Group all top level prims so the scene can be moved as one
+    let mut prepared = Prepared::default();
+
+    let root = prepared.add_entity(PreparedEntity {
+        name: import::display_name(path),
+        ..Default::default()
+    });
+
+    let mut converter = Converter {
+        prepared,
+        asset_store: asset_store.clone(),
+        package,
+        source: path,
+        options,
+        prims,
+        materials: HashMap::new(),
+        default_material: None,
+        bounds: None,
+        triangles: 0,
+        cleanup: Cleanup::default(),
+    };
+
+    for prim in &layer.prims {
+        converter.convert_prim(prim, root, &Matrix4::identity())?;
+    }
+
+    let Converter {
+        prepared,
+        bounds,
+        triangles,
+        cleanup,
+        ..
+    } = converter;
+
+    let mut scene = prepared.publish(&mut state.lock().unwrap(), asset_store);
+
+    scene.info.bounds = bounds;
+    scene.info.triangles = triangles;
+    scene.info.cleanup = cleanup;
+
+    // USD defaults to centimeters
+    let meters_per_unit = layer
+        .metadata
+        .get("metersPerUnit")
+        .and_then(|f| f.as_f32())
+        .unwrap_or(0.01);
+
+    scene.info.units = Some(match meters_per_unit {
+        x if (x - 1.0).abs() < 1e-6 => "meters".into(),
+        x if (x - 0.01).abs() < 1e-6 => "centimeters".into(),
+        x if (x - 0.001).abs() < 1e-6 => "millimeters".into(),
+        x => format!("{x} meters"),
+    });
+
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn child<'a>(prim: &'a Prim, name: &str) -> Option<&'a Prim> {
+        prim.children.iter().find(|f| f.name == name)
+    }
+
+    const SRC: &str = r#"#usda 1.0
+(
+    defaultPrim = "World"
+    metersPerUnit = 1
+    upAxis = "Y"
+)
+
+def Xform "World"
+{
+    double3 xformOp:translate = (1, 2, 3)
+    uniform token[] xformOpOrder = ["xformOp:translate"]
+
+    def Mesh "Quad" (
+        prepend apiSchemas = ["MaterialBindingAPI"]
+    )
+    {
+        int[] faceVertexCounts = [4]
+        int[] faceVertexIndices = [0, 1, 2, 3]
+        point3f[] points = [(0, 0, 0), (1, 0, 0), (1, 1, 0), (0, 1, 0)]
+        texCoord2f[] primvars:st = [(0, 0), (1, 0), (1, 1), (0, 1)] (
+            interpolation = "faceVarying"
+        )
+        rel material:binding = </World/Looks/Red>
+    }
+
+    def Scope "Looks"
+    {
+        def Material "Red"
+        {
+            token outputs:surface.connect = </World/Looks/Red/Surface.outputs:surface>
+
+            def Shader "Surface"
+            {
+                uniform token info:id = "UsdPreviewSurface"
+                color3f inputs:diffuseColor = (1, 0, 0)
+                float inputs:roughness = 0.25
+                token outputs:surface
+            }
+        }
+    }
+}
+"#;
+
+    #[test]
+    fn test_parse_usda() {
+        let layer = parse_usda(SRC).unwrap();
+
+        assert_eq!(
+            layer.metadata.get("upAxis").and_then(|f| f.as_str()),
+            Some("Y")
+        );
+        assert_eq!(layer.prims.len(), 1);
+
+        let world = &layer.prims[0];
+        assert_eq!(world.type_name.as_deref(), Some("Xform"));
+
+        let tf = prim_transform(world);
+        assert_eq!(tf.column(3).as_slice(), &[1.0, 2.0, 3.0, 1.0]);
+
+        let quad = child(world, "Quad").unwrap();
+        assert_eq!(
+            quad.value("material:binding").and_then(|f| f.as_str()),
+            Some("/World/Looks/Red")
+        );
+        assert_eq!(interpolation(quad, "primvars:st"), "faceVarying");
+
+        let (verts, faces) = pack_mesh(quad).unwrap();
+        assert_eq!(verts.len(), 4);
+        assert_eq!(faces, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(verts[3].texture, [0, 0]);
+
+        let shader = child(world, "Looks")
+            .and_then(|f| child(f, "Red"))
+            .and_then(|f| child(f, "Surface"))
+            .unwrap();
+        assert_eq!(
+            shader.value("inputs:roughness").and_then(|f| f.as_f32()),
+            Some(0.25)
+        );
+    }
+}
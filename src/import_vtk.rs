@@ -0,0 +1,1106 @@
+//! Import VTK unstructured grids and polygon data, from legacy `.vtk` files
+//! and XML `.vtu` files. Only the outer surface is published; scalar data is
+//! shown as color.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::Result;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::{alphabet, Engine};
+
+use crate::colored_mesh::{publish_mesh, ColoredMesh};
+use crate::import::{self, ImportError, ImportOptions};
+use crate::scene::Scene;
+use colabrodo_server::{server_http::*, server_state::*};
+
+// Cell types we can draw, from vtkCellType.h
+const VTK_TRIANGLE: u8 = 5;
+const VTK_TRIANGLE_STRIP: u8 = 6;
+const VTK_POLYGON: u8 = 7;
+const VTK_PIXEL: u8 = 8;
+const VTK_QUAD: u8 = 9;
+const VTK_TETRA: u8 = 10;
+const VTK_VOXEL: u8 = 11;
+const VTK_HEXAHEDRON: u8 = 12;
+const VTK_WEDGE: u8 = 13;
+const VTK_PYRAMID: u8 = 14;
+const VTK_QUADRATIC_TRIANGLE: u8 = 22;
+const VTK_QUADRATIC_QUAD: u8 = 23;
+const VTK_QUADRATIC_TETRA: u8 = 24;
+const VTK_QUADRATIC_HEXAHEDRON: u8 = 25;
+const VTK_QUADRATIC_WEDGE: u8 = 26;
+const VTK_QUADRATIC_PYRAMID: u8 = 27;
+
+fn bad(msg: impl Into<String>) -> anyhow::Error {
+    ImportError::UnableToImport(msg.into()).into()
+}
+
+/// Everything we use from a VTK file
+#[derive(Debug, Default)]
+struct Dataset {
+    points: Vec<[f32; 3]>,
+    /// Cell type and point ids
+    cells: Vec<(u8, Vec<u32>)>,
+    point_scalars: Option<Vec<f32>>,
+    cell_scalars: Option<Vec<f32>>,
+}
+
+impl Dataset {
+    /// Append another piece, offsetting its point ids
+    fn extend(&mut self, other: Dataset) {
+        let base = self.points.len() as u32;
+        let (points, cells) = (self.points.len(), self.cells.len());
+
+        self.points.extend(other.points);
+        self.cells.extend(
+            other
+                .cells
+                .into_iter()
+                .map(|(t, ids)| (t, ids.into_iter().map(|i| i + base).collect())),
+        );
+
+        // Scalars only survive if every piece has them
+        let merge = |mine: &mut Option<Vec<f32>>, theirs: Option<Vec<f32>>, had: usize| match (
+            mine.as_mut(),
+            theirs,
+        ) {
+            (Some(m), Some(t)) => m.extend(t),
+            (None, Some(t)) if had == 0 => *mine = Some(t),
+            _ => *mine = None,
+        };
+
+        merge(&mut self.point_scalars, other.point_scalars, points);
+        merge(&mut self.cell_scalars, other.cell_scalars, cells);
+    }
+
+    fn check(&self) -> Result<()> {
+        let n = self.points.len();
+
+        if let Some((_, ids)) = self
+            .cells
+            .iter()
+            .find(|(_, ids)| ids.iter().any(|i| *i as usize >= n))
+        {
+            return Err(bad(format!("Cell refers to a missing point: {ids:?}")));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reduce multi-component tuples to one value each, by magnitude
+fn to_scalars(values: &[f64], components: usize) -> Vec<f32> {
+    match components {
+        0 | 1 => values.iter().map(|f| *f as f32).collect(),
+        n => values
+            .chunks_exact(n)
+            .map(|c| c.iter().map(|f| f * f).sum::<f64>().sqrt() as f32)
+            .collect(),
+    }
+}
+
+fn to_points(values: &[f64]) -> Vec<[f32; 3]> {
+    values
+        .chunks_exact(3)
+        .map(|c| [c[0] as f32, c[1] as f32, c[2] as f32])
+        .collect()
+}
+
+/// Split legacy cell lists of the form `n id id id n id id ...`
+fn split_cells(values: &[u32], cell_type: Option<u8>) -> Result<Vec<(u8, Vec<u32>)>> {
+    let mut ret = Vec::new();
+    let mut rest = values;
+
+    while let Some((n, tail)) = rest.split_first() {
+        let n = *n as usize;
+        if tail.len() < n {
+            return Err(bad("Truncated cell list"));
+        }
+        ret.push((cell_type.unwrap_or(0), tail[..n].to_vec()));
+        rest = &tail[n..];
+    }
+
+    Ok(ret)
+}
+
+/// Split cells given as offsets into a connectivity list. Offsets may be
+/// given with a leading zero, as in legacy files, or without, as in VTU.
+fn split_offsets(offsets: &[u32], connectivity: &[u32]) -> Result<Vec<Vec<u32>>> {
+    let mut start = 0;
+    let mut ret = Vec::with_capacity(offsets.len());
+
+    for end in offsets.iter().map(|f| *f as usize) {
+        if end < start || end > connectivity.len() {
+            return Err(bad("Bad cell offsets"));
+        }
+        if end == 0 {
+            continue;
+        }
+        ret.push(connectivity[start..end].to_vec());
+        start = end;
+    }
+
+    Ok(ret)
+}
+
+fn to_ids(values: &[f64]) -> Result<Vec<u32>> {
+    values
+        .iter()
+        .map(|f| match *f >= 0.0 && *f <= u32::MAX as f64 {
+            true => Ok(*f as u32),
+            false => Err(bad(format!("Bad point index {f}"))),
+        })
+        .collect()
+}
+
+// Legacy format =============================================
+
+/// Byte size of a legacy data type, for binary files
+fn legacy_size(ty: &str) -> Result<usize> {
+    Ok(match ty.to_ascii_lowercase().as_str() {
+        "bit" | "char" | "unsigned_char" => 1,
+        "short" | "unsigned_short" => 2,
+        "int" | "unsigned_int" | "float" => 4,
+        "long" | "unsigned_long" | "double" | "vtkidtype" | "vtktypeint64" | "vtktypeuint64" => 8,
+        "vtktypeint32" | "vtktypeuint32" => 4,
+        _ => return Err(bad(format!("Unknown data type {ty}"))),
+    })
+}
+
+/// Reads the mix of text lines and data blocks in a legacy file
+struct LegacyReader<'a> {
+    data: &'a [u8],
+    at: usize,
+    binary: bool,
+}
+
+impl<'a> LegacyReader<'a> {
+    /// The next line of text, or None at the end
+    fn line(&mut self) -> Option<String> {
+        loop {
+            if self.at >= self.data.len() {
+                return None;
+            }
+
+            let rest = &self.data[self.at..];
+            let end = rest.iter().position(|f| *f == b'\n').unwrap_or(rest.len());
+            self.at += (end + 1).min(rest.len());
+
+            let line = String::from_utf8_lossy(&rest[..end]).trim().to_string();
+
+            if !line.is_empty() {
+                return Some(line);
+            }
+        }
+    }
+
+    /// Look at the next line without consuming it
+    fn peek_line(&mut self) -> Option<String> {
+        let at = self.at;
+        let ret = self.line();
+        self.at = at;
+        ret
+    }
+
+    fn token(&mut self) -> Result<&'a str> {
+        let data = self.data;
+
+        while self.at < data.len() && data[self.at].is_ascii_whitespace() {
+            self.at += 1;
+        }
+
+        let start = self.at;
+
+        while self.at < data.len() && !data[self.at].is_ascii_whitespace() {
+            self.at += 1;
+        }
+
+        match start == self.at {
+            true => Err(bad("Unexpected end of file")),
+            false => std::str::from_utf8(&data[start..self.at]).map_err(|_| bad("Bad number")),
+        }
+    }
+
+    /// Read `n` values of a given type
+    fn values(&mut self, n: usize, ty: &str) -> Result<Vec<f64>> {
+        if !self.binary {
+            return (0..n)
+                .map(|_| {
+                    let t = self.token()?;
+                    t.parse::<f64>().map_err(|_| bad(format!("Bad number {t}")))
+                })
+                .collect();
+        }
+
+        // Binary blocks start right after the line that introduces them
+        let size = legacy_size(ty)?;
+
+        let bytes = n
+            .checked_mul(size)
+            .and_then(|len| self.data.get(self.at..self.at.checked_add(len)?))
+            .ok_or_else(|| bad("Truncated binary data"))?;
+
+        self.at += bytes.len();
+
+        let ty = ty.to_ascii_lowercase();
+        let float = ty == "float" || ty == "double";
+        let signed = !ty.starts_with("unsigned") && !ty.contains("uint");
+
+        Ok(bytes
+            .chunks_exact(size)
+            .map(|c| decode_number(c, float, signed, false))
+            .collect())
+    }
+
+    fn ids(&mut self, n: usize, ty: &str) -> Result<Vec<u32>> {
+        to_ids(&self.values(n, ty)?)
+    }
+
+    /// Read cells in either the classic layout, or the OFFSETS and
+    /// CONNECTIVITY layout of version 5 files
+    fn cells(&mut self, counts: &[&str], cell_type: Option<u8>) -> Result<Vec<(u8, Vec<u32>)>> {
+        let a = parse_count(counts.first())?;
+        let b = parse_count(counts.get(1))?;
+
+        if !self
+            .peek_line()
+            .is_some_and(|f| f.to_ascii_uppercase().starts_with("OFFSETS"))
+        {
+            return split_cells(&self.ids(b, "int")?, cell_type);
+        }
+
+        let line = self.line().unwrap_or_default();
+        let ty = line.split_whitespace().nth(1).unwrap_or("vtktypeint64");
+        let offsets = self.ids(a, ty)?;
+
+        let line = self
+            .line()
+            .filter(|f| f.to_ascii_uppercase().starts_with("CONNECTIVITY"))
+            .ok_or_else(|| bad("Missing CONNECTIVITY"))?;
+        let ty = line.split_whitespace().nth(1).unwrap_or("vtktypeint64");
+        let connectivity = self.ids(b, ty)?;
+
+        Ok(split_offsets(&offsets, &connectivity)?
+            .into_iter()
+            .map(|ids| (cell_type.unwrap_or(0), ids))
+            .collect())
+    }
+}
+
+fn parse_count(s: Option<&&str>) -> Result<usize> {
+    s.and_then(|f| f.parse().ok())
+        .ok_or_else(|| bad("Bad element count"))
+}
+
+/// Number of values in `n` tuples of `size`, which a file may overstate
+fn total(n: usize, size: usize) -> Result<usize> {
+    n.checked_mul(size)
+        .ok_or_else(|| bad("Element count is too large"))
+}
+
+/// Decode one number of 1, 2, 4 or 8 bytes
+fn decode_number(c: &[u8], float: bool, signed: bool, little: bool) -> f64 {
+    macro_rules! from {
+        ($t:ty) => {{
+            let b = c.try_into().unwrap();
+            (if little {
+                <$t>::from_le_bytes(b)
+            } else {
+                <$t>::from_be_bytes(b)
+            }) as f64
+        }};
+    }
+
+    match (c.len(), float, signed) {
+        (4, true, _) => from!(f32),
+        (8, true, _) => from!(f64),
+        (1, _, true) => c[0] as i8 as f64,
+        (1, _, false) => c[0] as f64,
+        (2, _, true) => from!(i16),
+        (2, _, false) => from!(u16),
+        (4, _, true) => from!(i32),
+        (4, _, false) => from!(u32),
+        (8, _, true) => from!(i64),
+        _ => from!(u64),
+    }
+}
+
+/// Which attribute section we are in
+#[derive(Clone, Copy)]
+enum Attributes {
+    Point(usize),
+    Cell(usize),
+}
+
+fn parse_legacy(data: &[u8]) -> Result<Dataset> {
+    let mut reader = LegacyReader {
+        data,
+        at: 0,
+        binary: false,
+    };
+
+    let mut header = || reader.line().ok_or_else(|| bad("Unexpected end of file"));
+
+    if !header()?.starts_with("# vtk DataFile") {
+        return Err(bad("Not a VTK file"));
+    }
+
+    let _title = header()?;
+
+    let binary = match header()?.to_ascii_uppercase().as_str() {
+        "ASCII" => false,
+        "BINARY" => true,
+        f => return Err(bad(format!("Unknown VTK encoding {f}"))),
+    };
+
+    let dataset = header()?;
+
+    let kind = dataset
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+
+    if kind != "UNSTRUCTURED_GRID" && kind != "POLYDATA" {
+        return Err(bad(format!("Unsupported VTK dataset {kind}")));
+    }
+
+    reader.binary = binary;
+
+    let mut ret = Dataset::default();
+    let mut cell_types = None;
+    let mut attributes = None;
+
+    // Data arrays, in file order, for when there are no SCALARS
+    let mut fields: Vec<(bool, Vec<f32>)> = Vec::new();
+
+    while let Some(line) = reader.line() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let keyword = words[0].to_ascii_uppercase();
+
+        // How many tuples the current attribute section holds
+        let tuples = match attributes {
+            Some(Attributes::Point(n) | Attributes::Cell(n)) => n,
+            None => 0,
+        };
+
+        let word = |i: usize| words.get(i).copied().unwrap_or("float");
+
+        match keyword.as_str() {
+            "POINTS" => {
+                let n = parse_count(words.get(1))?;
+                ret.points = to_points(&reader.values(total(n, 3)?, word(2))?);
+            }
+            "CELLS" => ret.cells.extend(reader.cells(&words[1..], None)?),
+            "POLYGONS" => ret
+                .cells
+                .extend(reader.cells(&words[1..], Some(VTK_POLYGON))?),
+            "TRIANGLE_STRIPS" => ret
+                .cells
+                .extend(reader.cells(&words[1..], Some(VTK_TRIANGLE_STRIP))?),
+            "VERTICES" | "LINES" => {
+                // Nothing to draw as a surface, but they count as cells
+                let n = reader.cells(&words[1..], None)?.len();
+                ret.cells.extend((0..n).map(|_| (0, vec![])));
+            }
+            "CELL_TYPES" => {
+                let n = parse_count(words.get(1))?;
+                cell_types = Some(reader.ids(n, "int")?);
+            }
+            "POINT_DATA" => attributes = Some(Attributes::Point(parse_count(words.get(1))?)),
+            "CELL_DATA" => attributes = Some(Attributes::Cell(parse_count(words.get(1))?)),
+            "SCALARS" => {
+                let components = words.get(3).and_then(|f| f.parse().ok()).unwrap_or(1);
+
+                if reader
+                    .peek_line()
+                    .is_some_and(|f| f.to_ascii_uppercase().starts_with("LOOKUP_TABLE"))
+                {
+                    reader.line();
+                }
+
+                let values = reader.values(total(tuples, components)?, word(2))?;
+                let values = to_scalars(&values, components);
+
+                let target = match attributes {
+                    Some(Attributes::Point(_)) => &mut ret.point_scalars,
+                    Some(Attributes::Cell(_)) => &mut ret.cell_scalars,
+                    None => return Err(bad("SCALARS outside of a data section")),
+                };
+
+                target.get_or_insert(values);
+            }
+            "FIELD" => {
+                let arrays = parse_count(words.get(2))?;
+
+                for _ in 0..arrays {
+                    let line = reader.line().ok_or_else(|| bad("Unexpected end of file"))?;
+                    let words: Vec<&str> = line.split_whitespace().collect();
+
+                    let components = parse_count(words.get(1))?;
+                    let count = parse_count(words.get(2))?;
+                    let values =
+                        reader.values(total(components, count)?, words.get(3).unwrap_or(&""))?;
+
+                    fields.push((
+                        matches!(attributes, Some(Attributes::Point(_))),
+                        to_scalars(&values, components),
+                    ));
+                }
+            }
+            "VECTORS" | "NORMALS" => {
+                reader.values(total(tuples, 3)?, word(2))?;
+            }
+            "TENSORS" => {
+                reader.values(total(tuples, 9)?, word(2))?;
+            }
+            "TENSORS6" => {
+                reader.values(total(tuples, 6)?, word(2))?;
+            }
+            "TEXTURE_COORDINATES" => {
+                let dim = parse_count(words.get(2))?;
+                reader.values(total(tuples, dim)?, word(3))?;
+            }
+            "COLOR_SCALARS" => {
+                let n = parse_count(words.get(2))?;
+                reader.values(total(tuples, n)?, "unsigned_char")?;
+            }
+            "LOOKUP_TABLE" => {
+                let n = parse_count(words.get(2))?;
+                reader.values(total(n, 4)?, "unsigned_char")?;
+            }
+            "METADATA" => {
+                // Text, ended by a blank line
+                while reader.at < data.len() && !data[reader.at..].starts_with(b"\n\n") {
+                    reader.at += 1;
+                }
+            }
+            _ => return Err(bad(format!("Unknown VTK section {}", words[0]))),
+        }
+    }
+
+    if let Some(types) = cell_types {
+        if types.len() != ret.cells.len() {
+            return Err(bad("Cell type count does not match cell count"));
+        }
+        for ((t, _), ty) in ret.cells.iter_mut().zip(types) {
+            *t = ty as u8;
+        }
+    }
+
+    // Fall back to the first field array if nothing was marked as scalars
+    if ret.point_scalars.is_none() && ret.cell_scalars.is_none() {
+        if let Some((on_points, values)) = fields.into_iter().next() {
+            match on_points {
+                true => ret.point_scalars = Some(values),
+                false => ret.cell_scalars = Some(values),
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+// XML format ================================================
+
+/// Base64 as VTK writes it, where the last group of a block may be left
+/// unpadded
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Decode base64, allowing padding in the middle where separately encoded
+/// blocks are joined
+fn decode_base64(text: &[u8]) -> Result<Vec<u8>> {
+    let chars: Vec<u8> = text
+        .iter()
+        .copied()
+        .filter(|f| !f.is_ascii_whitespace())
+        .collect();
+
+    let mut ret = Vec::with_capacity(chars.len() / 4 * 3);
+
+    // Each block ends with its padding, or with the text
+    let mut rest = &chars[..];
+
+    while !rest.is_empty() {
+        let end = match rest.iter().position(|f| *f == b'=') {
+            Some(i) => i + rest[i..].iter().take_while(|f| **f == b'=').count(),
+            None => rest.len(),
+        };
+
+        BASE64
+            .decode_vec(&rest[..end], &mut ret)
+            .map_err(|e| bad(format!("Bad base64 data: {e}")))?;
+
+        rest = &rest[end..];
+    }
+
+    Ok(ret)
+}
+
+/// How binary arrays are laid out in a VTU file
+struct XmlEncoding {
+    little: bool,
+    header_size: usize,
+    compressed: bool,
+}
+
+impl XmlEncoding {
+    fn header(&self, bytes: &[u8], i: usize) -> Result<usize> {
+        let h = self.header_size;
+        bytes
+            .get(i * h..)
+            .and_then(|f| f.get(..h))
+            .map(|c| decode_number(c, false, false, self.little) as usize)
+            .ok_or_else(|| bad("Truncated array header"))
+    }
+
+    /// Unpack a block of header and data, returning the raw array bytes
+    fn unpack(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let h = self.header_size;
+
+        if !self.compressed {
+            let len = self.header(bytes, 0)?;
+            return bytes
+                .get(h..)
+                .and_then(|f| f.get(..len))
+                .map(|f| f.to_vec())
+                .ok_or_else(|| bad("Truncated array data"));
+        }
+
+        // Block count, block size, last block size, then compressed sizes
+        let blocks = self.header(bytes, 0)?;
+        let mut at = blocks
+            .checked_add(3)
+            .and_then(|f| f.checked_mul(h))
+            .ok_or_else(|| bad("Too many compressed blocks"))?;
+        let mut ret = Vec::new();
+
+        for i in 0..blocks {
+            let len = self.header(bytes, 3 + i)?;
+            let block = bytes
+                .get(at..)
+                .and_then(|f| f.get(..len))
+                .ok_or_else(|| bad("Truncated compressed block"))?;
+
+            flate2::read::ZlibDecoder::new(block)
+                .read_to_end(&mut ret)
+                .map_err(|e| bad(format!("Unable to decompress array: {e}")))?;
+
+            at += len;
+        }
+
+        Ok(ret)
+    }
+}
+
+/// Read a DataArray as numbers, with its component count
+fn read_array(
+    node: roxmltree::Node,
+    encoding: &XmlEncoding,
+    appended: &[u8],
+    appended_base64: bool,
+) -> Result<(Vec<f64>, usize)> {
+    let ty = node.attribute("type").unwrap_or("Float32");
+    let components = node
+        .attribute("NumberOfComponents")
+        .and_then(|f| f.parse().ok())
+        .unwrap_or(1);
+
+    let (size, float, signed) = match ty {
+        "Int8" => (1, false, true),
+        "UInt8" => (1, false, false),
+        "Int16" => (2, false, true),
+        "UInt16" => (2, false, false),
+        "Int32" => (4, false, true),
+        "UInt32" => (4, false, false),
+        "Int64" => (8, false, true),
+        "UInt64" => (8, false, false),
+        "Float32" => (4, true, true),
+        "Float64" => (8, true, true),
+        _ => return Err(bad(format!("Unsupported array type {ty}"))),
+    };
+
+    let bytes = match node.attribute("format").unwrap_or("ascii") {
+        "ascii" => {
+            let values = node
+                .text()
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(|f| f.parse().map_err(|_| bad(format!("Bad number {f}"))))
+                .collect::<Result<_>>()?;
+            return Ok((values, components));
+        }
+        "binary" => {
+            let text = node.text().unwrap_or_default().as_bytes();
+            encoding.unpack(&decode_base64(text)?)?
+        }
+        "appended" => {
+            let offset: usize = node
+                .attribute("offset")
+                .and_then(|f| f.parse().ok())
+                .ok_or_else(|| bad("Appended array without an offset"))?;
+
+            let data = appended
+                .get(offset..)
+                .ok_or_else(|| bad("Array offset is past the appended data"))?;
+
+            match appended_base64 {
+                // Arrays are not delimited; unpacking takes what it needs
+                true => encoding.unpack(&decode_base64(data)?)?,
+                false => encoding.unpack(data)?,
+            }
+        }
+        f => return Err(bad(format!("Unknown array format {f}"))),
+    };
+
+    Ok((
+        bytes
+            .chunks_exact(size)
+            .map(|c| decode_number(c, float, signed, encoding.little))
+            .collect(),
+        components,
+    ))
+}
+
+/// Raw appended data may hold anything, including what looks like markup,
+/// so it is cut out before the rest is parsed
+fn split_appended(data: &[u8]) -> (&[u8], &[u8]) {
+    const TAG: &[u8] = b"<AppendedData";
+
+    let Some(start) = data.windows(TAG.len()).position(|f| f == TAG) else {
+        return (data, &[]);
+    };
+
+    // Data starts after an underscore following the tag
+    let Some(underscore) = data[start..].iter().position(|f| *f == b'_') else {
+        return (data, &[]);
+    };
+
+    let body = start + underscore + 1;
+
+    const END: &[u8] = b"</AppendedData>";
+    let end = data
+        .windows(END.len())
+        .rposition(|f| f == END)
+        .filter(|f| *f >= body)
+        .unwrap_or(data.len());
+
+    (&data[..start], &data[body..end])
+}
+
+fn child<'a, 'i>(node: roxmltree::Node<'a, 'i>, name: &str) -> Option<roxmltree::Node<'a, 'i>> {
+    node.children().find(|f| f.has_tag_name(name))
+}
+
+fn parse_vtu(data: &[u8]) -> Result<Dataset> {
+    let (head, appended) = split_appended(data);
+
+    // Close the document again if we cut out the appended data
+    let mut text = String::from_utf8_lossy(head).to_string();
+    if !appended.is_empty() {
+        text.push_str("</VTKFile>");
+    }
+
+    let doc = roxmltree::Document::parse(&text)
+        .map_err(|e| ImportError::UnableToOpenFile(format!("Bad VTU file: {e}")))?;
+
+    let root = doc.root_element();
+
+    if root.tag_name().name() != "VTKFile" {
+        return Err(bad("Not a VTK XML file"));
+    }
+
+    match root.attribute("type") {
+        Some("UnstructuredGrid") => (),
+        t => return Err(bad(format!("Unsupported VTK XML dataset {t:?}"))),
+    }
+
+    let encoding = XmlEncoding {
+        little: root.attribute("byte_order") != Some("BigEndian"),
+        header_size: match root.attribute("header_type") {
+            Some("UInt64") => 8,
+            _ => 4,
+        },
+        compressed: match root.attribute("compressor") {
+            None | Some("") => false,
+            Some("vtkZLibDataCompressor") => true,
+            Some(c) => return Err(bad(format!("Unsupported compressor {c}"))),
+        },
+    };
+
+    let appended_base64 = doc
+        .descendants()
+        .find(|f| f.has_tag_name("AppendedData"))
+        .is_some_and(|f| f.attribute("encoding") == Some("base64"));
+
+    let array = |node: roxmltree::Node| read_array(node, &encoding, appended, appended_base64);
+
+    // Active scalars are named by the section, otherwise take the first
+    let scalars = |section: Option<roxmltree::Node>| -> Result<Option<Vec<f32>>> {
+        let Some(section) = section else {
+            return Ok(None);
+        };
+
+        let mut arrays = section.children().filter(|f| f.has_tag_name("DataArray"));
+
+        let found = match section.attribute("Scalars") {
+            Some(name) => arrays.find(|f| f.attribute("Name") == Some(name)),
+            None => arrays.next(),
+        };
+
+        Ok(match found {
+            Some(node) => {
+                let (values, components) = array(node)?;
+                Some(to_scalars(&values, components))
+            }
+            None => None,
+        })
+    };
+
+    let grid = child(root, "UnstructuredGrid").ok_or_else(|| bad("Missing grid"))?;
+
+    let mut ret = Dataset::default();
+
+    for piece in grid.children().filter(|f| f.has_tag_name("Piece")) {
+        let mut part = Dataset::default();
+
+        if let Some(points) = child(piece, "Points").and_then(|f| child(f, "DataArray")) {
+            part.points = to_points(&array(points)?.0);
+        }
+
+        if let Some(cells) = child(piece, "Cells") {
+            let named = |name: &str| -> Result<Vec<u32>> {
+                let node = cells
+                    .children()
+                    .find(|f| f.attribute("Name") == Some(name))
+                    .ok_or_else(|| bad(format!("Missing cell {name}")))?;
+                to_ids(&array(node)?.0)
+            };
+
+            let offsets = split_offsets(&named("offsets")?, &named("connectivity")?)?;
+            let types = named("types")?;
+
+            if types.len() != offsets.len() {
+                return Err(bad("Cell type count does not match cell count"));
+            }
+
+            part.cells = types.into_iter().map(|f| f as u8).zip(offsets).collect();
+        }
+
+        part.point_scalars = scalars(child(piece, "PointData"))?;
+        part.cell_scalars = scalars(child(piece, "CellData"))?;
+
+        ret.extend(part);
+    }
+
+    Ok(ret)
+}
+
+// Surface ===================================================
+
+/// Outward facing faces of a 3D cell, as indices into its points
+fn cell_faces(cell_type: u8) -> &'static [&'static [usize]] {
+    match cell_type {
+        VTK_TETRA | VTK_QUADRATIC_TETRA => &[&[0, 1, 3], &[1, 2, 3], &[2, 0, 3], &[0, 2, 1]],
+        VTK_VOXEL => &[
+            &[0, 2, 6, 4],
+            &[1, 5, 7, 3],
+            &[0, 4, 5, 1],
+            &[2, 3, 7, 6],
+            &[0, 1, 3, 2],
+            &[4, 6, 7, 5],
+        ],
+        VTK_HEXAHEDRON | VTK_QUADRATIC_HEXAHEDRON => &[
+            &[0, 4, 7, 3],
+            &[1, 2, 6, 5],
+            &[0, 1, 5, 4],
+            &[3, 7, 6, 2],
+            &[0, 3, 2, 1],
+            &[4, 5, 6, 7],
+        ],
+        VTK_WEDGE | VTK_QUADRATIC_WEDGE => &[
+            &[0, 1, 2],
+            &[3, 5, 4],
+            &[0, 3, 4, 1],
+            &[1, 4, 5, 2],
+            &[2, 5, 3, 0],
+        ],
+        VTK_PYRAMID | VTK_QUADRATIC_PYRAMID => &[
+            &[0, 3, 2, 1],
+            &[0, 1, 4],
+            &[1, 2, 4],
+            &[2, 3, 4],
+            &[3, 0, 4],
+        ],
+        _ => &[],
+    }
+}
+
+/// Collect the polygons to draw, with the cell each came from. Surface
+/// cells are kept as is. Faces of volume cells are kept only if no other
+/// cell shares them, which leaves the outer boundary.
+fn extract_surface(cells: &[(u8, Vec<u32>)]) -> Vec<(Vec<u32>, usize)> {
+    let mut ret = Vec::new();
+
+    // Keyed by sorted point ids, so shared faces match whatever their winding
+    let mut volume: HashMap<Vec<u32>, (usize, Vec<u32>, usize)> = HashMap::new();
+    let mut order = Vec::new();
+
+    for (cell, (ty, ids)) in cells.iter().enumerate() {
+        match *ty {
+            VTK_TRIANGLE | VTK_POLYGON | VTK_QUAD if ids.len() >= 3 => {
+                ret.push((ids.clone(), cell));
+            }
+            VTK_QUADRATIC_TRIANGLE if ids.len() >= 6 => ret.push((ids[..3].to_vec(), cell)),
+            VTK_QUADRATIC_QUAD if ids.len() >= 8 => ret.push((ids[..4].to_vec(), cell)),
+            VTK_PIXEL if ids.len() == 4 => ret.push((vec![ids[0], ids[1], ids[3], ids[2]], cell)),
+            VTK_TRIANGLE_STRIP => {
+                for (i, w) in ids.windows(3).enumerate() {
+                    // Every other triangle is wound the other way
+                    let tri = match i % 2 {
+                        0 => vec![w[0], w[1], w[2]],
+                        _ => vec![w[1], w[0], w[2]],
+                    };
+                    ret.push((tri, cell));
+                }
+            }
+            ty => {
+                for face in cell_faces(ty) {
+                    let Some(face) = face
+                        .iter()
+                        .map(|i| ids.get(*i).copied())
+                        .collect::<Option<Vec<u32>>>()
+                    else {
+                        continue;
+                    };
+
+                    let mut key = face.clone();
+                    key.sort_unstable();
+
+                    let entry = volume.entry(key.clone()).or_insert_with(|| {
+                        order.push(key);
+                        (0, face, cell)
+                    });
+                    entry.0 += 1;
+                }
+            }
+        }
+    }
+
+    ret.extend(order.into_iter().filter_map(|key| {
+        let (count, face, cell) = volume.remove(&key)?;
+        (count == 1).then_some((face, cell))
+    }));
+
+    ret
+}
+
+fn to_mesh(dataset: Dataset, options: &ImportOptions) -> Result<ColoredMesh> {
+    dataset.check()?;
+
+    let faces = extract_surface(&dataset.cells);
+
+    if faces.is_empty() {
+        return Err(bad("No surface cells to draw"));
+    }
+
+    let mut mesh = ColoredMesh {
+        vertex_colors: vec![None; dataset.points.len()],
+        vertices: dataset.points,
+        faces: Vec::with_capacity(faces.len()),
+    };
+
+    let point_colors = dataset
+        .point_scalars
+        .filter(|f| f.len() == mesh.vertices.len())
+        .map(|f| options.colormap.colorize(&f));
+
+    let cell_colors = dataset
+        .cell_scalars
+        .filter(|f| f.len() == dataset.cells.len())
+        .map(|f| options.colormap.colorize(&f));
+
+    if let Some(colors) = point_colors {
+        mesh.vertex_colors = colors.into_iter().map(Some).collect();
+    }
+
+    mesh.faces = faces
+        .into_iter()
+        .map(|(face, cell)| {
+            let color = cell_colors.as_ref().map(|f| f[cell]);
+            (face, color)
+        })
+        .collect();
+
+    Ok(mesh)
+}
+
+/// Import a legacy or XML VTK file
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let data = std::fs::read(path)?;
+
+    let dataset = match import::extension(path).as_deref() {
+        Some("vtu") => parse_vtu(&data)?,
+        _ => parse_legacy(&data)?,
+    };
+
+    log::debug!(
+        "VTK file has {} points and {} cells",
+        dataset.points.len(),
+        dataset.cells.len()
+    );
+
+    let mesh = to_mesh(dataset, options)?;
+
+    publish_mesh(&mesh, path, state, asset_store, options)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Two tetrahedra sharing a face, with a scalar per point
+    const LEGACY: &str = "# vtk DataFile Version 3.0
+two tets
+ASCII
+DATASET UNSTRUCTURED_GRID
+POINTS 5 float
+0 0 0  1 0 0  0 1 0  0 0 1
+1 1 1
+CELLS 2 10
+4 0 1 2 3
+4 1 2 3 4
+CELL_TYPES 2
+10
+10
+POINT_DATA 5
+SCALARS temperature float 1
+LOOKUP_TABLE default
+0 1 2 3 4
+CELL_DATA 2
+VECTORS flow float
+1 0 0 0 1 0
+";
+
+    #[test]
+    fn test_parse_legacy() {
+        let data = parse_legacy(LEGACY.as_bytes()).unwrap();
+
+        assert_eq!(data.points.len(), 5);
+        assert_eq!(data.cells.len(), 2);
+        assert_eq!(data.cells[1], (VTK_TETRA, vec![1, 2, 3, 4]));
+        assert_eq!(data.point_scalars, Some(vec![0.0, 1.0, 2.0, 3.0, 4.0]));
+        assert_eq!(data.cell_scalars, None);
+
+        // The shared face is inside, leaving 3 faces from each
+        let faces = extract_surface(&data.cells);
+        assert_eq!(faces.len(), 6);
+        assert!(faces.iter().all(|(f, _)| {
+            let mut f = f.clone();
+            f.sort();
+            f != vec![1, 2, 3]
+        }));
+
+        let mesh = to_mesh(data, &ImportOptions::default()).unwrap();
+        assert!(mesh.vertex_colors.iter().all(|f| f.is_some()));
+    }
+
+    #[test]
+    fn test_legacy_binary() {
+        let mut data = b"# vtk DataFile Version 5.1\nquad\nBINARY\nDATASET POLYDATA\n".to_vec();
+
+        data.extend(b"POINTS 4 float\n");
+        for p in [
+            [0.0f32, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ] {
+            p.iter().for_each(|f| data.extend(f.to_be_bytes()));
+        }
+
+        data.extend(b"\nPOLYGONS 2 4\nOFFSETS vtktypeint64\n");
+        [0i64, 4].iter().for_each(|f| data.extend(f.to_be_bytes()));
+        data.extend(b"\nCONNECTIVITY vtktypeint64\n");
+        [0i64, 1, 2, 3]
+            .iter()
+            .for_each(|f| data.extend(f.to_be_bytes()));
+
+        data.extend(b"\nCELL_DATA 1\nFIELD FieldData 1\npressure 1 1 double\n");
+        data.extend(2.5f64.to_be_bytes());
+        data.extend(b"\n");
+
+        let data = parse_legacy(&data).unwrap();
+
+        assert_eq!(data.points[2], [1.0, 1.0, 0.0]);
+        assert_eq!(data.cells, vec![(VTK_POLYGON, vec![0, 1, 2, 3])]);
+        assert_eq!(data.cell_scalars, Some(vec![2.5]));
+
+        // Counts too large to hold fail rather than overflow
+        let huge = format!("POINTS {} float\n", usize::MAX / 2);
+        let mut data = b"# vtk DataFile Version 5.1\nquad\nBINARY\nDATASET POLYDATA\n".to_vec();
+        data.extend(huge.as_bytes());
+        assert!(parse_legacy(&data).is_err());
+    }
+
+    #[test]
+    fn test_parse_vtu() {
+        // Points are inline base64 with the header encoded separately;
+        // cells are ascii
+        let mut points = Vec::new();
+        for p in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            points.extend(p.to_le_bytes());
+        }
+
+        let encode = |bytes: &[u8]| base64::engine::general_purpose::STANDARD.encode(bytes);
+
+        let text = format!(
+            r#"<?xml version="1.0"?>
+<VTKFile type="UnstructuredGrid" version="1.0" byte_order="LittleEndian" header_type="UInt32">
+  <UnstructuredGrid>
+    <Piece NumberOfPoints="3" NumberOfCells="1">
+      <Points>
+        <DataArray type="Float32" NumberOfComponents="3" format="binary">{}{}</DataArray>
+      </Points>
+      <Cells>
+        <DataArray type="Int32" Name="connectivity" format="ascii">0 1 2</DataArray>
+        <DataArray type="Int32" Name="offsets" format="ascii">3</DataArray>
+        <DataArray type="UInt8" Name="types" format="ascii">5</DataArray>
+      </Cells>
+      <CellData Scalars="id">
+        <DataArray type="Float64" Name="other" format="ascii">9</DataArray>
+        <DataArray type="Float64" Name="id" format="ascii">7</DataArray>
+      </CellData>
+    </Piece>
+  </UnstructuredGrid>
+</VTKFile>"#,
+            encode(&(points.len() as u32).to_le_bytes()),
+            encode(&points),
+        );
+
+        let data = parse_vtu(text.as_bytes()).unwrap();
+
+        assert_eq!(
+            data.points,
+            vec![[0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+        );
+        assert_eq!(data.cells, vec![(VTK_TRIANGLE, vec![0, 1, 2])]);
+        assert_eq!(data.cell_scalars, Some(vec![7.0]));
+        assert_eq!(data.point_scalars, None);
+
+        // Blocks may be joined with or without their padding
+        assert_eq!(decode_base64(b"AQI= AwQ=").unwrap(), [1, 2, 3, 4]);
+        assert_eq!(decode_base64(b"AQI").unwrap(), [1, 2]);
+        assert!(decode_base64(b"A!==").is_err());
+    }
+}
@@ -0,0 +1,403 @@
+//! Import plain text point clouds: XYZ, PTS, and ASCII PCD
+
+use std::{
+    io::{BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::Result;
+use nalgebra::{Matrix4, Vector3};
+
+use crate::import::{ImportError, ImportOptions};
+use crate::points::{PointChunk, PointCloud, POINTS_PER_CHUNK};
+use crate::scene::Scene;
+use colabrodo_server::{server_http::*, server_state::*};
+
+/// Meaning of a column in a text point cloud
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointColumn {
+    X,
+    Y,
+    Z,
+    R,
+    G,
+    B,
+    /// Shown as gray when there is no color
+    Intensity,
+    /// PCD style color, a float whose bits are 0x00RRGGBB
+    PackedRgb,
+    Skip,
+}
+
+impl FromStr for PointColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "x" => PointColumn::X,
+            "y" => PointColumn::Y,
+            "z" => PointColumn::Z,
+            "r" | "red" => PointColumn::R,
+            "g" | "green" => PointColumn::G,
+            "b" | "blue" => PointColumn::B,
+            "i" | "intensity" => PointColumn::Intensity,
+            "rgb" | "rgba" => PointColumn::PackedRgb,
+            "_" | "-" | "skip" => PointColumn::Skip,
+            x => return Err(format!("Unknown point column '{x}'")),
+        })
+    }
+}
+
+/// Parse a column mapping such as "x y z r g b" or "x,y,z,_,i"
+pub fn parse_columns(s: &str) -> Result<Vec<PointColumn>, String> {
+    let ret: Vec<PointColumn> = split_fields(s)
+        .map(|f| f.parse())
+        .collect::<Result<_, _>>()?;
+
+    for axis in [PointColumn::X, PointColumn::Y, PointColumn::Z] {
+        if !ret.contains(&axis) {
+            return Err(format!("Point columns must include {axis:?}"));
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Guess a mapping from the number of columns, using the common layouts
+fn default_columns(count: usize) -> Vec<PointColumn> {
+    use PointColumn::*;
+    match count {
+        4 => vec![X, Y, Z, Intensity],
+        6 => vec![X, Y, Z, R, G, B],
+        // PTS
+        7 => vec![X, Y, Z, Intensity, R, G, B],
+        _ => vec![X, Y, Z],
+    }
+}
+
+/// Column mapping from a PCD header, expanding fields with a count > 1
+fn pcd_columns(fields: &[&str], counts: &[usize]) -> Vec<PointColumn> {
+    let mut ret = Vec::new();
+
+    for (i, name) in fields.iter().enumerate() {
+        let count = counts.get(i).copied().unwrap_or(1);
+        ret.push(name.parse().unwrap_or(PointColumn::Skip));
+        ret.extend(std::iter::repeat_n(
+            PointColumn::Skip,
+            count.saturating_sub(1),
+        ));
+    }
+
+    ret
+}
+
+fn split_fields(line: &str) -> impl Iterator<Item = &str> {
+    line.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|f| !f.is_empty())
+}
+
+/// A parsed point, before color scaling
+struct RawPoint {
+    position: [f64; 3],
+    color: Option<[f32; 3]>,
+    intensity: Option<f32>,
+}
+
+fn parse_point(line: &str, columns: &[PointColumn]) -> Option<RawPoint> {
+    let mut p = RawPoint {
+        position: [0.0; 3],
+        color: None,
+        intensity: None,
+    };
+
+    let mut fields = split_fields(line);
+
+    for column in columns {
+        let field = fields.next()?;
+
+        if *column == PointColumn::Skip {
+            continue;
+        }
+
+        // Packed colors are raw bits, so they must not pass through f64
+        if *column == PointColumn::PackedRgb {
+            let bits = field.parse::<f32>().ok()?.to_bits();
+            p.color = Some([bits >> 16, bits >> 8, bits].map(|f| (f & 0xFF) as f32));
+            continue;
+        }
+
+        let v: f64 = field.parse().ok()?;
+
+        match column {
+            PointColumn::X => p.position[0] = v,
+            PointColumn::Y => p.position[1] = v,
+            PointColumn::Z => p.position[2] = v,
+            PointColumn::R => p.color.get_or_insert([0.0; 3])[0] = v as f32,
+            PointColumn::G => p.color.get_or_insert([0.0; 3])[1] = v as f32,
+            PointColumn::B => p.color.get_or_insert([0.0; 3])[2] = v as f32,
+            PointColumn::Intensity => p.intensity = Some(v as f32),
+            PointColumn::PackedRgb | PointColumn::Skip => (),
+        }
+    }
+
+    Some(p)
+}
+
+/// Reads a text cloud line by line, handing chunks to `f`.
+///
+/// Positions are made relative to the first point, which is given to `f`
+/// alongside each chunk, so that large survey coordinates survive the trip
+/// to f32. Returns the number of points read.
+fn read_points<R: BufRead>(
+    reader: R,
+    is_pcd: bool,
+    columns: Option<&[PointColumn]>,
+    chunk_size: usize,
+    mut f: impl FnMut(PointChunk, &[f64; 3]) -> Result<()>,
+) -> Result<u64> {
+    let mut columns = columns.map(|f| f.to_vec());
+    let mut in_header = is_pcd;
+    let mut pcd_fields = Vec::<String>::new();
+    let mut pcd_counts = Vec::<usize>::new();
+
+    let mut origin = None;
+    let mut count = 0;
+
+    // Colors may be 0-1 or 0-255. The first chunk decides, so a cloud
+    // doesn't change brightness partway through.
+    let mut color_scale: Option<f32> = None;
+    let mut pending = Vec::<RawPoint>::new();
+
+    let mut flush = |pending: &mut Vec<RawPoint>, origin: &[f64; 3]| -> Result<()> {
+        let scale = *color_scale.get_or_insert_with(|| {
+            let max = pending
+                .iter()
+                .filter_map(|p| p.color)
+                .flatten()
+                .fold(0.0f32, f32::max);
+            if max <= 1.0 {
+                255.0
+            } else {
+                1.0
+            }
+        });
+
+        // Intensity has no standard range, so stretch it per chunk
+        let (lo, hi) = pending
+            .iter()
+            .filter_map(|p| p.intensity)
+            .fold((f32::MAX, f32::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+
+        let mut chunk = PointChunk::with_capacity(pending.len());
+
+        for p in pending.drain(..) {
+            let color = match (p.color, p.intensity) {
+                (Some(c), _) => c.map(|v| (v * scale).clamp(0.0, 255.0) as u8),
+                (None, Some(i)) if hi > lo => [((i - lo) / (hi - lo) * 255.0) as u8; 3],
+                _ => [255; 3],
+            };
+
+            let position = [0, 1, 2].map(|a| (p.position[a] - origin[a]) as f32);
+
+            chunk.push(position, [color[0], color[1], color[2], 255]);
+        }
+
+        f(chunk, origin)
+    };
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+
+        if in_header {
+            let mut parts = line.split_whitespace();
+            match parts.next().map(|f| f.to_uppercase()).as_deref() {
+                Some("FIELDS") => pcd_fields = parts.map(|f| f.to_string()).collect(),
+                Some("COUNT") => pcd_counts = parts.filter_map(|f| f.parse().ok()).collect(),
+                Some("DATA") => {
+                    if parts.next() != Some("ascii") {
+                        return Err(ImportError::UnableToImport(
+                            "Only ASCII PCD files are supported".into(),
+                        )
+                        .into());
+                    }
+
+                    // The header describes the file; it wins over a guess
+                    if columns.is_none() {
+                        let fields: Vec<_> = pcd_fields.iter().map(|f| f.as_str()).collect();
+                        columns = Some(pcd_columns(&fields, &pcd_counts));
+                    }
+
+                    in_header = false;
+                }
+                _ => (),
+            }
+            continue;
+        }
+
+        let fields = split_fields(line).count();
+
+        // PTS files start with a bare point count
+        if fields < 3 {
+            log::debug!("Skipping line: {line}");
+            continue;
+        }
+
+        let columns = columns.get_or_insert_with(|| default_columns(fields));
+
+        let Some(point) = parse_point(line, columns) else {
+            log::debug!("Skipping line: {line}");
+            continue;
+        };
+
+        let origin = *origin.get_or_insert(point.position);
+
+        pending.push(point);
+        count += 1;
+
+        if pending.len() == chunk_size {
+            flush(&mut pending, &origin)?;
+        }
+    }
+
+    if let Some(origin) = &origin {
+        if !pending.is_empty() {
+            flush(&mut pending, origin)?;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Import a text point cloud
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let file = BufReader::new(std::fs::File::open(path)?);
+
+    let is_pcd = path
+        .extension()
+        .is_some_and(|f| f.eq_ignore_ascii_case("pcd"));
+
+    let mut cloud = PointCloud::new(asset_store, path, options)?;
+
+    let count = read_points(
+        file,
+        is_pcd,
+        options.point_columns.as_deref(),
+        POINTS_PER_CHUNK,
+        |chunk, origin| {
+            cloud.start(Matrix4::new_translation(&Vector3::from(
+                origin.map(|f| f as f32),
+            )));
+            cloud.publish(chunk)
+        },
+    )?;
+
+    if count == 0 {
+        return Err(ImportError::UnableToImport("No points found".into()).into());
+    }
+
+    cloud.finish(&mut state.lock().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::points::POINT_STRIDE;
+
+    fn color_of(chunk: &PointChunk, i: usize) -> [u8; 4] {
+        let at = i * POINT_STRIDE + 12;
+        chunk.bytes[at..at + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_parse_columns() {
+        use PointColumn::*;
+        assert_eq!(
+            parse_columns("x y z r g b").unwrap(),
+            vec![X, Y, Z, R, G, B]
+        );
+        assert_eq!(parse_columns("x,_,y,z").unwrap(), vec![X, Skip, Y, Z]);
+        assert!(parse_columns("x y").is_err());
+        assert!(parse_columns("x y z w").is_err());
+    }
+
+    #[test]
+    fn test_read_xyz() {
+        let src = "# a comment\n\
+                   100 200 300 255 0 0\n\
+                   101 200 300 0 255 0\n\
+                   102 202 300 0 0 255\n";
+
+        let mut chunks = Vec::new();
+        let count = read_points(src.as_bytes(), false, None, 2, |c, origin| {
+            assert_eq!(origin, &[100.0, 200.0, 300.0]);
+            chunks.push(c);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(count, 3);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].len(), 2);
+        assert_eq!(color_of(&chunks[0], 1), [0, 255, 0, 255]);
+        assert_eq!(chunks[1].bounds.unwrap().max, Vector3::new(2.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn test_read_pts_with_mapping() {
+        // PTS point count, then x y z intensity r g b; only keep intensity
+        let src = "2\n0 0 0 10 1 2 3\n1 1 1 20 4 5 6\n";
+
+        use PointColumn::*;
+        let columns = [X, Y, Z, Intensity];
+
+        let mut chunks = Vec::new();
+        read_points(src.as_bytes(), false, Some(&columns), 16, |c, _| {
+            chunks.push(c);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(chunks[0].len(), 2);
+        assert_eq!(color_of(&chunks[0], 0), [0, 0, 0, 255]);
+        assert_eq!(color_of(&chunks[0], 1), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_read_pcd() {
+        let rgb = f32::from_bits(0x00FF8000);
+        let src = format!(
+            "# .PCD v0.7\n\
+             VERSION 0.7\n\
+             FIELDS x y z rgb\n\
+             SIZE 4 4 4 4\n\
+             TYPE F F F F\n\
+             COUNT 1 1 1 1\n\
+             POINTS 1\n\
+             DATA ascii\n\
+             1 2 3 {rgb:e}\n"
+        );
+
+        let mut chunks = Vec::new();
+        read_points(src.as_bytes(), true, None, 16, |c, _| {
+            chunks.push(c);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(color_of(&chunks[0], 0), [255, 128, 0, 255]);
+
+        let binary = "FIELDS x y z\nDATA binary\n";
+        assert!(read_points(binary.as_bytes(), true, None, 16, |_, _| Ok(())).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Import ZIP archives holding a model and the files it references, such as
+//! a glTF with its buffers and textures
+
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::import::{self, ImportError, ImportOptions};
+use crate::scene::Scene;
+use colabrodo_server::{server_http::*, server_state::*};
+
+/// Formats we look for in an archive, most preferred first. Images and
+/// tables are left out, as they are more likely to be textures or data.
+const MODEL_FORMATS: &[&str] = &[
+    "gltf", "glb", "usdz", "usda", "usd", "3mf", "obj", "fbx", "dae", "off", "vtu", "vtk", "e57",
+    "las", "ply", "splat", "xyz", "pts", "pcd",
+];
+
+/// Choose the model to import from a list of entry names. Entries nearest
+/// the top of the archive win, then by format preference, then by name.
+fn find_model<'a>(names: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    names
+        .filter(|f| !f.ends_with('/'))
+        // Resource forks and the like are not models, whatever their names
+        .filter(|f| !f.split('/').any(|c| c.starts_with('.') || c == "__MACOSX"))
+        .filter_map(|f| {
+            let rank = MODEL_FORMATS
+                .iter()
+                .position(|e| import::extension(Path::new(f)).as_deref() == Some(*e))?;
+            Some((f.matches('/').count(), rank, f))
+        })
+        .min()
+        .map(|f| f.2)
+}
+
+/// Extract an archive to scratch space and import the model inside
+pub fn import_file(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<Scene> {
+    let scratch = options.scratch.as_ref().ok_or_else(|| {
+        ImportError::UnableToImport("Archives need scratch space to extract into".into())
+    })?;
+
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)
+        .map_err(|e| ImportError::UnableToOpenFile(format!("Bad ZIP archive: {e}")))?;
+
+    let model = find_model(archive.file_names())
+        .ok_or_else(|| ImportError::UnableToImport("No model found in archive".into()))?
+        .to_string();
+
+    log::info!("Importing {model} from {}", path.display());
+
+    let mut dir = scratch.allocate(&import::display_name(path).unwrap_or_default())?;
+
+    // Extract everything, so relative references from the model resolve
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+
+        if entry.is_dir() {
+            continue;
+        }
+
+        let Some(name) = entry
+            .enclosed_name()
+            .and_then(|f| f.to_str().map(String::from))
+        else {
+            log::warn!("Skipping archive entry {}", entry.name());
+            continue;
+        };
+
+        // Sizes in the archive may lie; the quota is checked as it is read
+        dir.write_from(&name, &mut entry)?;
+    }
+
+    // Extracted paths change between runs, so ids come from the archive
+    let options = ImportOptions {
+        archive: Some(path.to_path_buf()),
+        ..options.clone()
+    };
+
+    let mut scene = import::import_file(&dir.path().join(&model), state, asset_store, &options)?;
+
+    scene.scratch.push(dir);
+
+    Ok(scene)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_find_model() {
+        let names = [
+            "README.txt",
+            "textures/albedo.png",
+            "__MACOSX/._scene.gltf",
+            "extra/other.glb",
+            "scene.obj",
+            "scene.gltf",
+            "scene.bin",
+        ];
+        assert_eq!(find_model(names.into_iter()), Some("scene.gltf"));
+
+        // A single wrapping folder is common
+        let names = ["model/", "model/part.obj", "model/deeper/main.gltf"];
+        assert_eq!(find_model(names.into_iter()), Some("model/part.obj"));
+
+        assert_eq!(find_model(["notes.txt"].into_iter()), None);
+    }
+
+    #[test]
+    fn test_archive_entries() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("mesh.zip");
+
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let opts = zip::write::SimpleFileOptions::default();
+        zip.start_file("../escape.txt", opts).unwrap();
+        zip.write_all(b"no").unwrap();
+        zip.start_file("mesh/tri.off", opts).unwrap();
+        zip.write_all(b"OFF\n3 1 0\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n")
+            .unwrap();
+        zip.finish().unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(find_model(archive.file_names()), Some("mesh/tri.off"));
+        assert!(archive.by_index(0).unwrap().enclosed_name().is_none());
+    }
+}
@@ -0,0 +1,232 @@
+//! Transcoding of KTX2 textures.
+//!
+//! glTF files using `KHR_texture_basisu` keep their textures in KTX2
+//! containers, which many clients can't decode. Their fallback images are
+//! published instead, unless the `ktx2` capability is enabled. KTX2 images
+//! without a fallback are transcoded to PNG where that is possible.
+//!
+//! Only textures stored as plain 8 bit pixels, uncompressed or with zlib
+//! supercompression, can be transcoded here. Basis Universal (ETC1S and
+//! UASTC) and zstd are not transcoded; those images are published as they
+//! are, and only clients that decode KTX2 will show them.
+
+use std::io::{Cursor, Read};
+
+use anyhow::Result;
+use image::{DynamicImage, ImageBuffer, ImageFormat};
+
+use crate::import::ImportError;
+
+/// Identifier every KTX2 file starts with
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+/// Size of the header and index, up to the level index
+const HEADER_SIZE: usize = 80;
+
+/// Largest image we will transcode, in bytes. Sizes come from the header,
+/// so they are checked before anything is allocated.
+const MAX_IMAGE_SIZE: usize = 1 << 28;
+
+/// Supercompression schemes
+const SUPERCOMPRESSION_NONE: u32 = 0;
+const SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+const SUPERCOMPRESSION_ZLIB: u32 = 3;
+
+/// Data format descriptor color models for Basis Universal
+const MODEL_ETC1S: u8 = 163;
+const MODEL_UASTC: u8 = 166;
+
+/// Whether bytes hold a KTX2 file
+pub fn is_ktx2(bytes: &[u8]) -> bool {
+    bytes.starts_with(&IDENTIFIER)
+}
+
+fn unable(message: impl Into<String>) -> anyhow::Error {
+    ImportError::UnableToImport(message.into()).into()
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|f| u32::from_le_bytes(f.try_into().unwrap()))
+        .ok_or_else(|| unable("Truncated KTX2 file"))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> Result<usize> {
+    bytes
+        .get(offset..offset + 8)
+        .map(|f| u64::from_le_bytes(f.try_into().unwrap()) as usize)
+        .ok_or_else(|| unable("Truncated KTX2 file"))
+}
+
+/// Channels per pixel of the Vulkan formats we can read, and whether red
+/// and blue are swapped
+fn channels(format: u32) -> Option<(usize, bool)> {
+    Some(match format {
+        // R8_UNORM, R8_SRGB
+        9 | 15 => (1, false),
+        // R8G8B8_UNORM, R8G8B8_SRGB
+        23 | 29 => (3, false),
+        // R8G8B8A8_UNORM, R8G8B8A8_SRGB
+        37 | 43 => (4, false),
+        // B8G8R8A8_UNORM, B8G8R8A8_SRGB
+        44 | 50 => (4, true),
+        _ => return None,
+    })
+}
+
+/// Transcode the largest mip level of a KTX2 texture to PNG. Arrays and
+/// cube maps keep only their first image.
+pub fn to_png(bytes: &[u8]) -> Result<Vec<u8>> {
+    if !is_ktx2(bytes) {
+        return Err(unable("Not a KTX2 file"));
+    }
+
+    let format = u32_at(bytes, 12)?;
+    let width = u32_at(bytes, 20)?;
+    let height = u32_at(bytes, 24)?.max(1);
+    let scheme = u32_at(bytes, 44)?;
+    let dfd = u32_at(bytes, 48)? as usize;
+
+    let model = (dfd > 0).then(|| bytes.get(dfd + 12).copied()).flatten();
+
+    if scheme == SUPERCOMPRESSION_BASIS_LZ || matches!(model, Some(MODEL_ETC1S | MODEL_UASTC)) {
+        return Err(unable(
+            "Basis Universal textures can't be transcoded; \
+             enable the ktx2 capability for clients that decode them",
+        ));
+    }
+
+    let Some((count, swapped)) = channels(format) else {
+        return Err(unable(format!("Unsupported KTX2 pixel format {format}")));
+    };
+
+    let offset = u64_at(bytes, HEADER_SIZE)?;
+    let length = u64_at(bytes, HEADER_SIZE + 8)?;
+
+    let level = offset
+        .checked_add(length)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| unable("Truncated KTX2 file"))?;
+
+    let size = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|f| f.checked_mul(count))
+        .filter(|f| *f <= MAX_IMAGE_SIZE)
+        .ok_or_else(|| unable(format!("KTX2 image of {width}x{height} is too large")))?;
+
+    let mut pixels = match scheme {
+        SUPERCOMPRESSION_NONE => level.get(..size).map(|f| f.to_vec()),
+        SUPERCOMPRESSION_ZLIB => {
+            let mut ret = Vec::with_capacity(size);
+            flate2::read::ZlibDecoder::new(level)
+                .take(size as u64)
+                .read_to_end(&mut ret)?;
+            (ret.len() == size).then_some(ret)
+        }
+        _ => {
+            return Err(unable(format!(
+                "Unsupported KTX2 supercompression {scheme}"
+            )))
+        }
+    }
+    .ok_or_else(|| unable("KTX2 level is smaller than its image"))?;
+
+    if swapped {
+        pixels.chunks_exact_mut(4).for_each(|f| f.swap(0, 2));
+    }
+
+    let image = match count {
+        1 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        3 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        _ => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+    }
+    .ok_or_else(|| unable("Bad KTX2 image size"))?;
+
+    let mut ret = Vec::new();
+    image.write_to(&mut Cursor::new(&mut ret), ImageFormat::Png)?;
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    /// Size of each entry of the level index
+    const LEVEL_SIZE: usize = 24;
+
+    /// A single level KTX2 file, without a data format descriptor
+    fn ktx2(format: u32, scheme: u32, width: u32, height: u32, level: &[u8]) -> Vec<u8> {
+        let mut ret = IDENTIFIER.to_vec();
+
+        for f in [format, 1, width, height, 0, 1, 1, 1, scheme] {
+            ret.extend(f.to_le_bytes());
+        }
+
+        // Index, with no descriptor, key/values, or global data
+        ret.extend([0; 32]);
+
+        let offset = (HEADER_SIZE + LEVEL_SIZE) as u64;
+        for f in [offset, level.len() as u64, level.len() as u64] {
+            ret.extend(f.to_le_bytes());
+        }
+
+        ret.extend(level);
+        ret
+    }
+
+    fn decode(png: &[u8]) -> DynamicImage {
+        image::load_from_memory_with_format(png, ImageFormat::Png).unwrap()
+    }
+
+    #[test]
+    fn test_ktx2() {
+        let pixels = [1, 2, 3, 4, 5, 6, 7, 8];
+
+        let file = ktx2(43, SUPERCOMPRESSION_NONE, 2, 1, &pixels);
+        assert!(is_ktx2(&file));
+        assert!(!is_ktx2(b"\x89PNG"));
+
+        let image = decode(&to_png(&file).unwrap());
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.to_rgba8().into_raw(), pixels);
+
+        // BGRA comes out as RGBA
+        let file = ktx2(50, SUPERCOMPRESSION_NONE, 2, 1, &pixels);
+        assert_eq!(
+            decode(&to_png(&file).unwrap()).to_rgba8().into_raw(),
+            [3, 2, 1, 4, 7, 6, 5, 8]
+        );
+
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), Default::default());
+        zlib.write_all(&[9, 10, 11]).unwrap();
+        let file = ktx2(23, SUPERCOMPRESSION_ZLIB, 1, 1, &zlib.finish().unwrap());
+        assert_eq!(
+            decode(&to_png(&file).unwrap()).to_rgb8().into_raw(),
+            [9, 10, 11]
+        );
+
+        // Basis Universal, formats we don't know, and short levels fail
+        assert!(to_png(&ktx2(0, SUPERCOMPRESSION_BASIS_LZ, 1, 1, &[0; 8])).is_err());
+        assert!(to_png(&ktx2(131, SUPERCOMPRESSION_NONE, 1, 1, &[0; 8])).is_err());
+        assert!(to_png(&ktx2(37, SUPERCOMPRESSION_NONE, 4, 4, &pixels)).is_err());
+        assert!(to_png(&file[..HEADER_SIZE]).is_err());
+    }
+
+    #[test]
+    fn test_ktx2_huge() {
+        // Refused from the header alone, before anything is allocated
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), Default::default());
+        zlib.write_all(&[0; 16]).unwrap();
+        let level = zlib.finish().unwrap();
+
+        for (w, h) in [(65535, 65535), (u32::MAX, u32::MAX)] {
+            let err = to_png(&ktx2(37, SUPERCOMPRESSION_ZLIB, w, h, &level)).unwrap_err();
+            assert!(err.to_string().contains("too large"), "{err}");
+        }
+    }
+}
@@ -1,58 +1,104 @@
 mod arguments;
+mod capabilities;
+mod coalesce;
+mod color;
+mod colored_mesh;
+mod colormap;
+mod demo;
 mod dir_watcher;
+mod events;
+mod export;
+mod fetch;
+mod gen_test;
+mod geometry;
 pub mod import;
+pub mod import_3mf;
+#[cfg(feature = "assimp")]
+pub mod import_assimp;
+pub mod import_csv;
+pub mod import_e57;
 pub mod import_gltf;
+pub mod import_heightmap;
+pub mod import_las;
 pub mod import_obj;
+pub mod import_off;
+pub mod import_ply;
+mod import_queue;
+pub mod import_splat;
+pub mod import_usd;
+pub mod import_vtk;
+pub mod import_xyz;
+pub mod import_zip;
+mod ktx2;
+mod manifest;
+mod mdns;
 mod methods;
 mod platter_state;
+mod points;
+mod prepared;
+mod references;
+mod report;
 mod scene;
+mod scheduler;
+mod scratch;
+mod sequence;
+mod shadow;
+mod signals;
+mod snapshot;
+mod texture;
+mod watchers;
 
 use colabrodo_common::network::default_server_address;
 use colabrodo_server::server::{server_main, tokio, ServerOptions};
 use colabrodo_server::server_http::*;
 use colabrodo_server::server_state::ServerState;
+use import_queue::ImportQueue;
 use platter_state::PlatterState;
 use platter_state::PlatterStatePtr;
 use platter_state::{handle_command, PlatterCommand};
 use std::env;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::time::Duration;
 
+/// Run commands as they arrive. Loads, and other commands that convert or
+/// copy content, go through the import queue and run on blocking threads;
+/// everything else runs here, in order.
 async fn command_handler(
     ps: PlatterStatePtr,
     mut command_stream: tokio::sync::mpsc::Receiver<PlatterCommand>,
+    mut imports: ImportQueue,
 ) {
-    while let Some(msg) = command_stream.recv().await {
-        handle_command(ps.clone(), msg);
-    }
-}
-
-fn mdns_publish(port: u16) -> mdns_sd::ServiceDaemon {
-    let mdns = mdns_sd::ServiceDaemon::new().expect("unable to create mdns daemon");
-
-    const SERVICE_TYPE: &'static str = "_noodles._tcp.local.";
-    const INSTANCE_NAME: &'static str = "platter";
-
-    if let Ok(nif) = local_ip_address::list_afinet_netifas() {
-        for (_, ip) in nif.iter().filter(|f| f.1.is_ipv4()) {
-            let ip_str = ip.to_string();
-            let host = format!("{}.local.", ip);
-
-            if ip.to_string().contains("10.15.88") {
-                continue;
-            }
+    let (done_tx, mut done_rx) = tokio::sync::mpsc::unbounded_channel();
 
-            let srv_info =
-                mdns_sd::ServiceInfo::new(SERVICE_TYPE, INSTANCE_NAME, &host, ip_str, port, None)
-                    .expect("unable to  build MDNS service information");
+    loop {
+        while let Some((key, msg)) = imports.next() {
+            let ps = ps.clone();
+            let done_tx = done_tx.clone();
 
-            log::info!("registering MDNS SD on {}", ip);
+            tokio::task::spawn_blocking(move || {
+                handle_command(ps, msg);
+                let _ = done_tx.send(key);
+            });
+        }
 
-            if mdns.register(srv_info).is_err() {
-                log::warn!("unable to register MDNS SD for {}", ip);
-            }
+        tokio::select! {
+            msg = command_stream.recv() => match msg {
+                Some(msg) if ImportQueue::is_import(&msg) => {
+                    imports.push(msg);
+                    log::debug!("{} imports waiting", imports.waiting());
+                }
+                Some(msg) => {
+                    if matches!(msg, PlatterCommand::Shutdown) {
+                        imports.clear();
+                    }
+                    handle_command(ps.clone(), msg);
+                }
+                None => break,
+            },
+            Some(key) = done_rx.recv() => imports.finish(key),
         }
     }
-
-    mdns
 }
 
 #[tokio::main]
@@ -62,7 +108,7 @@ async fn main() {
     }
     env_logger::init();
 
-    let args = arguments::get_arguments();
+    let mut args = arguments::get_arguments();
 
     // Set up options for the noodles server
 
@@ -78,87 +124,310 @@ async fn main() {
     let asset_server = make_asset_server(AssetServerOptions::new(&opts));
 
     // Prep command streams
-    let (command_tx, command_rx) = tokio::sync::mpsc::channel(16);
+    // Commands block when full, as dropping a load or clear would leave the
+    // scene list inconsistent
+    let (command_tx, command_rx) = tokio::sync::mpsc::channel(args.command_queue.max(1));
 
-    let (stop_tx, _) = tokio::sync::broadcast::channel(1);
+    let (stop_tx, mut shutdown_rx) = tokio::sync::broadcast::channel(1);
 
     // Prep streams for the watcher controller
-    let (watcher_tx, mut watcher_rx) = tokio::sync::mpsc::unbounded_channel();
-
-    let offset = args.offset.map(|f| {
-        let mut iter = f.split(",").map(|g| g.trim().parse().unwrap());
-        nalgebra_glm::Vec3::new(
-            iter.next().unwrap_or_default(),
-            iter.next().unwrap_or_default(),
-            iter.next().unwrap_or_default(),
-        )
-    });
+    // Watch requests are rejected when full
+    let (watcher_tx, watcher_rx) = tokio::sync::mpsc::channel(args.watch_queue.max(1));
 
-    let init = platter_state::PlatterInit {
-        command_stream: command_tx.clone(),
-        watcher_command_stream: watcher_tx,
-        asset_store: asset_server.clone(),
-        size_large_limit: args.size_large_limit,
-        resize: args.rescale.unwrap_or(1.0),
-        offset: offset.unwrap_or_default(),
+    let watchers = watchers::Watchers::default();
+
+    let scheduler = scheduler::Scheduler::new(args.low_power);
+
+    let watcher_options = dir_watcher::WatcherOptions {
+        event_queue: args.fs_event_queue,
+        overflow: args.fs_event_overflow,
+        settle_interval: Duration::from_millis(args.settle_interval),
+        settle_checks: args.settle_checks,
+        scheduler: Some(scheduler.clone()),
     };
 
-    // take a copy of the command sender to move into the watcher command task
-    let spawner_tx_clone = command_tx.clone();
-
-    // start up a command task for the watcher: this will spawn new dir watchers upon request.
-    tokio::spawn(async move {
-        while let Some(msg) = watcher_rx.recv().await {
-            tokio::spawn(dir_watcher::launch_file_watcher(
-                spawner_tx_clone.clone(),
-                msg,
-                stop_tx.subscribe(),
-            ));
-        }
+    let scratch = scratch::ScratchSpace::new(args.scratch_dir.as_deref(), args.scratch_quota)
+        .expect("unable to create scratch space");
+
+    let point_columns = args.point_columns.as_deref().map(|f| {
+        import_xyz::parse_columns(f).unwrap_or_else(|e| {
+            log::error!("Bad point columns: {e}");
+            panic!("Unable to continue");
+        })
     });
 
-    // Based on args, insert an initial command into the command stream
-    match args.source {
-        arguments::Source::File { ref name } => {
-            if !name.try_exists().unwrap() {
-                log::error!("File {} is not readable.", name.display());
-                panic!("Unable to continue");
-            }
+    let table_position: [String; 3] = args
+        .table_position
+        .split(',')
+        .map(|f| f.trim().to_string())
+        .collect::<Vec<_>>()
+        .try_into()
+        .unwrap_or_else(|_| {
+            log::error!("Table position needs three column names, such as x,y,z");
+            panic!("Unable to continue");
+        });
+
+    // The demo stands in for a file source per bundled sample. The files
+    // live as long as this directory.
+    let mut demo_files = None;
+
+    if let Some(spacing) = args.sources.iter().find_map(|f| match f {
+        arguments::Source::Demo { spacing } => Some(*spacing),
+        _ => None,
+    }) {
+        let (dir, sources) = demo::sources(&scratch, spacing).unwrap_or_else(|e| {
+            log::error!("Unable to write demo samples: {e}");
+            panic!("Unable to continue");
+        });
+
+        args.sources
+            .retain(|f| !matches!(f, arguments::Source::Demo { .. }));
+        args.sources.extend(sources);
+        demo_files = Some(dir);
+    }
 
-            command_tx
-                .send(platter_state::PlatterCommand::LoadFile(name.clone(), None))
-                .await
-                .unwrap();
-        }
+    // Report every missing or unsupported input before giving up
+    let mut missing = false;
 
-        arguments::Source::Watch(ref dir) => {
-            // early exit
-            if !dir.dir.try_exists().unwrap() {
-                log::error!("Directory {} is not readable.", dir.dir.display());
-                panic!("Unable to continue");
+    for source in &args.sources {
+        match source {
+            arguments::Source::File { names, .. } => {
+                for name in names.iter().filter(|f| !f.try_exists().unwrap_or(false)) {
+                    log::error!("File {} is not readable.", name.display());
+                    missing = true;
+                }
             }
-
-            command_tx
-                .send(platter_state::PlatterCommand::WatchDirectory(dir.clone()))
-                .await
-                .unwrap();
+            arguments::Source::Watch(dir) => {
+                if !dir.dir.try_exists().unwrap_or(false) {
+                    log::error!("Directory {} is not readable.", dir.dir.display());
+                    missing = true;
+                }
+            }
+            arguments::Source::Websocket { port } => {
+                log::error!("Websocket sources (port {port}) are not supported yet.");
+                missing = true;
+            }
+            arguments::Source::GenTest { .. } | arguments::Source::Demo { .. } => (),
         }
+    }
+
+    if missing {
+        panic!("Unable to continue");
+    }
+
+    // Everything exists, so these paths can be compared against loaded files
+    let source_transforms = args
+        .sources
+        .iter()
+        .filter(|f| !f.transform().is_empty())
+        .flat_map(|f| {
+            f.paths()
+                .into_iter()
+                .map(|p| (p.canonicalize().unwrap_or(p), f.transform()))
+        })
+        .collect();
+
+    let source_ttls = args
+        .sources
+        .iter()
+        .filter_map(|f| Some((f.paths(), f.ttl()?)))
+        .flat_map(|(paths, ttl)| {
+            paths
+                .into_iter()
+                .map(move |p| (p.canonicalize().unwrap_or(p), ttl))
+        })
+        .collect();
+
+    let discovery = mdns::DiscoveryStatus::default();
+
+    let fetch_limits = fetch::FetchLimits {
+        timeout: Duration::from_secs(args.fetch_timeout),
+        max_size: args.fetch_max_size,
+    };
 
-        arguments::Source::Websocket { port: _ } => todo!(),
+    let scene_count = Arc::<AtomicUsize>::default();
+    let asset_sizes = import::AssetSizes::default();
+
+    if let Some(period) = args.self_report {
+        report::start(
+            &scheduler,
+            period.0,
+            report::Sources {
+                scenes: scene_count.clone(),
+                asset_sizes: asset_sizes.clone(),
+                commands: command_tx.clone(),
+                watch_requests: watcher_tx.clone(),
+            },
+        );
     }
 
+    let init = platter_state::PlatterInit {
+        command_stream: command_tx.clone(),
+        watcher_command_stream: watcher_tx,
+        asset_store: asset_server.clone(),
+        transform: arguments::SourceTransform {
+            offset: args.offset,
+            rescale: args.rescale,
+            rotate: args.rotate,
+        },
+        source_transforms,
+        ttl: args.ttl.map(|f| f.0),
+        source_ttls,
+        scheduler: scheduler.clone(),
+        coalescer: (args.transform_rate > 0.0)
+            .then(|| coalesce::Coalescer::new(args.transform_rate, scheduler.clone())),
+        placeholder: match (args.placeholder, args.placeholder_text) {
+            (Some(path), _) => Some(platter_state::Placeholder::File(path)),
+            (None, Some(txt)) => Some(platter_state::Placeholder::Text(txt)),
+            (None, None) => None,
+        },
+        import_options: import::ImportOptions {
+            fetch_remote: args.fetch_remote.then(|| fetch_limits.clone()),
+            deterministic: args.deterministic,
+            generate_normals: args.generate_normals,
+            keep_degenerate: args.keep_degenerate,
+            weld: args.weld.then_some(geometry::WeldOptions {
+                position: args.weld_position,
+                normal: args.weld_normal,
+                texture: args.weld_texture,
+            }),
+            instance_threshold: args.instance_threshold,
+            max_texture_size: args.max_texture_size,
+            scratch: Some(scratch.clone()),
+            size_large_limit: args.size_large_limit,
+            point_columns,
+            tint: None,
+            colormap: args.colormap,
+            table: import_csv::TableOptions {
+                position: table_position,
+                color_column: args.table_color,
+                glyph_size: args.table_glyph_size,
+            },
+            heightmap: import_heightmap::HeightmapOptions {
+                enabled: args.heightmaps,
+                spacing: args.heightmap_spacing,
+                scale: args.heightmap_scale,
+            },
+            capabilities: capabilities::Capabilities::new(
+                &args.enable_capability,
+                &args.disable_capability,
+            ),
+            archive: None,
+            up_axis: args.up_axis,
+            units: args.units,
+            vertex_colors: args.vertex_color_space,
+            center: args.center,
+            fit: args.fit,
+            asset_sizes,
+            asset_limit: args.asset_limit,
+            cancel: None,
+            claim: None,
+        },
+        label_scenes: args.label_scenes,
+        ground_shadows: args.ground_shadows,
+        turntable: args.turntable,
+        gc_interval: Some(args.gc_interval.0).filter(|f| !f.is_zero()),
+        scene_count: scene_count.clone(),
+        load_url: args.load_url.then_some(fetch_limits),
+        export_dir: args.export_dir,
+        export_on_exit: args.export_on_exit,
+        watch_root: args.watch_root,
+        tint_sources: args.tint_sources,
+        discovery: discovery.clone(),
+        method_attachment: args.method_attachment,
+        control_token: args.control_token,
+        stop: stop_tx.clone(),
+        watchers: watchers.clone(),
+        watcher_options: watcher_options.clone(),
+    };
+
+    // Start dir watchers upon request. Each is tracked until it exits, and
+    // all are stopped on shutdown.
+    let watcher_task = tokio::spawn(watchers::run(
+        watchers,
+        watcher_rx,
+        command_tx.clone(),
+        watcher_options,
+        stop_tx.subscribe(),
+    ));
+
     let server_state = ServerState::new();
 
     let platter_state = PlatterState::new(server_state.clone(), init);
 
-    tokio::spawn(command_handler(platter_state, command_rx));
+    let imports = ImportQueue::new(
+        args.sources
+            .iter()
+            .map(|f| {
+                let paths = f
+                    .paths()
+                    .into_iter()
+                    .map(|p| p.canonicalize().unwrap_or(p))
+                    .collect();
+                (paths, f.policy())
+            })
+            .collect(),
+        args.url_priority,
+        args.import_concurrency,
+    );
+
+    tokio::spawn(command_handler(platter_state, command_rx, imports));
+
+    // Queue up the initial sources. The handler is running, so this cannot
+    // fill the queue and stall.
+    for source in args.sources {
+        match source {
+            arguments::Source::File { names, .. } => {
+                for name in names {
+                    command_tx
+                        .send(platter_state::PlatterCommand::LoadFile(name, None, None))
+                        .await
+                        .unwrap();
+                }
+            }
+            arguments::Source::Watch(dir) => {
+                command_tx
+                    .send(platter_state::PlatterCommand::WatchDirectory(dir))
+                    .await
+                    .unwrap();
+            }
+            // Rejected above
+            arguments::Source::Websocket { .. } => (),
+            arguments::Source::GenTest { kind } => {
+                command_tx
+                    .send(platter_state::PlatterCommand::Generate(kind))
+                    .await
+                    .unwrap();
+            }
+            // Replaced by file sources above
+            arguments::Source::Demo { .. } => (),
+        }
+    }
 
     log::info!("Starting up.");
 
-    let mdns = mdns_publish(opts.host.port().unwrap());
+    let mdns = mdns::publish(opts.host.port().unwrap(), &scheduler, discovery).await;
+
+    // Launch the main noodles task and wait for it to complete, or for a
+    // shutdown request. Scenes are dropped and watchers stopped by then.
+    let mut server = Box::pin(server_main(opts, server_state));
+
+    tokio::select! {
+        _ = &mut server => {}
+        _ = shutdown_rx.recv() => {}
+    }
+
+    if let Some(mdns) = mdns {
+        mdns.shutdown().unwrap();
+    }
+
+    // Dropping the server closes it
+    drop(server);
 
-    // Launch the main noodles task and wait for it to complete
-    server_main(opts, server_state).await;
+    // The server may have stopped on its own; make sure watchers hear of it
+    let _ = stop_tx.send(true);
+    let _ = watcher_task.await;
 
-    mdns.shutdown().unwrap();
+    drop(demo_files);
+    scratch.cleanup();
 }
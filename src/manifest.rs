@@ -0,0 +1,183 @@
+//! Manifests for watched directories.
+//!
+//! Without a manifest, a watcher loads whatever turns up and guesses at the
+//! rest. A `platter.json` dropped into the top of a watched directory says
+//! exactly which files to load, and how to place, name, and group each one:
+//!
+//! ```json
+//! {
+//!     "files": [
+//!         { "path": "engine/block.glb", "name": "Engine block", "group": "engine",
+//!           "offset": [0, 1, 0], "rotate": [0, 90, 0], "rescale": 0.001 },
+//!         { "path": "chassis.obj", "group": "body" }
+//!     ]
+//! }
+//! ```
+//!
+//! Paths are relative to the manifest. While a manifest is in place, files it
+//! does not list are ignored. Removing it, or stopping the watcher, drops it;
+//! files already loaded stay as they are.
+
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::arguments::SourceTransform;
+
+/// Name of the manifest file
+pub const FILE_NAME: &str = "platter.json";
+
+/// One file listed in a manifest
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    /// Path of the file, relative to the manifest
+    pub path: PathBuf,
+
+    /// Name to show for the scene
+    pub name: Option<String>,
+
+    /// Group the scene belongs to, for clients to collect related scenes
+    pub group: Option<String>,
+
+    pub offset: Option<[f32; 3]>,
+    pub rescale: Option<f32>,
+    pub rotate: Option<[f32; 3]>,
+}
+
+impl Entry {
+    /// Placement for this file. Anything left unset falls back to the
+    /// source and global options.
+    pub fn transform(&self) -> SourceTransform {
+        SourceTransform {
+            offset: self.offset,
+            rescale: self.rescale,
+            rotate: self.rotate,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Document {
+    files: Vec<Entry>,
+}
+
+/// A parsed manifest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// Directory holding the manifest
+    dir: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl Manifest {
+    /// Parse a manifest held in `dir`
+    pub fn parse(dir: &Path, text: &str) -> Result<Self> {
+        let doc: Document = serde_json::from_str(text)?;
+
+        for entry in &doc.files {
+            if entry.path.as_os_str().is_empty()
+                || !entry
+                    .path
+                    .components()
+                    .all(|f| matches!(f, Component::Normal(_) | Component::CurDir))
+            {
+                bail!(
+                    "Manifest paths must stay within the manifest's directory, got {}",
+                    entry.path.display()
+                );
+            }
+        }
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            entries: doc.files,
+        })
+    }
+
+    /// Read a manifest file
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read {}", path.display()))?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        Self::parse(dir, &text).with_context(|| format!("Bad manifest {}", path.display()))
+    }
+
+    /// Whether a path is the manifest for a directory
+    pub fn is_manifest(dir: &Path, path: &Path) -> bool {
+        path.parent() == Some(dir) && path.file_name() == Some(FILE_NAME.as_ref())
+    }
+
+    /// Every listed file, with its full path, in manifest order
+    pub fn files(&self) -> impl Iterator<Item = (PathBuf, &Entry)> {
+        self.entries.iter().map(|f| (self.dir.join(&f.path), f))
+    }
+
+    /// The entry listing a file, if any
+    pub fn entry(&self, path: &Path) -> Option<&Entry> {
+        self.files().find(|(p, _)| p == path).map(|(_, f)| f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let dir = Path::new("/data/run");
+
+        let manifest = Manifest::parse(
+            dir,
+            r#"{
+                "files": [
+                    { "path": "./parts/a.glb", "name": "Part A", "group": "parts",
+                      "offset": [1, 0, 0], "rescale": 2 },
+                    { "path": "b.obj" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let files: Vec<_> = manifest.files().map(|(p, _)| p).collect();
+        assert_eq!(files, [dir.join("parts/a.glb"), dir.join("b.obj")]);
+
+        let a = manifest.entry(&dir.join("parts/a.glb")).unwrap();
+        assert_eq!(a.name.as_deref(), Some("Part A"));
+        assert_eq!(a.group.as_deref(), Some("parts"));
+        assert_eq!(
+            a.transform(),
+            SourceTransform {
+                offset: Some([1.0, 0.0, 0.0]),
+                rescale: Some(2.0),
+                rotate: None,
+            }
+        );
+
+        let b = manifest.entry(&dir.join("b.obj")).unwrap();
+        assert!(b.name.is_none());
+        assert!(b.transform().is_empty());
+
+        assert!(manifest.entry(&dir.join("c.obj")).is_none());
+
+        // Listed files must be inside the directory
+        for bad in [
+            r#"{"files":[{"path":"../a.obj"}]}"#,
+            r#"{"files":[{"path":"/a.obj"}]}"#,
+        ] {
+            assert!(Manifest::parse(dir, bad).is_err());
+        }
+
+        assert!(Manifest::parse(dir, r#"{"files":[{"path":"a.obj","scale":2}]}"#).is_err());
+
+        assert!(Manifest::is_manifest(dir, &dir.join(FILE_NAME)));
+        assert!(!Manifest::is_manifest(
+            dir,
+            &dir.join("sub").join(FILE_NAME)
+        ));
+    }
+}
@@ -0,0 +1,316 @@
+//! Advertise the server over mDNS, following network interface changes
+
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use colabrodo_server::server::tokio;
+use if_watch::{tokio::IfWatcher, IfEvent};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+use crate::scheduler::Scheduler;
+
+const SERVICE_TYPE: &str = "_noodles._tcp.local.";
+const INSTANCE_NAME: &str = "platter";
+
+/// Should we advertise on this address?
+fn is_eligible(ip: &IpAddr) -> bool {
+    ip.is_ipv4() && !ip.to_string().contains("10.15.88")
+}
+
+/// Whether clients can find us over mDNS
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Discovery {
+    #[default]
+    Starting,
+    /// Registered on at least one interface
+    Advertising,
+    /// There are no interfaces to advertise on
+    NoInterfaces,
+    /// Registration failed, and will be tried again
+    Retrying,
+    /// Discovery could not be started at all
+    Disabled,
+}
+
+impl Discovery {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Discovery::Starting => "starting",
+            Discovery::Advertising => "advertising",
+            Discovery::NoInterfaces => "no_interfaces",
+            Discovery::Retrying => "retrying",
+            Discovery::Disabled => "disabled",
+        }
+    }
+}
+
+/// Shared handle to the current discovery state
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveryStatus(Arc<Mutex<Discovery>>);
+
+impl DiscoveryStatus {
+    pub fn get(&self) -> Discovery {
+        *self.0.lock().unwrap()
+    }
+
+    fn set(&self, d: Discovery) {
+        *self.0.lock().unwrap() = d;
+    }
+}
+
+/// First delay between registration attempts; this doubles on each failure
+const RETRY_MIN: Duration = Duration::from_secs(1);
+const RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// How long to wait after a number of consecutive failures
+fn backoff(failures: u32) -> Duration {
+    (RETRY_MIN * 2u32.pow(failures.saturating_sub(1).min(16))).min(RETRY_MAX)
+}
+
+/// Tracks what we have registered with the daemon
+struct Publisher {
+    mdns: ServiceDaemon,
+    port: u16,
+    addresses: BTreeSet<IpAddr>,
+    fullname: Option<String>,
+    status: DiscoveryStatus,
+    /// Consecutive registration failures
+    failures: u32,
+    retry_pending: bool,
+}
+
+impl Publisher {
+    /// Record a new set of addresses, returning true if it changed
+    fn set_addresses(&mut self, addresses: BTreeSet<IpAddr>) -> bool {
+        if addresses == self.addresses {
+            return false;
+        }
+
+        for ip in addresses.difference(&self.addresses) {
+            log::info!("MDNS SD now available on {}", ip);
+        }
+
+        for ip in self.addresses.difference(&addresses) {
+            log::info!("MDNS SD no longer available on {}", ip);
+        }
+
+        self.addresses = addresses;
+
+        true
+    }
+
+    /// Replace any registration with one for the current addresses
+    fn register(&mut self) -> Result<(), String> {
+        // Withdraw the old record first, so clients drop stale addresses
+        if let Some(fullname) = self.fullname.take() {
+            if self.mdns.unregister(&fullname).is_err() {
+                log::warn!("unable to unregister MDNS SD");
+            }
+        }
+
+        let Some(first) = self.addresses.first() else {
+            self.status.set(Discovery::NoInterfaces);
+            return Ok(());
+        };
+
+        let host = format!("{}.local.", first);
+        let ips: Vec<IpAddr> = self.addresses.iter().copied().collect();
+
+        let srv_info = ServiceInfo::new(
+            SERVICE_TYPE,
+            INSTANCE_NAME,
+            &host,
+            &ips[..],
+            self.port,
+            None,
+        )
+        .map_err(|e| format!("unable to build service information: {e}"))?;
+
+        let fullname = srv_info.get_fullname().to_string();
+
+        self.mdns
+            .register(srv_info)
+            .map_err(|e| format!("unable to register: {e}"))?;
+
+        self.fullname = Some(fullname);
+        self.status.set(Discovery::Advertising);
+
+        Ok(())
+    }
+}
+
+/// Register, and on failure, try again later with increasing delays
+fn sync(publisher: &Arc<Mutex<Publisher>>, scheduler: &Scheduler) {
+    let mut this = publisher.lock().unwrap();
+
+    let Err(e) = this.register() else {
+        this.failures = 0;
+        return;
+    };
+
+    this.failures += 1;
+    this.status.set(Discovery::Retrying);
+
+    let delay = backoff(this.failures);
+
+    log::warn!("MDNS SD {e}; trying again in {delay:?}");
+
+    if std::mem::replace(&mut this.retry_pending, true) {
+        return;
+    }
+
+    let publisher = publisher.clone();
+    let retry_scheduler = scheduler.clone();
+
+    scheduler.after(delay, move || {
+        let registered = {
+            let mut this = publisher.lock().unwrap();
+            this.retry_pending = false;
+            this.fullname.is_some()
+        };
+
+        // An interface change may have fixed things in the meantime
+        if !registered {
+            sync(&publisher, &retry_scheduler);
+        }
+    });
+}
+
+/// Interface changes come in bursts when docking or connecting a VPN; wait
+/// for things to settle before registering again
+const SETTLE: Duration = Duration::from_millis(500);
+
+/// How long to wait for the interface watcher to report the addresses there
+/// already are, and for a gap in that report that says it is done
+const FIRST_REPORT: Duration = Duration::from_secs(2);
+const REPORT_GAP: Duration = Duration::from_millis(100);
+
+/// Take the events a new watcher gives for the interfaces that are already
+/// up. It knows of none until it has been polled.
+async fn first_report(watcher: &mut IfWatcher) {
+    let mut wait = FIRST_REPORT;
+
+    while let Ok(event) =
+        tokio::time::timeout(wait, std::future::poll_fn(|cx| watcher.poll_if_event(cx))).await
+    {
+        if let Err(e) = event {
+            log::warn!("unable to list network interfaces: {e}");
+            return;
+        }
+        wait = REPORT_GAP;
+    }
+}
+
+/// Register the server over mDNS on every eligible interface, and keep the
+/// registration current as interfaces come and go (docking, VPNs, etc).
+/// Failed registrations are retried. Progress is reported through `status`.
+pub async fn publish(
+    port: u16,
+    scheduler: &Scheduler,
+    status: DiscoveryStatus,
+) -> Option<ServiceDaemon> {
+    let mdns = match ServiceDaemon::new() {
+        Ok(d) => d,
+        Err(e) => {
+            log::error!("Unable to start MDNS SD, clients will not discover this server: {e}");
+            status.set(Discovery::Disabled);
+            return None;
+        }
+    };
+
+    let mut watcher = match IfWatcher::new() {
+        Ok(w) => w,
+        Err(e) => {
+            log::error!(
+                "Unable to watch network interfaces, clients will not discover this server: {e}"
+            );
+            status.set(Discovery::Disabled);
+            return Some(mdns);
+        }
+    };
+
+    first_report(&mut watcher).await;
+
+    let current = |w: &IfWatcher| -> BTreeSet<IpAddr> {
+        w.iter().map(|f| f.addr()).filter(is_eligible).collect()
+    };
+
+    let publisher = Arc::new(Mutex::new(Publisher {
+        mdns: mdns.clone(),
+        port,
+        addresses: BTreeSet::new(),
+        fullname: None,
+        status: status.clone(),
+        failures: 0,
+        retry_pending: false,
+    }));
+
+    publisher.lock().unwrap().set_addresses(current(&watcher));
+    sync(&publisher, scheduler);
+
+    if status.get() != Discovery::Advertising {
+        log::error!(
+            "MDNS SD could not register on any interface ({}); clients will need this server's address until it does",
+            status.get().name()
+        );
+    }
+
+    // Latest addresses, and whether an update is already scheduled
+    let pending = Arc::new(Mutex::new(None::<BTreeSet<IpAddr>>));
+
+    let scheduler = scheduler.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let event = std::future::poll_fn(|cx| watcher.poll_if_event(cx)).await;
+
+            match event {
+                Ok(IfEvent::Up(net)) | Ok(IfEvent::Down(net)) => {
+                    log::debug!("Network interface change on {}", net);
+                }
+                Err(e) => {
+                    log::warn!("unable to watch network interfaces: {e}");
+                    break;
+                }
+            }
+
+            let already_scheduled = pending.lock().unwrap().replace(current(&watcher)).is_some();
+
+            if already_scheduled {
+                continue;
+            }
+
+            let pending = pending.clone();
+            let publisher = publisher.clone();
+            let sync_scheduler = scheduler.clone();
+
+            scheduler.after(SETTLE, move || {
+                let Some(addresses) = pending.lock().unwrap().take() else {
+                    return;
+                };
+
+                let changed = publisher.lock().unwrap().set_addresses(addresses);
+
+                if changed {
+                    sync(&publisher, &sync_scheduler);
+                }
+            });
+        }
+    });
+
+    Some(mdns)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), RETRY_MIN);
+        assert_eq!(backoff(3), RETRY_MIN * 4);
+        assert_eq!(backoff(100), RETRY_MAX);
+    }
+}
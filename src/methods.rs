@@ -8,10 +8,13 @@ use colabrodo_server::server::make_method_function;
 use colabrodo_server::server_messages::*;
 use colabrodo_server::server_state::*;
 
+use crate::arguments::{Directory, HumanDuration};
+use crate::events::EventKind;
 use crate::platter_state::PlatterState;
 use crate::platter_state::PlatterStatePtr;
-use crate::scene::Scene;
+use crate::scene::{MaterialOverrides, Scene};
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::Mutex;
 
@@ -113,6 +116,884 @@ make_method_function!(set_scale,
     }
 );
 
+make_method_function!(
+    delete,
+    PlatterState,
+    "platter.delete",
+    "Remove this scene, and unpublish its assets.",
+    {
+        let reference = get_entity(context, state)?;
+
+        let id = app
+            .find_id(&reference)
+            .ok_or_else(|| MethodException::internal_error(None))?;
+
+        app.request_remove(id).map_err(|e| {
+            log::error!("{e}");
+            MethodException::internal_error(None)
+        })?;
+
+        Ok(None)
+    }
+);
+
+make_method_function!(reload,
+    PlatterState,
+    "platter.reload",
+    "Import this scene again from its source file, keeping its transform. The scene keeps its id, but its entities are replaced.",
+    {
+        let reference = get_entity(context, state)?;
+
+        let id = app
+            .find_id(&reference)
+            .ok_or_else(|| MethodException::internal_error(None))?;
+
+        if app.get_object(id).and_then(|f| f.info.source.as_ref()).is_none() {
+            return Err(MethodException::invalid_parameters(None));
+        }
+
+        app.request_reload(id).map_err(|e| {
+            log::error!("{e}");
+            MethodException::internal_error(None)
+        })?;
+
+        Ok(None)
+    }
+);
+
+make_method_function!(duplicate,
+    PlatterState,
+    "platter.duplicate",
+    "Copy this scene as a new scene beside it, sharing buffers and assets. The copy has its own transform and materials.",
+    {
+        let reference = get_entity(context, state)?;
+
+        let id = app
+            .find_id(&reference)
+            .ok_or_else(|| MethodException::internal_error(None))?;
+
+        app.request_duplicate(id).map_err(|e| {
+            log::error!("{e}");
+            MethodException::internal_error(None)
+        })?;
+
+        Ok(None)
+    }
+);
+
+make_method_function!(set_base_color,
+    PlatterState,
+    "platter.set_base_color",
+    "Recolor every material in this scene. Textures are kept, and tinted by the new color.",
+    |color : Value : "New base color, as vec3, or null to restore the original colors"|,
+    {
+        let base_color = match color {
+            Value::Null => None,
+            c => Some(
+                c.deserialized::<[f32; 3]>()
+                    .map_err(|_| MethodException::invalid_parameters(None))?
+                    .sanitize(),
+            ),
+        };
+
+        let obj = get_object(app, state, context)?;
+
+        let overrides = MaterialOverrides {
+            base_color,
+            ..obj.overrides()
+        };
+
+        obj.set_overrides(state, overrides);
+
+        Ok(None)
+    }
+);
+
+make_method_function!(set_opacity,
+    PlatterState,
+    "platter.set_opacity",
+    "Make this scene see-through, by scaling the opacity of every material.",
+    |opacity : f32 : "Opacity, from 0 for invisible to 1 for the original opacity"|,
+    {
+        let [opacity] = [opacity].sanitize();
+        let opacity = opacity.clamp(0.0, 1.0);
+
+        let obj = get_object(app, state, context)?;
+
+        let overrides = MaterialOverrides {
+            opacity: (opacity < 1.0).then_some(opacity),
+            ..obj.overrides()
+        };
+
+        obj.set_overrides(state, overrides);
+
+        Ok(None)
+    }
+);
+
+make_method_function!(set_wireframe,
+    PlatterState,
+    "platter.set_wireframe",
+    "Ask clients to draw this scene as a wireframe. NOODLES materials cannot express this, so the root entity is tagged with platter.render=wireframe for clients that support it.",
+    |wireframe : bool : "True for a wireframe, false for solid"|,
+    {
+        let obj = get_object(app, state, context)?;
+
+        let overrides = MaterialOverrides {
+            wireframe,
+            ..obj.overrides()
+        };
+
+        obj.set_overrides(state, overrides);
+
+        Ok(None)
+    }
+);
+
+make_method_function!(set_clip_plane,
+    PlatterState,
+    "platter.set_clip_plane",
+    "Cut away the part of this scene in front of a plane, to see inside. The plane is given in world coordinates, and then moves with the scene; a plane facing the same way as an earlier one replaces it. NOODLES cannot express clipping, so the root entity is tagged with platter.clip_plane=x,y,z,offset for each plane, in the root entity's own coordinates, for clients that support it.",
+    |normal : [f32;3] : "Direction the cut away side lies in, as vec3"|,
+    |offset : f32 : "Content p where dot(normal, p) is greater than this is cut away"|,
+    {
+        let obj = get_object(app, state, context)?;
+
+        let normal = normal.sanitize();
+
+        if !offset.is_finite() || !obj.set_clip_plane(normal.into(), offset) {
+            return Err(MethodException::invalid_parameters(None));
+        }
+
+        Ok(None)
+    }
+);
+
+make_method_function!(
+    clear_clip_planes,
+    PlatterState,
+    "platter.clear_clip_planes",
+    "Remove every clip plane from this scene.",
+    {
+        let obj = get_object(app, state, context)?;
+
+        obj.set_clip_planes(Vec::new());
+
+        Ok(None)
+    }
+);
+
+make_method_function!(highlight,
+    PlatterState,
+    "platter.highlight",
+    "Highlight this scene, so clients can draw attention to it. Only one scene is highlighted at a time; its root entity is tagged with platter.highlight, and the platter.highlight_changed signal announces every change.",
+    |on : bool : "True to highlight this scene, false to stop"|,
+    {
+        let reference = get_entity(context, state)?;
+
+        let id = app
+            .find_id(&reference)
+            .ok_or_else(|| MethodException::internal_error(None))?;
+
+        app.set_highlight(state, id, on);
+
+        Ok(None)
+    }
+);
+
+/// One entry of a transform batch
+struct TransformEntry {
+    scene: u32,
+    position: Option<[f32; 3]>,
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>,
+}
+
+impl TransformEntry {
+    /// Parse a map with a scene_id, and optional position, rotation, and scale
+    fn from_value(v: Value) -> Result<Self, MethodException> {
+        let Value::Map(map) = v else {
+            return Err(MethodException::invalid_parameters(None));
+        };
+
+        let bad = |_| MethodException::invalid_parameters(None);
+
+        let mut scene = None;
+        let mut position = None;
+        let mut rotation = None;
+        let mut scale = None;
+
+        for (k, v) in map {
+            match k.as_text() {
+                Some("scene_id") => scene = Some(v.deserialized().map_err(bad)?),
+                Some("position") => position = Some(v.deserialized().map_err(bad)?),
+                Some("rotation") => rotation = Some(v.deserialized().map_err(bad)?),
+                Some("scale") => scale = Some(v.deserialized().map_err(bad)?),
+                _ => (),
+            }
+        }
+
+        Ok(Self {
+            scene: scene.ok_or_else(|| MethodException::invalid_parameters(None))?,
+            position,
+            rotation,
+            scale,
+        })
+    }
+}
+
+make_method_function!(transform_many,
+    PlatterState,
+    "platter.transform_many",
+    "Set the transforms of many scenes at once. Each entry is a map with a scene_id, and any of a position as vec3, a rotation as vec4, and a scale as vec3. Nothing is changed if any entry is invalid.",
+    |transforms : Vec<Value> : "List of transform entries"|,
+    {
+        let entries = transforms
+            .into_iter()
+            .map(TransformEntry::from_value)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if entries.iter().any(|f| app.get_object(f.scene).is_none()) {
+            return Err(MethodException::invalid_parameters(None));
+        }
+
+        for entry in entries {
+            let obj = app.get_object_mut(entry.scene).unwrap();
+
+            obj.set_transform_parts(
+                entry.position.map(|f| f.sanitize().into()),
+                entry.rotation.map(|f| {
+                    let q = f.sanitize();
+                    Quaternion::new(q[3], q[0], q[1], q[2])
+                }),
+                entry.scale.map(|f| f.sanitize().into()),
+            );
+        }
+
+        Ok(None)
+    }
+);
+
+make_method_function!(set_global_transform,
+    PlatterState,
+    "platter.set_global_transform",
+    "Replace the rescale and offset given at startup, and apply them to every loaded scene. Sources with their own rescale or offset keep them, and client changes to scene transforms are kept.",
+    |rescale : f32 : "Factor to rescale content by"|,
+    |offset : [f32;3] : "Offset for content, as vec3"|,
+    {
+        let offset = offset.sanitize();
+
+        if !rescale.is_finite() || rescale == 0.0 || offset.iter().any(|f| !f.is_finite()) {
+            return Err(MethodException::invalid_parameters(None));
+        }
+
+        app.set_global_transform(rescale, offset);
+
+        Ok(None)
+    }
+);
+
+make_method_function!(list_viewpoints,
+    PlatterState,
+    "platter.list_viewpoints",
+    "List the camera viewpoints authored in a scene. Returns a list of maps with a name, a transform as a column-major mat4, and an optional vertical field of view in radians.",
+    {
+        let obj = get_object(app, state, context)?;
+
+        let list = obj
+            .viewpoints
+            .iter()
+            .map(|f| {
+                let mut map = vec![(
+                    Value::Text("transform".into()),
+                    Value::Array(
+                        f.transform
+                            .iter()
+                            .map(|&v| Value::Float(v as f64))
+                            .collect(),
+                    ),
+                )];
+
+                if let Some(name) = &f.name {
+                    map.push((Value::Text("name".into()), Value::Text(name.clone())));
+                }
+
+                if let Some(yfov) = f.yfov {
+                    map.push((Value::Text("yfov".into()), Value::Float(yfov as f64)));
+                }
+
+                Value::Map(map)
+            })
+            .collect();
+
+        Ok(Some(Value::Array(list)))
+    }
+);
+
+/// Encode a vector as a list of floats
+fn vec3_value(v: &nalgebra::Vector3<f32>) -> Value {
+    Value::Array(v.iter().map(|&f| Value::Float(f as f64)).collect())
+}
+
+make_method_function!(get_bounds,
+    PlatterState,
+    "platter.get_bounds",
+    "Get the axis-aligned bounding box of a scene, so a camera can be framed without fetching geometry. Returns a map with min and max in content coordinates, and world_min and world_max as currently placed, each as vec3. Returns null for scenes without geometry.",
+    {
+        let obj = get_object(app, state, context)?;
+
+        let (Some(local), Some(world)) = (obj.info.bounds, obj.world_bounds()) else {
+            return Ok(Some(Value::Null));
+        };
+
+        Ok(Some(Value::Map(vec![
+            (Value::Text("min".into()), vec3_value(&local.min)),
+            (Value::Text("max".into()), vec3_value(&local.max)),
+            (Value::Text("world_min".into()), vec3_value(&world.min)),
+            (Value::Text("world_max".into()), vec3_value(&world.max)),
+        ])))
+    }
+);
+
+/// Build a hierarchy node for an entity, and recursively its children
+fn hierarchy_node(
+    ent: &EntityReference,
+    state: &ServerState,
+    children: &HashMap<EntityReference, Vec<EntityReference>>,
+) -> Value {
+    let name = state
+        .entities
+        .inspect(ent.id(), |f| f.name.clone())
+        .flatten();
+
+    let kids = children
+        .get(ent)
+        .map(|list| {
+            list.iter()
+                .map(|f| hierarchy_node(f, state, children))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Value::Map(vec![
+        (
+            Value::Text("id".into()),
+            Value::serialized(&ent.id()).unwrap_or(Value::Null),
+        ),
+        (
+            Value::Text("name".into()),
+            name.map(Value::Text).unwrap_or(Value::Null),
+        ),
+        (Value::Text("children".into()), Value::Array(kids)),
+    ])
+}
+
+make_method_function!(get_hierarchy,
+    PlatterState,
+    "platter.get_hierarchy",
+    "Get the entity tree of a scene. Returns a list of root nodes; each node is a map with an entity id, an optional name, and a list of child nodes.",
+    |scene : u32 : "Scene ID to inspect"|,
+    {
+        let obj = app
+            .get_object(scene)
+            .ok_or_else(|| MethodException::invalid_parameters(None))?;
+
+        let parts = obj.root.all_parts();
+
+        // Entities may be parented to one another, independent of how the
+        // scene groups them
+        let mut roots = Vec::new();
+        let mut children = HashMap::<EntityReference, Vec<EntityReference>>::new();
+
+        for ent in &parts {
+            let parent = state
+                .entities
+                .inspect(ent.id(), |f| f.mutable.parent.clone())
+                .flatten();
+
+            match parent {
+                Some(p) if parts.contains(&p) => children.entry(p).or_default().push(ent.clone()),
+                _ => roots.push(ent.clone()),
+            }
+        }
+
+        let list = roots
+            .iter()
+            .map(|f| hierarchy_node(f, state, &children))
+            .collect();
+
+        Ok(Some(Value::Array(list)))
+    }
+);
+
+make_method_function!(get_events,
+    PlatterState,
+    "platter.get_events",
+    "Get recent load, removal, watch, and export events. Returns a list of maps with a sequence number, a unix timestamp, a kind, and kind-specific fields. Only a bounded number of events are kept.",
+    |since : u64 : "Only return events with a sequence number greater than this; use 0 for all"|,
+    {
+        let list = app
+            .events_since(since)
+            .into_iter()
+            .map(|f| {
+                let time = f
+                    .time
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_secs_f64())
+                    .unwrap_or_default();
+
+                let mut map = vec![
+                    (Value::Text("seq".into()), Value::Integer(f.seq.into())),
+                    (Value::Text("time".into()), Value::Float(time)),
+                    (Value::Text("kind".into()), Value::Text(f.kind.name().into())),
+                ];
+
+                match f.kind {
+                    EventKind::Loaded { path, scene }
+                    | EventKind::Reloaded { path, scene }
+                    | EventKind::Refreshed { path, scene } => {
+                        map.push((Value::Text("path".into()), Value::Text(path.display().to_string())));
+                        map.push((Value::Text("scene".into()), Value::Integer(scene.into())));
+                    }
+                    EventKind::Duplicated { from, scene } => {
+                        map.push((Value::Text("from".into()), Value::Integer(from.into())));
+                        map.push((Value::Text("scene".into()), Value::Integer(scene.into())));
+                    }
+                    EventKind::LoadFailed { path, error } | EventKind::ExportFailed { path, error } => {
+                        map.push((Value::Text("path".into()), Value::Text(path.display().to_string())));
+                        map.push((Value::Text("error".into()), Value::Text(error)));
+                    }
+                    EventKind::Removed { scene } | EventKind::Expired { scene } => {
+                        map.push((Value::Text("scene".into()), Value::Integer(scene.into())));
+                    }
+                    EventKind::WatchStarted { path }
+                    | EventKind::WatchStopped { path }
+                    | EventKind::Exported { path } => {
+                        map.push((Value::Text("path".into()), Value::Text(path.display().to_string())));
+                    }
+                }
+
+                Value::Map(map)
+            })
+            .collect();
+
+        Ok(Some(Value::Array(list)))
+    }
+);
+
+make_method_function!(
+    status,
+    PlatterState,
+    "platter.status",
+    "Get live task counts, so runaway tasks can be seen. Returns a map with the number of live async tasks (null if unknown), the number of scenes, the command queue depth and capacity, the running directory watchers as maps of a directory and an uptime in seconds, and the number of referenced files watched for changes.",
+    {
+        let tasks = colabrodo_server::server::tokio::runtime::Handle::try_current()
+            .map(|f| Value::Integer((f.metrics().num_alive_tasks() as u64).into()))
+            .unwrap_or(Value::Null);
+
+        let (depth, capacity) = app.queued_commands();
+
+        let watchers = app
+            .watchers()
+            .into_iter()
+            .map(|f| {
+                Value::Map(vec![
+                    (Value::Text("dir".into()), Value::Text(f.dir.display().to_string())),
+                    (Value::Text("uptime".into()), Value::Float(f.uptime.as_secs_f64())),
+                ])
+            })
+            .collect();
+
+        Ok(Some(Value::Map(vec![
+            (Value::Text("tasks".into()), tasks),
+            (
+                Value::Text("scenes".into()),
+                Value::Integer((app.scenes().len() as u64).into()),
+            ),
+            (
+                Value::Text("commands".into()),
+                Value::Array(vec![
+                    Value::Integer((depth as u64).into()),
+                    Value::Integer((capacity as u64).into()),
+                ]),
+            ),
+            (Value::Text("watchers".into()), Value::Array(watchers)),
+            (
+                Value::Text("references".into()),
+                Value::Integer((app.referenced_files() as u64).into()),
+            ),
+        ])))
+    }
+);
+
+make_method_function!(
+    get_capabilities,
+    PlatterState,
+    "platter.get_capabilities",
+    "Get the platter version and the optional component types this server may publish. Returns a map with a version string, a list of capability names, such as lights, text, and points, and the state of mDNS discovery.",
+    {
+        let list = app
+            .capabilities()
+            .enabled()
+            .map(|f| Value::Text(f.name()))
+            .collect();
+
+        Ok(Some(Value::Map(vec![
+            (
+                Value::Text("version".into()),
+                Value::Text(clap::crate_version!().into()),
+            ),
+            (Value::Text("capabilities".into()), Value::Array(list)),
+            (
+                Value::Text("discovery".into()),
+                Value::Text(app.discovery().name().into()),
+            ),
+        ])))
+    }
+);
+
+make_method_function!(get_document,
+    PlatterState,
+    "platter.get_document",
+    "Get a read-only snapshot of all live scenes, with their sources, transforms, asset ids, and the state of their entities, materials, textures, and images, for debugging and bug reports. Asset contents are not included. Returns a map, or the same as JSON text.",
+    |format : String : "Either 'cbor' for a map, or 'json' for text"|,
+    {
+        let snapshot = crate::snapshot::snapshot(app, state);
+
+        match format.as_str() {
+            "cbor" => Ok(Some(snapshot)),
+            "json" => {
+                let json = crate::snapshot::to_json(&snapshot).map_err(|e| {
+                    log::warn!("Unable to encode snapshot: {e}");
+                    MethodException::internal_error(None)
+                })?;
+                Ok(Some(Value::Text(json)))
+            }
+            _ => Err(MethodException::invalid_parameters(None)),
+        }
+    }
+);
+
+make_method_function!(
+    list_assets,
+    PlatterState,
+    "platter.list_assets",
+    "List published assets. Returns a list of [asset id, scene id] pairs. Assets no live scene owns have leaked; their scene id is null, and they can be removed with platter.remove_asset.",
+    {
+        let list = app
+            .list_assets()
+            .into_iter()
+            .map(|(asset, scene)| {
+                Value::Array(vec![
+                    Value::Text(asset.to_string()),
+                    scene
+                        .map(|f| Value::Integer(f.into()))
+                        .unwrap_or(Value::Null),
+                ])
+            })
+            .collect();
+
+        Ok(Some(Value::Array(list)))
+    }
+);
+
+make_method_function!(
+    list_scenes,
+    PlatterState,
+    "platter.list_scenes",
+    "List live scenes. Returns a list of maps with a scene id, the source path if any, the scene's tags, a triangle count, and its assets as [asset id, size in bytes, color space] lists. Sizes are null if unknown. Color spaces are 'srgb' or 'linear' for images, and null for other assets.",
+    {
+        let list = app
+            .scenes()
+            .into_iter()
+            .map(|(id, scene)| {
+                let source = scene
+                    .info
+                    .source
+                    .as_ref()
+                    .map(|f| Value::Text(f.display().to_string()))
+                    .unwrap_or(Value::Null);
+
+                let tags = scene.info.to_tags().into_iter().map(Value::Text).collect();
+
+                let assets = scene
+                    .published
+                    .iter()
+                    .map(|f| {
+                        Value::Array(vec![
+                            Value::Text(f.to_string()),
+                            app.asset_size(f)
+                                .map(|s| Value::Integer(s.into()))
+                                .unwrap_or(Value::Null),
+                            app.asset_color_space(f)
+                                .map(|s| Value::Text(s.name().into()))
+                                .unwrap_or(Value::Null),
+                        ])
+                    })
+                    .collect();
+
+                Value::Map(vec![
+                    (Value::Text("id".into()), Value::Integer(id.into())),
+                    (Value::Text("source".into()), source),
+                    (Value::Text("tags".into()), Value::Array(tags)),
+                    (
+                        Value::Text("triangles".into()),
+                        Value::Integer(scene.info.triangles.into()),
+                    ),
+                    (Value::Text("assets".into()), Value::Array(assets)),
+                ])
+            })
+            .collect();
+
+        Ok(Some(Value::Array(list)))
+    }
+);
+
+make_method_function!(remove_asset,
+    PlatterState,
+    "platter.remove_asset",
+    "Remove a leaked asset from the asset server. Assets used by live scenes or running imports cannot be removed.",
+    |asset : String : "Asset ID to remove"|,
+    {
+        let asset = uuid::Uuid::parse_str(&asset)
+            .map_err(|_| MethodException::invalid_parameters(None))?;
+
+        app.remove_asset(asset).map_err(|e| {
+            log::warn!("Unable to remove asset: {e}");
+            MethodException::invalid_parameters(None)
+        })?;
+
+        Ok(None)
+    }
+);
+
+make_method_function!(
+    gc,
+    PlatterState,
+    "platter.gc",
+    "Remove assets that no live scene uses, along with entries left by removed scenes. Returns a map with the removed asset ids, their total size in bytes, and the number of stale entries dropped.",
+    {
+        let report = app.collect_garbage();
+
+        if !report.is_empty() {
+            log::warn!("Leak detected; removed {report}");
+        }
+
+        let assets = report
+            .assets
+            .iter()
+            .map(|(asset, _)| Value::Text(asset.to_string()))
+            .collect();
+
+        Ok(Some(Value::Map(vec![
+            (Value::Text("assets".into()), Value::Array(assets)),
+            (
+                Value::Text("bytes".into()),
+                Value::Integer(report.bytes().into()),
+            ),
+            (
+                Value::Text("stale_entries".into()),
+                Value::Integer((report.stale_entries as u64).into()),
+            ),
+        ])))
+    }
+);
+
+make_method_function!(load_url,
+    PlatterState,
+    "platter.load_url",
+    "Download a model from an http or https URL and load it as a new scene. Downloads are limited in size, and must have a content type a model could have. The model must be a single file, such as a GLB or a zip archive.",
+    |url : String : "URL of the model"|,
+    {
+        app.request_load_url(&url).map_err(|e| {
+            log::warn!("Unable to load {url}: {e}");
+            MethodException::invalid_parameters(None)
+        })?;
+
+        Ok(None)
+    }
+);
+
+make_method_function!(export,
+    PlatterState,
+    "platter.export",
+    "Write scenes as they are now, with any changes made by clients, to a GLB file in the server's export directory. Meshes, materials, and base color textures are exported. The file is written in the background; check the event log for the result.",
+    |name : String : "File name, without directories. '.glb' is added if missing."|,
+    |scene : Value : "Id of a scene to export, or null for every scene"|,
+    {
+        let scene = match scene {
+            Value::Null => None,
+            s => Some(
+                s.deserialized::<u32>()
+                    .map_err(|_| MethodException::invalid_parameters(None))?,
+            ),
+        };
+
+        app.request_export(&name, scene).map_err(|e| {
+            log::warn!("Unable to export {name}: {e}");
+            MethodException::invalid_parameters(None)
+        })?;
+
+        Ok(None)
+    }
+);
+
+/// A length of time in seconds, as an integer or a float
+fn seconds(v: Value) -> Result<std::time::Duration, MethodException> {
+    let secs = match v {
+        Value::Float(f) => f,
+        Value::Integer(i) => i128::from(i) as f64,
+        _ => return Err(MethodException::invalid_parameters(None)),
+    };
+
+    std::time::Duration::try_from_secs_f64(secs)
+        .map_err(|_| MethodException::invalid_parameters(None))
+}
+
+/// Shortest poll interval clients may ask for; each poll scans the whole
+/// directory
+const MIN_POLL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Parse watch options: a map with any of the watch subcommand's options,
+/// named with underscores. Times are in seconds.
+fn watch_options(path: String, options: Value) -> Result<Directory, MethodException> {
+    let map = match options {
+        Value::Null => Vec::new(),
+        Value::Map(map) => map,
+        _ => return Err(MethodException::invalid_parameters(None)),
+    };
+
+    let bad = |_| MethodException::invalid_parameters(None);
+
+    let patterns = |v: Value| {
+        v.deserialized::<Vec<String>>()
+            .map_err(bad)?
+            .iter()
+            .map(|f| {
+                f.parse()
+                    .map_err(|_| MethodException::invalid_parameters(None))
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    let mut dir = Directory {
+        dir: path.into(),
+        ..Default::default()
+    };
+
+    for (k, v) in map {
+        match k.as_text() {
+            Some("load_existing") => dir.load_existing = v.deserialized().map_err(bad)?,
+            Some("latest_only") => dir.latest_only = v.deserialized().map_err(bad)?,
+            Some("sequence") => dir.sequence = Some(v.deserialized().map_err(bad)?),
+            Some("organize_by_dir") => dir.organize_by_dir = v.deserialized().map_err(bad)?,
+            Some("include") => dir.include = patterns(v)?,
+            Some("exclude") => dir.exclude = patterns(v)?,
+            Some("ttl") => dir.ttl = Some(HumanDuration(seconds(v)?)),
+            Some("poll") => dir.poll = Some(HumanDuration(seconds(v)?)),
+            Some("max_depth") => dir.max_depth = Some(v.deserialized().map_err(bad)?),
+            Some("no_recursive") => dir.no_recursive = v.deserialized().map_err(bad)?,
+            _ => return Err(MethodException::invalid_parameters(None)),
+        }
+    }
+
+    if dir.ttl.is_some_and(|f| f.0.is_zero()) || dir.poll.is_some_and(|f| f.0 < MIN_POLL) {
+        return Err(MethodException::invalid_parameters(None));
+    }
+
+    // As on the command line
+    if dir.latest_only && dir.sequence.is_some() {
+        return Err(MethodException::invalid_parameters(None));
+    }
+
+    Ok(dir)
+}
+
+make_method_function!(watch_directory,
+    PlatterState,
+    "platter.watch_directory",
+    "Watch a directory below the server's watch root, loading new files as they appear. Options is null or a map with any of load_existing, latest_only, organize_by_dir, and no_recursive as bools; include and exclude as lists of glob patterns; max_depth as an integer; sequence as a positive integer; and ttl and poll in seconds. Unknown options, a ttl of zero, and polls under a second are refused, as are watches past 16 below the root.",
+    |path : String : "Directory to watch, relative to the watch root"|,
+    |options : Value : "Map of watch options, or null"|,
+    {
+        let dir = watch_options(path, options)?;
+
+        app.request_watch(dir).map_err(|e| {
+            log::warn!("Unable to watch directory: {e}");
+            MethodException::invalid_parameters(None)
+        })?;
+
+        Ok(None)
+    }
+);
+
+make_method_function!(unwatch,
+    PlatterState,
+    "platter.unwatch",
+    "Stop watching a directory below the watch root. Scenes already loaded from it stay.",
+    |path : String : "Directory as listed by platter.status, or relative to the watch root"|,
+    {
+        app.unwatch(std::path::Path::new(&path)).map_err(|e| {
+            log::warn!("Unable to stop watching: {e}");
+            MethodException::invalid_parameters(None)
+        })?;
+
+        Ok(None)
+    }
+);
+
+make_method_function!(
+    next_step,
+    PlatterState,
+    "platter.next",
+    "Show the next newer scene of every directory watched as a sequence. Returns the ids of the scenes now shown.",
+    {
+        let shown = app
+            .step_sequences(1)
+            .into_iter()
+            .map(|f| Value::Integer(f.into()))
+            .collect();
+
+        Ok(Some(Value::Array(shown)))
+    }
+);
+
+make_method_function!(
+    previous_step,
+    PlatterState,
+    "platter.previous",
+    "Show the next older scene of every directory watched as a sequence. Returns the ids of the scenes now shown.",
+    {
+        let shown = app
+            .step_sequences(-1)
+            .into_iter()
+            .map(|f| Value::Integer(f.into()))
+            .collect();
+
+        Ok(Some(Value::Array(shown)))
+    }
+);
+
+make_method_function!(shutdown,
+    PlatterState,
+    "platter.shutdown",
+    "Shut the server down: stop watching directories, drop every scene, withdraw from mDNS, and close. Requires the control token the server was started with.",
+    |token : String : "Control token"|,
+    {
+        if !app.check_control_token(&token) {
+            log::warn!("Refusing shutdown: bad control token");
+            return Err(MethodException::invalid_parameters(None));
+        }
+
+        app.request_shutdown().map_err(|e| {
+            log::error!("{e}");
+            MethodException::internal_error(None)
+        })?;
+
+        Ok(None)
+    }
+);
+
 pub fn setup_methods(state: ServerStatePtr, app_state: PlatterStatePtr) -> Vec<MethodReference> {
     let mut lock = state.lock().unwrap();
 
@@ -122,8 +1003,114 @@ pub fn setup_methods(state: ServerStatePtr, app_state: PlatterStatePtr) -> Vec<M
         lock.methods
             .new_owned_component(create_set_rotation(app_state.clone())),
         lock.methods
-            .new_owned_component(create_set_scale(app_state)),
+            .new_owned_component(create_set_scale(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_list_viewpoints(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_get_bounds(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_delete(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_reload(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_duplicate(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_set_base_color(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_set_opacity(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_set_wireframe(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_set_clip_plane(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_clear_clip_planes(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_highlight(app_state)),
+    ];
+
+    ret
+}
+
+/// Create methods that are attached to the document, rather than to a scene.
+/// Methods that change scenes are left out if `read_only` is set, and control
+/// methods are only added if `control` is set. Loading by URL also needs
+/// `load_url`, and exporting needs `export`.
+pub fn setup_document_methods(
+    state: ServerStatePtr,
+    app_state: PlatterStatePtr,
+    read_only: bool,
+    control: bool,
+    load_url: bool,
+    export: bool,
+    watch: bool,
+) -> Vec<MethodReference> {
+    let mut lock = state.lock().unwrap();
+
+    let mut ret = vec![
+        lock.methods
+            .new_owned_component(create_get_capabilities(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_list_scenes(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_list_assets(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_get_hierarchy(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_get_events(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_status(app_state.clone())),
+        lock.methods
+            .new_owned_component(create_get_document(app_state.clone())),
     ];
 
+    if !read_only {
+        ret.extend([
+            lock.methods
+                .new_owned_component(create_transform_many(app_state.clone())),
+            lock.methods
+                .new_owned_component(create_set_global_transform(app_state.clone())),
+            lock.methods
+                .new_owned_component(create_remove_asset(app_state.clone())),
+            lock.methods
+                .new_owned_component(create_gc(app_state.clone())),
+            lock.methods
+                .new_owned_component(create_next_step(app_state.clone())),
+            lock.methods
+                .new_owned_component(create_previous_step(app_state.clone())),
+        ]);
+    }
+
+    if !read_only && load_url {
+        ret.push(
+            lock.methods
+                .new_owned_component(create_load_url(app_state.clone())),
+        );
+    }
+
+    if !read_only && export {
+        ret.push(
+            lock.methods
+                .new_owned_component(create_export(app_state.clone())),
+        );
+    }
+
+    if !read_only && watch {
+        ret.extend([
+            lock.methods
+                .new_owned_component(create_watch_directory(app_state.clone())),
+            lock.methods
+                .new_owned_component(create_unwatch(app_state.clone())),
+        ]);
+    }
+
+    if control {
+        ret.push(lock.methods.new_owned_component(create_shutdown(app_state)));
+    }
+
+    lock.update_document(ServerDocumentUpdate {
+        methods_list: Some(ret.clone()),
+        ..Default::default()
+    });
+
     ret
 }
@@ -1,43 +1,142 @@
 use crate::arguments;
 use crate::arguments::Directory;
+use crate::capabilities::{Capabilities, Capability};
+use crate::coalesce::Coalescer;
+use crate::color::ColorSpace;
+use crate::dir_watcher;
+use crate::events::{Event, EventKind, EventLog};
+use crate::export;
+use crate::fetch::{self, FetchLimits};
+use crate::gen_test;
 use crate::import;
-use crate::methods::setup_methods;
-use crate::scene::Scene;
+use crate::manifest::{self, Manifest};
+use crate::mdns;
+use crate::methods::{setup_document_methods, setup_methods};
+use crate::references::References;
+use crate::report;
+use crate::scene::{MaterialOverrides, Scene, SceneObject};
+use crate::scheduler::Scheduler;
+use crate::scratch::ScratchDir;
+use crate::sequence::Sequence;
+use crate::shadow;
+use crate::signals::{self, Removal, Signals};
+use crate::texture;
+use crate::watchers::{WatcherStatus, Watchers};
 
 use anyhow::Result;
+use nalgebra::{Matrix4, UnitQuaternion, Vector3};
 
-#[cfg(use_assimp)]
-use crate::assimp_import;
-
+use colabrodo_common::common::ComponentID;
+use colabrodo_common::value_tools::Value;
 use colabrodo_server::server::*;
 use colabrodo_server::server_http::*;
 use colabrodo_server::server_messages::*;
 use std::collections::HashSet;
 use std::fs;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use std::{collections::HashMap, path::Path};
 
+/// Most directories below the watch root that may be watched at once, so
+/// clients can't pile up watchers
+const MAX_CLIENT_WATCHES: usize = 16;
+
 /// Initization info for our platter server
 pub struct PlatterInit {
     /// Stream for commands
     pub command_stream: tokio::sync::mpsc::Sender<PlatterCommand>,
 
     /// Stream for commands from the directory watcher
-    pub watcher_command_stream: tokio::sync::mpsc::UnboundedSender<Directory>,
+    pub watcher_command_stream: tokio::sync::mpsc::Sender<Directory>,
 
     /// Where to store large assets
     pub asset_store: AssetStorePtr,
 
-    /// What constitutes a 'large' buffer. Buffers smaller than this will be
-    /// possibly sent inline
-    pub size_large_limit: u64,
+    /// User asks to translate, rotate, or rescale everything
+    pub transform: arguments::SourceTransform,
+
+    /// Transforms for particular files or directories, replacing parts of
+    /// the global transform for anything loaded from them
+    pub source_transforms: Vec<(PathBuf, arguments::SourceTransform)>,
+
+    /// Remove scenes once they are older than this
+    pub ttl: Option<Duration>,
+
+    /// TTLs for particular files or directories, replacing the global one
+    pub source_ttls: Vec<(PathBuf, Duration)>,
+
+    /// Timer task, used to expire scenes
+    pub scheduler: Scheduler,
+
+    /// Rate limiter for scene transform updates
+    pub coalescer: Option<Coalescer>,
+
+    /// Content to show until the first real scene is loaded
+    pub placeholder: Option<Placeholder>,
+
+    /// Options passed along to importers
+    pub import_options: import::ImportOptions,
+
+    /// Show the name of each scene above it
+    pub label_scenes: bool,
+
+    /// Put a shadow under each scene
+    pub ground_shadows: bool,
+
+    /// Spin every scene at this many degrees per second
+    pub turntable: Option<f32>,
+
+    /// How often to sweep for assets no scene owns, if at all
+    pub gc_interval: Option<Duration>,
+
+    /// Kept up to date with the number of scenes, for the self report
+    pub scene_count: Arc<AtomicUsize>,
+
+    /// Limits on downloads for clients loading models by URL. Clients may
+    /// not if this is unset.
+    pub load_url: Option<FetchLimits>,
+
+    /// Where clients may export scenes to. Clients may not if this is unset.
+    pub export_dir: Option<PathBuf>,
 
-    /// User asks to rescale using this factor
-    pub resize: f32,
+    /// Where to export every scene to on shutdown, if anywhere
+    pub export_on_exit: Option<PathBuf>,
 
-    /// User asks to translate
-    pub offset: nalgebra_glm::Vec3,
+    /// Directory below which clients may start watches. Clients may neither
+    /// start nor stop watches if this is unset.
+    pub watch_root: Option<PathBuf>,
+
+    /// Tint scenes by the watcher they came from
+    pub tint_sources: bool,
+
+    /// Whether clients can find us over mDNS
+    pub discovery: mdns::DiscoveryStatus,
+
+    /// Which entities of a scene get our methods
+    pub method_attachment: arguments::MethodAttachment,
+
+    /// Secret required by control methods, which are only offered if set
+    pub control_token: Option<String>,
+
+    /// Tells directory watchers and the server to stop
+    pub stop: tokio::sync::broadcast::Sender<bool>,
+
+    /// Running directory watchers
+    pub watchers: Watchers,
+
+    /// How changes settle, for files watched by scenes that reference them
+    pub watcher_options: dir_watcher::WatcherOptions,
+}
+
+/// Stand-in content for an otherwise empty server
+#[derive(Debug, Clone)]
+pub enum Placeholder {
+    /// A mesh file to import
+    File(PathBuf),
+    /// A line of text
+    Text(String),
 }
 
 /// Our server state
@@ -48,13 +147,16 @@ pub struct PlatterState {
     /// NOODLES server
     state: ServerStatePtr,
 
-    /// Application specific methods
+    /// Application specific methods, attached to scenes
     methods: Vec<MethodReference>,
 
+    /// Application specific methods, attached to the document
+    document_methods: Vec<MethodReference>,
+
     /// Each file roughly maps to a scene. Each Scene gets an ID.
     items: HashMap<u32, Scene>,
 
-    /// We attach some methods to entities; this maps entities to scenes
+    /// We attach some methods to entities; this maps those entities to scenes
     root_to_item: HashMap<EntityReference, u32>,
 
     /// The next Scene ID to use. Just a monotonic counter
@@ -62,6 +164,30 @@ pub struct PlatterState {
 
     /// Tag UUID to Scene to identify scenes derived from a single source
     source_map: HashMap<Tag, HashSet<u32>>,
+
+    /// Stand-in scene, dropped when the first real scene is added
+    placeholder: Option<Scene>,
+
+    /// Recent loads, removals, and watches
+    events: EventLog,
+
+    /// Colors handed out to sources when tinting
+    tints: HashMap<Tag, [f32; 3]>,
+
+    /// The scene clients are asked to look at, if any
+    highlighted: Option<u32>,
+
+    /// Signals sent on the document
+    signals: Signals,
+
+    /// Sources played as sequences, with their recent scenes
+    sequences: HashMap<Tag, Sequence>,
+
+    /// Manifests of watched directories, by the tag of their watcher
+    manifests: HashMap<Tag, Manifest>,
+
+    /// Files scenes read images from, watched for changes
+    references: References,
 }
 
 pub type PlatterStatePtr = Arc<std::sync::Mutex<PlatterState>>;
@@ -75,15 +201,49 @@ impl Tag {
     }
 }
 
+impl std::fmt::Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 /// An instruction to platter
 #[derive(Debug)]
 pub enum PlatterCommand {
-    /// Load a file from disk, with an optional tag
-    LoadFile(PathBuf, Option<Tag>),
+    /// Load a file from disk, with an optional tag. The import is skipped or
+    /// abandoned if the token is cancelled first.
+    LoadFile(PathBuf, Option<Tag>, Option<import::CancelToken>),
     /// Start watching a directory
     WatchDirectory(arguments::Directory),
+    /// Play scenes with a tag as a sequence, keeping this many
+    Sequence(Tag, usize),
+    /// Place, name, and group files loaded under a tag as a manifest lists
+    /// them, replacing any earlier manifest for the tag. None drops the
+    /// manifest.
+    Manifest(Tag, Option<Manifest>),
     /// Clear a tag
     ClearTag(Tag),
+    /// The watcher loading files under a tag has stopped. Its scenes stay
+    /// as they are, but no longer play as a sequence or follow a manifest.
+    Unwatched(Tag),
+    /// Remove a scene that has outlived its TTL, if it is still around
+    Expire(u32),
+    /// Remove a scene at a client's request
+    Remove(u32),
+    /// Import a scene again from its source file
+    Reload(u32),
+    /// Publish images read from a file again, after it changed
+    Refresh(PathBuf),
+    /// Download a model and import it
+    LoadUrl(url::Url),
+    /// Write a scene, or every scene, to a GLB file
+    Export(PathBuf, Option<u32>),
+    /// Publish a generated test scene
+    Generate(arguments::TestScene),
+    /// Copy a scene, sharing its assets
+    Duplicate(u32),
+    /// Drop every scene, then stop the watchers and the server
+    Shutdown,
 }
 
 impl PlatterState {
@@ -91,21 +251,115 @@ impl PlatterState {
     pub fn new(state: ServerStatePtr, init: PlatterInit) -> PlatterStatePtr {
         // awkwardness with the methods...
 
+        let control = init.control_token.is_some();
+        let load_url = init.load_url.is_some();
+        let export = init.export_dir.is_some();
+        let watch = init.watch_root.is_some();
+        let read_only = init.method_attachment == arguments::MethodAttachment::None;
+
+        let signals = Signals::new(&mut state.lock().unwrap());
+        let references = References::new(init.command_stream.clone(), &init.watcher_options);
+
         let ret = Arc::new(std::sync::Mutex::new(Self {
             init,
             state: state.clone(),
             methods: Vec::new(),
+            document_methods: Vec::new(),
             items: Default::default(),
             root_to_item: HashMap::new(),
             next_item_id: 0,
             source_map: HashMap::new(),
+            placeholder: None,
+            events: EventLog::default(),
+            tints: HashMap::new(),
+            highlighted: None,
+            signals,
+            sequences: HashMap::new(),
+            manifests: HashMap::new(),
+            references,
         }));
 
-        ret.lock().unwrap().methods = setup_methods(state, ret.clone());
+        ret.lock().unwrap().methods = setup_methods(state.clone(), ret.clone());
+        ret.lock().unwrap().document_methods = setup_document_methods(
+            state,
+            ret.clone(),
+            read_only,
+            control,
+            load_url,
+            export,
+            watch,
+        );
+
+        ret.lock().unwrap().setup_placeholder();
+
+        if let Some(speed) = ret.lock().unwrap().init.turntable.filter(|f| *f != 0.0) {
+            start_turntable(&ret, speed);
+        }
+
+        if let Some(period) = ret.lock().unwrap().init.gc_interval {
+            start_gc(&ret, period);
+        }
 
         ret
     }
 
+    /// Build the placeholder scene, if one was requested
+    fn setup_placeholder(&mut self) {
+        let Some(placeholder) = self.init.placeholder.clone() else {
+            return;
+        };
+
+        log::info!("Showing placeholder: {placeholder:?}");
+
+        self.placeholder = match placeholder {
+            Placeholder::File(p) => {
+                match handle_import(
+                    &p,
+                    self.state.clone(),
+                    self.init.asset_store.clone(),
+                    &self.init.import_options,
+                ) {
+                    Ok(x) => Some(x),
+                    Err(x) => {
+                        log::error!("Unable to load placeholder: {x:?}");
+                        None
+                    }
+                }
+            }
+            Placeholder::Text(_) if !self.capabilities().has(Capability::Text) => {
+                log::warn!("Text is disabled, not showing placeholder text");
+                None
+            }
+            Placeholder::Text(txt) => {
+                let mut lock = self.state.lock().unwrap();
+
+                let entity = lock.entities.new_component(ServerEntityState {
+                    name: Some("Placeholder".into()),
+                    mutable: ServerEntityStateUpdatable {
+                        representation: Some(ServerEntityRepresentation::new_text(
+                            ServerTextRepresentation {
+                                txt,
+                                font: None,
+                                height: None,
+                                width: None,
+                            },
+                        )),
+                        ..Default::default()
+                    },
+                });
+
+                Some(Scene::new(
+                    SceneObject {
+                        parts: vec![entity],
+                        children: vec![],
+                    },
+                    vec![],
+                    None,
+                ))
+            }
+        };
+    }
+
     /// Obtain the next scene ID
     fn get_next_scene_id(&mut self) -> u32 {
         let ret = self.next_item_id;
@@ -113,137 +367,1887 @@ impl PlatterState {
         ret
     }
 
-    /// An order to import a filesystem item. This could be a directory or a file
-    fn import_filesystem_item(&mut self, p: &Path, source: Option<Tag>) {
-        if p.is_dir() {
-            self.import_dir(p, source);
-        } else if p.is_file() {
-            self.import_file(p, source);
+    /// Asset ids are derived from the source in deterministic mode, so a
+    /// second copy would share (and later pull out) the assets of the first
+    fn is_duplicate(&self, p: &Path) -> bool {
+        let dup = self.init.import_options.deterministic
+            && self
+                .items
+                .values()
+                .any(|f| f.info.source.as_deref() == Some(p));
+
+        if dup {
+            log::error!(
+                "{} is already loaded; deterministic mode allows one copy",
+                p.display()
+            );
+        }
+
+        dup
+    }
+
+    /// Check that a file may be imported, and work out the options to import
+    /// it with
+    fn prepare_import(
+        &mut self,
+        p: &Path,
+        source: Option<Tag>,
+        cancel: Option<import::CancelToken>,
+    ) -> Option<import::ImportOptions> {
+        if self.is_duplicate(p) {
+            return None;
         }
+
+        let mut options = self.options_for(source);
+        options.cancel = cancel;
+
+        Some(options)
     }
 
-    /// Import a specific file.
-    fn import_file(&mut self, p: &Path, source: Option<Tag>) {
-        log::info!("Loading file: {}", p.display());
-        let res = match handle_import(p, self.state.clone(), self.init.asset_store.clone()) {
+    /// Add the result of an import. Imports run without the platter state
+    /// held, so the file may have been loaded again, or its source cleared,
+    /// in the meantime.
+    fn finish_import(
+        &mut self,
+        p: &Path,
+        source: Option<Tag>,
+        options: &import::ImportOptions,
+        res: Result<Scene>,
+    ) {
+        // Scenes that are added own their assets from here on
+        if let Some(claim) = &options.claim {
+            claim.release();
+        }
+
+        let res = match res {
+            Ok(mut x) if options.cancelled() || self.is_duplicate(p) => {
+                log::info!("Dropped {}", p.display());
+                self.keep_shared_assets(&mut x);
+                self.init.import_options.asset_sizes.forget(&x.published);
+                return;
+            }
             Ok(x) => x,
+            Err(_) if options.cancelled() => {
+                log::info!("Dropped {}, its import was cancelled", p.display());
+                return;
+            }
             Err(x) => {
-                log::error!("Error loading file: {x:?}");
+                log::error!("Error loading {}: {x:?}", p.display());
+                self.events.record(EventKind::LoadFailed {
+                    path: p.to_path_buf(),
+                    error: x.to_string(),
+                });
                 return;
             }
         };
 
-        self.add_object(res, source);
+        let scene = self.add_object(res, source);
+
+        self.events.record(EventKind::Loaded {
+            path: p.to_path_buf(),
+            scene,
+        });
     }
 
-    /// Import a directory.
-    ///
-    /// Searches through the directory and tries to load every file encountered.
-    fn import_dir(&mut self, p: &Path, source: Option<Tag>) {
-        let paths = fs::read_dir(p).unwrap();
+    /// Add a generated test scene
+    fn finish_generate(&mut self, kind: &arguments::TestScene, res: Result<Scene>) {
+        let path = PathBuf::from(format!("gen-test/{}", kind.name()));
 
-        for path in paths {
-            self.import_file(path.unwrap().path().as_path(), source);
+        match res {
+            Ok(scene) => {
+                let scene = self.add_object(scene, None);
+                self.events.record(EventKind::Loaded { path, scene });
+            }
+            Err(x) => {
+                log::error!("Error generating {kind:?}: {x:?}");
+                self.events.record(EventKind::LoadFailed {
+                    path,
+                    error: x.to_string(),
+                });
+            }
         }
     }
 
-    /// Add an object scene to the state
-    fn add_object(&mut self, o: Scene, source: Option<Tag>) -> u32 {
-        let id = self.get_next_scene_id();
+    /// Write scenes to a GLB. Components are copied now; buffers are then
+    /// fetched from the asset server, and the file written, on a blocking
+    /// thread so commands keep flowing meanwhile. Gives that thread, unless
+    /// the scene is already gone.
+    fn export(
+        &mut self,
+        platter_state: PlatterStatePtr,
+        path: PathBuf,
+        scene: Option<u32>,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        let mut ids: Vec<_> = match scene {
+            Some(id) => vec![id],
+            None => self.items.keys().copied().collect(),
+        };
+        ids.sort();
 
-        let ent = o.root.parts.first().unwrap().clone();
+        let Some(entities) = ids
+            .iter()
+            .map(|id| self.items.get(id).map(|f| f.root.all_parts()))
+            .collect::<Option<Vec<_>>>()
+        else {
+            self.events.record(EventKind::ExportFailed {
+                path,
+                error: "The scene was removed".into(),
+            });
+            return None;
+        };
 
-        self.root_to_item.insert(ent.clone(), id);
+        let capture = export::capture(&self.state.lock().unwrap(), &entities.concat());
+
+        // Our own assets; only the time is worth limiting
+        let limits = FetchLimits {
+            timeout: Duration::from_secs(60),
+            max_size: u64::MAX,
+        };
 
-        if false {
-            let offset = self.init.offset;
-            let offset = nalgebra_glm::translation(&offset);
+        let task = tokio::task::spawn_blocking(move || {
+            let res =
+                export::to_glb(&capture, |url| fetch::fetch(url, &limits)).and_then(|bytes| {
+                    if let Some(dir) = path.parent() {
+                        fs::create_dir_all(dir)?;
+                    }
+                    Ok(fs::write(&path, bytes)?)
+                });
 
-            let rescale = self.init.resize;
-            let rescale = nalgebra_glm::vec3(rescale, rescale, rescale);
-            let rescale = nalgebra_glm::scale(&offset, &rescale);
+            let kind = match res {
+                Ok(()) => {
+                    log::info!("Exported to {}", path.display());
+                    EventKind::Exported { path }
+                }
+                Err(x) => {
+                    log::error!("Unable to export to {}: {x:?}", path.display());
+                    EventKind::ExportFailed {
+                        path,
+                        error: x.to_string(),
+                    }
+                }
+            };
 
-            let rescale: [f32; 16] = rescale.as_slice().try_into().unwrap();
+            platter_state.lock().unwrap().events.record(kind);
+        });
 
-            log::debug!("Resetting scale tf: {rescale:?}");
+        Some(task)
+    }
 
-            ServerEntityStateUpdatable {
-                methods_list: Some(self.methods.clone()),
-                transform: Some(rescale),
-                ..Default::default()
+    /// Remove every scene and stop the server
+    fn shut_down(&mut self) {
+        let mut ids: Vec<_> = self.items.keys().copied().collect();
+        ids.sort();
+
+        for id in ids {
+            self.remove_object(id, Removal::Cleared);
+        }
+
+        self.placeholder = None;
+
+        // Nobody may be listening if there are no watchers and the
+        // server is already on its way out
+        let _ = self.init.stop.send(true);
+    }
+
+    /// Import options for content from a source, with a new claim on the
+    /// assets the import will use
+    fn options_for(&mut self, source: Option<Tag>) -> import::ImportOptions {
+        let mut options = self.init.import_options.clone();
+
+        options.claim = Some(options.asset_sizes.claim());
+
+        if let Some(tag) = source.filter(|_| self.init.tint_sources) {
+            options.tint = Some(self.source_tint(tag));
+        }
+
+        options
+    }
+
+    /// The scene loaded from a path under a source tag, if it is still live.
+    /// The first, if it was duplicated.
+    fn watched_scene(&self, tag: Tag, path: &Path) -> Option<u32> {
+        self.source_map
+            .get(&tag)?
+            .iter()
+            .copied()
+            .filter(|id| {
+                self.items
+                    .get(id)
+                    .is_some_and(|f| f.info.source.as_deref() == Some(path))
+            })
+            .min()
+    }
+
+    /// The source file of a scene to reload, and the options to import it
+    /// with
+    fn prepare_reload(&mut self, id: u32) -> Option<(PathBuf, import::ImportOptions)> {
+        let old = self.items.get(&id)?;
+
+        let Some(path) = old.info.source.clone() else {
+            log::warn!("Scene {id} has no source file to reload");
+            return None;
+        };
+
+        let source = self
+            .source_map
+            .iter()
+            .find(|(_, list)| list.contains(&id))
+            .map(|(tag, _)| *tag);
+
+        let options = self.options_for(source);
+
+        log::info!("Reloading scene {id} from {}", path.display());
+
+        Some((path, options))
+    }
+
+    /// Swap a reloaded scene in for the old one. The old scene may have been
+    /// removed while the import ran, in which case the new one is dropped.
+    fn finish_reload(
+        &mut self,
+        id: u32,
+        path: PathBuf,
+        options: &import::ImportOptions,
+        res: Result<Scene>,
+    ) {
+        if let Some(claim) = &options.claim {
+            claim.release();
+        }
+
+        let mut scene = match res {
+            Ok(mut x) if !self.items.contains_key(&id) => {
+                log::info!("Dropped {}, scene {id} was removed", path.display());
+                self.keep_shared_assets(&mut x);
+                self.init.import_options.asset_sizes.forget(&x.published);
+                return;
+            }
+            Ok(x) => x,
+            Err(x) => {
+                log::error!("Error reloading file: {x:?}");
+                self.events.record(EventKind::LoadFailed {
+                    path,
+                    error: x.to_string(),
+                });
+                return;
             }
-            .patch(&ent);
+        };
+
+        self.root_to_item.retain(|_, f| *f != id);
+
+        self.apply_listing(&mut scene);
+        self.attach_root(&mut scene, id);
+
+        for r in &scene.references {
+            self.references.add(&r.path);
         }
 
-        self.items.insert(id, o);
+        let mut old = self.items.insert(id, scene).unwrap();
 
-        if let Some(sid) = source {
-            if let Some(list) = self.source_map.get_mut(&sid) {
-                list.insert(id);
+        for r in &old.references {
+            self.references.remove(&r.path);
+        }
+
+        self.keep_shared_assets(&mut old);
+        self.init.import_options.asset_sizes.forget(&old.published);
+
+        let scene = self.items.get_mut(&id).unwrap();
+
+        // Downloaded sources live in the scene's scratch space; keep them for
+        // the next reload
+        let (kept, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut old.scratch)
+            .into_iter()
+            .partition(|f| path.starts_with(f.path()));
+        scene.scratch.extend(kept);
+        old.scratch = rest;
+
+        scene.copy_transform(&old);
+        scene.set_highlighted(old.highlighted());
+        scene.set_hidden(old.hidden());
+        scene.set_clip_planes(old.clip_planes().to_vec());
+
+        // The new materials start out as the file has them
+        if old.overrides() != MaterialOverrides::default() {
+            scene.set_overrides(&self.state.lock().unwrap(), old.overrides());
+        }
+
+        drop(old);
+
+        self.events.record(EventKind::Reloaded { path, scene: id });
+    }
+
+    /// Ids of scenes showing a version of a referenced file older than
+    /// `now`
+    fn stale_references(&self, path: &Path, now: dir_watcher::Stamp) -> Vec<u32> {
+        self.items
+            .iter()
+            .filter(|(_, scene)| {
+                scene
+                    .references
+                    .iter()
+                    .any(|r| r.path == path && r.stamp != Some(now))
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Point the textures showing a referenced file at images of its new
+    /// content, read at `now`. Scenes are not imported again.
+    fn finish_refresh(&mut self, path: &Path, now: dir_watcher::Stamp, bytes: &[u8]) {
+        // Scenes may have come or gone while the file was read
+        let ids = self.stale_references(path, now);
+
+        let options = &self.init.import_options;
+        let mut retired = Vec::new();
+
+        for id in ids {
+            let scene = self.items.get_mut(&id).unwrap();
+            let mut swaps = Vec::new();
+
+            let mut lock = self.state.lock().unwrap();
+
+            for r in scene.references.iter_mut().filter(|r| r.path == path) {
+                r.stamp = Some(now);
+
+                let asset = import::asset_id(path, bytes, options);
+
+                // Touched, but the same as before
+                if asset == r.asset {
+                    continue;
+                }
+
+                options.asset_sizes.tag_color_space(&asset, r.color_space);
+
+                let url = import::add_asset(
+                    self.init.asset_store.clone(),
+                    asset,
+                    bytes,
+                    import::AssetKind::Image,
+                    options,
+                );
+
+                let name = lock
+                    .images
+                    .inspect(r.image.id(), |f| f.name.clone())
+                    .flatten();
+
+                r.image = lock.images.new_component(ServerImageState {
+                    name,
+                    source: ImageSource::new_uri(url),
+                });
+
+                // Textures are immutable too; swap in new ones that show the
+                // new image
+                for tex in &mut r.textures {
+                    let Some((name, sampler)) = lock
+                        .textures
+                        .inspect(tex.id(), |f| (f.name.clone(), f.sampler.clone()))
+                    else {
+                        continue;
+                    };
+
+                    let new = lock.textures.new_component(ServerTextureState {
+                        name,
+                        image: r.image.clone(),
+                        sampler,
+                    });
+
+                    swaps.push((std::mem::replace(tex, new.clone()), new));
+                }
+
+                retired.push((id, std::mem::replace(&mut r.asset, asset), asset));
             }
+
+            scene.replace_textures(&lock, &swaps);
+
+            drop(lock);
+
+            log::info!("Refreshed {} in scene {id}", path.display());
+
+            self.events.record(EventKind::Refreshed {
+                path: path.to_path_buf(),
+                scene: id,
+            });
         }
 
-        id
+        for (id, old, new) in retired {
+            self.retire_asset(id, old, new);
+        }
     }
 
-    /// Remove an object scene from the state
-    fn remove_object(&mut self, id: u32) {
-        let ent = self.items.get(&id).unwrap().root.parts.first().unwrap();
+    /// Hand an asset's place in a refreshed scene over to its replacement,
+    /// and remove it once no scene publishes it. Scenes with the same content
+    /// share it without referencing the file; they keep it.
+    fn retire_asset(&mut self, id: u32, old: uuid::Uuid, new: uuid::Uuid) {
+        if let Some(scene) = self.items.get_mut(&id) {
+            if !scene.published.contains(&new) {
+                scene.published.push(new);
+            }
+
+            if !scene.references.iter().any(|r| r.asset == old) {
+                scene.published.retain(|f| *f != old);
+            }
+        }
+
+        let sizes = &self.init.import_options.asset_sizes;
 
-        self.root_to_item.remove(ent);
+        if sizes.is_claimed(&old) || self.items.values().any(|f| f.published.contains(&old)) {
+            return;
+        }
 
-        self.items.remove(&id);
+        sizes.forget([&old]);
+        remove_asset(self.init.asset_store.clone(), old);
     }
 
-    /// Clear all objects with the same source tag
-    fn clear_source(&mut self, source: Tag) -> Option<()> {
-        let list = self.source_map.remove(&source)?;
+    /// Get the tint for a source, picking a new hue for new sources.
+    ///
+    /// Hues are spread by the golden ratio so consecutive sources contrast,
+    /// and kept light so textures stay readable.
+    fn source_tint(&mut self, tag: Tag) -> [f32; 3] {
+        let n = self.tints.len() as f32;
 
-        for item in list.iter() {
-            self.remove_object(*item);
+        *self.tints.entry(tag).or_insert_with(|| {
+            let hue = (n * 0.618_034).fract() * 6.0;
+            let x = 1.0 - (hue % 2.0 - 1.0).abs();
+            let (r, g, b) = match hue as u32 {
+                0 => (1.0, x, 0.0),
+                1 => (x, 1.0, 0.0),
+                2 => (0.0, 1.0, x),
+                3 => (0.0, x, 1.0),
+                4 => (x, 0.0, 1.0),
+                _ => (1.0, 0.0, x),
+            };
+            // Half saturation
+            [r, g, b].map(|c: f32| 0.5 + c * 0.5)
+        })
+    }
+
+    /// Files in a directory to load, in order if deterministic
+    fn dir_files(&self, p: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = match fs::read_dir(p) {
+            Ok(list) => list
+                .filter_map(|f| f.ok().map(|f| f.path()))
+                .filter(|f| f.is_file())
+                .collect(),
+            Err(e) => {
+                log::error!("Unable to read {}: {e}", p.display());
+                vec![]
+            }
+        };
+
+        if self.init.import_options.deterministic {
+            paths.sort();
         }
 
-        Some(())
+        paths
     }
 
-    /// Given an entity reference, get the object scene it belongs to
-    pub fn find_id(&self, ent: &EntityReference) -> Option<u32> {
-        self.root_to_item.get(ent).copied()
+    /// Transform for content loaded from a path. The most specific source
+    /// override wins, then the global options.
+    fn transform_for(&self, path: Option<&Path>) -> arguments::SourceTransform {
+        let listed = self.listing(path).map(|f| f.transform());
+        let over = source_override(&self.init.source_transforms, path);
+
+        listed
+            .unwrap_or_default()
+            .or(over.unwrap_or_default())
+            .or(self.init.transform)
     }
 
-    /// Given an object scene id, get the scene object to mutuate
-    pub fn get_object_mut(&mut self, id: u32) -> Option<&mut Scene> {
-        self.items.get_mut(&id)
+    /// The manifest entry for a file, if a manifest lists it
+    fn listing(&self, path: Option<&Path>) -> Option<&manifest::Entry> {
+        let path = path?;
+        self.manifests.values().find_map(|f| f.entry(path))
     }
-}
 
-/// Handle a command and mutate the platter state
-pub fn handle_command(platter_state: PlatterStatePtr, c: PlatterCommand) {
-    let mut this = platter_state.lock().unwrap();
+    /// Name and group a scene as its manifest lists it
+    fn apply_listing(&self, scene: &mut Scene) {
+        let Some(entry) = self.listing(scene.info.source.as_deref()) else {
+            return;
+        };
 
-    match c {
-        PlatterCommand::LoadFile(f, s_id) => {
-            this.import_filesystem_item(f.as_path(), s_id);
+        scene.info.name = entry.name.clone();
+        scene.info.group = entry.group.clone();
+        scene.publish_info();
+    }
+
+    /// Replace the global rescale and offset, and place every scene again.
+    /// Source overrides still win, and client edits are kept.
+    pub fn set_global_transform(&mut self, rescale: f32, offset: [f32; 3]) {
+        log::info!("Setting global rescale {rescale} and offset {offset:?}");
+
+        self.init.transform.rescale = Some(rescale);
+        self.init.transform.offset = Some(offset);
+
+        let ids: Vec<_> = self.items.keys().copied().collect();
+
+        for id in ids {
+            let source = self.items[&id].info.source.clone();
+            let m = placement(&self.transform_for(source.as_deref()));
+            self.items.get_mut(&id).unwrap().set_placement(m);
         }
-        PlatterCommand::WatchDirectory(dir) => {
-            if !dir.dir.try_exists().unwrap() {
-                log::error!("Directory {} is not readable.", dir.dir.display());
-                return;
+    }
+
+    /// How long content loaded from a path lives, if it expires at all
+    fn ttl_for(&self, path: Option<&Path>) -> Option<Duration> {
+        source_override(&self.init.source_ttls, path).or(self.init.ttl)
+    }
+
+    /// Ask for a scene to be removed once its TTL is up. The removal goes
+    /// through the command queue, like any other change to the scene list.
+    fn schedule_expiry(&self, id: u32, ttl: Duration) {
+        let tx = self.init.command_stream.clone();
+        let due = tokio::time::Instant::now() + ttl;
+
+        self.init.scheduler.at(due, move || {
+            tokio::spawn(async move {
+                if tx.send(PlatterCommand::Expire(id)).await.is_err() {
+                    log::warn!("Unable to expire scene {id}: command queue closed");
+                }
+            });
+        });
+    }
+
+    /// Map the root of a scene to its id, and give it a shadow, a label, and
+    /// our methods
+    fn attach_root(&mut self, o: &mut Scene, id: u32) {
+        let ent = o.root.parts.first().unwrap().clone();
+
+        if let Some(c) = &self.init.coalescer {
+            o.set_coalescer(c.clone());
+        }
+
+        self.root_to_item.insert(ent.clone(), id);
+
+        let name = o
+            .info
+            .source
+            .as_deref()
+            .and_then(import::display_name)
+            .unwrap_or_else(|| format!("Scene {id}"));
+
+        if self.init.ground_shadows {
+            if let Err(e) = shadow::attach_shadow(
+                o,
+                &format!("{name} {id}"),
+                self.state.clone(),
+                self.init.asset_store.clone(),
+                &self.init.import_options,
+            ) {
+                log::warn!("Unable to add a shadow to scene {id}: {e:#}");
             }
+        }
 
-            this.init.watcher_command_stream.send(dir).unwrap();
+        if self.init.label_scenes && self.capabilities().has(Capability::Text) {
+            o.attach_label(&mut self.state.lock().unwrap(), name);
         }
-        PlatterCommand::ClearTag(tag) => {
-            this.clear_source(tag);
+
+        let targets = match self.init.method_attachment {
+            arguments::MethodAttachment::None => vec![],
+            arguments::MethodAttachment::Root => vec![ent],
+            arguments::MethodAttachment::Named => {
+                let lock = self.state.lock().unwrap();
+
+                let named = |f: &EntityReference| {
+                    lock.entities
+                        .inspect(f.id(), |s| {
+                            let helper = s
+                                .mutable
+                                .tags
+                                .iter()
+                                .flatten()
+                                .any(|t| t.starts_with("platter.helper="));
+                            s.name.is_some() && !helper
+                        })
+                        .unwrap_or(false)
+                };
+
+                o.root
+                    .all_parts()
+                    .into_iter()
+                    .filter(|f| *f == ent || named(f))
+                    .collect()
+            }
+        };
+
+        // Methods invoked on any of these act on the whole scene
+        for target in targets {
+            ServerEntityStateUpdatable {
+                methods_list: Some(self.methods.clone()),
+                ..Default::default()
+            }
+            .patch(&target);
+
+            self.root_to_item.insert(target, id);
         }
     }
-}
 
-/// Dispatch a request to import. Depending on options this will either use builtin import tools or use assimp.
-fn handle_import(path: &Path, state: ServerStatePtr, asset_store: AssetStorePtr) -> Result<Scene> {
-    #[cfg(use_assimp)]
-    return assimp_import::import_file(p);
+    /// Add an object scene to the state
+    fn add_object(&mut self, mut o: Scene, source: Option<Tag>) -> u32 {
+        if let Some(mut placeholder) = self.placeholder.take() {
+            log::info!("Removing placeholder");
+
+            // Keep anything the new scene shares with it
+            placeholder.published.retain(|a| !o.published.contains(a));
+            self.keep_shared_assets(&mut placeholder);
+            self.init
+                .import_options
+                .asset_sizes
+                .forget(&placeholder.published);
+        }
+
+        let id = self.get_next_scene_id();
+
+        self.apply_listing(&mut o);
+        self.attach_root(&mut o, id);
+
+        for r in &o.references {
+            self.references.add(&r.path);
+        }
+
+        let tf = self.transform_for(o.info.source.as_deref());
+
+        if !tf.is_empty() {
+            log::debug!("Placing scene with {tf:?}");
+            o.set_placement(placement(&tf));
+        }
+
+        if let Some(ttl) = self.ttl_for(o.info.source.as_deref()) {
+            log::debug!("Scene {id} expires in {ttl:?}");
+            self.schedule_expiry(id, ttl);
+        }
+
+        let args = signals::scene_args(id, o.info.source.as_deref(), source.map(|f| f.to_string()));
+
+        let urls = self
+            .init
+            .import_options
+            .asset_sizes
+            .prefetch_order(&o.published);
+
+        self.items.insert(id, o);
+        self.init
+            .scene_count
+            .store(self.items.len(), Ordering::Relaxed);
+
+        if let Some(sid) = source {
+            self.source_map.entry(sid).or_default().insert(id);
+            self.push_sequence(sid, id);
+        }
+
+        let mut lock = self.state.lock().unwrap();
+
+        lock.issue_signal(&self.signals.scene_loaded, None, args);
+
+        if !urls.is_empty() {
+            lock.issue_signal(
+                &self.signals.prefetch,
+                None,
+                signals::prefetch_args(id, &urls),
+            );
+        }
+
+        id
+    }
+
+    /// Add a new scene to its source's sequence, if the source plays as one,
+    /// and show it in place of the step before. Steps that no longer fit are
+    /// removed.
+    fn push_sequence(&mut self, tag: Tag, id: u32) {
+        let Some(seq) = self.sequences.get_mut(&tag) else {
+            return;
+        };
+
+        for old in seq.push(id) {
+            log::debug!("Scene {old} dropped out of its sequence");
+            self.remove_object(old, Removal::Removed);
+        }
+
+        self.show_sequence(tag);
+    }
+
+    /// Show the current step of a sequence, and hide the rest
+    fn show_sequence(&mut self, tag: Tag) {
+        let Some(seq) = self.sequences.get(&tag) else {
+            return;
+        };
 
-    #[cfg(not(use_assimp))]
-    return import::import_file(path, state, asset_store);
+        let current = seq.current();
+
+        for id in seq.scenes() {
+            if let Some(scene) = self.items.get_mut(&id) {
+                let hide = Some(id) != current;
+                if scene.hidden() != hide {
+                    scene.set_hidden(hide);
+                }
+            }
+        }
+    }
+
+    /// Step every sequence `delta` scenes newer, or older if negative.
+    /// Returns the scenes now shown.
+    pub fn step_sequences(&mut self, delta: isize) -> Vec<u32> {
+        let tags: Vec<_> = self
+            .sequences
+            .iter_mut()
+            .filter_map(|(tag, seq)| seq.step(delta).then_some(*tag))
+            .collect();
+
+        for tag in tags {
+            self.show_sequence(tag);
+        }
+
+        let mut ret: Vec<_> = self
+            .sequences
+            .values()
+            .filter_map(|f| f.current())
+            .collect();
+        ret.sort();
+        ret
+    }
+
+    /// Keep a scene that is on its way out from unpublishing assets that
+    /// live scenes still use, such as those of its duplicates, or that a
+    /// running import has found by content. A dropped import must release
+    /// its own claim first.
+    fn keep_shared_assets(&self, scene: &mut Scene) {
+        let sizes = &self.init.import_options.asset_sizes;
+
+        scene.published.retain(|a| {
+            !sizes.is_claimed(a) && !self.items.values().any(|f| f.published.contains(a))
+        });
+    }
+
+    /// Copy a scene, sharing its buffers and assets, and place the copy
+    /// beside the original. Materials are copied, so the copy can be
+    /// recolored on its own. Returns the new scene id.
+    fn duplicate_object(&mut self, id: u32) -> Option<u32> {
+        let orig = self.items.get(&id)?;
+
+        let mut map = HashMap::new();
+
+        let (root, materials) = {
+            let mut lock = self.state.lock().unwrap();
+
+            let root = duplicate_entities(&orig.root, &mut lock, &mut map);
+
+            // Entities may be parented to one another; point copies at copies
+            for (old, new) in &map {
+                let parent = lock
+                    .entities
+                    .inspect(old.id(), |f| f.mutable.parent.clone())
+                    .flatten();
+
+                if let Some(p) = parent.and_then(|p| map.get(&p)) {
+                    ServerEntityStateUpdatable {
+                        parent: Some(p.clone()),
+                        ..Default::default()
+                    }
+                    .patch(new);
+                }
+            }
+
+            let materials = duplicate_materials(&orig.materials, map.values(), &mut lock);
+
+            (root, materials)
+        };
+
+        if root.parts.is_empty() {
+            log::warn!("Scene {id} has nothing to duplicate");
+            return None;
+        }
+
+        let mut copy = Scene::new(
+            root,
+            orig.published.clone(),
+            Some(self.init.asset_store.clone()),
+        );
+
+        copy.info = orig.info.clone();
+        copy.viewpoints = orig.viewpoints.clone();
+        copy.references = orig.references.clone();
+        copy.set_conversion(orig.conversion());
+        copy.copy_transform(orig);
+        copy.copy_materials(orig, materials);
+        copy.set_clip_planes(orig.clip_planes().to_vec());
+
+        // Copies of hidden sequence steps are not part of the sequence
+        copy.set_hidden(false);
+
+        // Side by side, with a little room between
+        let width = orig.world_bounds().map(|f| f.extent().x).unwrap_or(1.0);
+        copy.set_position(orig.position() + Vector3::x() * width * 1.1);
+
+        copy.publish_info();
+
+        let new_id = self.add_object(copy, None);
+
+        self.events.record(EventKind::Duplicated {
+            from: id,
+            scene: new_id,
+        });
+
+        Some(new_id)
+    }
+
+    /// Remove an object scene from the state. Dropping the scene unpublishes
+    /// its assets. Clients are told why it went.
+    fn remove_object(&mut self, id: u32, reason: Removal) {
+        let Some(mut scene) = self.items.remove(&id) else {
+            return;
+        };
+
+        self.root_to_item.retain(|_, f| *f != id);
+        self.init
+            .scene_count
+            .store(self.items.len(), Ordering::Relaxed);
+
+        for r in &scene.references {
+            self.references.remove(&r.path);
+        }
+
+        self.keep_shared_assets(&mut scene);
+
+        self.init
+            .import_options
+            .asset_sizes
+            .forget(&scene.published);
+
+        let tag = self
+            .source_map
+            .iter()
+            .find(|(_, list)| list.contains(&id))
+            .map(|(tag, _)| tag.to_string());
+
+        for list in self.source_map.values_mut() {
+            list.remove(&id);
+        }
+
+        let steps: Vec<_> = self
+            .sequences
+            .iter_mut()
+            .filter_map(|(tag, seq)| seq.remove(id).then_some(*tag))
+            .collect();
+
+        for tag in steps {
+            self.show_sequence(tag);
+        }
+
+        let args = signals::removed_args(id, scene.info.source.as_deref(), tag, reason);
+
+        self.state
+            .lock()
+            .unwrap()
+            .issue_signal(&self.signals.scene_removed, None, args);
+
+        if self.highlighted == Some(id) {
+            let state = self.state.clone();
+            self.set_highlight(&mut state.lock().unwrap(), id, false);
+        }
+
+        drop(scene);
+
+        self.events.record(EventKind::Removed { scene: id });
+    }
+
+    /// Clear all objects with the same source tag
+    fn clear_source(&mut self, source: Tag) -> Option<()> {
+        let list = self.source_map.remove(&source)?;
+
+        for item in list.iter() {
+            self.remove_object(*item, Removal::Cleared);
+        }
+
+        Some(())
+    }
+
+    /// Highlight a scene, or stop highlighting it. Only one scene is
+    /// highlighted at a time; clients are told of every change.
+    pub fn set_highlight(&mut self, state: &mut ServerState, id: u32, on: bool) {
+        let next = match (on, self.highlighted) {
+            (true, _) => Some(id),
+            (false, Some(current)) if current == id => None,
+            (false, current) => current,
+        };
+
+        if next == self.highlighted {
+            return;
+        }
+
+        if let Some(scene) = self.highlighted.and_then(|f| self.items.get_mut(&f)) {
+            scene.set_highlighted(false);
+        }
+
+        if let Some(scene) = next.and_then(|f| self.items.get_mut(&f)) {
+            scene.set_highlighted(true);
+        }
+
+        log::info!("Highlighted scene is now {next:?}");
+
+        self.highlighted = next;
+
+        state.issue_signal(
+            &self.signals.highlight_changed,
+            None,
+            vec![next
+                .map(|f| Value::Integer(f.into()))
+                .unwrap_or(Value::Null)],
+        );
+    }
+
+    /// Check a token given to a control method
+    pub fn check_control_token(&self, given: &str) -> bool {
+        let Some(expected) = &self.init.control_token else {
+            return false;
+        };
+
+        // Compare every byte, so timing does not reveal how much matched
+        given.len() == expected.len()
+            && given
+                .bytes()
+                .zip(expected.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+
+    /// Queue removal of a scene. This goes through the command queue so it
+    /// stays in order with loads.
+    pub fn request_remove(&self, id: u32) -> Result<()> {
+        self.init
+            .command_stream
+            .try_send(PlatterCommand::Remove(id))
+            .map_err(|e| anyhow::anyhow!("Unable to queue removal of scene {id}: {e}"))
+    }
+
+    /// Queue a reload of a scene from its source file
+    pub fn request_reload(&self, id: u32) -> Result<()> {
+        self.init
+            .command_stream
+            .try_send(PlatterCommand::Reload(id))
+            .map_err(|e| anyhow::anyhow!("Unable to queue reload of scene {id}: {e}"))
+    }
+
+    /// Queue a copy of a scene
+    pub fn request_duplicate(&self, id: u32) -> Result<()> {
+        self.init
+            .command_stream
+            .try_send(PlatterCommand::Duplicate(id))
+            .map_err(|e| anyhow::anyhow!("Unable to queue duplicate of scene {id}: {e}"))
+    }
+
+    /// Queue a download and import of a model. Only web URLs are accepted.
+    pub fn request_load_url(&self, url: &str) -> Result<()> {
+        if self.init.load_url.is_none() {
+            anyhow::bail!("Loading by URL is not enabled");
+        }
+
+        let url = url::Url::parse(url)?;
+
+        if !fetch::is_remote(url.as_str()) {
+            anyhow::bail!("Only http and https URLs can be loaded, not {url}");
+        }
+
+        self.init
+            .command_stream
+            .try_send(PlatterCommand::LoadUrl(url))
+            .map_err(|e| anyhow::anyhow!("Unable to queue download: {e}"))
+    }
+
+    /// Queue an export of a scene, or every scene if none is given, to a
+    /// file in the export directory. The name may not name a directory.
+    pub fn request_export(&self, name: &str, scene: Option<u32>) -> Result<()> {
+        let Some(dir) = &self.init.export_dir else {
+            anyhow::bail!("Exporting is not enabled");
+        };
+
+        let mut parts = Path::new(name).components();
+
+        if !matches!(
+            (parts.next(), parts.next()),
+            (Some(std::path::Component::Normal(_)), None)
+        ) {
+            anyhow::bail!("{name:?} is not a plain file name");
+        }
+
+        if let Some(id) = scene {
+            if !self.items.contains_key(&id) {
+                anyhow::bail!("No scene {id}");
+            }
+        }
+
+        let path = if name.to_lowercase().ends_with(".glb") {
+            dir.join(name)
+        } else {
+            dir.join(format!("{name}.glb"))
+        };
+
+        self.init
+            .command_stream
+            .try_send(PlatterCommand::Export(path, scene))
+            .map_err(|e| anyhow::anyhow!("Unable to queue export: {e}"))
+    }
+
+    /// Queue a watch of a directory, given relative to the watch root
+    pub fn request_watch(&self, mut dir: Directory) -> Result<()> {
+        let Some(root) = &self.init.watch_root else {
+            anyhow::bail!("Watching directories is not enabled");
+        };
+
+        if !dir
+            .dir
+            .components()
+            .all(|f| matches!(f, std::path::Component::Normal(_)))
+        {
+            anyhow::bail!("{} is not a path below the watch root", dir.dir.display());
+        }
+
+        // Symbolic links below the root may lead out of it
+        let root = root.canonicalize()?;
+        let path = root.join(&dir.dir);
+
+        dir.dir = match path.canonicalize() {
+            Ok(f) if f.starts_with(&root) => f,
+            Ok(_) => anyhow::bail!("{} leads out of the watch root", path.display()),
+            Err(e) => anyhow::bail!("Unable to watch {}: {e}", path.display()),
+        };
+
+        if !dir.dir.is_dir() {
+            anyhow::bail!("{} is not a directory", dir.dir.display());
+        }
+
+        let below_root = self
+            .init
+            .watchers
+            .list()
+            .iter()
+            .filter(|f| f.dir.starts_with(&root))
+            .count();
+
+        if below_root >= MAX_CLIENT_WATCHES {
+            anyhow::bail!("Already watching {below_root} directories below the watch root");
+        }
+
+        self.init
+            .command_stream
+            .try_send(PlatterCommand::WatchDirectory(dir))
+            .map_err(|e| anyhow::anyhow!("Unable to queue watch: {e}"))
+    }
+
+    /// Stop watching a directory, given as `platter.status` lists it or
+    /// relative to the watch root. Only directories below the watch root may
+    /// be stopped, as `request_watch` only starts those. Scenes already
+    /// loaded from it stay.
+    pub fn unwatch(&mut self, path: &Path) -> Result<()> {
+        let Some(root) = &self.init.watch_root else {
+            anyhow::bail!("Watching directories is not enabled");
+        };
+
+        // Absolute paths replace the root here, and are checked below
+        let path = root.join(path);
+
+        if !self.init.watchers.stop_below(root, &path) {
+            anyhow::bail!("{} is not watched below the watch root", path.display());
+        }
+
+        log::info!("Stopped watching {}", path.display());
+
+        self.events.record(EventKind::WatchStopped { path });
+
+        Ok(())
+    }
+
+    /// Queue an orderly shutdown
+    pub fn request_shutdown(&self) -> Result<()> {
+        self.init
+            .command_stream
+            .try_send(PlatterCommand::Shutdown)
+            .map_err(|e| anyhow::anyhow!("Unable to queue shutdown: {e}"))
+    }
+
+    /// Whether clients can find us over mDNS
+    pub fn discovery(&self) -> mdns::Discovery {
+        self.init.discovery.get()
+    }
+
+    /// Optional component types in use
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.init.import_options.capabilities
+    }
+
+    /// Get retained events newer than the given sequence number
+    pub fn events_since(&self, seq: u64) -> Vec<Event> {
+        self.events.since(seq).cloned().collect()
+    }
+
+    /// Given an entity reference, get the object scene it belongs to
+    pub fn find_id(&self, ent: &EntityReference) -> Option<u32> {
+        self.root_to_item.get(ent).copied()
+    }
+
+    /// Given an object scene id, get the scene object
+    pub fn get_object(&self, id: u32) -> Option<&Scene> {
+        self.items.get(&id)
+    }
+
+    /// Given an object scene id, get the scene object to mutuate
+    pub fn get_object_mut(&mut self, id: u32) -> Option<&mut Scene> {
+        self.items.get_mut(&id)
+    }
+
+    /// All live scenes, in id order
+    pub fn scenes(&self) -> Vec<(u32, &Scene)> {
+        let mut ret: Vec<_> = self.items.iter().map(|(id, s)| (*id, s)).collect();
+        ret.sort_by_key(|f| f.0);
+        ret
+    }
+
+    /// Size in bytes of a published asset, if known
+    pub fn asset_size(&self, asset: &uuid::Uuid) -> Option<u64> {
+        self.init.import_options.asset_sizes.get(asset)
+    }
+
+    /// Color space of a published image, if known
+    pub fn asset_color_space(&self, asset: &uuid::Uuid) -> Option<ColorSpace> {
+        self.init.import_options.asset_sizes.color_space(asset)
+    }
+
+    /// Running directory watchers
+    pub fn watchers(&self) -> Vec<WatcherStatus> {
+        self.init.watchers.list()
+    }
+
+    /// Number of files scenes reference that are watched for changes
+    pub fn referenced_files(&self) -> usize {
+        self.references.count()
+    }
+
+    /// Depth and capacity of the command queue
+    pub fn queued_commands(&self) -> (usize, usize) {
+        report::depth(&self.init.command_stream)
+    }
+
+    /// List all assets published by live scenes, along with the scene that
+    /// owns them, then those nobody owns, which have leaked
+    pub fn list_assets(&self) -> Vec<(uuid::Uuid, Option<u32>)> {
+        let mut ret: Vec<_> = self
+            .items
+            .iter()
+            .flat_map(|(id, scene)| scene.published.iter().map(|f| (*f, Some(*id))))
+            .collect();
+
+        ret.sort_by_key(|f| f.1);
+
+        let sizes = &self.init.import_options.asset_sizes;
+        ret.extend(
+            sizes
+                .unowned(&self.owned_assets())
+                .into_iter()
+                .map(|f| (f.0, None)),
+        );
+
+        ret
+    }
+
+    /// Assets published by live scenes, or the placeholder
+    fn owned_assets(&self) -> HashSet<uuid::Uuid> {
+        self.items
+            .values()
+            .chain(self.placeholder.iter())
+            .flat_map(|f| f.published.iter().copied())
+            .collect()
+    }
+
+    /// Remove an asset from the asset store.
+    ///
+    /// Assets that belong to a live scene (or the placeholder) are refused;
+    /// remove the scene instead. So are assets a running import holds, as
+    /// it may be about to publish a scene using them.
+    pub fn remove_asset(&mut self, asset: uuid::Uuid) -> Result<()> {
+        let owner = self
+            .items
+            .iter()
+            .chain(self.placeholder.iter().map(|f| (&u32::MAX, f)))
+            .find(|(_, scene)| scene.published.contains(&asset));
+
+        if let Some((id, _)) = owner {
+            anyhow::bail!("Asset {asset} is in use by scene {id}");
+        }
+
+        if !self
+            .init
+            .import_options
+            .asset_sizes
+            .forget_unclaimed(&asset)
+        {
+            anyhow::bail!("Asset {asset} is in use by a running import");
+        }
+
+        log::info!("Removing asset {asset}");
+
+        remove_asset(self.init.asset_store.clone(), asset);
+
+        Ok(())
+    }
+
+    /// Remove every published asset that no live scene (or the placeholder)
+    /// owns, and that no running import holds, and drop entries for scenes
+    /// that are gone. Anything found here has leaked.
+    pub fn collect_garbage(&mut self) -> GcReport {
+        let owned = self.owned_assets();
+
+        let sizes = &self.init.import_options.asset_sizes;
+        let assets = sizes.unowned(&owned);
+
+        for (asset, _) in &assets {
+            remove_asset(self.init.asset_store.clone(), *asset);
+        }
+
+        sizes.forget(assets.iter().map(|f| &f.0));
+
+        let items = &self.items;
+        let before =
+            self.root_to_item.len() + self.source_map.values().map(|f| f.len()).sum::<usize>();
+
+        self.root_to_item.retain(|_, f| items.contains_key(f));
+
+        for list in self.source_map.values_mut() {
+            list.retain(|f| items.contains_key(f));
+        }
+        self.source_map.retain(|_, f| !f.is_empty());
+
+        let after =
+            self.root_to_item.len() + self.source_map.values().map(|f| f.len()).sum::<usize>();
+
+        GcReport {
+            assets,
+            stale_entries: before - after,
+        }
+    }
+}
+
+/// What a garbage collection sweep removed
+#[derive(Debug, Default)]
+pub struct GcReport {
+    /// Assets no scene owned, with their sizes in bytes
+    pub assets: Vec<(uuid::Uuid, u64)>,
+
+    /// Entity and source entries that pointed at removed scenes
+    pub stale_entries: usize,
+}
+
+impl GcReport {
+    pub fn is_empty(&self) -> bool {
+        self.assets.is_empty() && self.stale_entries == 0
+    }
+
+    /// Total size of the removed assets
+    pub fn bytes(&self) -> u64 {
+        self.assets.iter().map(|f| f.1).sum()
+    }
+}
+
+impl std::fmt::Display for GcReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} assets ({} bytes), {} stale entries",
+            self.assets.len(),
+            self.bytes(),
+            self.stale_entries
+        )
+    }
+}
+
+/// How often the turntable moves scenes
+const TURNTABLE_PERIOD: Duration = Duration::from_millis(50);
+
+/// Spin every scene at `speed` degrees per second, until the state is
+/// dropped. The angle follows the clock, so stretched timers in low power
+/// mode only make the motion coarser.
+fn start_turntable(platter_state: &PlatterStatePtr, speed: f32) {
+    log::info!("Turntable at {speed} degrees per second");
+
+    let weak = Arc::downgrade(platter_state);
+    let start = std::time::Instant::now();
+
+    platter_state
+        .lock()
+        .unwrap()
+        .init
+        .scheduler
+        .every(TURNTABLE_PERIOD, move || {
+            let Some(this) = weak.upgrade() else {
+                return false;
+            };
+
+            let angle =
+                (start.elapsed().as_secs_f32() * speed).to_radians() % std::f32::consts::TAU;
+
+            for scene in this.lock().unwrap().items.values_mut() {
+                scene.set_spin(angle);
+            }
+
+            true
+        });
+}
+
+/// Sweep for leaked assets every `period`, until the state is dropped. Imports
+/// run without the state held, but claim their assets until their scene is
+/// added, so a sweep leaves them alone.
+fn start_gc(platter_state: &PlatterStatePtr, period: Duration) {
+    log::info!("Collecting unowned assets every {period:?}");
+
+    let weak = Arc::downgrade(platter_state);
+
+    platter_state
+        .lock()
+        .unwrap()
+        .init
+        .scheduler
+        .every(period, move || {
+            let Some(this) = weak.upgrade() else {
+                return false;
+            };
+
+            let report = this.lock().unwrap().collect_garbage();
+
+            if !report.is_empty() {
+                log::warn!("Leak detected; removed {report}");
+                for (asset, size) in &report.assets {
+                    log::debug!("Removed unowned asset {asset} ({size} bytes)");
+                }
+            }
+
+            true
+        });
+}
+
+/// Handle a command and mutate the platter state
+pub fn handle_command(platter_state: PlatterStatePtr, c: PlatterCommand) {
+    let mut this = platter_state.lock().unwrap();
+
+    match c {
+        PlatterCommand::LoadFile(f, s_id, cancel) => {
+            drop(this);
+            load_file(platter_state, f, s_id, cancel);
+        }
+        PlatterCommand::WatchDirectory(dir) => {
+            if !dir.dir.try_exists().unwrap() {
+                log::error!("Directory {} is not readable.", dir.dir.display());
+                return;
+            }
+
+            if this.init.watchers.is_watching(&dir.dir) {
+                log::info!("{} is already watched", dir.dir.display());
+                return;
+            }
+
+            let path = dir.dir.clone();
+
+            // This queue does not block, so requests beyond its capacity are
+            // rejected
+            if let Err(e) = this.init.watcher_command_stream.try_send(dir) {
+                log::error!("Unable to start watcher for {}: {e}", path.display());
+                return;
+            }
+
+            this.events.record(EventKind::WatchStarted { path });
+        }
+        PlatterCommand::Sequence(tag, length) => {
+            this.sequences
+                .entry(tag)
+                .or_insert_with(|| Sequence::new(length));
+        }
+        PlatterCommand::Manifest(tag, Some(manifest)) => {
+            this.manifests.insert(tag, manifest);
+        }
+        PlatterCommand::Manifest(tag, None) => {
+            this.manifests.remove(&tag);
+        }
+        PlatterCommand::ClearTag(tag) => {
+            this.clear_source(tag);
+        }
+        PlatterCommand::Unwatched(tag) => {
+            this.sequences.remove(&tag);
+            this.manifests.remove(&tag);
+        }
+        PlatterCommand::Expire(id) => {
+            // Scene ids are not reused, so a missing scene was removed some
+            // other way
+            if this.items.contains_key(&id) {
+                log::info!("Scene {id} has expired");
+                this.events.record(EventKind::Expired { scene: id });
+                this.remove_object(id, Removal::Expired);
+            }
+        }
+        PlatterCommand::Remove(id) => {
+            log::info!("Removing scene {id}");
+            this.remove_object(id, Removal::Removed);
+        }
+        PlatterCommand::Reload(id) => {
+            drop(this);
+            reload_object(&platter_state, id);
+        }
+        PlatterCommand::Refresh(path) => {
+            drop(this);
+            refresh_references(&platter_state, &path);
+        }
+        PlatterCommand::LoadUrl(url) => {
+            drop(this);
+            load_url(&platter_state, url);
+        }
+        PlatterCommand::Export(path, scene) => {
+            this.export(platter_state.clone(), path, scene);
+        }
+        PlatterCommand::Generate(kind) => {
+            drop(this);
+            generate(&platter_state, kind);
+        }
+        PlatterCommand::Duplicate(id) => {
+            if let Some(copy) = this.duplicate_object(id) {
+                log::info!("Duplicated scene {id} as {copy}");
+            }
+        }
+        PlatterCommand::Shutdown => {
+            log::info!("Shutting down on request");
+
+            let Some(path) = this.init.export_on_exit.clone() else {
+                this.shut_down();
+                return;
+            };
+
+            // Buffers are fetched from the asset server, so scenes stay
+            // published until they are written
+            let task = this.export(platter_state.clone(), path, None);
+            drop(this);
+
+            tokio::spawn(async move {
+                if let Some(task) = task {
+                    let _ = task.await;
+                }
+                platter_state.lock().unwrap().shut_down();
+            });
+        }
+    }
+}
+
+/// Create copies of every entity in a scene object and below, recording
+/// which copy belongs to which original. Labels and shadows are left out, as
+/// new scenes get their own.
+fn duplicate_entities(
+    obj: &SceneObject,
+    state: &mut ServerState,
+    map: &mut HashMap<EntityReference, EntityReference>,
+) -> SceneObject {
+    let parts = obj
+        .parts
+        .iter()
+        .filter_map(|ent| {
+            let orig = state.entities.inspect(ent.id(), |f| f.clone())?;
+
+            let own_helper = orig.mutable.tags.as_ref().is_some_and(|f| {
+                f.iter()
+                    .any(|t| t == "platter.helper=label" || t == "platter.helper=shadow")
+            });
+
+            if own_helper {
+                return None;
+            }
+
+            let copy = state.entities.new_component(ServerEntityState {
+                name: orig.name,
+                mutable: ServerEntityStateUpdatable {
+                    parent: None,
+                    ..orig.mutable
+                },
+            });
+
+            map.insert(ent.clone(), copy.clone());
+
+            Some(copy)
+        })
+        .collect();
+
+    SceneObject {
+        parts,
+        children: obj
+            .children
+            .iter()
+            .map(|f| duplicate_entities(f, state, map))
+            .collect(),
+    }
+}
+
+/// Give copied entities copies of the materials they draw with. Geometries
+/// using the materials are copied to match; their buffers stay shared.
+/// Returns the copies, in the order of `materials`.
+fn duplicate_materials<'a>(
+    materials: &[MaterialReference],
+    entities: impl Iterator<Item = &'a EntityReference>,
+    state: &mut ServerState,
+) -> Vec<MaterialReference> {
+    let mut copies = HashMap::new();
+
+    for m in materials {
+        let Some(copy) = state.materials.inspect(m.id(), |f| ServerMaterialState {
+            name: f.name.clone(),
+            mutable: f.mutable.clone(),
+        }) else {
+            continue;
+        };
+
+        copies.insert(m.clone(), state.materials.new_component(copy));
+    }
+
+    let mut geometries: HashMap<ComponentID, Option<GeometryReference>> = HashMap::new();
+
+    for ent in entities {
+        let Some(value) = state
+            .entities
+            .inspect(ent.id(), |f| Value::serialized(f).ok())
+            .flatten()
+        else {
+            continue;
+        };
+
+        let rep = export::field(&value, "render_rep");
+
+        let Some(mesh) = export::id(rep.and_then(|f| export::field(f, "mesh"))) else {
+            continue;
+        };
+
+        let geom = match geometries.get(&mesh) {
+            Some(f) => f.clone(),
+            None => {
+                let geom = duplicate_geometry(state, mesh, &copies);
+                geometries.insert(mesh, geom.clone());
+                geom
+            }
+        };
+
+        // Nothing to change if the geometry uses none of the materials
+        let Some(geom) = geom else {
+            continue;
+        };
+
+        let instances = rep
+            .and_then(|f| export::field(f, "instances"))
+            .and_then(|f| {
+                Some(ServerGeometryInstance {
+                    view: state
+                        .buffer_views
+                        .resolve(export::id(export::field(f, "view"))?)?,
+                    stride: export::field(f, "stride").and_then(|f| f.deserialized().ok()),
+                    bb: export::field(f, "bb").and_then(|f| f.deserialized().ok()),
+                })
+            });
+
+        ServerEntityStateUpdatable {
+            representation: Some(ServerEntityRepresentation::new_render(
+                RenderRepresentation {
+                    mesh: geom,
+                    instances,
+                },
+            )),
+            ..Default::default()
+        }
+        .patch(ent);
+    }
+
+    materials
+        .iter()
+        .map(|m| copies.get(m).unwrap_or(m).clone())
+        .collect()
+}
+
+/// Copy a geometry, drawing with copies of its materials. Returns None if
+/// none of its materials were copied.
+fn duplicate_geometry(
+    state: &mut ServerState,
+    id: ComponentID,
+    copies: &HashMap<MaterialReference, MaterialReference>,
+) -> Option<GeometryReference> {
+    let copy = state
+        .geometries
+        .inspect(id, |f| {
+            if !f.patches.iter().any(|p| copies.contains_key(&p.material)) {
+                return None;
+            }
+
+            let patches = f
+                .patches
+                .iter()
+                .map(|p| ServerGeometryPatch {
+                    attributes: p
+                        .attributes
+                        .iter()
+                        .map(|a| ServerGeometryAttribute {
+                            view: a.view.clone(),
+                            semantic: a.semantic,
+                            channel: a.channel,
+                            offset: a.offset,
+                            stride: a.stride,
+                            format: a.format,
+                            normalized: a.normalized,
+                            minimum_value: a.minimum_value.clone(),
+                            maximum_value: a.maximum_value.clone(),
+                        })
+                        .collect(),
+                    vertex_count: p.vertex_count,
+                    indices: p.indices.as_ref().map(|i| ServerGeometryIndex {
+                        view: i.view.clone(),
+                        count: i.count,
+                        offset: i.offset,
+                        stride: i.stride,
+                        format: i.format,
+                    }),
+                    patch_type: p.patch_type,
+                    material: copies.get(&p.material).unwrap_or(&p.material).clone(),
+                })
+                .collect();
+
+            Some(ServerGeometryState {
+                name: f.name.clone(),
+                patches,
+            })
+        })
+        .flatten()?;
+
+    Some(state.geometries.new_component(copy))
+}
+
+/// Matrix for an offset, rotation, and rescale from the options
+fn placement(tf: &arguments::SourceTransform) -> Matrix4<f32> {
+    let offset = Vector3::from(tf.offset.unwrap_or_default());
+    let rotation = tf
+        .rotate
+        .map(|[x, y, z]| {
+            UnitQuaternion::from_euler_angles(x.to_radians(), y.to_radians(), z.to_radians())
+        })
+        .unwrap_or_default();
+    let rescale = tf.rescale.unwrap_or(1.0);
+
+    Matrix4::new_translation(&offset) * rotation.to_homogeneous() * Matrix4::new_scaling(rescale)
+}
+
+/// Find the override for the most specific source containing a path
+pub fn source_override<T: Copy>(list: &[(PathBuf, T)], path: Option<&Path>) -> Option<T> {
+    let path = path?;
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+    list.iter()
+        .filter(|(p, _)| path.starts_with(p))
+        .max_by_key(|(p, _)| p.components().count())
+        .map(|(_, t)| *t)
+}
+
+/// The stages of loading a file
+enum Load {
+    New(PathBuf, Option<Tag>, import::ImportOptions),
+    Reload(u32, PathBuf, import::ImportOptions),
+}
+
+/// Load a file, or queue a load of every file in a directory. The platter
+/// state is only held to check the load and to add the result; the conversion
+/// itself, which can take a while for a large file, runs without it, so
+/// methods and other commands are not held up.
+fn load_file(
+    platter_state: PlatterStatePtr,
+    f: PathBuf,
+    s_id: Option<Tag>,
+    cancel: Option<import::CancelToken>,
+) {
+    let mut this = platter_state.lock().unwrap();
+
+    // The tag was cleared while this sat in the queue
+    if cancel.as_ref().is_some_and(|f| f.is_cancelled()) {
+        log::info!("Skipping {}, its import was cancelled", f.display());
+        return;
+    }
+
+    let load = if let Some(id) = s_id.and_then(|t| this.watched_scene(t, &f)) {
+        // A watched file was written again; replace its scene in place
+        log::info!("{} changed", f.display());
+
+        let Some((path, options)) = this.prepare_reload(id) else {
+            return;
+        };
+
+        Load::Reload(id, path, options)
+    } else if f.is_file() {
+        let Some(options) = this.prepare_import(&f, s_id, cancel) else {
+            return;
+        };
+
+        log::info!("Loading file: {}", f.display());
+
+        Load::New(f, s_id, options)
+    } else if f.is_dir() {
+        let files = this.dir_files(&f);
+        let tx = this.init.command_stream.clone();
+        drop(this);
+
+        // Each file waits in the import queue on its own, under its source's
+        // limits, rather than all running here one after another
+        for file in files {
+            let command = PlatterCommand::LoadFile(file, s_id, cancel.clone());
+
+            if tx.blocking_send(command).is_err() {
+                break;
+            }
+        }
+        return;
+    } else {
+        return;
+    };
+
+    drop(this);
+
+    run_load(&platter_state, load);
+}
+
+/// Reload a scene from its source file, keeping its id and transform. The
+/// old scene stays if the import fails.
+fn reload_object(platter_state: &PlatterStatePtr, id: u32) {
+    let Some((path, options)) = platter_state.lock().unwrap().prepare_reload(id) else {
+        return;
+    };
+
+    run_load(platter_state, Load::Reload(id, path, options));
+}
+
+/// Run a checked load without the platter state, then add the result
+fn run_load(platter_state: &PlatterStatePtr, load: Load) {
+    let (state, asset_store) = {
+        let this = platter_state.lock().unwrap();
+        (this.state.clone(), this.init.asset_store.clone())
+    };
+
+    match load {
+        Load::New(f, s_id, options) => {
+            let res = handle_import(&f, state, asset_store, &options);
+
+            platter_state
+                .lock()
+                .unwrap()
+                .finish_import(&f, s_id, &options, res);
+        }
+        Load::Reload(id, path, options) => {
+            let res = handle_import(&path, state, asset_store, &options);
+
+            platter_state
+                .lock()
+                .unwrap()
+                .finish_reload(id, path, &options, res);
+        }
+    }
+}
+
+/// Publish images read from a file again, after it changed. The file is read
+/// and fitted without the platter state held.
+fn refresh_references(platter_state: &PlatterStatePtr, path: &Path) {
+    let Some(now) = dir_watcher::stamp(path) else {
+        return;
+    };
+
+    let options = {
+        let this = platter_state.lock().unwrap();
+
+        if this.stale_references(path, now).is_empty() {
+            return;
+        }
+
+        this.init.import_options.clone()
+    };
+
+    let bytes = match fs::read(path) {
+        Ok(f) => texture::fit(f, &options),
+        Err(e) => {
+            log::warn!("Unable to read {}: {e}", path.display());
+            return;
+        }
+    };
+
+    platter_state
+        .lock()
+        .unwrap()
+        .finish_refresh(path, now, &bytes);
+}
+
+/// Build a test scene without the platter state held, then add it
+fn generate(platter_state: &PlatterStatePtr, kind: arguments::TestScene) {
+    let (state, asset_store, options) = {
+        let this = platter_state.lock().unwrap();
+        (
+            this.state.clone(),
+            this.init.asset_store.clone(),
+            this.init.import_options.clone(),
+        )
+    };
+
+    let res = gen_test::generate(&kind, state, asset_store, &options);
+
+    platter_state.lock().unwrap().finish_generate(&kind, res);
+}
+
+/// Download a model to scratch space and import it, without the platter
+/// state held. The download is removed with the scene.
+fn load_url(platter_state: &PlatterStatePtr, url: url::Url) {
+    let path = PathBuf::from(url.as_str());
+
+    let (state, asset_store, limits, options) = {
+        let mut this = platter_state.lock().unwrap();
+        (
+            this.state.clone(),
+            this.init.asset_store.clone(),
+            this.init.load_url.clone(),
+            this.options_for(None),
+        )
+    };
+
+    let res = download(&url, limits.as_ref(), &options).and_then(|(dir, file)| {
+        log::info!("Loading {url} from {}", file.display());
+
+        let mut scene = handle_import(&file, state, asset_store, &options)?;
+
+        scene.scratch.push(dir);
+        Ok(scene)
+    });
+
+    platter_state
+        .lock()
+        .unwrap()
+        .finish_import(&path, None, &options, res);
+}
+
+/// Fetch a model into a new scratch directory
+fn download(
+    url: &url::Url,
+    limits: Option<&FetchLimits>,
+    options: &import::ImportOptions,
+) -> Result<(ScratchDir, PathBuf)> {
+    let limits = limits.ok_or_else(|| anyhow::anyhow!("Loading by URL is not enabled"))?;
+
+    let scratch = options.scratch.as_ref().ok_or_else(|| {
+        import::ImportError::UnableToImport("Downloads need scratch space".into())
+    })?;
+
+    let (bytes, content_type) = fetch::fetch_model(url.as_str(), limits)?;
+
+    let name = fetch::file_name_for(url, content_type.as_deref());
+
+    let mut dir = scratch.allocate(&name)?;
+    let file = dir.write(&name, &bytes)?;
+
+    Ok((dir, file))
+}
+
+/// Dispatch a request to import. Formats handled by assimp are only available
+/// with the `assimp` feature.
+fn handle_import(
+    path: &Path,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &import::ImportOptions,
+) -> Result<Scene> {
+    import::import_file(path, state, asset_store, options)
 }
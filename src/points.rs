@@ -0,0 +1,433 @@
+//! Shared support for publishing point clouds
+
+use std::path::Path;
+
+use anyhow::Result;
+use nalgebra::{Matrix4, Vector3};
+
+use crate::capabilities::Capability;
+use crate::color;
+use crate::import::{self, AssetKind, AssetWriter, BufferData, ImportOptions};
+use crate::scene::{Bounds, Scene, SceneObject};
+use colabrodo_common::{components::*, types::Format};
+use colabrodo_server::{server_http::*, server_messages::*, server_state::*};
+
+/// Huge clouds are split into geometries of at most this many points, so
+/// clients can start drawing before everything has arrived.
+pub const POINTS_PER_CHUNK: usize = 1 << 20;
+
+/// Size of a packed point: position, then an RGBA color
+pub const POINT_STRIDE: usize = 16;
+
+/// Large chunks written to scratch space are appended into shared buffers
+/// of up to this many chunks, which keeps the number of files down while
+/// buffers stay small enough to arrive one at a time.
+const CHUNKS_PER_BUFFER: usize = 4;
+
+/// A batch of packed points, destined for a single buffer
+#[derive(Debug, Default)]
+pub struct PointChunk {
+    pub bytes: Vec<u8>,
+    pub bounds: Option<Bounds>,
+    /// Points dropped for NaN or infinite positions
+    pub nonfinite: u64,
+}
+
+impl PointChunk {
+    pub fn with_capacity(points: usize) -> Self {
+        Self {
+            bytes: Vec::with_capacity(points * POINT_STRIDE),
+            bounds: None,
+            nonfinite: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len() / POINT_STRIDE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Add a point. Points with NaN or infinite positions are counted and
+    /// dropped, in which case this returns false.
+    pub fn push(&mut self, position: [f32; 3], color: [u8; 4]) -> bool {
+        if !position.iter().all(|f| f.is_finite()) {
+            self.nonfinite += 1;
+            return false;
+        }
+
+        let p = Vector3::from(position);
+        match &mut self.bounds {
+            Some(b) => b.extend(&p),
+            None => self.bounds = Some(Bounds::new(p)),
+        }
+
+        for v in position {
+            self.bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        self.bytes.extend_from_slice(&color);
+
+        true
+    }
+
+    /// Replace the color of an already packed point
+    pub fn set_color(&mut self, index: usize, color: [u8; 4]) {
+        let at = index * POINT_STRIDE + 12;
+        self.bytes[at..at + 4].copy_from_slice(&color);
+    }
+}
+
+/// A published chunk, waiting for its components
+struct Chunk {
+    /// Index of the buffer holding the chunk
+    buffer: usize,
+    offset: u64,
+    length: u64,
+    count: usize,
+}
+
+/// A group of chunks, such as a single scan
+struct Group {
+    name: Option<String>,
+    transform: Matrix4<f32>,
+    tags: Option<Vec<String>>,
+    chunks: Vec<Chunk>,
+}
+
+/// Collects chunks of a point cloud, to be published under a single root
+/// entity.
+///
+/// Chunk bytes go to the asset server as they arrive, which needs no lock.
+/// Chunks over the large size limit are appended to buffers in scratch
+/// space, when there is some, so a huge cloud is never held in memory.
+/// Components are only created by [`Self::finish`], so importers can read
+/// the whole file before taking the server state lock, and then hold it
+/// briefly.
+pub struct PointCloud<'a> {
+    asset_store: AssetStorePtr,
+    source: &'a Path,
+    options: &'a ImportOptions,
+
+    groups: Vec<Group>,
+
+    /// Buffers published so far
+    buffers: Vec<BufferData>,
+
+    /// Buffer being appended to, and the number of chunks in it. It becomes
+    /// the next of `buffers` once it is full.
+    appending: Option<(AssetWriter, usize)>,
+
+    /// Whether scratch space has run out
+    scratch_full: bool,
+
+    published: Vec<uuid::Uuid>,
+    bounds: Option<Bounds>,
+    nonfinite: u64,
+}
+
+impl<'a> PointCloud<'a> {
+    pub fn new(
+        asset_store: AssetStorePtr,
+        source: &'a Path,
+        options: &'a ImportOptions,
+    ) -> Result<Self> {
+        options.capabilities.require(Capability::Points)?;
+
+        Ok(Self {
+            asset_store,
+            source,
+            options,
+            groups: Vec::new(),
+            buffers: Vec::new(),
+            appending: None,
+            scratch_full: false,
+            published: Vec::new(),
+            bounds: None,
+            nonfinite: 0,
+        })
+    }
+
+    /// Start the cloud, if no group has been started yet. Points are given
+    /// relative to `transform`, which lets importers keep large coordinates
+    /// precise.
+    pub fn start(&mut self, transform: Matrix4<f32>) {
+        if self.groups.is_empty() {
+            self.begin_group(None, transform, None);
+        }
+    }
+
+    /// Start a new group of points, such as a single scan, placed by
+    /// `transform`. Later chunks are published into this group.
+    ///
+    /// Tags go on the group rather than the root, as the root's tags carry
+    /// the scene information.
+    ///
+    /// The transform goes on a child of the root, as the root transform is
+    /// replaced when the scene is moved.
+    pub fn begin_group(
+        &mut self,
+        name: Option<String>,
+        transform: Matrix4<f32>,
+        tags: Option<Vec<String>>,
+    ) {
+        self.groups.push(Group {
+            name,
+            transform,
+            tags,
+            chunks: Vec::new(),
+        });
+    }
+
+    /// Publish bytes on the asset server, to be removed with the scene
+    pub fn publish_asset(&mut self, bytes: &[u8]) -> url::Url {
+        let asset = import::asset_id(self.source, bytes, self.options);
+
+        let url = import::add_asset(
+            self.asset_store.clone(),
+            asset,
+            bytes,
+            AssetKind::Geometry,
+            self.options,
+        );
+
+        self.published.push(asset);
+
+        url
+    }
+
+    /// Publish the bytes of a buffer, to be removed with the scene
+    pub fn publish_buffer(&mut self, bytes: &[u8]) -> BufferData {
+        import::publish_buffer(
+            &self.asset_store,
+            self.source,
+            bytes,
+            self.options,
+            &mut self.published,
+        )
+    }
+
+    /// Publish a chunk's bytes, to become a buffer and a point geometry
+    pub fn publish(&mut self, mut chunk: PointChunk) -> Result<()> {
+        self.nonfinite += chunk.nonfinite;
+
+        color::convert_packed(
+            &mut chunk.bytes,
+            POINT_STRIDE,
+            12,
+            self.options.vertex_colors,
+        );
+
+        if chunk.is_empty() {
+            return Ok(());
+        }
+
+        self.start(Matrix4::identity());
+
+        let (buffer, offset) = self.place(&chunk.bytes)?;
+
+        let group = self.groups.last_mut().unwrap();
+
+        if let Some(b) = chunk.bounds.map(|f| f.transformed(&group.transform)) {
+            self.bounds = Some(self.bounds.map_or(b, |f| f.union(&b)));
+        }
+
+        group.chunks.push(Chunk {
+            buffer,
+            offset,
+            length: chunk.bytes.len() as u64,
+            count: chunk.len(),
+        });
+
+        Ok(())
+    }
+
+    /// Put a chunk's bytes into a buffer. Returns the index of the buffer,
+    /// and where in it the bytes start.
+    fn place(&mut self, bytes: &[u8]) -> Result<(usize, u64)> {
+        let large = bytes.len() as u64 >= self.options.size_large_limit;
+
+        if large && !self.scratch_full {
+            if self.appending.is_none() {
+                self.appending = AssetWriter::new(self.source, self.options)?.map(|f| (f, 0));
+            }
+
+            if let Some((writer, count)) = &mut self.appending {
+                let offset = writer.len();
+
+                if writer.append(bytes)? {
+                    *count += 1;
+
+                    let buffer = self.buffers.len();
+
+                    if *count == CHUNKS_PER_BUFFER {
+                        self.close()?;
+                    }
+
+                    return Ok((buffer, offset));
+                }
+
+                log::warn!("Scratch space is full; keeping the rest of the point cloud in memory");
+                self.scratch_full = true;
+            }
+        }
+
+        // The buffer being appended to comes first
+        self.close()?;
+
+        let data = self.publish_buffer(bytes);
+        self.buffers.push(data);
+
+        Ok((self.buffers.len() - 1, 0))
+    }
+
+    /// Serve the buffer being appended to, if there is one
+    fn close(&mut self) -> Result<()> {
+        let Some((writer, _)) = self.appending.take().filter(|(f, _)| !f.is_empty()) else {
+            return Ok(());
+        };
+
+        let data = writer.finish(
+            &self.asset_store,
+            AssetKind::Geometry,
+            self.options,
+            &mut self.published,
+        )?;
+
+        self.buffers.push(data);
+
+        Ok(())
+    }
+
+    /// Create the components for everything published, and wrap them into
+    /// a scene
+    pub fn finish(mut self, state: &mut ServerState) -> Result<Scene> {
+        self.start(Matrix4::identity());
+        self.close()?;
+
+        log::debug!("Published {} point chunks", self.published.len());
+
+        // Points carry their own color; keep the material plain
+        let material = state.materials.new_component(ServerMaterialState {
+            name: None,
+            mutable: ServerMaterialStateUpdatable {
+                pbr_info: Some(PBRInfo {
+                    base_color: self.options.tinted([1.0; 4]),
+                    metallic: Some(0.0),
+                    roughness: Some(1.0),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        });
+
+        let root = state.entities.new_component(ServerEntityState {
+            name: import::display_name(self.source),
+            mutable: Default::default(),
+        });
+
+        let mut parts = vec![root.clone()];
+
+        let buffers: Vec<_> = self.buffers.iter().map(|f| f.create(state)).collect();
+
+        for group in self.groups {
+            let parent = state.entities.new_component(ServerEntityState {
+                name: group.name,
+                mutable: ServerEntityStateUpdatable {
+                    parent: Some(root.clone()),
+                    transform: Some(group.transform.as_slice().try_into().unwrap()),
+                    tags: group.tags,
+                    ..Default::default()
+                },
+            });
+
+            parts.push(parent.clone());
+
+            for chunk in group.chunks {
+                let buffer = &buffers[chunk.buffer];
+                parts.push(publish_chunk(state, &chunk, buffer, &material, &parent));
+            }
+        }
+
+        let mut scene = Scene::new(
+            SceneObject {
+                parts,
+                children: vec![],
+            },
+            self.published,
+            Some(self.asset_store),
+        );
+
+        scene.materials.push(material);
+        scene.info.bounds = self.bounds;
+        scene.info.cleanup.nonfinite = self.nonfinite;
+
+        Ok(scene)
+    }
+}
+
+/// Create the view, geometry, and entity for a published chunk
+fn publish_chunk(
+    state: &mut ServerState,
+    chunk: &Chunk,
+    buffer: &BufferReference,
+    material: &MaterialReference,
+    parent: &EntityReference,
+) -> EntityReference {
+    let view = state.buffer_views.new_component(ServerBufferViewState {
+        name: None,
+        source_buffer: buffer.clone(),
+        view_type: BufferViewType::Geometry,
+        offset: chunk.offset,
+        length: chunk.length,
+    });
+
+    let geom = state.geometries.new_component(ServerGeometryState {
+        name: None,
+        patches: vec![ServerGeometryPatch {
+            attributes: vec![
+                ServerGeometryAttribute {
+                    view: view.clone(),
+                    semantic: AttributeSemantic::Position,
+                    channel: None,
+                    offset: Some(0),
+                    stride: Some(POINT_STRIDE as u32),
+                    format: Format::VEC3,
+                    normalized: Some(false),
+                    minimum_value: None,
+                    maximum_value: None,
+                },
+                ServerGeometryAttribute {
+                    view,
+                    semantic: AttributeSemantic::Color,
+                    channel: None,
+                    offset: Some(12),
+                    stride: Some(POINT_STRIDE as u32),
+                    format: Format::U8VEC4,
+                    normalized: Some(true),
+                    minimum_value: None,
+                    maximum_value: None,
+                },
+            ],
+            vertex_count: chunk.count as u64,
+            indices: None,
+            patch_type: PrimitiveType::Points,
+            material: material.clone(),
+        }],
+    });
+
+    state.entities.new_component(ServerEntityState {
+        name: None,
+        mutable: ServerEntityStateUpdatable {
+            parent: Some(parent.clone()),
+            representation: Some(ServerEntityRepresentation::new_render(
+                RenderRepresentation {
+                    mesh: geom,
+                    instances: None,
+                },
+            )),
+            ..Default::default()
+        },
+    })
+}
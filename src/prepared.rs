@@ -0,0 +1,196 @@
+//! Scenes converted, but not yet published.
+//!
+//! Importers fill in a [`Prepared`] scene without holding the server state:
+//! meshes are cleaned, packed, and their buffers published, and textures are
+//! read, fitted, and published. Creating the components from it is quick, so
+//! the state is only held for that last step.
+
+use std::path::Path;
+
+use colabrodo_server::{server_http::*, server_messages::*, server_state::*};
+
+use crate::color::ColorSpace;
+use crate::geometry::{PackedMesh, TriangleMesh};
+use crate::import::{self, AssetKind, ImportOptions};
+use crate::scene::{Scene, SceneObject};
+
+/// A material waiting to be created
+#[derive(Clone, Default)]
+pub struct PreparedMaterial {
+    pub name: Option<String>,
+
+    /// Base color and factors. The texture is given separately.
+    pub pbr: PBRInfo,
+
+    /// Index of the base color texture, if any
+    pub texture: Option<usize>,
+
+    pub emissive: Option<[f32; 3]>,
+    pub use_alpha: Option<bool>,
+}
+
+/// An entity waiting to be created
+#[derive(Debug, Clone, Default)]
+pub struct PreparedEntity {
+    pub name: Option<String>,
+
+    /// Index of the parent entity, which must come first
+    pub parent: Option<usize>,
+
+    pub transform: Option<[f32; 16]>,
+
+    /// Index of the mesh to show, if any
+    pub mesh: Option<usize>,
+}
+
+/// Everything an importer found, ready to become components
+#[derive(Default)]
+pub struct Prepared {
+    /// Assets published so far
+    pub published: Vec<uuid::Uuid>,
+
+    /// Name of each texture's image, and where it is served from
+    textures: Vec<(Option<String>, url::Url)>,
+
+    materials: Vec<PreparedMaterial>,
+
+    /// Meshes, with the index of their material
+    meshes: Vec<(PackedMesh, usize)>,
+
+    entities: Vec<PreparedEntity>,
+}
+
+impl Prepared {
+    /// Publish the bytes of a color texture, already converted to sRGB and
+    /// fitted to clients. Returns the index of the texture.
+    pub fn add_texture(
+        &mut self,
+        name: Option<String>,
+        bytes: &[u8],
+        source: &Path,
+        asset_store: &AssetStorePtr,
+        options: &ImportOptions,
+    ) -> usize {
+        let id = import::asset_id(source, bytes, options);
+        let url = import::add_asset(asset_store.clone(), id, bytes, AssetKind::Image, options);
+
+        self.published.push(id);
+        options.asset_sizes.tag_color_space(&id, ColorSpace::Srgb);
+
+        self.textures.push((name, url));
+        self.textures.len() - 1
+    }
+
+    /// Add a material. Returns its index.
+    pub fn add_material(&mut self, material: PreparedMaterial) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    /// Pack a mesh and publish its buffer. Returns the index of the mesh.
+    pub fn add_mesh(
+        &mut self,
+        mesh: &TriangleMesh,
+        material: usize,
+        source: &Path,
+        asset_store: &AssetStorePtr,
+        options: &ImportOptions,
+    ) -> usize {
+        let packed = mesh.publish(asset_store, source, options, &mut self.published);
+
+        self.meshes.push((packed, material));
+        self.meshes.len() - 1
+    }
+
+    /// Add an entity. Returns its index.
+    pub fn add_entity(&mut self, entity: PreparedEntity) -> usize {
+        self.entities.push(entity);
+        self.entities.len() - 1
+    }
+
+    /// Create the components, and a scene holding them. Entities are the
+    /// scene's parts, in the order they were added.
+    pub fn publish(self, state: &mut ServerState, asset_store: AssetStorePtr) -> Scene {
+        let textures: Vec<_> = self
+            .textures
+            .into_iter()
+            .map(|(name, url)| {
+                let image = state.images.new_component(ServerImageState {
+                    name,
+                    source: ImageSource::new_uri(url),
+                });
+
+                state.textures.new_component(ServerTextureState {
+                    name: None,
+                    image,
+                    sampler: None,
+                })
+            })
+            .collect();
+
+        let materials: Vec<_> = self
+            .materials
+            .into_iter()
+            .map(|f| {
+                let texture = f.texture.map(|t| ServerTextureRef {
+                    texture: textures[t].clone(),
+                    transform: None,
+                    texture_coord_slot: None,
+                });
+
+                state.materials.new_component(ServerMaterialState {
+                    name: f.name,
+                    mutable: ServerMaterialStateUpdatable {
+                        pbr_info: Some(PBRInfo {
+                            base_color_texture: texture,
+                            ..f.pbr
+                        }),
+                        emissive_factor: f.emissive,
+                        use_alpha: f.use_alpha,
+                        ..Default::default()
+                    },
+                })
+            })
+            .collect();
+
+        let meshes: Vec<_> = self
+            .meshes
+            .iter()
+            .map(|(mesh, material)| mesh.build_geometry(state, materials[*material].clone()))
+            .collect();
+
+        let mut parts: Vec<EntityReference> = Vec::with_capacity(self.entities.len());
+
+        for f in self.entities {
+            let entity = state.entities.new_component(ServerEntityState {
+                name: f.name,
+                mutable: ServerEntityStateUpdatable {
+                    parent: f.parent.map(|p| parts[p].clone()),
+                    transform: f.transform,
+                    representation: f.mesh.map(|m| {
+                        ServerEntityRepresentation::new_render(RenderRepresentation {
+                            mesh: meshes[m].clone(),
+                            instances: None,
+                        })
+                    }),
+                    ..Default::default()
+                },
+            });
+
+            parts.push(entity);
+        }
+
+        let mut scene = Scene::new(
+            SceneObject {
+                parts,
+                children: vec![],
+            },
+            self.published,
+            Some(asset_store),
+        );
+
+        scene.materials = materials;
+
+        scene
+    }
+}
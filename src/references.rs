@@ -0,0 +1,193 @@
+//! Watching of files that scenes reference.
+//!
+//! A glTF can keep its textures in files of their own. Editing one of those
+//! should show up live, without importing the whole scene again. Scenes list
+//! the files their images were read from; the directories holding them are
+//! watched here, and a change to one of the files asks the platter state to
+//! publish just that image again. Changes settle first, like new files in a
+//! watched directory, so a texture still being written is not published.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use colabrodo_server::server::tokio::{self, sync::mpsc};
+use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::dir_watcher::{Settle, WatcherOptions};
+use crate::platter_state::PlatterCommand;
+
+/// Referenced files, with the number of scenes using each
+type Files = Arc<Mutex<HashMap<PathBuf, usize>>>;
+
+/// Watches for changes to referenced files
+pub struct References {
+    watcher: Option<RecommendedWatcher>,
+    files: Files,
+}
+
+impl References {
+    /// Ask for a refresh through `tx` whenever a referenced file changes and
+    /// has settled, as `options` settle new files
+    pub fn new(tx: mpsc::Sender<PlatterCommand>, options: &WatcherOptions) -> Self {
+        let files = Files::default();
+        let watched = files.clone();
+
+        let settle = Settle::new(options);
+        let heed = settle.clone();
+
+        // Settling waits, so changed files are handed to a task
+        let (changed_tx, mut changed_rx) = mpsc::unbounded_channel::<PathBuf>();
+
+        tokio::spawn(async move {
+            while let Some(path) = changed_rx.recv().await {
+                let command = PlatterCommand::Refresh(path.clone());
+                settle.send(&tx, path, command, None).await;
+            }
+        });
+
+        let watcher = RecommendedWatcher::new(
+            move |result: notify::Result<Event>| {
+                let Ok(event) = result else {
+                    return;
+                };
+
+                if !heed.heeds(&event.kind) {
+                    return;
+                }
+
+                for path in event.paths {
+                    if !watched.lock().unwrap().contains_key(&path) {
+                        continue;
+                    }
+
+                    let _ = changed_tx.send(path);
+                }
+            },
+            Config::default(),
+        );
+
+        let watcher = match watcher {
+            Ok(f) => Some(f),
+            Err(e) => {
+                log::warn!("Unable to watch referenced files: {e}");
+                None
+            }
+        };
+
+        Self { watcher, files }
+    }
+
+    /// Number of referenced files in a directory
+    fn in_dir(files: &HashMap<PathBuf, usize>, dir: &Path) -> usize {
+        files.keys().filter(|f| f.parent() == Some(dir)).count()
+    }
+
+    /// Note a scene's use of a file, and watch it if it is new. Directories
+    /// are watched rather than files, so files replaced by a rename, as many
+    /// editors save, are still seen.
+    pub fn add(&mut self, path: &Path) {
+        let mut files = self.files.lock().unwrap();
+
+        let Some(dir) = path.parent() else {
+            return;
+        };
+
+        let first = Self::in_dir(&files, dir) == 0;
+
+        *files.entry(path.into()).or_default() += 1;
+
+        if let Some(watcher) = self.watcher.as_mut().filter(|_| first) {
+            log::debug!("Watching {} for referenced files", dir.display());
+
+            if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+                log::warn!("Unable to watch {}: {e}", dir.display());
+            }
+        }
+    }
+
+    /// Note that a scene no longer uses a file, and stop watching once
+    /// nothing in its directory is used
+    pub fn remove(&mut self, path: &Path) {
+        let mut files = self.files.lock().unwrap();
+
+        let Some(count) = files.get_mut(path) else {
+            return;
+        };
+
+        *count -= 1;
+
+        if *count > 0 {
+            return;
+        }
+
+        files.remove(path);
+
+        let Some(dir) = path.parent() else {
+            return;
+        };
+
+        if Self::in_dir(&files, dir) > 0 {
+            return;
+        }
+
+        if let Some(watcher) = self.watcher.as_mut() {
+            let _ = watcher.unwatch(dir);
+        }
+    }
+
+    /// Number of files watched
+    pub fn count(&self) -> usize {
+        self.files.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serial_test::serial;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_references() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let dir_path = dir.path().canonicalize().unwrap();
+        let texture = dir_path.join("albedo.png");
+        let other = dir_path.join("other.png");
+
+        std::fs::write(&texture, b"one").unwrap();
+        std::fs::write(&other, b"one").unwrap();
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut refs = References::new(tx, &WatcherOptions::default());
+
+        // Two scenes share the texture
+        refs.add(&texture);
+        refs.add(&texture);
+        assert_eq!(refs.count(), 1);
+
+        // Files nobody references are not reported
+        std::fs::write(&other, b"two").unwrap();
+        std::fs::write(&texture, b"two").unwrap();
+
+        let command = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap();
+        let Some(PlatterCommand::Refresh(path)) = command else {
+            panic!("Expected a refresh, got {command:?}");
+        };
+        assert_eq!(path, texture);
+
+        // The events of one write settle into one refresh
+        let more = tokio::time::timeout(std::time::Duration::from_secs(1), rx.recv()).await;
+        assert!(more.is_err(), "Unexpected {more:?}");
+
+        refs.remove(&texture);
+        assert_eq!(refs.count(), 1);
+        refs.remove(&texture);
+        assert_eq!(refs.count(), 0);
+
+        // Unknown files are ignored
+        refs.remove(&other);
+    }
+}
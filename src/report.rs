@@ -0,0 +1,138 @@
+//! Periodic self-report.
+//!
+//! Every so often, log a line with memory use, published asset bytes, scene
+//! and task counts, and how full our queues are. Runaway CPU or memory can
+//! then be lined up with the workload at the time from plain logs.
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use colabrodo_server::server::tokio;
+use tokio::sync::mpsc::Sender;
+
+use crate::arguments::Directory;
+use crate::import::AssetSizes;
+use crate::platter_state::PlatterCommand;
+use crate::scheduler::Scheduler;
+
+/// What the report reads from
+pub struct Sources {
+    /// Number of live scenes, kept up to date by the platter state
+    pub scenes: Arc<AtomicUsize>,
+    pub asset_sizes: AssetSizes,
+    pub commands: Sender<PlatterCommand>,
+    pub watch_requests: Sender<Directory>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Report {
+    rss: Option<u64>,
+    asset_bytes: u64,
+    scenes: usize,
+    tasks: usize,
+    /// Depth and capacity of the command and watch request queues
+    commands: (usize, usize),
+    watch_requests: (usize, usize),
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mib = |b: u64| b as f64 / (1024.0 * 1024.0);
+
+        match self.rss {
+            Some(rss) => write!(f, "rss {:.1} MiB", mib(rss))?,
+            None => write!(f, "rss unknown")?,
+        }
+
+        write!(
+            f,
+            ", assets {:.1} MiB, {} scenes, {} tasks, queues: commands {}/{}, watch {}/{}",
+            mib(self.asset_bytes),
+            self.scenes,
+            self.tasks,
+            self.commands.0,
+            self.commands.1,
+            self.watch_requests.0,
+            self.watch_requests.1,
+        )
+    }
+}
+
+/// Items waiting in a bounded queue, and its capacity
+pub fn depth<T>(tx: &Sender<T>) -> (usize, usize) {
+    (tx.max_capacity() - tx.capacity(), tx.max_capacity())
+}
+
+/// Resident set size from the text of `/proc/self/status`
+fn parse_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|f| f.starts_with("VmRSS:"))?;
+    let mut parts = line["VmRSS:".len()..].split_whitespace();
+
+    let value: u64 = parts.next()?.parse().ok()?;
+
+    match parts.next()? {
+        "kB" => Some(value * 1024),
+        _ => None,
+    }
+}
+
+/// Resident set size of this process. Only known on Linux.
+fn rss() -> Option<u64> {
+    parse_rss(&std::fs::read_to_string("/proc/self/status").ok()?)
+}
+
+/// Log a report every `period`
+pub fn start(scheduler: &Scheduler, period: Duration, sources: Sources) {
+    log::info!("Reporting resource use every {period:?}");
+
+    let runtime = tokio::runtime::Handle::current();
+
+    scheduler.every(period, move || {
+        let report = Report {
+            rss: rss(),
+            asset_bytes: sources.asset_sizes.total(),
+            scenes: sources.scenes.load(Ordering::Relaxed),
+            tasks: runtime.metrics().num_alive_tasks(),
+            commands: depth(&sources.commands),
+            watch_requests: depth(&sources.watch_requests),
+        };
+
+        log::info!("Self report: {report}");
+
+        // Stop once everything else has shut down
+        !sources.commands.is_closed()
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let status = "Name:\tplatter\nVmPeak:\t  9000 kB\nVmRSS:\t    2048 kB\nThreads:\t4\n";
+        assert_eq!(parse_rss(status), Some(2 * 1024 * 1024));
+        assert_eq!(parse_rss("Name:\tplatter\n"), None);
+        assert_eq!(parse_rss("VmRSS:\tlots\n"), None);
+
+        let report = Report {
+            rss: Some(3 * 1024 * 1024),
+            asset_bytes: 512 * 1024,
+            scenes: 2,
+            tasks: 9,
+            commands: (1, 32),
+            watch_requests: (0, 8),
+        };
+
+        assert_eq!(
+            report.to_string(),
+            "rss 3.0 MiB, assets 0.5 MiB, 2 scenes, 9 tasks, queues: commands 1/32, watch 0/8"
+        );
+
+        let (tx, _rx) = tokio::sync::mpsc::channel::<u32>(4);
+        tx.try_send(1).unwrap();
+        assert_eq!(depth(&tx), (1, 4));
+    }
+}
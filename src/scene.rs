@@ -1,5 +1,239 @@
-use colabrodo_server::{server_http::*, server_messages::*};
-use nalgebra::{Matrix4, Quaternion, Scale3, Translation3, UnitQuaternion, Vector3};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use colabrodo_server::{server_http::*, server_messages::*, server_state::ServerState};
+use nalgebra::{
+    Matrix4, Point3, Quaternion, Scale3, Translation3, UnitQuaternion, Vector3, Vector4,
+};
+
+use crate::coalesce::Coalescer;
+use crate::color::ColorSpace;
+use crate::dir_watcher::Stamp;
+use crate::geometry::Cleanup;
+use crate::scratch::ScratchDir;
+use crate::shadow;
+
+/// Most clip planes a scene may have. Clients commonly support six.
+pub const MAX_CLIP_PLANES: usize = 6;
+
+/// An axis-aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Bounds {
+    /// Create a bounding box that contains a single point
+    pub fn new(p: Vector3<f32>) -> Self {
+        Self { min: p, max: p }
+    }
+
+    /// Compute bounds for a list of points. Returns None if there are no points.
+    pub fn from_points<'a>(mut iter: impl Iterator<Item = &'a [f32; 3]>) -> Option<Self> {
+        let mut ret = Self::new((*iter.next()?).into());
+
+        for p in iter {
+            ret.extend(&(*p).into());
+        }
+
+        Some(ret)
+    }
+
+    /// Grow the box to contain a point
+    pub fn extend(&mut self, p: &Vector3<f32>) {
+        self.min = self.min.inf(p);
+        self.max = self.max.sup(p);
+    }
+
+    /// Combine two boxes
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    /// Size of the box along each axis
+    pub fn extent(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    /// Center of the box
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
+
+    /// Transform the box, returning a new box that contains the transformed corners
+    pub fn transformed(&self, tf: &Matrix4<f32>) -> Bounds {
+        let corner = |i: usize| {
+            let p = Point3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            );
+            tf.transform_point(&p).coords
+        };
+
+        let mut ret = Bounds::new(corner(0));
+
+        for i in 1..8 {
+            ret.extend(&corner(i));
+        }
+
+        ret
+    }
+}
+
+/// Descriptive information about a scene and where it came from
+#[derive(Debug, Clone, Default)]
+pub struct SceneInfo {
+    /// File this scene was imported from
+    pub source: Option<PathBuf>,
+
+    /// Name of the format of the source file
+    pub format: Option<String>,
+
+    /// Our best guess as to the units the content is authored in
+    pub units: Option<String>,
+
+    /// Bounds of the content, in scene coordinates
+    pub bounds: Option<Bounds>,
+
+    /// Number of triangles in the scene
+    pub triangles: u64,
+
+    /// Triangles dropped while packing
+    pub cleanup: Cleanup,
+
+    /// When the scene was imported
+    pub imported: Option<SystemTime>,
+
+    /// Name given to the scene by a manifest
+    pub name: Option<String>,
+
+    /// Group given to the scene by a manifest
+    pub group: Option<String>,
+}
+
+impl SceneInfo {
+    /// Encode this information as a list of entity tags.
+    ///
+    /// NOODLES entities do not have a free-form data field, so we use
+    /// `platter.key=value` tags instead.
+    pub fn to_tags(&self) -> Vec<String> {
+        let mut ret = Vec::new();
+
+        if let Some(source) = &self.source {
+            ret.push(format!("platter.source={}", source.display()));
+        }
+
+        if let Some(format) = &self.format {
+            ret.push(format!("platter.format={format}"));
+        }
+
+        if let Some(units) = &self.units {
+            ret.push(format!("platter.units={units}"));
+        }
+
+        if let Some(b) = &self.bounds {
+            ret.push(format!(
+                "platter.bounds_min={},{},{}",
+                b.min.x, b.min.y, b.min.z
+            ));
+            ret.push(format!(
+                "platter.bounds_max={},{},{}",
+                b.max.x, b.max.y, b.max.z
+            ));
+        }
+
+        ret.push(format!("platter.triangles={}", self.triangles));
+
+        if !self.cleanup.is_empty() {
+            ret.push(format!("platter.degenerate={}", self.cleanup.degenerate));
+            ret.push(format!("platter.duplicate={}", self.cleanup.duplicate));
+            ret.push(format!("platter.welded={}", self.cleanup.welded));
+            ret.push(format!("platter.nonfinite={}", self.cleanup.nonfinite));
+        }
+
+        if let Some(t) = self
+            .imported
+            .and_then(|f| f.duration_since(SystemTime::UNIX_EPOCH).ok())
+        {
+            ret.push(format!("platter.imported={}", t.as_secs()));
+        }
+
+        if let Some(name) = &self.name {
+            ret.push(format!("platter.name={name}"));
+        }
+
+        if let Some(group) = &self.group {
+            ret.push(format!("platter.group={group}"));
+        }
+
+        ret
+    }
+}
+
+/// An authored camera position in a scene
+#[derive(Debug, Clone)]
+pub struct Viewpoint {
+    pub name: Option<String>,
+
+    /// Camera transform, relative to the scene root
+    pub transform: Matrix4<f32>,
+
+    /// Vertical field of view in radians, for perspective cameras
+    pub yfov: Option<f32>,
+}
+
+/// An image read from a file outside the scene's source, such as a texture
+/// beside a glTF. The file is watched, and the image published again when it
+/// changes.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    /// Canonical path of the file
+    pub path: PathBuf,
+
+    /// The file as it was when last published
+    pub stamp: Option<Stamp>,
+
+    pub image: ImageReference,
+
+    /// Asset holding the file's bytes
+    pub asset: uuid::Uuid,
+
+    /// Textures showing the image
+    pub textures: Vec<TextureReference>,
+
+    pub color_space: ColorSpace,
+}
+
+/// Client changes to the look of a scene, applied over its own materials
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MaterialOverrides {
+    /// Replaces the RGB of every base color. Textures are kept, and tinted.
+    pub base_color: Option<[f32; 3]>,
+
+    /// Multiplies the alpha of every base color
+    pub opacity: Option<f32>,
+
+    /// NOODLES materials have no wireframe flag, so this is advertised with
+    /// a tag on the root entity instead
+    pub wireframe: bool,
+}
+
+impl MaterialOverrides {
+    /// Base color and alpha blending for a material, given its own
+    fn apply(&self, color: [f32; 4], use_alpha: Option<bool>) -> ([f32; 4], Option<bool>) {
+        let [r, g, b] = self.base_color.unwrap_or([color[0], color[1], color[2]]);
+        let a = color[3] * self.opacity.unwrap_or(1.0);
+
+        let use_alpha = if a < 1.0 { Some(true) } else { use_alpha };
+
+        ([r, g, b, a], use_alpha)
+    }
+}
 
 /// A scene; a collection of renderable objects
 pub struct Scene {
@@ -7,6 +241,17 @@ pub struct Scene {
     rotation: UnitQuaternion<f32>,
     scale: Scale3<f32>,
 
+    /// Axis and unit conversion, applied before everything else
+    conversion: Matrix4<f32>,
+
+    /// Offset, rotation, and rescale from platter's options, applied after
+    /// the conversion and before the client's transform above
+    placement: Matrix4<f32>,
+
+    /// Turntable angle in radians, about a vertical axis through the center
+    /// of the placed content
+    spin: f32,
+
     /// A list of related binary assets published on the http server
     pub published: Vec<uuid::Uuid>,
 
@@ -15,6 +260,43 @@ pub struct Scene {
 
     /// A reference to the http server. Needed when we drop to unpublish assets.
     asset_store: Option<AssetStorePtr>,
+
+    /// Metadata about this scene
+    pub info: SceneInfo,
+
+    /// Camera positions provided by the source file
+    pub viewpoints: Vec<Viewpoint>,
+
+    /// Temporary files used by this scene, removed with it
+    pub scratch: Vec<ScratchDir>,
+
+    /// Rate limiter for transform updates, if any
+    coalescer: Option<Coalescer>,
+
+    /// Materials used by this scene, filled in by importers
+    pub materials: Vec<MaterialReference>,
+
+    /// Client changes to those materials
+    overrides: MaterialOverrides,
+
+    /// Base color and alpha blending of each material before any overrides
+    originals: Vec<(PBRInfo, Option<bool>)>,
+
+    /// Set if this is the scene clients are asked to look at
+    highlighted: bool,
+
+    /// Clip planes as normal and offset, in the coordinates of the root
+    /// entity
+    clip_planes: Vec<[f32; 4]>,
+
+    /// Set if clients are asked not to draw this scene
+    hidden: bool,
+
+    /// Files images were read from, filled in by importers
+    pub references: Vec<Reference>,
+
+    /// Ground shadow, kept under the content in world space
+    pub shadow: Option<EntityReference>,
 }
 
 /// Some file formats have a heirarchy. Some don't. This tries to cater to both.
@@ -28,6 +310,17 @@ pub struct SceneObject {
     pub children: Vec<SceneObject>,
 }
 
+impl SceneObject {
+    /// Collect every entity at this level and below
+    pub fn all_parts(&self) -> Vec<EntityReference> {
+        let mut ret = self.parts.clone();
+        for child in &self.children {
+            ret.extend(child.all_parts());
+        }
+        ret
+    }
+}
+
 impl Drop for Scene {
     fn drop(&mut self) {
         if let Some(ptr) = &self.asset_store {
@@ -49,12 +342,302 @@ impl Scene {
             position: Translation3::identity(),
             rotation: UnitQuaternion::identity(),
             scale: Scale3::identity(),
+            conversion: Matrix4::identity(),
+            placement: Matrix4::identity(),
+            spin: 0.0,
             published: assets,
             root,
             asset_store,
+            info: SceneInfo::default(),
+            viewpoints: Vec::new(),
+            scratch: Vec::new(),
+            coalescer: None,
+            materials: Vec::new(),
+            overrides: MaterialOverrides::default(),
+            originals: Vec::new(),
+            highlighted: false,
+            clip_planes: Vec::new(),
+            hidden: false,
+            references: Vec::new(),
+            shadow: None,
+        }
+    }
+
+    /// Send later transform updates through a rate limiter
+    pub fn set_coalescer(&mut self, coalescer: Coalescer) {
+        self.coalescer = Some(coalescer);
+    }
+
+    /// Publish scene metadata to clients as tags on the root entity
+    pub fn publish_info(&self) {
+        if let Some(first) = self.root.parts.first() {
+            let mut tags = self.info.to_tags();
+
+            if self.overrides.wireframe {
+                tags.push("platter.render=wireframe".into());
+            }
+
+            if self.highlighted {
+                tags.push("platter.highlight".into());
+            }
+
+            for [x, y, z, d] in &self.clip_planes {
+                tags.push(format!("platter.clip_plane={x},{y},{z},{d}"));
+            }
+
+            ServerEntityStateUpdatable {
+                tags: Some(tags),
+                ..Default::default()
+            }
+            .patch(first);
+        }
+    }
+
+    /// Client changes to this scene's materials
+    pub fn overrides(&self) -> MaterialOverrides {
+        self.overrides
+    }
+
+    /// Change the look of this scene, and send the changed materials.
+    pub fn set_overrides(&mut self, state: &ServerState, overrides: MaterialOverrides) {
+        let wireframe = overrides.wireframe != self.overrides.wireframe;
+
+        // Remember what the materials looked like before we touched them
+        if self.originals.len() != self.materials.len() {
+            self.originals = self
+                .materials
+                .iter()
+                .map(|m| {
+                    state
+                        .materials
+                        .inspect(m.id(), |f| {
+                            (f.mutable.pbr_info.clone(), f.mutable.use_alpha)
+                        })
+                        .and_then(|(pbr, alpha)| Some((pbr?, alpha)))
+                        .unwrap_or_else(|| {
+                            // Without PBR info, clients use plain white
+                            let pbr = PBRInfo {
+                                base_color: [1.0; 4],
+                                ..Default::default()
+                            };
+                            (pbr, None)
+                        })
+                })
+                .collect();
+        }
+
+        self.overrides = overrides;
+
+        for (m, (pbr, use_alpha)) in self.materials.iter().zip(&self.originals) {
+            let (base_color, use_alpha) = overrides.apply(pbr.base_color, *use_alpha);
+
+            ServerMaterialStateUpdatable {
+                pbr_info: Some(PBRInfo {
+                    base_color,
+                    ..pbr.clone()
+                }),
+                use_alpha,
+                ..Default::default()
+            }
+            .patch(m);
+        }
+
+        if wireframe {
+            self.publish_info();
+        }
+    }
+
+    /// Clip planes of this scene, in the coordinates of the root entity
+    pub fn clip_planes(&self) -> &[[f32; 4]] {
+        &self.clip_planes
+    }
+
+    /// Replace every clip plane, and tell clients
+    pub fn set_clip_planes(&mut self, planes: Vec<[f32; 4]>) {
+        if planes != self.clip_planes {
+            self.clip_planes = planes;
+            self.publish_info();
+        }
+    }
+
+    /// Cut away content where `dot(normal, p) > offset`, for a plane given
+    /// in world coordinates as the scene is placed now. The plane is kept
+    /// relative to the scene, so it moves with it, and replaces any plane
+    /// facing the same way. Returns false for a zero normal, or if the scene
+    /// already has the most planes it may.
+    pub fn set_clip_plane(&mut self, normal: Vector3<f32>, offset: f32) -> bool {
+        // Planes transform by the inverse transpose; as a row vector, that
+        // is the plane times the transform
+        let world = Vector4::new(normal.x, normal.y, normal.z, -offset);
+        let local = self.transform().transpose() * world;
+
+        let len = local.xyz().norm();
+
+        if !(len > 1e-6 && len.is_finite()) {
+            return false;
+        }
+
+        let local = local / len;
+        let plane = [local.x, local.y, local.z, -local.w];
+
+        let mut planes = self.clip_planes.clone();
+
+        match planes
+            .iter()
+            .position(|p| Vector3::new(p[0], p[1], p[2]).dot(&local.xyz()) > 0.9999)
+        {
+            Some(i) => planes[i] = plane,
+            None if planes.len() < MAX_CLIP_PLANES => planes.push(plane),
+            None => return false,
+        }
+
+        self.set_clip_planes(planes);
+
+        true
+    }
+
+    /// Are clients asked not to draw this scene?
+    pub fn hidden(&self) -> bool {
+        self.hidden
+    }
+
+    /// Hide or show the root entities of this scene. Sent even if nothing
+    /// changed, as copied entities may not match.
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+
+        for part in &self.root.parts {
+            ServerEntityStateUpdatable {
+                visible: Some(!hidden),
+                ..Default::default()
+            }
+            .patch(part);
+        }
+    }
+
+    /// Is this the scene clients are asked to look at?
+    pub fn highlighted(&self) -> bool {
+        self.highlighted
+    }
+
+    /// Mark this scene, with a tag on the root entity, as the one clients
+    /// should draw attention to
+    pub fn set_highlighted(&mut self, on: bool) {
+        if on != self.highlighted {
+            self.highlighted = on;
+            self.publish_info();
         }
     }
 
+    /// Take the overrides of the scene our materials were copied from. The
+    /// copies are in the order of its materials.
+    pub fn copy_materials(&mut self, other: &Scene, materials: Vec<MaterialReference>) {
+        self.materials = materials;
+        self.overrides = other.overrides;
+        self.originals = other.originals.clone();
+    }
+
+    /// Point materials at new textures in place of old ones, such as when an
+    /// image file changes.
+    pub fn replace_textures(
+        &mut self,
+        state: &ServerState,
+        swaps: &[(TextureReference, TextureReference)],
+    ) {
+        let swap = |slot: &mut Option<ServerTextureRef>| {
+            let Some(tex) = slot else {
+                return false;
+            };
+
+            let Some((_, new)) = swaps.iter().find(|(old, _)| *old == tex.texture) else {
+                return false;
+            };
+
+            tex.texture = new.clone();
+            true
+        };
+
+        for m in &self.materials {
+            let Some(mut current) = state.materials.inspect(m.id(), |f| f.mutable.clone()) else {
+                continue;
+            };
+
+            let mut update = ServerMaterialStateUpdatable::default();
+            let mut changed = false;
+
+            if let Some(pbr) = current.pbr_info.as_mut() {
+                if swap(&mut pbr.base_color_texture) | swap(&mut pbr.metal_rough_texture) {
+                    update.pbr_info = current.pbr_info;
+                    changed = true;
+                }
+            }
+
+            for (slot, out) in [
+                (&mut current.normal_texture, &mut update.normal_texture),
+                (
+                    &mut current.occlusion_texture,
+                    &mut update.occlusion_texture,
+                ),
+                (&mut current.emissive_texture, &mut update.emissive_texture),
+            ] {
+                if swap(slot) {
+                    *out = slot.take();
+                    changed = true;
+                }
+            }
+
+            if changed {
+                update.patch(m);
+            }
+        }
+
+        // Overrides are applied over the originals, so they must follow too
+        for (pbr, _) in &mut self.originals {
+            swap(&mut pbr.base_color_texture);
+            swap(&mut pbr.metal_rough_texture);
+        }
+    }
+
+    /// Attach a text label above the scene, parented to the root so it
+    /// follows the scene around. The label is tagged as a helper so clients
+    /// can hide it.
+    pub fn attach_label(&mut self, state: &mut ServerState, text: String) {
+        let Some(root) = self.root.parts.first().cloned() else {
+            return;
+        };
+
+        // Without bounds we can only put it at the origin
+        let bounds = self.info.bounds.unwrap_or(Bounds::new(Vector3::zeros()));
+
+        let extent = bounds.extent().max();
+        let height = if extent > 0.0 { extent * 0.05 } else { 0.1 };
+
+        let center = bounds.center();
+        let position = Vector3::new(center.x, bounds.max.y + height * 2.0, center.z);
+
+        let tf = Matrix4::new_translation(&position);
+
+        let label = state.entities.new_component(ServerEntityState {
+            name: Some(format!("{text} label")),
+            mutable: ServerEntityStateUpdatable {
+                parent: Some(root),
+                transform: Some(tf.as_slice().try_into().unwrap()),
+                representation: Some(ServerEntityRepresentation::new_text(
+                    ServerTextRepresentation {
+                        txt: text,
+                        font: None,
+                        height: Some(height),
+                        width: None,
+                    },
+                )),
+                tags: Some(vec!["platter.helper=label".into()]),
+                ..Default::default()
+            },
+        });
+
+        self.root.parts.push(label);
+    }
+
     /// Update the position of this scene
     pub fn set_position(&mut self, p: Vector3<f32>) {
         log::debug!("Setting position: {p:?}");
@@ -76,27 +659,145 @@ impl Scene {
         self.update_transform();
     }
 
-    /// Refresh the transformation matrix of this scene
-    pub fn update_transform(&mut self) -> Matrix4<f32> {
-        log::debug!("Update object transform with: {:?}", self.scale);
+    /// Update any of position, rotation, and scale, sending a single update
+    pub fn set_transform_parts(
+        &mut self,
+        position: Option<Vector3<f32>>,
+        rotation: Option<Quaternion<f32>>,
+        scale: Option<Vector3<f32>>,
+    ) {
+        if let Some(p) = position {
+            self.position = Translation3::new(p.x, p.y, p.z);
+        }
+        if let Some(q) = rotation {
+            self.rotation = UnitQuaternion::from_quaternion(q);
+        }
+        if let Some(s) = scale {
+            self.scale = Scale3::new(s.x, s.y, s.z);
+        }
+        self.update_transform();
+    }
+
+    /// Take the client transform and placement of another scene, such as
+    /// the one this replaces. The conversion stays, as it depends on content.
+    pub fn copy_transform(&mut self, other: &Scene) {
+        self.position = other.position;
+        self.rotation = other.rotation;
+        self.scale = other.scale;
+        self.placement = other.placement;
+        self.spin = other.spin;
+        self.update_transform();
+    }
+
+    /// Position set by clients
+    pub fn position(&self) -> Vector3<f32> {
+        self.position.vector
+    }
+
+    /// Axis and unit conversion for this scene's content
+    pub fn conversion(&self) -> Matrix4<f32> {
+        self.conversion
+    }
+
+    /// Set the axis and unit conversion for this scene's content
+    pub fn set_conversion(&mut self, m: Matrix4<f32>) {
+        if m == self.conversion {
+            return;
+        }
+        log::debug!("Setting conversion: {m:?}");
+        self.conversion = m;
+        self.update_transform();
+    }
+
+    /// Set the placement from platter's options. Client edits to position,
+    /// rotation, and scale are kept, and apply on top of it.
+    pub fn set_placement(&mut self, m: Matrix4<f32>) {
+        if m == self.placement {
+            return;
+        }
+        log::debug!("Setting placement: {m:?}");
+        self.placement = m;
+        self.update_transform();
+    }
+
+    /// Set the turntable angle. This spins the scene in place, and leaves
+    /// the client's transform alone.
+    pub fn set_spin(&mut self, angle: f32) {
+        if angle == self.spin {
+            return;
+        }
+        self.spin = angle;
+        self.update_transform();
+    }
+
+    /// Turntable rotation, about the center of the placed content
+    fn spin_matrix(&self) -> Matrix4<f32> {
+        if self.spin == 0.0 {
+            return Matrix4::identity();
+        }
+
+        let placed = self.placement * self.conversion;
+
+        let center = match self.info.bounds {
+            Some(b) => b.transformed(&placed).center(),
+            None => placed.transform_point(&Point3::origin()).coords,
+        };
+
+        Matrix4::new_translation(&center)
+            * Matrix4::from_axis_angle(&Vector3::y_axis(), self.spin)
+            * Matrix4::new_translation(&-center)
+    }
+
+    /// Full transform from content to world coordinates
+    pub fn transform(&self) -> Matrix4<f32> {
         let scale = self.scale.to_homogeneous();
         let rotation = self.rotation.to_homogeneous();
         let translate = self.position.to_homogeneous();
 
         //let iso = Isometry3::from_parts(self.position, self.rotation);
-        let tf = translate * rotation * scale;
+        translate * rotation * scale * self.spin_matrix() * self.placement * self.conversion
+    }
+
+    /// Bounds of the content as currently placed, in world coordinates
+    pub fn world_bounds(&self) -> Option<Bounds> {
+        self.info.bounds.map(|f| f.transformed(&self.transform()))
+    }
+
+    /// Refresh the transformation matrix of this scene
+    pub fn update_transform(&mut self) -> Matrix4<f32> {
+        log::debug!("Update object transform with: {:?}", self.scale);
+        let tf = self.transform();
 
         if log::log_enabled!(log::Level::Debug) {
             log::debug!("Update object transform: {tf:?}");
         }
 
-        if let Some(first) = self.root.parts.first() {
-            let update = ServerEntityStateUpdatable {
-                transform: Some(tf.as_slice().try_into().unwrap()),
-                ..Default::default()
-            };
+        let shadow = self.shadow.as_ref().map(|f| {
+            let world = self.world_bounds().unwrap_or(Bounds::new(Vector3::zeros()));
+            (f, shadow::placement(&world))
+        });
 
-            update.patch(first);
+        for (entity, tf) in self
+            .root
+            .parts
+            .first()
+            .map(|f| (f, tf))
+            .into_iter()
+            .chain(shadow)
+        {
+            let transform: [f32; 16] = tf.as_slice().try_into().unwrap();
+
+            match &self.coalescer {
+                Some(c) => c.submit(entity, transform),
+                None => {
+                    let update = ServerEntityStateUpdatable {
+                        transform: Some(transform),
+                        ..Default::default()
+                    };
+
+                    update.patch(entity);
+                }
+            }
         }
 
         tf
@@ -105,10 +806,172 @@ impl Scene {
 
 #[cfg(test)]
 mod test {
-    use super::Scene;
+    use super::{Bounds, MaterialOverrides, Scene, SceneInfo};
     use approx::assert_relative_eq;
     use nalgebra::{point, vector, Matrix4, Quaternion};
 
+    #[test]
+    fn test_bounds() {
+        let b = Bounds::from_points([[1.0, 2.0, 3.0], [-1.0, 5.0, 0.0]].iter()).unwrap();
+
+        assert_eq!(b.min, vector![-1.0, 2.0, 0.0]);
+        assert_eq!(b.max, vector![1.0, 5.0, 3.0]);
+        assert_eq!(b.center(), vector![0.0, 3.5, 1.5]);
+
+        let moved = b.transformed(&Matrix4::new_translation(&vector![1.0, 1.0, 1.0]));
+
+        assert_eq!(moved.min, vector![0.0, 3.0, 1.0]);
+        assert_eq!(moved.max, vector![2.0, 6.0, 4.0]);
+
+        assert!(Bounds::from_points(std::iter::empty()).is_none());
+    }
+
+    #[test]
+    fn test_world_bounds() {
+        let mut s = Scene::new(
+            super::SceneObject {
+                parts: Vec::new(),
+                children: Vec::new(),
+            },
+            Vec::new(),
+            None,
+        );
+
+        assert!(s.world_bounds().is_none());
+
+        s.info.bounds = Bounds::from_points([[0.0, 0.0, 0.0], [1.0, 2.0, 3.0]].iter());
+
+        s.set_conversion(Matrix4::new_scaling(0.5));
+        s.set_scale(vector![2.0, 2.0, 2.0]);
+        s.set_position(vector![10.0, 0.0, 0.0]);
+
+        let b = s.world_bounds().unwrap();
+
+        assert_relative_eq!(b.min, vector![10.0, 0.0, 0.0]);
+        assert_relative_eq!(b.max, vector![11.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let color = [0.2, 0.4, 0.6, 1.0];
+
+        let none = MaterialOverrides::default();
+        assert_eq!(none.apply(color, None), (color, None));
+
+        let ghost = MaterialOverrides {
+            opacity: Some(0.25),
+            ..Default::default()
+        };
+        assert_eq!(
+            ghost.apply(color, Some(false)),
+            ([0.2, 0.4, 0.6, 0.25], Some(true))
+        );
+
+        let red = MaterialOverrides {
+            base_color: Some([1.0, 0.0, 0.0]),
+            ..Default::default()
+        };
+        assert_eq!(
+            red.apply([0.2, 0.4, 0.6, 0.5], Some(true)).0,
+            [1.0, 0.0, 0.0, 0.5]
+        );
+    }
+
+    #[test]
+    fn test_clip_planes() {
+        let mut s = Scene::new(
+            super::SceneObject {
+                parts: Vec::new(),
+                children: Vec::new(),
+            },
+            Vec::new(),
+            None,
+        );
+
+        s.set_conversion(Matrix4::new_scaling(0.5));
+        s.set_position(vector![0.0, 1.0, 0.0]);
+
+        // Cut everything above y = 2 in the world, which is y = 2 in
+        // content
+        assert!(s.set_clip_plane(vector![0.0, 3.0, 0.0], 6.0));
+        assert_eq!(s.clip_planes().len(), 1);
+
+        let [x, y, z, d] = s.clip_planes()[0];
+        assert_relative_eq!(vector![x, y, z], vector![0.0, 1.0, 0.0]);
+        assert_relative_eq!(d, 2.0);
+
+        // The same way again replaces it
+        assert!(s.set_clip_plane(vector![0.0, 1.0, 0.0], 1.0));
+        assert_eq!(s.clip_planes().len(), 1);
+        assert_relative_eq!(s.clip_planes()[0][3], 0.0);
+
+        assert!(!s.set_clip_plane(vector![0.0, 0.0, 0.0], 1.0));
+
+        for i in 1..super::MAX_CLIP_PLANES {
+            assert!(s.set_clip_plane(vector![1.0, i as f32, 0.0], 0.0));
+        }
+        assert!(!s.set_clip_plane(vector![-1.0, 0.0, 0.0], 0.0));
+
+        s.set_clip_planes(Vec::new());
+        assert!(s.clip_planes().is_empty());
+    }
+
+    #[test]
+    fn test_info_tags() {
+        let info = SceneInfo {
+            format: Some("obj".into()),
+            bounds: Bounds::from_points([[0.0, 0.0, 0.0], [1.0, 2.0, 3.0]].iter()),
+            triangles: 12,
+            group: Some("parts".into()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            info.to_tags(),
+            vec![
+                "platter.format=obj",
+                "platter.bounds_min=0,0,0",
+                "platter.bounds_max=1,2,3",
+                "platter.triangles=12",
+                "platter.group=parts",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_placement() {
+        let mut s = Scene::new(
+            super::SceneObject {
+                parts: Vec::new(),
+                children: Vec::new(),
+            },
+            Vec::new(),
+            None,
+        );
+
+        s.set_position(vector![1.0, 0.0, 0.0]);
+        s.set_placement(Matrix4::new_scaling(2.0));
+
+        let p = s.update_transform().transform_point(&point![1.0, 1.0, 1.0]);
+        assert_relative_eq!(p, point![3.0, 2.0, 2.0]);
+
+        // The client's edit survives a new placement
+        s.set_placement(Matrix4::new_translation(&vector![0.0, 5.0, 0.0]));
+
+        let p = s.update_transform().transform_point(&point![1.0, 1.0, 1.0]);
+        assert_relative_eq!(p, point![2.0, 6.0, 1.0]);
+
+        // A turntable spins the content in place
+        s.info.bounds = Bounds::from_points([[0.0, 0.0, 0.0], [2.0, 2.0, 4.0]].iter());
+        let before = s.world_bounds().unwrap();
+
+        s.set_spin(std::f32::consts::FRAC_PI_2);
+        let after = s.world_bounds().unwrap();
+
+        assert_relative_eq!(before.center(), after.center(), epsilon = 1e-5);
+        assert_relative_eq!(after.extent(), vector![4.0, 2.0, 2.0], epsilon = 1e-5);
+    }
+
     #[test]
     fn test_scene_transforms() {
         let mut s = Scene::new(
@@ -0,0 +1,236 @@
+//! A single timer task for everything periodic or delayed in platter.
+//!
+//! Rather than each feature spawning its own interval, timers are kept in one
+//! queue and run from one task. That task sleeps until the next deadline, or
+//! indefinitely when there is nothing to do, and deadlines that fall close
+//! together run in the same wakeup. In low power mode delays are stretched
+//! and grouped more coarsely, so idle instances wake rarely.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::Duration;
+
+use colabrodo_server::server::tokio;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Delays are multiplied by this in low power mode
+const LOW_POWER_FACTOR: u32 = 4;
+
+/// Timers due within this much of each other run in one wakeup
+const SLACK: Duration = Duration::from_millis(5);
+const LOW_POWER_SLACK: Duration = Duration::from_secs(1);
+
+/// Work to run. Periodic tasks return false to stop.
+type Task = Box<dyn FnMut() -> bool + Send>;
+
+struct Timer {
+    due: Instant,
+    period: Option<Duration>,
+    task: Task,
+}
+
+/// Pending timers, soonest first
+#[derive(Default)]
+struct TimerQueue {
+    /// Deadline and insertion order, so equal deadlines run in order
+    heap: BinaryHeap<Reverse<(Instant, u64)>>,
+    timers: HashMap<u64, Timer>,
+    next_id: u64,
+}
+
+impl TimerQueue {
+    fn push(&mut self, timer: Timer) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.heap.push(Reverse((timer.due, id)));
+        self.timers.insert(id, timer);
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.heap.peek().map(|f| f.0 .0)
+    }
+
+    /// Run everything due by `now + slack`, rescheduling periodic timers
+    fn run_due(&mut self, now: Instant, slack: Duration) -> usize {
+        let mut ran = 0;
+        let mut again = Vec::new();
+
+        while let Some(Reverse((due, id))) = self.heap.peek().copied() {
+            if due > now + slack {
+                break;
+            }
+
+            self.heap.pop();
+
+            let Some(mut timer) = self.timers.remove(&id) else {
+                continue;
+            };
+
+            ran += 1;
+
+            let keep = (timer.task)();
+
+            if let (true, Some(period)) = (keep, timer.period) {
+                // Skip missed ticks rather than running a burst to catch up
+                timer.due = (due + period).max(now);
+                again.push(timer);
+            }
+        }
+
+        for timer in again {
+            self.push(timer);
+        }
+
+        ran
+    }
+}
+
+/// Handle to the timer task
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    tx: mpsc::UnboundedSender<Timer>,
+    low_power: bool,
+}
+
+impl Scheduler {
+    /// Start the timer task
+    pub fn new(low_power: bool) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Timer>();
+
+        let slack = if low_power { LOW_POWER_SLACK } else { SLACK };
+
+        if low_power {
+            log::info!("Low power mode: timers are stretched by {LOW_POWER_FACTOR}x");
+        }
+
+        tokio::spawn(async move {
+            let mut queue = TimerQueue::default();
+
+            loop {
+                let next = queue.next_due();
+
+                tokio::select! {
+                    timer = rx.recv() => match timer {
+                        Some(timer) => queue.push(timer),
+                        None => break,
+                    },
+                    _ = tokio::time::sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                        let ran = queue.run_due(Instant::now(), slack);
+                        log::trace!("Scheduler ran {ran} timers");
+                    }
+                }
+            }
+        });
+
+        Self { tx, low_power }
+    }
+
+    /// Stretch a delay if we are saving power
+    pub fn scaled(&self, d: Duration) -> Duration {
+        if self.low_power {
+            d * LOW_POWER_FACTOR
+        } else {
+            d
+        }
+    }
+
+    /// Run `f` once, after `delay`
+    pub fn after(&self, delay: Duration, f: impl FnOnce() + Send + 'static) {
+        self.at(Instant::now() + self.scaled(delay), f);
+    }
+
+    /// Run `f` once, at `due`. Deadlines are not stretched in low power mode.
+    pub fn at(&self, due: Instant, f: impl FnOnce() + Send + 'static) {
+        let mut f = Some(f);
+
+        self.add(Timer {
+            due,
+            period: None,
+            task: Box::new(move || {
+                if let Some(f) = f.take() {
+                    f();
+                }
+                false
+            }),
+        });
+    }
+
+    /// Wait for `delay`, woken by the timer task. The delay is stretched in
+    /// low power mode.
+    pub async fn sleep(&self, delay: Duration) {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+
+        self.after(delay, move || {
+            let _ = tx.send(());
+        });
+
+        // The timer is dropped if the task has stopped
+        if rx.await.is_err() {
+            tokio::time::sleep(self.scaled(delay)).await;
+        }
+    }
+
+    /// Run `f` every `period`, starting one period from now, until it
+    /// returns false. The period is stretched in low power mode.
+    pub fn every(&self, period: Duration, f: impl FnMut() -> bool + Send + 'static) {
+        let period = self.scaled(period);
+
+        self.add(Timer {
+            due: Instant::now() + period,
+            period: Some(period),
+            task: Box::new(f),
+        });
+    }
+
+    fn add(&self, timer: Timer) {
+        if self.tx.send(timer).is_err() {
+            log::warn!("Scheduler has stopped; dropping timer");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_timer_queue() {
+        let now = Instant::now();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let mut queue = TimerQueue::default();
+
+        let timer = |name: &'static str, due: u64, period: Option<u64>, runs: usize| {
+            let log = log.clone();
+            let mut left = runs;
+            Timer {
+                due: now + Duration::from_millis(due),
+                period: period.map(Duration::from_millis),
+                task: Box::new(move || {
+                    log.lock().unwrap().push(name);
+                    left -= 1;
+                    left > 0
+                }),
+            }
+        };
+
+        queue.push(timer("b", 20, None, 1));
+        queue.push(timer("a", 10, Some(30), 2));
+        queue.push(timer("c", 100, None, 1));
+
+        assert_eq!(queue.next_due(), Some(now + Duration::from_millis(10)));
+
+        // Slack pulls b into the same wakeup as a
+        let ran = queue.run_due(now + Duration::from_millis(10), Duration::from_millis(10));
+        assert_eq!(ran, 2);
+        assert_eq!(*log.lock().unwrap(), vec!["a", "b"]);
+
+        // a comes back once more, then stops
+        assert_eq!(queue.next_due(), Some(now + Duration::from_millis(40)));
+        queue.run_due(now + Duration::from_millis(200), Duration::ZERO);
+        assert_eq!(*log.lock().unwrap(), vec!["a", "b", "a", "c"]);
+        assert_eq!(queue.next_due(), None);
+    }
+}
@@ -0,0 +1,364 @@
+//! Managed scratch space for importers.
+//!
+//! Importers that need to put things on disk (extracted archives, downloads,
+//! transcoded textures) ask for a [`ScratchDir`]. Each one is removed when it
+//! is dropped, which normally happens when the owning scene is removed. The
+//! whole space is removed at shutdown. Writes are counted against a quota so a
+//! runaway import can't fill the disk.
+//!
+//! Each space holds a lock on a file in its root for as long as it lives. A
+//! crash leaves the space behind but releases the lock, so the next start
+//! can tell abandoned spaces from those of other running instances. Only
+//! roots with a lock file are ever reclaimed; anything else in the parent
+//! directory, even with a matching name, is left alone.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+
+use crate::import::ImportError;
+
+/// Prefix of each scratch space root
+const ROOT_PREFIX: &str = "platter-";
+
+/// Held by a live scratch space
+const LOCK_FILE: &str = ".platter.lock";
+
+/// Unlocked spaces younger than this may still be starting up
+const STARTUP_GRACE: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct ScratchInner {
+    /// Root of our scratch space. Taken at shutdown.
+    root: Mutex<Option<tempfile::TempDir>>,
+    /// Lock marking the space as in use. Released at shutdown.
+    lock: Mutex<Option<File>>,
+    path: PathBuf,
+    quota: u64,
+    used: AtomicU64,
+}
+
+/// Shared handle to the scratch space
+#[derive(Debug, Clone)]
+pub struct ScratchSpace(Arc<ScratchInner>);
+
+impl ScratchSpace {
+    /// Create a new scratch space inside `parent`, or the system temp
+    /// directory, limited to `quota` bytes.
+    pub fn new(parent: Option<&Path>, quota: u64) -> Result<Self> {
+        let parent = parent
+            .map(|f| f.to_path_buf())
+            .unwrap_or_else(std::env::temp_dir);
+
+        std::fs::create_dir_all(&parent)?;
+
+        let removed = recover(&parent, STARTUP_GRACE);
+
+        if removed > 0 {
+            log::info!(
+                "Removed {removed} abandoned scratch spaces from {}",
+                parent.display()
+            );
+        }
+
+        let root = tempfile::Builder::new()
+            .prefix(ROOT_PREFIX)
+            .tempdir_in(&parent)?;
+
+        let lock = File::create(root.path().join(LOCK_FILE))?;
+        lock.try_lock()
+            .map_err(|e| anyhow::anyhow!("Unable to lock scratch space: {e}"))?;
+
+        log::info!("Scratch space at {}", root.path().display());
+
+        Ok(Self(Arc::new(ScratchInner {
+            path: root.path().to_path_buf(),
+            root: Mutex::new(Some(root)),
+            lock: Mutex::new(Some(lock)),
+            quota,
+            used: AtomicU64::new(0),
+        })))
+    }
+
+    /// Bytes currently in use
+    pub fn used(&self) -> u64 {
+        self.0.used.load(Ordering::Relaxed)
+    }
+
+    /// Create a new directory, removed when the returned handle is dropped
+    pub fn allocate(&self, hint: &str) -> Result<ScratchDir> {
+        // Keep the hint readable, but don't let it escape the scratch root
+        let hint: String = hint
+            .chars()
+            .map(|f| if f.is_alphanumeric() { f } else { '_' })
+            .take(32)
+            .collect();
+
+        let dir = tempfile::Builder::new()
+            .prefix(&format!("{hint}-"))
+            .tempdir_in(&self.0.path)?;
+
+        Ok(ScratchDir {
+            dir,
+            space: self.clone(),
+            reserved: 0,
+        })
+    }
+
+    /// Remove everything. Later allocations will fail.
+    pub fn cleanup(&self) {
+        // Some platforms refuse to remove open files
+        self.0.lock.lock().unwrap().take();
+
+        if let Some(root) = self.0.root.lock().unwrap().take() {
+            log::info!("Removing scratch space at {}", root.path().display());
+            if let Err(e) = root.close() {
+                log::warn!("Unable to remove scratch space: {e}");
+            }
+        }
+    }
+
+    fn reserve(&self, bytes: u64) -> Result<()> {
+        let inner = &self.0;
+
+        inner
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                let next = used.checked_add(bytes)?;
+                (next <= inner.quota).then_some(next)
+            })
+            .map_err(|used| {
+                ImportError::UnableToImport(format!(
+                    "Scratch quota exceeded: {used} of {} bytes in use, {bytes} requested",
+                    inner.quota
+                ))
+            })?;
+
+        Ok(())
+    }
+
+    /// Bytes that may still be written
+    fn remaining(&self) -> u64 {
+        self.0.quota.saturating_sub(self.used())
+    }
+
+    fn release(&self, bytes: u64) {
+        self.0.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Whether a scratch root must be kept: it is younger than `grace`, as its
+/// owner may not have taken the lock yet, another process holds its lock,
+/// or it has no lock file and so may not be ours at all.
+fn in_use(root: &Path, grace: Duration) -> bool {
+    let age = std::fs::metadata(root)
+        .and_then(|f| f.modified())
+        .ok()
+        .and_then(|f| SystemTime::now().duration_since(f).ok())
+        .unwrap_or_default();
+
+    if age < grace {
+        return true;
+    }
+
+    match File::open(root.join(LOCK_FILE)) {
+        Ok(file) => match file.try_lock() {
+            Ok(()) => false,
+            Err(std::fs::TryLockError::WouldBlock) => true,
+            Err(std::fs::TryLockError::Error(e)) => {
+                log::warn!("Unable to check scratch space {}: {e}", root.display());
+                true
+            }
+        },
+        Err(_) => true,
+    }
+}
+
+/// Remove scratch roots in `parent` left behind by processes that have
+/// exited without cleaning up. Returns the number removed.
+fn recover(parent: &Path, grace: Duration) -> usize {
+    let Ok(entries) = std::fs::read_dir(parent) else {
+        return 0;
+    };
+
+    let mut removed = 0;
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        let ours = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|f| f.starts_with(ROOT_PREFIX));
+
+        if !ours || !path.is_dir() || in_use(&path, grace) {
+            continue;
+        }
+
+        log::debug!("Removing abandoned scratch space {}", path.display());
+
+        match std::fs::remove_dir_all(&path) {
+            Ok(()) => removed += 1,
+            Err(e) => log::warn!("Unable to remove {}: {e}", path.display()),
+        }
+    }
+
+    removed
+}
+
+/// A directory of scratch space for a single import
+#[derive(Debug)]
+pub struct ScratchDir {
+    dir: tempfile::TempDir,
+    space: ScratchSpace,
+    reserved: u64,
+}
+
+impl ScratchDir {
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Count `bytes` against the quota, for content written by other means
+    /// (an archive extractor, for example).
+    pub fn reserve(&mut self, bytes: u64) -> Result<()> {
+        self.space.reserve(bytes)?;
+        self.reserved += bytes;
+        Ok(())
+    }
+
+    /// Path of a file in this directory, creating the directories above it
+    pub fn file_path(&self, name: &str) -> Result<PathBuf> {
+        let path = self.dir.path().join(name);
+
+        // Names come from files we are importing; keep them inside
+        if !path.starts_with(self.dir.path()) || name.contains("..") {
+            return Err(
+                ImportError::UnableToImport(format!("Bad scratch file name {name}")).into(),
+            );
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        Ok(path)
+    }
+
+    /// Write a file into this directory, counting it against the quota
+    pub fn write(&mut self, name: &str, bytes: &[u8]) -> Result<PathBuf> {
+        let path = self.file_path(name)?;
+
+        self.reserve(bytes.len() as u64)?;
+
+        std::fs::write(&path, bytes)?;
+
+        Ok(path)
+    }
+
+    /// Copy a file into this directory from a reader, counting it against
+    /// the quota. Copying stops, failing, as soon as the quota is exceeded,
+    /// so a reader that says nothing of its size can't fill the disk.
+    pub fn write_from(&mut self, name: &str, reader: impl Read) -> Result<PathBuf> {
+        let path = self.file_path(name)?;
+
+        let limit = self.space.remaining().saturating_add(1);
+        let copied = std::io::copy(&mut reader.take(limit), &mut File::create(&path)?);
+
+        if let Err(e) = copied.map_err(Into::into).and_then(|f| self.reserve(f)) {
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+
+        Ok(path)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        self.space.release(self.reserved);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scratch_quota() {
+        let parent = tempfile::TempDir::new().unwrap();
+
+        let space = ScratchSpace::new(Some(parent.path()), 10).unwrap();
+
+        let mut a = space.allocate("a").unwrap();
+        let path = a.write("one.bin", &[0; 6]).unwrap();
+        assert!(path.exists());
+        assert_eq!(space.used(), 6);
+
+        let mut b = space.allocate("b").unwrap();
+        assert!(b.write("two.bin", &[0; 6]).is_err());
+        assert!(b.write("../escape.bin", &[0; 1]).is_err());
+
+        let dir = a.path().to_path_buf();
+        drop(a);
+        assert!(!dir.exists());
+        assert_eq!(space.used(), 0);
+
+        b.write("two.bin", &[0; 6]).unwrap();
+
+        // Copies stop once they pass the quota, and leave nothing behind
+        let mut c = space.allocate("c").unwrap();
+        assert!(c.write_from("big.bin", std::io::repeat(0)).is_err());
+        assert!(!c.path().join("big.bin").exists());
+        assert_eq!(space.used(), 6);
+
+        c.write_from("three.bin", &[0; 4][..]).unwrap();
+        assert_eq!(space.used(), 10);
+
+        space.cleanup();
+        assert_eq!(std::fs::read_dir(parent.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_recover() {
+        let parent = tempfile::TempDir::new().unwrap();
+
+        let live = ScratchSpace::new(Some(parent.path()), 10).unwrap();
+        live.allocate("a")
+            .unwrap()
+            .write("one.bin", &[0; 4])
+            .unwrap();
+
+        // A crashed instance: its lock file is there, but nobody holds it
+        let dead = parent.path().join("platter-dead");
+        std::fs::create_dir_all(dead.join("x")).unwrap();
+        std::fs::write(dead.join(LOCK_FILE), b"").unwrap();
+        std::fs::write(dead.join("x/two.bin"), [0; 4]).unwrap();
+
+        // Not ours at all
+        let other = parent.path().join("other");
+        std::fs::create_dir(&other).unwrap();
+
+        // A matching name, but no lock file, so not provably ours
+        let stranger = parent.path().join("platter-stranger");
+        std::fs::create_dir(&stranger).unwrap();
+        std::fs::write(stranger.join("data.bin"), [0; 4]).unwrap();
+
+        // Too new to judge
+        assert_eq!(recover(parent.path(), Duration::from_secs(3600)), 0);
+
+        assert_eq!(recover(parent.path(), Duration::ZERO), 1);
+        assert!(!dead.exists());
+        assert!(other.exists());
+        assert!(stranger.join("data.bin").exists());
+        assert!(live.0.path.join(LOCK_FILE).exists());
+
+        live.cleanup();
+        assert_eq!(recover(parent.path(), Duration::ZERO), 0);
+    }
+}
@@ -0,0 +1,132 @@
+//! Playback of watched directories as sequences.
+//!
+//! Simulations write one file per time step. In sequence mode each new file
+//! is shown in place of the one before, but the last few are kept around,
+//! hidden, so clients can step back through them.
+
+use std::collections::VecDeque;
+
+/// The most recent scenes from one source, oldest first, and which of them
+/// is shown
+#[derive(Debug)]
+pub struct Sequence {
+    capacity: usize,
+    scenes: VecDeque<u32>,
+    current: usize,
+}
+
+impl Sequence {
+    /// A sequence keeping at most `capacity` scenes
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            scenes: VecDeque::new(),
+            current: 0,
+        }
+    }
+
+    /// Add the newest scene and show it. Returns scenes that no longer fit,
+    /// which should be removed.
+    pub fn push(&mut self, id: u32) -> Vec<u32> {
+        self.scenes.push_back(id);
+
+        let excess = self.scenes.len().saturating_sub(self.capacity);
+        let dropped = self.scenes.drain(..excess).collect();
+
+        self.current = self.scenes.len() - 1;
+
+        dropped
+    }
+
+    /// Forget a scene that was removed some other way. If it was shown, the
+    /// next newer scene is shown instead, or the newest if there is none.
+    pub fn remove(&mut self, id: u32) -> bool {
+        let Some(index) = self.scenes.iter().position(|f| *f == id) else {
+            return false;
+        };
+
+        self.scenes.remove(index);
+
+        if index < self.current {
+            self.current -= 1;
+        }
+
+        self.current = self.current.min(self.scenes.len().saturating_sub(1));
+
+        true
+    }
+
+    /// Show a scene `delta` steps newer, or older if negative, stopping at
+    /// either end. Returns true if the shown scene changed.
+    pub fn step(&mut self, delta: isize) -> bool {
+        if self.scenes.is_empty() {
+            return false;
+        }
+
+        let last = self.scenes.len() as isize - 1;
+        let next = (self.current as isize + delta).clamp(0, last) as usize;
+
+        std::mem::replace(&mut self.current, next) != next
+    }
+
+    /// The scene shown
+    pub fn current(&self) -> Option<u32> {
+        self.scenes.get(self.current).copied()
+    }
+
+    /// Every scene kept, oldest first
+    pub fn scenes(&self) -> impl Iterator<Item = u32> + '_ {
+        self.scenes.iter().copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sequence() {
+        let mut seq = Sequence::new(3);
+
+        assert_eq!(seq.current(), None);
+        assert!(!seq.step(-1));
+
+        for id in 0..3 {
+            assert!(seq.push(id).is_empty());
+        }
+        assert_eq!(seq.current(), Some(2));
+
+        // The oldest falls out
+        assert_eq!(seq.push(3), [0]);
+        assert_eq!(seq.scenes().collect::<Vec<_>>(), [1, 2, 3]);
+
+        assert!(seq.step(-1));
+        assert_eq!(seq.current(), Some(2));
+        assert!(seq.step(-5));
+        assert_eq!(seq.current(), Some(1));
+        assert!(!seq.step(-1));
+
+        // Removing the shown scene shows the next newer one
+        assert!(seq.remove(1));
+        assert_eq!(seq.current(), Some(2));
+        assert!(!seq.remove(1));
+
+        assert!(seq.step(1));
+        assert_eq!(seq.current(), Some(3));
+        assert!(!seq.step(1));
+
+        // Removing an older scene keeps the shown one
+        assert!(seq.remove(2));
+        assert_eq!(seq.current(), Some(3));
+
+        assert!(seq.remove(3));
+        assert_eq!(seq.current(), None);
+
+        // New scenes are shown as they arrive
+        seq.push(4);
+        seq.push(5);
+        seq.step(-1);
+        seq.push(6);
+        assert_eq!(seq.current(), Some(6));
+    }
+}
@@ -0,0 +1,176 @@
+//! Ground shadows.
+//!
+//! Many clients draw no shadows, which leaves scenes floating with nothing to
+//! say where the floor is. A soft dark blob under each scene, fading out at
+//! its edge, is a cheap stand-in for a contact shadow.
+//!
+//! The blob lies on the world's floor plane, under the scene as it is placed
+//! now. It is not parented to the scene, whose rotation and axis conversion
+//! would tip it over; the scene moves it instead whenever its transform
+//! changes.
+
+use std::f32::consts::PI;
+use std::path::Path;
+
+use anyhow::Result;
+use nalgebra::{Matrix4, Vector3};
+
+use crate::colored_mesh::{self, ColoredMesh};
+use crate::import::ImportOptions;
+use crate::scene::{Bounds, Scene};
+use colabrodo_server::{server_http::*, server_messages::*, server_state::*};
+
+/// Points around the edge of the blob
+const SEGMENTS: u32 = 32;
+
+/// How far past the footprint of the scene the blob reaches
+const MARGIN: f32 = 1.2;
+
+/// Opacity of the blob at its center, and at the inner ring where it starts
+/// to fade out
+const CENTER_ALPHA: u8 = 140;
+const RING_ALPHA: u8 = 100;
+
+/// Where the inner ring sits, as a fraction of the full radius
+const RING: f32 = 0.6;
+
+/// Build a flat disc of radius one on the XZ plane, centered on the origin.
+/// It is dark throughout, and its alpha falls to zero at the edge.
+pub fn blob() -> ColoredMesh {
+    let mut ret = ColoredMesh::default();
+
+    ret.vertices.push([0.0; 3]);
+    ret.vertex_colors.push(Some([0, 0, 0, CENTER_ALPHA]));
+
+    for (scale, alpha) in [(RING, RING_ALPHA), (1.0, 0)] {
+        for i in 0..SEGMENTS {
+            let a = 2.0 * PI * i as f32 / SEGMENTS as f32;
+            ret.vertices.push([a.cos() * scale, 0.0, a.sin() * scale]);
+            ret.vertex_colors.push(Some([0, 0, 0, alpha]));
+        }
+    }
+
+    let inner = |i: u32| 1 + i % SEGMENTS;
+    let outer = |i: u32| 1 + SEGMENTS + i % SEGMENTS;
+
+    // Wound to face up
+    for i in 0..SEGMENTS {
+        ret.faces.push((vec![0, inner(i + 1), inner(i)], None));
+        ret.faces
+            .push((vec![inner(i), inner(i + 1), outer(i + 1), outer(i)], None));
+    }
+
+    ret
+}
+
+/// Transform stretching the blob into an ellipse covering the footprint of
+/// `world`, bounds in world coordinates, just above their lowest point
+pub fn placement(world: &Bounds) -> Matrix4<f32> {
+    let center = world.center();
+    let extent = world.extent();
+
+    // Keep something to see for flat or degenerate content
+    let floor = extent.max().max(1e-3) * 0.05;
+    let rx = (extent.x / 2.0).max(floor) * MARGIN;
+    let rz = (extent.z / 2.0).max(floor) * MARGIN;
+
+    // Lift it a touch, so it doesn't fight with a floor drawn at the base
+    let y = world.min.y + extent.max() * 1e-3;
+
+    Matrix4::new_translation(&Vector3::new(center.x, y, center.z))
+        * Matrix4::new_nonuniform_scaling(&Vector3::new(rx, 1.0, rz))
+}
+
+/// Publish a shadow for a scene, placed in world space under it. The scene
+/// keeps it under the content as it moves. The shadow is tagged as a helper
+/// so clients can hide it, and its material is kept out of the scene's own
+/// so color overrides leave it alone. `name` keeps deterministic asset ids
+/// apart.
+pub fn attach_shadow(
+    scene: &mut Scene,
+    name: &str,
+    state: ServerStatePtr,
+    asset_store: AssetStorePtr,
+    options: &ImportOptions,
+) -> Result<()> {
+    if scene.root.parts.is_empty() {
+        return Ok(());
+    }
+
+    let options = ImportOptions {
+        tint: None,
+        // Flat anyway; this lets the rings share vertices
+        generate_normals: true,
+        ..options.clone()
+    };
+
+    let path = format!("{name} shadow");
+
+    let mut shadow =
+        colored_mesh::publish_mesh(&blob(), Path::new(&path), state, asset_store, &options)?;
+
+    // Take the parts; the emptied scene then unpublishes nothing on drop
+    let parts = std::mem::take(&mut shadow.root.parts);
+    scene.published.append(&mut shadow.published);
+
+    for part in &parts {
+        ServerEntityStateUpdatable {
+            tags: Some(vec!["platter.helper=shadow".into()]),
+            ..Default::default()
+        }
+        .patch(part);
+    }
+
+    scene.shadow = parts.first().cloned();
+    scene.root.parts.extend(parts);
+    scene.update_transform();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_blob() {
+        let bounds = Bounds {
+            min: Vector3::new(-1.0, 2.0, 0.0),
+            max: Vector3::new(1.0, 4.0, 4.0),
+        };
+
+        let mesh = blob();
+        let tf = placement(&bounds);
+
+        assert_eq!(mesh.vertices.len(), 1 + 2 * SEGMENTS as usize);
+        assert_eq!(mesh.faces.len(), 2 * SEGMENTS as usize);
+        assert!(mesh.has_alpha());
+
+        // Flat, just above the base, and covering the footprint
+        let placed: Vec<_> = mesh
+            .vertices
+            .iter()
+            .map(|v| tf.transform_point(&(*v).into()))
+            .collect();
+
+        for v in &placed {
+            assert!(v.y > 2.0 && v.y < 2.01);
+        }
+
+        let max_x = placed.iter().map(|v| v.x).fold(f32::MIN, f32::max);
+        let max_z = placed.iter().map(|v| v.z).fold(f32::MIN, f32::max);
+        assert!((max_x - MARGIN).abs() < 1e-4);
+        assert!((max_z - (2.0 + 2.0 * MARGIN)).abs() < 1e-4);
+
+        // Faces point up
+        for (face, _) in &mesh.faces {
+            let [a, b, c] = [0, 1, 2].map(|f| Vector3::from(mesh.vertices[face[f] as usize]));
+            assert!((b - a).cross(&(c - a)).y > 0.0);
+        }
+
+        // Fully transparent at the edge
+        assert!(mesh.vertex_colors[1 + SEGMENTS as usize..]
+            .iter()
+            .all(|c| c.unwrap()[3] == 0));
+    }
+}
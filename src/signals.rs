@@ -0,0 +1,155 @@
+//! NOODLES signals for the platter server
+
+use colabrodo_common::components::MethodArg;
+use colabrodo_common::value_tools::Value;
+use colabrodo_server::server_messages::*;
+use colabrodo_server::server_state::*;
+
+/// Signals sent on the document
+pub struct Signals {
+    /// A different scene is highlighted
+    pub highlight_changed: SignalReference,
+
+    /// A scene was added
+    pub scene_loaded: SignalReference,
+
+    /// A scene was removed
+    pub scene_removed: SignalReference,
+
+    /// Assets of a new scene, in the order to fetch them
+    pub prefetch: SignalReference,
+}
+
+/// Arguments shared by the scene signals
+const SCENE_ARGS: &[(&str, &str)] = &[
+    ("scene", "Id of the scene"),
+    ("source", "Path the scene was imported from, or null"),
+    (
+        "tag",
+        "Tag of the watched directory or load the scene came from, or null",
+    ),
+];
+
+/// Why a scene was removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Removal {
+    /// Deleted, or dropped from a sequence
+    Removed,
+    /// Older than its time to live
+    Expired,
+    /// Its source was cleared, or the server is shutting down
+    Cleared,
+}
+
+impl Removal {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Removal::Removed => "removed",
+            Removal::Expired => "expired",
+            Removal::Cleared => "cleared",
+        }
+    }
+}
+
+impl Signals {
+    /// Create the signals and attach them to the document
+    pub fn new(state: &mut ServerState) -> Self {
+        let ret = Self {
+            highlight_changed: new_signal(
+                state,
+                "platter.highlight_changed",
+                "Sent when a different scene is highlighted, so clients can draw attention to it.",
+                &[("scene", "Id of the highlighted scene, or null if none is")],
+            ),
+            scene_loaded: new_signal(
+                state,
+                "platter.scene_loaded",
+                "Sent when a scene is added, whether loaded, generated, or duplicated.",
+                SCENE_ARGS,
+            ),
+            scene_removed: new_signal(
+                state,
+                "platter.scene_removed",
+                "Sent when a scene is removed, for any reason.",
+                &[
+                    SCENE_ARGS,
+                    &[("reason", "Why: \"removed\", \"expired\", or \"cleared\"")],
+                ]
+                .concat(),
+            ),
+            prefetch: new_signal(
+                state,
+                "platter.prefetch",
+                "Sent after a scene is added, listing the URLs of its assets by importance: \
+                 geometry before images, smaller before larger. Clients can use it to \
+                 schedule downloads.",
+                &[
+                    ("scene", "Id of the scene"),
+                    ("urls", "Asset URLs, most important first"),
+                ],
+            ),
+        };
+
+        state.update_document(ServerDocumentUpdate {
+            signals_list: Some(vec![
+                ret.highlight_changed.clone(),
+                ret.scene_loaded.clone(),
+                ret.scene_removed.clone(),
+                ret.prefetch.clone(),
+            ]),
+            ..Default::default()
+        });
+
+        ret
+    }
+}
+
+fn new_signal(
+    state: &mut ServerState,
+    name: &str,
+    doc: &str,
+    args: &[(&str, &str)],
+) -> SignalReference {
+    state.signals.new_component(ServerSignalState {
+        name: name.into(),
+        doc: Some(doc.into()),
+        arguments: args
+            .iter()
+            .map(|(name, doc)| MethodArg {
+                name: name.to_string(),
+                doc: Some(doc.to_string()),
+            })
+            .collect(),
+    })
+}
+
+/// Arguments for a scene signal, in the order of `SCENE_ARGS`
+pub fn scene_args(id: u32, source: Option<&std::path::Path>, tag: Option<String>) -> Vec<Value> {
+    vec![
+        Value::Integer(id.into()),
+        source
+            .map(|f| Value::Text(f.display().to_string()))
+            .unwrap_or(Value::Null),
+        tag.map(Value::Text).unwrap_or(Value::Null),
+    ]
+}
+
+/// Arguments for the scene removed signal
+pub fn removed_args(
+    id: u32,
+    source: Option<&std::path::Path>,
+    tag: Option<String>,
+    reason: Removal,
+) -> Vec<Value> {
+    let mut ret = scene_args(id, source, tag);
+    ret.push(Value::Text(reason.as_str().into()));
+    ret
+}
+
+/// Arguments for the prefetch signal
+pub fn prefetch_args(id: u32, urls: &[url::Url]) -> Vec<Value> {
+    vec![
+        Value::Integer(id.into()),
+        Value::Array(urls.iter().map(|f| Value::Text(f.to_string())).collect()),
+    ]
+}
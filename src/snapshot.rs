@@ -0,0 +1,223 @@
+//! Read-only snapshots of what platter is publishing.
+//!
+//! A snapshot lists each scene with its source, its transform, its assets,
+//! and the state of its entities, materials, textures, and images. Asset
+//! contents are left out; only their ids are given, and images and buffers
+//! give their URLs or buffer views. Geometry components are left out, too.
+//! Scenes are in id order, so snapshots from two runs can be diffed.
+
+use anyhow::Result;
+
+use colabrodo_common::common::ComponentID;
+use colabrodo_common::value_tools::*;
+use colabrodo_server::server_messages::*;
+use colabrodo_server::server_state::*;
+
+use crate::platter_state::PlatterState;
+use crate::scene::Scene;
+
+fn text(s: impl Into<String>) -> Value {
+    Value::Text(s.into())
+}
+
+fn vec3(v: &nalgebra::Vector3<f32>) -> Value {
+    Value::Array(v.iter().map(|f| Value::Float(*f as f64)).collect())
+}
+
+/// A component's id and serialized state, which is null if it is gone
+fn component(id: ComponentID, body: Option<Value>) -> Value {
+    Value::Map(vec![
+        (text("id"), Value::serialized(&id).unwrap_or(Value::Null)),
+        (text("state"), body.unwrap_or(Value::Null)),
+    ])
+}
+
+fn entity(ent: &EntityReference, state: &ServerState) -> Value {
+    let body = state
+        .entities
+        .inspect(ent.id(), |f| Value::serialized(f).ok())
+        .flatten();
+
+    component(ent.id(), body)
+}
+
+/// Materials of a scene, with the textures and images they use
+fn materials(scene: &Scene, state: &ServerState) -> [Value; 3] {
+    let mut materials = Vec::new();
+    let mut textures: Vec<TextureReference> = Vec::new();
+    let mut images: Vec<ImageReference> = Vec::new();
+
+    for mat in &scene.materials {
+        let body = state.materials.inspect(mat.id(), |f| {
+            let m = &f.mutable;
+            let pbr = m.pbr_info.as_ref();
+
+            let used = [
+                pbr.and_then(|p| p.base_color_texture.as_ref()),
+                pbr.and_then(|p| p.metal_rough_texture.as_ref()),
+                m.normal_texture.as_ref(),
+                m.occlusion_texture.as_ref(),
+                m.emissive_texture.as_ref(),
+            ];
+
+            for t in used.into_iter().flatten() {
+                if !textures.contains(&t.texture) {
+                    textures.push(t.texture.clone());
+                }
+            }
+
+            Value::serialized(f).ok()
+        });
+
+        materials.push(component(mat.id(), body.flatten()));
+    }
+
+    let textures: Vec<_> = textures
+        .iter()
+        .map(|tex| {
+            let body = state.textures.inspect(tex.id(), |f| {
+                if !images.contains(&f.image) {
+                    images.push(f.image.clone());
+                }
+                Value::serialized(f).ok()
+            });
+
+            component(tex.id(), body.flatten())
+        })
+        .collect();
+
+    let images = images
+        .iter()
+        .map(|img| {
+            let body = state
+                .images
+                .inspect(img.id(), |f| Value::serialized(f).ok())
+                .flatten();
+
+            component(img.id(), body)
+        })
+        .collect();
+
+    [
+        Value::Array(materials),
+        Value::Array(textures),
+        Value::Array(images),
+    ]
+}
+
+fn scene(id: u32, scene: &Scene, state: &ServerState) -> Value {
+    let info = &scene.info;
+
+    let optional = |v: Option<String>| v.map(Value::Text).unwrap_or(Value::Null);
+
+    let bounds = match &info.bounds {
+        Some(b) => Value::Map(vec![
+            (text("min"), vec3(&b.min)),
+            (text("max"), vec3(&b.max)),
+        ]),
+        None => Value::Null,
+    };
+
+    let transform = scene
+        .transform()
+        .iter()
+        .map(|f| Value::Float(*f as f64))
+        .collect();
+
+    let [materials, textures, images] = materials(scene, state);
+
+    Value::Map(vec![
+        (text("id"), Value::Integer(id.into())),
+        (
+            text("source"),
+            optional(info.source.as_ref().map(|f| f.display().to_string())),
+        ),
+        (text("format"), optional(info.format.clone())),
+        (text("units"), optional(info.units.clone())),
+        (text("triangles"), Value::Integer(info.triangles.into())),
+        (
+            text("removed"),
+            Value::Map(vec![
+                (
+                    text("degenerate"),
+                    Value::Integer(info.cleanup.degenerate.into()),
+                ),
+                (
+                    text("duplicate"),
+                    Value::Integer(info.cleanup.duplicate.into()),
+                ),
+                (text("welded"), Value::Integer(info.cleanup.welded.into())),
+                (
+                    text("nonfinite"),
+                    Value::Integer(info.cleanup.nonfinite.into()),
+                ),
+            ]),
+        ),
+        (text("bounds"), bounds),
+        (text("transform"), Value::Array(transform)),
+        (
+            text("assets"),
+            Value::Array(
+                scene
+                    .published
+                    .iter()
+                    .map(|f| text(f.to_string()))
+                    .collect(),
+            ),
+        ),
+        (
+            text("entities"),
+            Value::Array(
+                scene
+                    .root
+                    .all_parts()
+                    .iter()
+                    .map(|f| entity(f, state))
+                    .collect(),
+            ),
+        ),
+        (text("materials"), materials),
+        (text("textures"), textures),
+        (text("images"), images),
+    ])
+}
+
+/// Take a snapshot of all live scenes
+pub fn snapshot(app: &PlatterState, state: &ServerState) -> Value {
+    let scenes = app
+        .scenes()
+        .into_iter()
+        .map(|(id, s)| scene(id, s, state))
+        .collect();
+
+    Value::Map(vec![
+        (text("version"), text(clap::crate_version!())),
+        (text("scenes"), Value::Array(scenes)),
+    ])
+}
+
+/// Render a snapshot as JSON, for tools that don't speak CBOR
+pub fn to_json(snapshot: &Value) -> Result<String> {
+    Ok(serde_json::to_string_pretty(snapshot)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let v = Value::Map(vec![
+            (text("id"), Value::Integer(3.into())),
+            (text("bounds"), vec3(&nalgebra::Vector3::new(1.0, 0.5, 0.0))),
+            (text("units"), Value::Null),
+        ]);
+
+        let json: serde_json::Value = serde_json::from_str(&to_json(&v).unwrap()).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({"id": 3, "bounds": [1.0, 0.5, 0.0], "units": null})
+        );
+    }
+}
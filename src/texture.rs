@@ -0,0 +1,127 @@
+//! Fitting textures to clients.
+//!
+//! Scans and photogrammetry models often come with 8K textures, which thin
+//! clients struggle to hold. With `--max-texture-size`, larger images are
+//! decoded, scaled down to fit, and encoded again before they are published.
+//! JPEGs stay JPEGs; everything else becomes a PNG. KTX2 textures are
+//! transcoded first where possible, unless the `ktx2` capability is enabled.
+
+use std::io::Cursor;
+
+use anyhow::Result;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader};
+
+use crate::capabilities::Capability;
+use crate::import::ImportOptions;
+use crate::ktx2;
+
+/// Quality of re-encoded JPEGs
+const JPEG_QUALITY: u8 = 90;
+
+/// Scale an image down so neither side is longer than `max` pixels. Returns
+/// None if it already fits.
+pub fn downscale(bytes: &[u8], max: u32) -> Result<Option<Vec<u8>>> {
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let format = reader.format();
+
+    // Only the header is read to check the size
+    let (width, height) = reader.into_dimensions()?;
+
+    if width.max(height) <= max {
+        return Ok(None);
+    }
+
+    log::debug!("Scaling a {width}x{height} texture to fit {max}");
+
+    let image = image::load_from_memory(bytes)?.resize(max, max, FilterType::Triangle);
+
+    let mut ret = Vec::new();
+    let mut out = Cursor::new(&mut ret);
+
+    match format {
+        Some(ImageFormat::Jpeg) => {
+            let encoder = JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY);
+            DynamicImage::ImageRgb8(image.into_rgb8()).write_with_encoder(encoder)?;
+        }
+        _ => image.write_to(&mut out, ImageFormat::Png)?,
+    }
+
+    Ok(Some(ret))
+}
+
+/// Transcode an image clients can't decode, and scale it to the texture
+/// size limit, if there is one. Returns None if it is fine as it is.
+pub fn prepare(bytes: &[u8], options: &ImportOptions) -> Result<Option<Vec<u8>>> {
+    let transcoded = match ktx2::is_ktx2(bytes) && !options.capabilities.has(Capability::Ktx2) {
+        true => Some(ktx2::to_png(bytes)?),
+        false => None,
+    };
+
+    let current = transcoded.as_deref().unwrap_or(bytes);
+
+    // KTX2 left for clients can't be scaled here
+    let Some(max) = options.max_texture_size.filter(|_| !ktx2::is_ktx2(current)) else {
+        return Ok(transcoded);
+    };
+
+    Ok(downscale(current, max)?.or(transcoded))
+}
+
+/// Prepare an image for clients. Images that can't be read are kept as
+/// they are; a client may still manage them.
+pub fn fit(bytes: Vec<u8>, options: &ImportOptions) -> Vec<u8> {
+    match prepare(&bytes, options) {
+        Ok(Some(ready)) => ready,
+        Ok(None) => bytes,
+        Err(e) => {
+            log::warn!("Unable to prepare texture, publishing it as is: {e}");
+            bytes
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encode(image: DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut ret = Vec::new();
+        image.write_to(&mut Cursor::new(&mut ret), format).unwrap();
+        ret
+    }
+
+    fn dimensions(bytes: &[u8]) -> (ImageFormat, u32, u32) {
+        let image = image::load_from_memory(bytes).unwrap();
+        (
+            image::guess_format(bytes).unwrap(),
+            image.width(),
+            image.height(),
+        )
+    }
+
+    #[test]
+    fn test_downscale() {
+        let png = encode(DynamicImage::new_rgba8(64, 32), ImageFormat::Png);
+        let jpeg = encode(DynamicImage::new_rgb8(20, 40), ImageFormat::Jpeg);
+
+        // The longest side fits, keeping the aspect ratio and format
+        let small = downscale(&png, 16).unwrap().unwrap();
+        assert_eq!(dimensions(&small), (ImageFormat::Png, 16, 8));
+
+        let small = downscale(&jpeg, 10).unwrap().unwrap();
+        assert_eq!(dimensions(&small), (ImageFormat::Jpeg, 5, 10));
+
+        assert!(downscale(&png, 64).unwrap().is_none());
+        assert!(downscale(b"not an image", 16).is_err());
+
+        // Without a limit, or for unreadable images, the bytes are kept
+        let mut options = ImportOptions::default();
+        assert_eq!(fit(png.clone(), &options), png);
+
+        options.max_texture_size = Some(16);
+        assert_eq!(fit(b"junk".to_vec(), &options), b"junk");
+        assert_eq!(dimensions(&fit(png, &options)).1, 16);
+    }
+}
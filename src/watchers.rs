@@ -0,0 +1,280 @@
+//! Supervision of directory watchers.
+//!
+//! Watchers used to be spawned and forgotten: asking for the same directory
+//! again started another watcher on it, and nothing could tell how many were
+//! running. They are now kept in a registry. Each has its own stop signal
+//! and join handle, a directory is watched at most once, and watchers that
+//! have exited are reaped. The registry is what `platter.status` reports.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use colabrodo_server::server::tokio;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+
+use crate::arguments::Directory;
+use crate::dir_watcher::{self, WatcherOptions};
+use crate::platter_state::PlatterCommand;
+
+/// How long a watcher has to stop at shutdown before it is aborted
+const STOP_GRACE: Duration = Duration::from_secs(2);
+
+/// Live watchers, shared between the platter state and the task that
+/// starts them
+#[derive(Clone, Default)]
+pub struct Watchers(Arc<Mutex<Vec<Entry>>>);
+
+struct Entry {
+    /// Canonical path of the watched directory
+    dir: PathBuf,
+    started: Instant,
+    stop: broadcast::Sender<bool>,
+    handle: JoinHandle<()>,
+}
+
+/// A running watcher, as reported to clients
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatcherStatus {
+    pub dir: PathBuf,
+    pub uptime: Duration,
+}
+
+fn canonical(dir: &Path) -> PathBuf {
+    dir.canonicalize().unwrap_or_else(|_| dir.into())
+}
+
+/// Drop entries whose watcher has exited
+fn reap(entries: &mut Vec<Entry>) {
+    entries.retain(|f| {
+        if f.handle.is_finished() {
+            log::warn!("Watcher for {} exited", f.dir.display());
+        }
+        !f.handle.is_finished()
+    });
+}
+
+impl Watchers {
+    /// Whether a live watcher covers this directory
+    pub fn is_watching(&self, dir: &Path) -> bool {
+        let dir = canonical(dir);
+        let mut entries = self.0.lock().unwrap();
+        reap(&mut entries);
+        entries.iter().any(|f| f.dir == dir)
+    }
+
+    /// Start watching a directory. Returns false, and starts nothing, if a
+    /// live watcher already covers it.
+    pub fn start(
+        &self,
+        dir: Directory,
+        tx: mpsc::Sender<PlatterCommand>,
+        options: WatcherOptions,
+    ) -> bool {
+        let key = canonical(&dir.dir);
+        let mut entries = self.0.lock().unwrap();
+
+        reap(&mut entries);
+
+        if entries.iter().any(|f| f.dir == key) {
+            log::info!("{} is already watched", key.display());
+            return false;
+        }
+
+        let (stop, stop_rx) = broadcast::channel(1);
+
+        let handle = tokio::spawn(dir_watcher::launch_file_watcher(tx, dir, stop_rx, options));
+
+        entries.push(Entry {
+            dir: key,
+            started: Instant::now(),
+            stop,
+            handle,
+        });
+
+        true
+    }
+
+    /// Stop the watcher on a directory. Returns false if there is none.
+    pub fn stop(&self, dir: &Path) -> bool {
+        let dir = canonical(dir);
+        let mut entries = self.0.lock().unwrap();
+
+        let Some(index) = entries.iter().position(|f| f.dir == dir) else {
+            return false;
+        };
+
+        // The watcher exits on its own; there is nothing to wait for
+        let _ = entries.remove(index).stop.send(true);
+
+        true
+    }
+
+    /// Stop the watcher on a directory, only if it is below `root` once
+    /// both are canonical. Returns false if there is no such watcher;
+    /// watchers elsewhere, such as those given on the command line, are
+    /// left alone.
+    pub fn stop_below(&self, root: &Path, dir: &Path) -> bool {
+        let root = canonical(root);
+        let dir = canonical(dir);
+
+        dir.starts_with(&root) && self.stop(&dir)
+    }
+
+    /// Live watchers, oldest first
+    pub fn list(&self) -> Vec<WatcherStatus> {
+        let mut entries = self.0.lock().unwrap();
+        reap(&mut entries);
+
+        entries
+            .iter()
+            .map(|f| WatcherStatus {
+                dir: f.dir.clone(),
+                uptime: f.started.elapsed(),
+            })
+            .collect()
+    }
+
+    /// Stop every watcher and wait for them to exit. Any that take too long
+    /// are aborted.
+    pub async fn shutdown(&self) {
+        let entries = std::mem::take(&mut *self.0.lock().unwrap());
+
+        for entry in &entries {
+            let _ = entry.stop.send(true);
+        }
+
+        for entry in entries {
+            let abort = entry.handle.abort_handle();
+
+            if tokio::time::timeout(STOP_GRACE, entry.handle)
+                .await
+                .is_err()
+            {
+                log::warn!("Watcher for {} did not stop; aborting", entry.dir.display());
+                abort.abort();
+            }
+        }
+    }
+}
+
+/// Start watchers as they are requested, until a stop is signalled. Every
+/// watcher is then stopped before this returns.
+pub async fn run(
+    watchers: Watchers,
+    mut requests: mpsc::Receiver<Directory>,
+    tx: mpsc::Sender<PlatterCommand>,
+    options: WatcherOptions,
+    mut stopper: broadcast::Receiver<bool>,
+) {
+    loop {
+        tokio::select! {
+            _ = stopper.recv() => break,
+            msg = requests.recv() => match msg {
+                Some(dir) => {
+                    watchers.start(dir, tx.clone(), options.clone());
+                }
+                None => break,
+            },
+        }
+    }
+
+    watchers.shutdown().await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serial_test::serial;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_watchers() {
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let request = Directory {
+            dir: dir.path().into(),
+            load_existing: false,
+            latest_only: false,
+            sequence: None,
+            organize_by_dir: false,
+            transform: Default::default(),
+            ttl: None,
+            policy: Default::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            poll: None,
+            max_depth: None,
+            no_recursive: false,
+        };
+
+        let watchers = Watchers::default();
+        let (tx, _rx) = mpsc::channel(4);
+
+        assert!(watchers.start(request.clone(), tx.clone(), Default::default()));
+
+        // A second request for the same directory starts nothing
+        assert!(!watchers.start(request.clone(), tx.clone(), Default::default()));
+        assert!(watchers.is_watching(dir.path()));
+
+        let list = watchers.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].dir, canonical(dir.path()));
+
+        // Stopped watchers can be started again
+        assert!(watchers.stop(dir.path()));
+        assert!(!watchers.stop(dir.path()));
+        assert!(watchers.list().is_empty());
+        assert!(watchers.start(request, tx, Default::default()));
+
+        watchers.shutdown().await;
+
+        assert!(watchers.list().is_empty());
+        assert!(!watchers.is_watching(dir.path()));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_stop_below() {
+        let root = tempfile::TempDir::new().unwrap();
+        let outside = tempfile::TempDir::new().unwrap();
+        let inside = root.path().join("inside");
+        std::fs::create_dir(&inside).unwrap();
+
+        let watchers = Watchers::default();
+        let (tx, _rx) = mpsc::channel(4);
+
+        for dir in [&inside, outside.path()] {
+            let request = Directory {
+                dir: dir.into(),
+                load_existing: false,
+                latest_only: false,
+                sequence: None,
+                organize_by_dir: false,
+                transform: Default::default(),
+                ttl: None,
+                policy: Default::default(),
+                include: Vec::new(),
+                exclude: Vec::new(),
+                poll: None,
+                max_depth: None,
+                no_recursive: false,
+            };
+            assert!(watchers.start(request, tx.clone(), Default::default()));
+        }
+
+        // Watches outside the root can't be stopped, however they are named
+        assert!(!watchers.stop_below(root.path(), outside.path()));
+        let escape = inside
+            .join("../..")
+            .join(outside.path().file_name().unwrap());
+        assert!(!watchers.stop_below(root.path(), &escape));
+        assert!(watchers.is_watching(outside.path()));
+
+        assert!(watchers.stop_below(root.path(), &inside));
+        assert!(!watchers.is_watching(&inside));
+
+        watchers.shutdown().await;
+    }
+}